@@ -28,8 +28,10 @@ use pool::PoolCliArgs;
 use rpc::RpcCliArgs;
 use rundler_rpc::EthApiSettings;
 use rundler_sim::{
-    EstimationSettings, PrecheckSettings, PriorityFeeMode, SimulationSettings, MIN_CALL_GAS_LIMIT,
+    EstimationSettings, EvmVersion, PrecheckSettings, PriorityFeeMode, SimulationSettings,
+    MIN_CALL_GAS_LIMIT,
 };
+use rundler_types::EntryPointVersion;
 
 /// Main entry point for the CLI
 ///
@@ -126,6 +128,17 @@ pub struct CommonArgs {
     )]
     max_verification_gas: u64,
 
+    /// Minimum verification gas limit that an operation must set to be
+    /// accepted
+    #[arg(
+        long = "min_verification_gas_limit",
+        name = "min_verification_gas_limit",
+        env = "MIN_VERIFICATION_GAS_LIMIT",
+        default_value = "0",
+        global = true
+    )]
+    min_verification_gas_limit: u64,
+
     #[arg(
         long = "max_bundle_gas",
         name = "max_bundle_gas",
@@ -172,6 +185,116 @@ pub struct CommonArgs {
     )]
     max_simulate_handle_ops_gas: u64,
 
+    /// Number of verification gas limits tried concurrently in the first
+    /// round of gas estimation. 1 tries a single limit per round.
+    #[arg(
+        long = "estimation_fanout",
+        name = "estimation_fanout",
+        env = "ESTIMATION_FANOUT",
+        default_value = "1",
+        global = true
+    )]
+    estimation_fanout: usize,
+
+    /// Maximum time in milliseconds to wait for the code hash and aggregator
+    /// checks after validation before returning a partial simulation result.
+    /// Defaults to waiting until the checks complete.
+    #[arg(
+        long = "simulation_soft_deadline_ms",
+        name = "simulation_soft_deadline_ms",
+        env = "SIMULATION_SOFT_DEADLINE_MS",
+        global = true
+    )]
+    simulation_soft_deadline_ms: Option<u64>,
+
+    /// Gas given to the traced validation call, to match the gas cap the node
+    /// applies to `debug_traceCall`. Defaults to the maximum verification gas
+    /// plus the operation's pre-verification gas.
+    #[arg(
+        long = "trace_gas_cap",
+        name = "trace_gas_cap",
+        env = "TRACE_GAS_CAP",
+        global = true
+    )]
+    trace_gas_cap: Option<u64>,
+
+    /// Percentage below the calculated minimum pre-verification gas that is
+    /// still accepted during precheck
+    #[arg(
+        long = "pre_verification_gas_tolerance_percent",
+        name = "pre_verification_gas_tolerance_percent",
+        env = "PRE_VERIFICATION_GAS_TOLERANCE_PERCENT",
+        default_value = "0",
+        global = true
+    )]
+    pre_verification_gas_tolerance_percent: u64,
+
+    /// Minimum entry point deposit, in wei, that the payer of an operation
+    /// must hold for the operation to be accepted
+    #[arg(
+        long = "min_payer_deposit",
+        name = "min_payer_deposit",
+        env = "MIN_PAYER_DEPOSIT",
+        default_value = "0",
+        global = true
+    )]
+    min_payer_deposit: u128,
+
+    /// Maximum sum of the verification and call gas limits of an operation
+    /// accepted during precheck
+    #[arg(
+        long = "max_verification_and_call_gas",
+        name = "max_verification_and_call_gas",
+        env = "MAX_VERIFICATION_AND_CALL_GAS",
+        default_value = "0",
+        global = true
+    )]
+    max_verification_and_call_gas: u64,
+
+    /// The hardfork the chain follows, one of paris, shanghai, cancun or
+    /// prague
+    #[arg(
+        long = "evm_version",
+        name = "evm_version",
+        env = "EVM_VERSION",
+        default_value = "cancun",
+        global = true
+    )]
+    evm_version: EvmVersion,
+
+    /// The version of the entry point, one of v0.6, v0.7 or v0.8
+    #[arg(
+        long = "entry_point_version",
+        name = "entry_point_version",
+        env = "ENTRY_POINT_VERSION",
+        default_value = "v0.6",
+        global = true
+    )]
+    entry_point_version: EntryPointVersion,
+
+    /// Whether to disable rundler's deviations from the validation rules,
+    /// for compatibility testing
+    #[arg(
+        long = "strict_spec_mode",
+        name = "strict_spec_mode",
+        env = "STRICT_SPEC_MODE",
+        required = false,
+        num_args = 0,
+        global = true
+    )]
+    strict_spec_mode: bool,
+
+    /// Minimum tip, in wei per gas, that an operation must pay above the
+    /// current base fee for bundling it to be profitable
+    #[arg(
+        long = "min_priority_fee",
+        name = "min_priority_fee",
+        env = "MIN_PRIORITY_FEE",
+        default_value = "0",
+        global = true
+    )]
+    min_priority_fee: u128,
+
     #[arg(
         long = "use_bundle_priority_fee",
         name = "use_bundle_priority_fee",
@@ -266,6 +389,8 @@ impl TryFrom<&CommonArgs> for EstimationSettings {
             max_verification_gas: value.max_verification_gas,
             max_call_gas,
             max_simulate_handle_ops_gas: value.max_simulate_handle_ops_gas,
+            estimation_fanout: value.estimation_fanout,
+            evm_version: value.evm_version,
         })
     }
 }
@@ -277,25 +402,39 @@ impl TryFrom<&CommonArgs> for PrecheckSettings {
         Ok(Self {
             chain_id: value.chain_id,
             max_verification_gas: value.max_verification_gas.into(),
+            min_verification_gas_limit: value.min_verification_gas_limit.into(),
             max_total_execution_gas: value.max_bundle_gas.into(),
+            max_verification_and_call_gas: value.max_verification_and_call_gas.into(),
             use_bundle_priority_fee: value.use_bundle_priority_fee,
             bundle_priority_fee_overhead_percent: value.bundle_priority_fee_overhead_percent,
             priority_fee_mode: PriorityFeeMode::try_from(
                 value.priority_fee_mode_kind.as_str(),
                 value.priority_fee_mode_value,
             )?,
+            pvg_tolerance_percent: value.pre_verification_gas_tolerance_percent,
+            min_payer_deposit: value.min_payer_deposit.into(),
+            min_priority_fee: value.min_priority_fee.into(),
+            evm_version: value.evm_version,
         })
     }
 }
 
 impl From<&CommonArgs> for SimulationSettings {
     fn from(value: &CommonArgs) -> Self {
-        Self::new(
-            value.min_unstake_delay,
-            value.min_stake_value,
-            value.max_simulate_handle_ops_gas,
-            value.max_verification_gas,
-        )
+        Self {
+            trace_gas_cap: value.trace_gas_cap.map(Into::into),
+            chain_id: value.chain_id,
+            evm_version: value.evm_version,
+            soft_deadline_ms: value.simulation_soft_deadline_ms,
+            entry_point_version: value.entry_point_version,
+            strict_spec_mode: value.strict_spec_mode,
+            ..Self::new(
+                value.min_unstake_delay,
+                value.min_stake_value,
+                value.max_simulate_handle_ops_gas,
+                value.max_verification_gas,
+            )
+        }
     }
 }
 
@@ -32,7 +32,9 @@ use rundler_sim::{
     gas, ExpectedStorage, FeeEstimator, PriorityFeeMode, SimulationError, SimulationSuccess,
     Simulator,
 };
-use rundler_types::{Entity, EntityType, GasFees, Timestamp, UserOperation, UserOpsPerAggregator};
+use rundler_types::{
+    Entity, EntityType, EntryPointVersion, GasFees, Timestamp, UserOperation, UserOpsPerAggregator,
+};
 use rundler_utils::{emit::WithEntryPoint, math};
 use tokio::{sync::broadcast, try_join};
 use tracing::{error, info};
@@ -104,6 +106,7 @@ pub(crate) struct Settings {
     pub(crate) use_bundle_priority_fee: Option<bool>,
     pub(crate) bundle_priority_fee_overhead_percent: u64,
     pub(crate) priority_fee_mode: PriorityFeeMode,
+    pub(crate) entry_point_version: EntryPointVersion,
 }
 
 #[async_trait]
@@ -261,9 +264,20 @@ where
             .simulate_validation(op.uo.clone(), Some(block_hash), Some(op.expected_code_hash))
             .await;
         match result {
+            // A partial result skipped the code hash and aggregator checks,
+            // so the operation is left out until it can be fully checked
+            Ok(success) if success.partial => Err(anyhow::anyhow!(
+                "simulation checks did not finish before the soft deadline"
+            )),
             Ok(success) => Ok((op.uo, Ok(success))),
             Err(error) => match error {
                 SimulationError::Violations(_) => Ok((op.uo, Err(error))),
+                SimulationError::Provider(error) => Err(error.into()),
+                SimulationError::Cancelled => Err(anyhow::anyhow!("simulation was cancelled")),
+                SimulationError::NodeUnavailable => Err(anyhow::anyhow!("node is unavailable")),
+                SimulationError::ReorgDetected => {
+                    Err(anyhow::anyhow!("simulation block was reorged"))
+                }
                 SimulationError::Other(error) => Err(error),
             },
         }
@@ -409,7 +423,7 @@ where
         // sum up the gas needed for all the ops in the bundle
         // and apply an overhead multiplier
         let gas = math::increase_by_percent(
-            context.get_total_gas_limit(self.settings.chain_id),
+            context.get_total_gas_limit(self.settings.chain_id, self.settings.entry_point_version),
             BUNDLE_TRANSACTION_GAS_OVERHEAD_PERCENT,
         );
         let handle_ops_out = self
@@ -736,8 +750,8 @@ impl ProposalContext {
             .collect()
     }
 
-    fn get_total_gas_limit(&self, chain_id: u64) -> U256 {
-        gas::bundle_gas_limit(self.iter_ops(), chain_id)
+    fn get_total_gas_limit(&self, chain_id: u64, entry_point_version: EntryPointVersion) -> U256 {
+        gas::bundle_gas_limit(self.iter_ops(), chain_id, entry_point_version)
     }
 
     fn iter_ops_with_simulations(&self) -> impl Iterator<Item = &OpWithSimulation> + '_ {
@@ -1312,6 +1326,7 @@ mod tests {
                 use_bundle_priority_fee: Some(true),
                 priority_fee_mode: PriorityFeeMode::PriorityFeeIncreasePercent(10),
                 bundle_priority_fee_overhead_percent: 0,
+                entry_point_version: EntryPointVersion::V0_6,
             },
             event_sender,
         );
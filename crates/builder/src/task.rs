@@ -32,7 +32,7 @@ use rundler_sim::{
     MempoolConfig, PriorityFeeMode, SimulateValidationTracerImpl, SimulationSettings, SimulatorImpl,
 };
 use rundler_task::Task;
-use rundler_types::contracts::i_entry_point::IEntryPoint;
+use rundler_types::{contracts::i_entry_point::IEntryPoint, EntryPointVersion};
 use rundler_utils::{emit::WithEntryPoint, eth, handle};
 use rusoto_core::Region;
 use tokio::{
@@ -87,6 +87,8 @@ pub struct Args {
     pub bundle_priority_fee_overhead_percent: u64,
     /// Priority fee mode to use for operation priority fee minimums
     pub priority_fee_mode: PriorityFeeMode,
+    /// Version of the entry point bundles are submitted to
+    pub entry_point_version: EntryPointVersion,
     /// Sender to be used by the builder
     pub sender_type: TransactionSenderType,
     /// RPC node poll interval
@@ -269,16 +271,19 @@ where
             use_bundle_priority_fee: self.args.use_bundle_priority_fee,
             priority_fee_mode: self.args.priority_fee_mode,
             bundle_priority_fee_overhead_percent: self.args.bundle_priority_fee_overhead_percent,
+            entry_point_version: self.args.entry_point_version,
         };
 
         let entry_point = IEntryPoint::new(self.args.entry_point_address, Arc::clone(&provider));
         let simulate_validation_tracer =
-            SimulateValidationTracerImpl::new(Arc::clone(&provider), entry_point.clone());
+            SimulateValidationTracerImpl::new(Arc::clone(&provider), entry_point.clone())
+                .with_gas_cap(self.args.sim_settings.trace_gas_cap)
+                .with_strict_spec_mode(self.args.sim_settings.strict_spec_mode);
         let simulator = SimulatorImpl::new(
             Arc::clone(&provider),
             entry_point.address(),
             simulate_validation_tracer,
-            self.args.sim_settings,
+            self.args.sim_settings.clone(),
             self.args.mempool_configs.clone(),
         );
 
@@ -13,7 +13,10 @@
 
 use std::mem;
 
-use ethers::{abi::Address, types::U256};
+use ethers::{
+    abi::Address,
+    types::{Bytes, U256},
+};
 use rundler_sim::{PrecheckError, PrecheckViolation, SimulationError, SimulationViolation};
 use rundler_types::Entity;
 
@@ -67,7 +70,10 @@ impl From<SimulationError> for MempoolError {
         };
 
         // extract violation and replace with dummy
-        Self::SimulationViolation(mem::replace(violation, SimulationViolation::DidNotRevert))
+        Self::SimulationViolation(mem::replace(
+            violation,
+            SimulationViolation::DidNotRevert(Bytes::default()),
+        ))
     }
 }
 
@@ -243,6 +243,12 @@ where
             .simulator
             .simulate_validation(op.clone(), None, None)
             .await?;
+        // A partial result skipped the code hash and aggregator checks
+        if sim_result.partial {
+            return Err(MempoolError::Other(anyhow::anyhow!(
+                "simulation checks did not finish before the soft deadline"
+            )));
+        }
         if let Some(agg) = &sim_result.aggregator {
             return Err(MempoolError::UnsupportedAggregator(agg.address));
         }
@@ -397,6 +403,7 @@ impl UoPoolMetrics {
 
 #[cfg(test)]
 mod tests {
+    use ethers::types::Bytes;
     use rundler_sim::{
         MockPrechecker, MockSimulator, PrecheckError, PrecheckSettings, PrecheckViolation,
         SimulationError, SimulationSettings, SimulationSuccess, SimulationViolation,
@@ -709,14 +716,14 @@ mod tests {
             0,
             0,
             None,
-            Some(SimulationViolation::DidNotRevert),
+            Some(SimulationViolation::DidNotRevert(Bytes::default())),
             false,
         );
         let ops = vec![op.clone()];
         let pool = create_pool(ops);
 
         match pool.add_operation(OperationOrigin::Local, op.op).await {
-            Err(MempoolError::SimulationViolation(SimulationViolation::DidNotRevert)) => {}
+            Err(MempoolError::SimulationViolation(SimulationViolation::DidNotRevert(_))) => {}
             _ => panic!("Expected DidNotRevert error"),
         }
         assert_eq!(pool.best_operations(1, 0).unwrap(), vec![]);
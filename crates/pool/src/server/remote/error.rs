@@ -15,23 +15,27 @@ use anyhow::{bail, Context};
 use ethers::types::Opcode;
 use rundler_sim::{PrecheckViolation, SimulationViolation, ViolationOpCode};
 use rundler_task::grpc::protos::{from_bytes, to_le_bytes, ConversionError};
-use rundler_types::StorageSlot;
 
 use super::protos::{
     mempool_error, precheck_violation_error, simulation_violation_error,
-    AccessedUndeployedContract, AggregatorValidationFailed, CallGasLimitTooLow, CallHadValue,
-    CalledBannedEntryPointMethod, CodeHashChanged, DidNotRevert, DiscardedOnInsertError, Entity,
+    AccessedUndeployedContract, AggregatorReturnedEmptySignature, AggregatorValidationFailed,
+    CallGasLimitTooLow, CallHadValue, CalledBannedEntryPointMethod, CalledHandleOps,
+    CodeHashChanged, CustomPrecheckFailed, DidNotRevert, DiscardedOnInsertError, Entity,
     EntityThrottledError, EntityType, ExistingSenderWithInitCode, FactoryCalledCreate2Twice,
-    FactoryIsNotContract, InitCodeTooShort, InvalidSignature, InvalidStorageAccess,
+    FactoryDenied, FactoryIsNotContract, FactoryNotDeployed, InitCodeTooShort,
+    InsufficientPriorityFee, InvalidEncoding, InvalidSignature, InvalidStorageAccess,
     MaxFeePerGasTooLow, MaxOperationsReachedError, MaxPriorityFeePerGasTooLow,
-    MempoolError as ProtoMempoolError, NotStaked, OperationAlreadyKnownError, OutOfGas,
+    MempoolError as ProtoMempoolError, NotStaked, OpGasExceedsBlockLimit,
+    OperationAlreadyKnownError, OutOfGas, PayerDepositBelowMinimum, PaymasterDenied,
     PaymasterDepositTooLow, PaymasterIsNotContract, PaymasterTooShort, PreVerificationGasTooLow,
     PrecheckViolationError as ProtoPrecheckViolationError, ReplacementUnderpricedError,
-    SenderFundsTooLow, SenderIsNotContractAndNoInitCode,
-    SimulationViolationError as ProtoSimulationViolationError, TotalGasLimitTooHigh,
+    RestrictedOpcode, SenderDenied, SenderFundsTooLow, SenderIsNotContractAndNoInitCode,
+    SimulationViolationError as ProtoSimulationViolationError, TooManyAccessedAddresses,
+    TotalGasLimitExceeded, TotalGasLimitTooHigh, TransientStorageAccess, TruncatedViolations,
     UnintendedRevert, UnintendedRevertWithMessage, UnknownEntryPointError,
-    UnsupportedAggregatorError, UsedForbiddenOpcode, UsedForbiddenPrecompile,
-    VerificationGasLimitTooHigh, WrongNumberOfPhases,
+    UnsupportedAggregatorError, UsedBlockHash, UsedForbiddenOpcode, UsedForbiddenPrecompile,
+    VerificationGasLimitTooHigh, VerificationGasLimitTooLow, WrongNumberOfPhases,
+    ZeroCallGasWithCallData,
 };
 use crate::{mempool::MempoolError, server::error::PoolServerError};
 
@@ -221,6 +225,14 @@ impl From<PrecheckViolation> for ProtoPrecheckViolationError {
                     },
                 )),
             },
+            PrecheckViolation::TotalGasLimitExceeded(actual, max) => ProtoPrecheckViolationError {
+                violation: Some(precheck_violation_error::Violation::TotalGasLimitExceeded(
+                    TotalGasLimitExceeded {
+                        actual_gas: to_le_bytes(actual),
+                        max_gas: to_le_bytes(max),
+                    },
+                )),
+            },
             PrecheckViolation::VerificationGasLimitTooHigh(actual, max) => {
                 ProtoPrecheckViolationError {
                     violation: Some(
@@ -233,6 +245,18 @@ impl From<PrecheckViolation> for ProtoPrecheckViolationError {
                     ),
                 }
             }
+            PrecheckViolation::VerificationGasLimitTooLow(actual, min) => {
+                ProtoPrecheckViolationError {
+                    violation: Some(
+                        precheck_violation_error::Violation::VerificationGasLimitTooLow(
+                            VerificationGasLimitTooLow {
+                                actual_gas: to_le_bytes(actual),
+                                min_gas: to_le_bytes(min),
+                            },
+                        ),
+                    ),
+                }
+            }
             PrecheckViolation::PreVerificationGasTooLow(actual, min) => {
                 ProtoPrecheckViolationError {
                     violation: Some(
@@ -275,6 +299,42 @@ impl From<PrecheckViolation> for ProtoPrecheckViolationError {
                     },
                 )),
             },
+            PrecheckViolation::PayerDepositBelowMinimum(payer, min) => {
+                ProtoPrecheckViolationError {
+                    violation: Some(
+                        precheck_violation_error::Violation::PayerDepositBelowMinimum(
+                            PayerDepositBelowMinimum {
+                                payer_address: payer.as_bytes().to_vec(),
+                                min_deposit: to_le_bytes(min),
+                            },
+                        ),
+                    ),
+                }
+            }
+            PrecheckViolation::ZeroCallGasWithCallData => ProtoPrecheckViolationError {
+                violation: Some(
+                    precheck_violation_error::Violation::ZeroCallGasWithCallData(
+                        ZeroCallGasWithCallData {},
+                    ),
+                ),
+            },
+            PrecheckViolation::InvalidEncoding(reason) => ProtoPrecheckViolationError {
+                violation: Some(precheck_violation_error::Violation::InvalidEncoding(
+                    InvalidEncoding { reason },
+                )),
+            },
+            PrecheckViolation::InsufficientPriorityFee(effective, required) => {
+                ProtoPrecheckViolationError {
+                    violation: Some(
+                        precheck_violation_error::Violation::InsufficientPriorityFee(
+                            InsufficientPriorityFee {
+                                effective_gas_price: to_le_bytes(effective),
+                                required_gas_price: to_le_bytes(required),
+                            },
+                        ),
+                    ),
+                }
+            }
             PrecheckViolation::MaxFeePerGasTooLow(actual, min) => ProtoPrecheckViolationError {
                 violation: Some(precheck_violation_error::Violation::MaxFeePerGasTooLow(
                     MaxFeePerGasTooLow {
@@ -330,12 +390,24 @@ impl TryFrom<ProtoPrecheckViolationError> for PrecheckViolation {
                     from_bytes(&e.max_gas)?,
                 )
             }
+            Some(precheck_violation_error::Violation::TotalGasLimitExceeded(e)) => {
+                PrecheckViolation::TotalGasLimitExceeded(
+                    from_bytes(&e.actual_gas)?,
+                    from_bytes(&e.max_gas)?,
+                )
+            }
             Some(precheck_violation_error::Violation::VerificationGasLimitTooHigh(e)) => {
                 PrecheckViolation::VerificationGasLimitTooHigh(
                     from_bytes(&e.actual_gas)?,
                     from_bytes(&e.max_gas)?,
                 )
             }
+            Some(precheck_violation_error::Violation::VerificationGasLimitTooLow(e)) => {
+                PrecheckViolation::VerificationGasLimitTooLow(
+                    from_bytes(&e.actual_gas)?,
+                    from_bytes(&e.min_gas)?,
+                )
+            }
             Some(precheck_violation_error::Violation::PreVerificationGasTooLow(e)) => {
                 PrecheckViolation::PreVerificationGasTooLow(
                     from_bytes(&e.actual_gas)?,
@@ -360,6 +432,24 @@ impl TryFrom<ProtoPrecheckViolationError> for PrecheckViolation {
                     from_bytes(&e.min_funds)?,
                 )
             }
+            Some(precheck_violation_error::Violation::PayerDepositBelowMinimum(e)) => {
+                PrecheckViolation::PayerDepositBelowMinimum(
+                    from_bytes(&e.payer_address)?,
+                    from_bytes(&e.min_deposit)?,
+                )
+            }
+            Some(precheck_violation_error::Violation::ZeroCallGasWithCallData(_)) => {
+                PrecheckViolation::ZeroCallGasWithCallData
+            }
+            Some(precheck_violation_error::Violation::InvalidEncoding(e)) => {
+                PrecheckViolation::InvalidEncoding(e.reason)
+            }
+            Some(precheck_violation_error::Violation::InsufficientPriorityFee(e)) => {
+                PrecheckViolation::InsufficientPriorityFee(
+                    from_bytes(&e.effective_gas_price)?,
+                    from_bytes(&e.required_gas_price)?,
+                )
+            }
             Some(precheck_violation_error::Violation::MaxFeePerGasTooLow(e)) => {
                 PrecheckViolation::MaxFeePerGasTooLow(
                     from_bytes(&e.actual_fee)?,
@@ -409,13 +499,24 @@ impl From<SimulationViolation> for ProtoSimulationViolationError {
                     ),
                 }
             }
-            SimulationViolation::UsedForbiddenOpcode(entity, addr, opcode) => {
+            SimulationViolation::UsedForbiddenOpcode(entity, addr, opcode, count) => {
                 ProtoSimulationViolationError {
                     violation: Some(simulation_violation_error::Violation::UsedForbiddenOpcode(
                         UsedForbiddenOpcode {
                             entity: Some((&entity).into()),
                             contract_address: addr.as_bytes().to_vec(),
                             opcode: opcode.0 as u32,
+                            count: count as u64,
+                        },
+                    )),
+                }
+            }
+            SimulationViolation::RestrictedOpcode(entity, opcode) => {
+                ProtoSimulationViolationError {
+                    violation: Some(simulation_violation_error::Violation::RestrictedOpcode(
+                        RestrictedOpcode {
+                            entity: Some((&entity).into()),
+                            opcode: opcode.0 as u32,
                         },
                     )),
                 }
@@ -444,28 +545,50 @@ impl From<SimulationViolation> for ProtoSimulationViolationError {
                     ),
                 ),
             },
-            SimulationViolation::InvalidStorageAccess(entity, slot) => {
+            SimulationViolation::InvalidStorageAccess(entity, address, slots) => {
                 ProtoSimulationViolationError {
                     violation: Some(simulation_violation_error::Violation::InvalidStorageAccess(
                         InvalidStorageAccess {
                             entity: Some((&entity).into()),
-                            contract_address: slot.address.as_bytes().to_vec(),
-                            slot: to_le_bytes(slot.slot),
+                            contract_address: address.as_bytes().to_vec(),
+                            slots: slots.into_iter().map(to_le_bytes).collect(),
                         },
                     )),
                 }
             }
-            SimulationViolation::NotStaked(entity, min_stake, min_unstake_delay) => {
-                ProtoSimulationViolationError {
-                    violation: Some(simulation_violation_error::Violation::NotStaked(
-                        NotStaked {
+            SimulationViolation::TransientStorageAccess(entity) => ProtoSimulationViolationError {
+                violation: Some(
+                    simulation_violation_error::Violation::TransientStorageAccess(
+                        TransientStorageAccess {
                             entity: Some((&entity).into()),
-                            min_stake: to_le_bytes(min_stake),
-                            min_unstake_delay: to_le_bytes(min_unstake_delay),
                         },
-                    )),
-                }
-            }
+                    ),
+                ),
+            },
+            SimulationViolation::UsedBlockHash(entity) => ProtoSimulationViolationError {
+                violation: Some(simulation_violation_error::Violation::UsedBlockHash(
+                    UsedBlockHash {
+                        entity: Some((&entity).into()),
+                    },
+                )),
+            },
+            SimulationViolation::NotStaked {
+                entity,
+                actual_stake,
+                required_stake,
+                actual_delay,
+                required_delay,
+            } => ProtoSimulationViolationError {
+                violation: Some(simulation_violation_error::Violation::NotStaked(
+                    NotStaked {
+                        entity: Some((&entity).into()),
+                        min_stake: to_le_bytes(required_stake),
+                        min_unstake_delay: to_le_bytes(required_delay),
+                        stake: to_le_bytes(actual_stake),
+                        unstake_delay: to_le_bytes(actual_delay),
+                    },
+                )),
+            },
             SimulationViolation::UnintendedRevert(et) => ProtoSimulationViolationError {
                 violation: Some(simulation_violation_error::Violation::UnintendedRevert(
                     UnintendedRevert {
@@ -473,9 +596,11 @@ impl From<SimulationViolation> for ProtoSimulationViolationError {
                     },
                 )),
             },
-            SimulationViolation::DidNotRevert => ProtoSimulationViolationError {
+            SimulationViolation::DidNotRevert(return_data) => ProtoSimulationViolationError {
                 violation: Some(simulation_violation_error::Violation::DidNotRevert(
-                    DidNotRevert {},
+                    DidNotRevert {
+                        return_data: return_data.to_vec(),
+                    },
                 )),
             },
             SimulationViolation::WrongNumberOfPhases(num_phases) => ProtoSimulationViolationError {
@@ -518,11 +643,99 @@ impl From<SimulationViolation> for ProtoSimulationViolationError {
                     ),
                 }
             }
-            SimulationViolation::CodeHashChanged => ProtoSimulationViolationError {
+            SimulationViolation::CalledHandleOps(entity) => ProtoSimulationViolationError {
+                violation: Some(simulation_violation_error::Violation::CalledHandleOps(
+                    CalledHandleOps {
+                        entity: Some((&entity).into()),
+                    },
+                )),
+            },
+            SimulationViolation::TooManyAccessedAddresses(max_accessed_addresses) => {
+                ProtoSimulationViolationError {
+                    violation: Some(
+                        simulation_violation_error::Violation::TooManyAccessedAddresses(
+                            TooManyAccessedAddresses {
+                                max_accessed_addresses: max_accessed_addresses as u64,
+                            },
+                        ),
+                    ),
+                }
+            }
+            SimulationViolation::OpGasExceedsBlockLimit(total_gas, block_gas_limit) => {
+                ProtoSimulationViolationError {
+                    violation: Some(
+                        simulation_violation_error::Violation::OpGasExceedsBlockLimit(
+                            OpGasExceedsBlockLimit {
+                                total_gas: to_le_bytes(total_gas),
+                                block_gas_limit: to_le_bytes(block_gas_limit),
+                            },
+                        ),
+                    ),
+                }
+            }
+            SimulationViolation::TruncatedViolations(total_count) => {
+                ProtoSimulationViolationError {
+                    violation: Some(simulation_violation_error::Violation::TruncatedViolations(
+                        TruncatedViolations {
+                            total_count: total_count as u64,
+                        },
+                    )),
+                }
+            }
+            SimulationViolation::SenderDenied(addr) => ProtoSimulationViolationError {
+                violation: Some(simulation_violation_error::Violation::SenderDenied(
+                    SenderDenied {
+                        sender_address: addr.as_bytes().to_vec(),
+                    },
+                )),
+            },
+            SimulationViolation::CustomPrecheckFailed(reason) => ProtoSimulationViolationError {
+                violation: Some(simulation_violation_error::Violation::CustomPrecheckFailed(
+                    CustomPrecheckFailed { reason },
+                )),
+            },
+            SimulationViolation::FactoryNotDeployed(addr) => ProtoSimulationViolationError {
+                violation: Some(simulation_violation_error::Violation::FactoryNotDeployed(
+                    FactoryNotDeployed {
+                        factory_address: addr.as_bytes().to_vec(),
+                    },
+                )),
+            },
+            SimulationViolation::FactoryDenied(addr) => ProtoSimulationViolationError {
+                violation: Some(simulation_violation_error::Violation::FactoryDenied(
+                    FactoryDenied {
+                        factory_address: addr.as_bytes().to_vec(),
+                    },
+                )),
+            },
+            SimulationViolation::PaymasterDenied(addr) => ProtoSimulationViolationError {
+                violation: Some(simulation_violation_error::Violation::PaymasterDenied(
+                    PaymasterDenied {
+                        paymaster_address: addr.as_bytes().to_vec(),
+                    },
+                )),
+            },
+            SimulationViolation::CodeHashChanged(addresses) => ProtoSimulationViolationError {
                 violation: Some(simulation_violation_error::Violation::CodeHashChanged(
-                    CodeHashChanged {},
+                    CodeHashChanged {
+                        contract_addresses: addresses
+                            .into_iter()
+                            .map(|address| address.as_bytes().to_vec())
+                            .collect(),
+                    },
                 )),
             },
+            SimulationViolation::AggregatorReturnedEmptySignature(addr) => {
+                ProtoSimulationViolationError {
+                    violation: Some(
+                        simulation_violation_error::Violation::AggregatorReturnedEmptySignature(
+                            AggregatorReturnedEmptySignature {
+                                aggregator_address: addr.as_bytes().to_vec(),
+                            },
+                        ),
+                    ),
+                }
+            }
             SimulationViolation::AggregatorValidationFailed => ProtoSimulationViolationError {
                 violation: Some(
                     simulation_violation_error::Violation::AggregatorValidationFailed(
@@ -563,6 +776,13 @@ impl TryFrom<ProtoSimulationViolationError> for SimulationViolation {
                     (&e.entity.context("should have entity in error")?).try_into()?,
                     from_bytes(&e.contract_address)?,
                     ViolationOpCode(Opcode::try_from(e.opcode as u8)?),
+                    e.count as usize,
+                )
+            }
+            Some(simulation_violation_error::Violation::RestrictedOpcode(e)) => {
+                SimulationViolation::RestrictedOpcode(
+                    (&e.entity.context("should have entity in error")?).try_into()?,
+                    ViolationOpCode(Opcode::try_from(e.opcode as u8)?),
                 )
             }
             Some(simulation_violation_error::Violation::UsedForbiddenPrecompile(e)) => {
@@ -578,26 +798,39 @@ impl TryFrom<ProtoSimulationViolationError> for SimulationViolation {
             Some(simulation_violation_error::Violation::InvalidStorageAccess(e)) => {
                 SimulationViolation::InvalidStorageAccess(
                     (&e.entity.context("should have entity in error")?).try_into()?,
-                    StorageSlot {
-                        address: from_bytes(&e.contract_address)?,
-                        slot: from_bytes(&e.slot)?,
-                    },
+                    from_bytes(&e.contract_address)?,
+                    e.slots
+                        .iter()
+                        .map(|slot| from_bytes(slot))
+                        .collect::<Result<_, _>>()?,
                 )
             }
-            Some(simulation_violation_error::Violation::NotStaked(e)) => {
-                SimulationViolation::NotStaked(
+            Some(simulation_violation_error::Violation::TransientStorageAccess(e)) => {
+                SimulationViolation::TransientStorageAccess(
+                    (&e.entity.context("should have entity in error")?).try_into()?,
+                )
+            }
+            Some(simulation_violation_error::Violation::UsedBlockHash(e)) => {
+                SimulationViolation::UsedBlockHash(
                     (&e.entity.context("should have entity in error")?).try_into()?,
-                    from_bytes(&e.min_stake)?,
-                    from_bytes(&e.min_unstake_delay)?,
                 )
             }
+            Some(simulation_violation_error::Violation::NotStaked(e)) => {
+                SimulationViolation::NotStaked {
+                    entity: (&e.entity.context("should have entity in error")?).try_into()?,
+                    actual_stake: from_bytes(&e.stake)?,
+                    required_stake: from_bytes(&e.min_stake)?,
+                    actual_delay: from_bytes(&e.unstake_delay)?,
+                    required_delay: from_bytes(&e.min_unstake_delay)?,
+                }
+            }
             Some(simulation_violation_error::Violation::UnintendedRevert(e)) => {
                 SimulationViolation::UnintendedRevert(rundler_types::EntityType::try_from(
                     EntityType::try_from(e.entity_type).context("unknown entity type")?,
                 )?)
             }
-            Some(simulation_violation_error::Violation::DidNotRevert(_)) => {
-                SimulationViolation::DidNotRevert
+            Some(simulation_violation_error::Violation::DidNotRevert(e)) => {
+                SimulationViolation::DidNotRevert(e.return_data.into())
             }
             Some(simulation_violation_error::Violation::WrongNumberOfPhases(e)) => {
                 SimulationViolation::WrongNumberOfPhases(e.num_phases)
@@ -623,8 +856,50 @@ impl TryFrom<ProtoSimulationViolationError> for SimulationViolation {
                     (&e.entity.context("should have entity in error")?).try_into()?,
                 )
             }
-            Some(simulation_violation_error::Violation::CodeHashChanged(_)) => {
-                SimulationViolation::CodeHashChanged
+            Some(simulation_violation_error::Violation::CalledHandleOps(e)) => {
+                SimulationViolation::CalledHandleOps(
+                    (&e.entity.context("should have entity in error")?).try_into()?,
+                )
+            }
+            Some(simulation_violation_error::Violation::TooManyAccessedAddresses(e)) => {
+                SimulationViolation::TooManyAccessedAddresses(e.max_accessed_addresses.try_into()?)
+            }
+            Some(simulation_violation_error::Violation::OpGasExceedsBlockLimit(e)) => {
+                SimulationViolation::OpGasExceedsBlockLimit(
+                    from_bytes(&e.total_gas)?,
+                    from_bytes(&e.block_gas_limit)?,
+                )
+            }
+            Some(simulation_violation_error::Violation::TruncatedViolations(e)) => {
+                SimulationViolation::TruncatedViolations(e.total_count.try_into()?)
+            }
+            Some(simulation_violation_error::Violation::SenderDenied(e)) => {
+                SimulationViolation::SenderDenied(from_bytes(&e.sender_address)?)
+            }
+            Some(simulation_violation_error::Violation::CustomPrecheckFailed(e)) => {
+                SimulationViolation::CustomPrecheckFailed(e.reason)
+            }
+            Some(simulation_violation_error::Violation::FactoryNotDeployed(e)) => {
+                SimulationViolation::FactoryNotDeployed(from_bytes(&e.factory_address)?)
+            }
+            Some(simulation_violation_error::Violation::FactoryDenied(e)) => {
+                SimulationViolation::FactoryDenied(from_bytes(&e.factory_address)?)
+            }
+            Some(simulation_violation_error::Violation::PaymasterDenied(e)) => {
+                SimulationViolation::PaymasterDenied(from_bytes(&e.paymaster_address)?)
+            }
+            Some(simulation_violation_error::Violation::CodeHashChanged(e)) => {
+                SimulationViolation::CodeHashChanged(
+                    e.contract_addresses
+                        .iter()
+                        .map(|address| from_bytes(address))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            Some(simulation_violation_error::Violation::AggregatorReturnedEmptySignature(e)) => {
+                SimulationViolation::AggregatorReturnedEmptySignature(from_bytes(
+                    &e.aggregator_address,
+                )?)
             }
             Some(simulation_violation_error::Violation::AggregatorValidationFailed(_)) => {
                 SimulationViolation::AggregatorValidationFailed
@@ -679,6 +954,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_code_hash_changed_error() {
+        let addresses = vec![
+            ethers::types::Address::random(),
+            ethers::types::Address::random(),
+        ];
+        let error = MempoolError::SimulationViolation(SimulationViolation::CodeHashChanged(
+            addresses.clone(),
+        ));
+        let proto_error: ProtoMempoolError = error.into();
+        let error2 = proto_error.try_into().unwrap();
+        match error2 {
+            MempoolError::SimulationViolation(SimulationViolation::CodeHashChanged(a)) => {
+                assert_eq!(a, addresses)
+            }
+            _ => panic!("wrong error type"),
+        }
+    }
+
     #[test]
     fn test_invalid_convert() {
         let error = ProtoMempoolError { error: None };
@@ -179,7 +179,9 @@ impl PoolTask {
 
         let i_entry_point = IEntryPoint::new(pool_config.entry_point, Arc::clone(&provider));
         let simulate_validation_tracer =
-            SimulateValidationTracerImpl::new(Arc::clone(&provider), i_entry_point.clone());
+            SimulateValidationTracerImpl::new(Arc::clone(&provider), i_entry_point.clone())
+                .with_gas_cap(pool_config.sim_settings.trace_gas_cap)
+                .with_strict_spec_mode(pool_config.sim_settings.strict_spec_mode);
         let prechecker = PrecheckerImpl::new(
             Arc::clone(&provider),
             i_entry_point.clone(),
@@ -189,7 +191,7 @@ impl PoolTask {
             Arc::clone(&provider),
             i_entry_point.address(),
             simulate_validation_tracer,
-            pool_config.sim_settings,
+            pool_config.sim_settings.clone(),
             pool_config.mempool_channel_configs.clone(),
         );
 
@@ -80,6 +80,13 @@ impl<C: JsonRpcClient + 'static> Provider for EthersProvider<C> {
             .as_u64())
     }
 
+    async fn get_chain_id(&self) -> ProviderResult<u64> {
+        Ok(Middleware::get_chainid(self)
+            .await
+            .context("should get chain id from provider")?
+            .as_u64())
+    }
+
     async fn get_block<T: Into<BlockId> + Send + Sync + 'static>(
         &self,
         block_hash_or_number: T,
@@ -192,6 +199,15 @@ impl<C: JsonRpcClient + 'static> Provider for EthersProvider<C> {
         Ok(Middleware::get_code(self, address, block_hash.map(|b| b.into())).await?)
     }
 
+    async fn get_storage_at(
+        &self,
+        address: Address,
+        slot: H256,
+        block_hash: Option<H256>,
+    ) -> ProviderResult<H256> {
+        Ok(Middleware::get_storage_at(self, address, slot, block_hash.map(|b| b.into())).await?)
+    }
+
     async fn get_transaction_count(&self, address: Address) -> ProviderResult<U256> {
         Ok(Middleware::get_transaction_count(self, address, None).await?)
     }
@@ -219,6 +235,7 @@ impl<C: JsonRpcClient + 'static> Provider for EthersProvider<C> {
         self: Arc<Self>,
         entry_point_address: Address,
         op: UserOperation,
+        l1_base_fee_override: Option<U256>,
     ) -> ProviderResult<U256> {
         let entry_point = IEntryPoint::new(entry_point_address, Arc::clone(&self));
         let data = entry_point
@@ -244,8 +261,17 @@ impl<C: JsonRpcClient + 'static> Provider for EthersProvider<C> {
 
         let (l1_fee, l2_base_fee, l2_priority_fee) = tokio::try_join!(
             async {
-                let l1_gas = gas_oracle.get_l1_fee(tx).call().await?;
-                Ok(l1_gas)
+                let Some(l1_base_fee) = l1_base_fee_override else {
+                    let l1_fee = gas_oracle.get_l1_fee(tx).call().await?;
+                    return Ok(l1_fee);
+                };
+                // Same as the oracle's getL1Fee, but with the given L1 base fee
+                let l1_gas_used = gas_oracle.get_l1_gas_used(tx);
+                let scalar = gas_oracle.scalar();
+                let decimals = gas_oracle.decimals();
+                let (l1_gas_used, scalar, decimals) =
+                    tokio::try_join!(l1_gas_used.call(), scalar.call(), decimals.call())?;
+                Ok(l1_gas_used * l1_base_fee * scalar / U256::exp10(decimals.as_usize()))
             },
             self.get_base_fee(),
             self.get_max_priority_fee(),
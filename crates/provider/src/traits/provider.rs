@@ -37,7 +37,7 @@ pub struct AggregatorSimOut {
 }
 
 /// Result of a signature aggregator call
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum AggregatorOut {
     /// No aggregator used
     NotNeeded,
@@ -74,6 +74,9 @@ pub trait Provider: Send + Sync + 'static {
     /// Get the current block number
     async fn get_block_number(&self) -> ProviderResult<u64>;
 
+    /// Get the chain ID of the node
+    async fn get_chain_id(&self) -> ProviderResult<u64>;
+
     /// Get a block by its hash or number
     async fn get_block<T: Into<BlockId> + Send + Sync + 'static>(
         &self,
@@ -122,6 +125,14 @@ pub trait Provider: Send + Sync + 'static {
     /// Get the code at an address
     async fn get_code(&self, address: Address, block_hash: Option<H256>) -> ProviderResult<Bytes>;
 
+    /// Get the value of a storage slot of an address
+    async fn get_storage_at(
+        &self,
+        address: Address,
+        slot: H256,
+        block_hash: Option<H256>,
+    ) -> ProviderResult<H256>;
+
     /// Get the nonce/transaction count of an address
     async fn get_transaction_count(&self, address: Address) -> ProviderResult<U256>;
 
@@ -151,9 +162,13 @@ pub trait Provider: Send + Sync + 'static {
     ) -> ProviderResult<U256>;
 
     /// Calculate the L1 portion of the gas for a user operation on optimism
+    ///
+    /// If `l1_base_fee_override` is set it is used as the L1 base fee instead
+    /// of the value reported by the gas price oracle.
     async fn calc_optimism_l1_gas(
         self: Arc<Self>,
         entry_point_address: Address,
         op: UserOperation,
+        l1_base_fee_override: Option<U256>,
     ) -> ProviderResult<U256>;
 }
@@ -176,9 +176,10 @@ where
             Err(GasEstimationError::RevertInCallWithMessage(message)) => {
                 Err(EthRpcError::ExecutionReverted(message))?
             }
-            Err(error @ GasEstimationError::RevertInCallWithBytes(_)) => {
-                Err(EthRpcError::ExecutionReverted(error.to_string()))?
-            }
+            Err(
+                error @ (GasEstimationError::RevertInCallWithBytes(_)
+                | GasEstimationError::RevertInPostOp(_)),
+            ) => Err(EthRpcError::ExecutionReverted(error.to_string()))?,
             Err(GasEstimationError::Other(error)) => Err(error)?,
         }
     }
@@ -18,7 +18,7 @@ use jsonrpsee::types::{
 };
 use rundler_pool::{MempoolError, PoolServerError};
 use rundler_provider::ProviderError;
-use rundler_sim::{PrecheckViolation, SimulationViolation};
+use rundler_sim::{PrecheckViolation, SimulationError, SimulationViolation};
 use rundler_types::{Entity, EntityType, Timestamp};
 use serde::Serialize;
 
@@ -214,28 +214,99 @@ impl From<SimulationViolation> for EthRpcError {
             SimulationViolation::UnintendedRevertWithMessage(_, reason, _) => {
                 Self::EntryPointValidationRejected(reason)
             }
-            SimulationViolation::UsedForbiddenOpcode(entity, _, op) => {
+            SimulationViolation::UsedForbiddenOpcode(entity, _, op, _)
+            | SimulationViolation::RestrictedOpcode(entity, op) => {
                 Self::OpcodeViolation(entity.kind, op.0)
             }
             SimulationViolation::UsedForbiddenPrecompile(_, _, _)
             | SimulationViolation::AccessedUndeployedContract(_, _)
             | SimulationViolation::CalledBannedEntryPointMethod(_)
+            | SimulationViolation::CalledHandleOps(_)
+            | SimulationViolation::TransientStorageAccess(_)
+            | SimulationViolation::UsedBlockHash(_)
             | SimulationViolation::CallHadValue(_) => Self::OpcodeViolationMap(value),
+            SimulationViolation::FactoryNotDeployed(_) => {
+                Self::EntryPointValidationRejected(value.to_string())
+            }
             SimulationViolation::FactoryCalledCreate2Twice(_) => {
                 Self::OpcodeViolation(EntityType::Factory, Opcode::CREATE2)
             }
-            SimulationViolation::InvalidStorageAccess(entity, slot) => {
-                Self::InvalidStorageAccess(entity.kind, slot.address, slot.slot)
+            SimulationViolation::InvalidStorageAccess(entity, address, slots) => {
+                Self::InvalidStorageAccess(
+                    entity.kind,
+                    address,
+                    slots.first().copied().unwrap_or_default(),
+                )
+            }
+            SimulationViolation::NotStaked {
+                entity,
+                required_stake,
+                required_delay,
+                ..
+            } => Self::StakeTooLow(StakeTooLowData::new(entity, required_stake, required_delay)),
+            SimulationViolation::AggregatorValidationFailed
+            | SimulationViolation::AggregatorReturnedEmptySignature(_) => {
+                Self::SignatureCheckFailed
+            }
+            SimulationViolation::SenderDenied(address) => {
+                Self::ThrottledOrBanned(Entity::account(address))
+            }
+            SimulationViolation::FactoryDenied(address) => {
+                Self::ThrottledOrBanned(Entity::factory(address))
             }
-            SimulationViolation::NotStaked(entity, min_stake, min_unstake_delay) => {
-                Self::StakeTooLow(StakeTooLowData::new(entity, min_stake, min_unstake_delay))
+            SimulationViolation::PaymasterDenied(address) => {
+                Self::ThrottledOrBanned(Entity::paymaster(address))
             }
-            SimulationViolation::AggregatorValidationFailed => Self::SignatureCheckFailed,
             _ => Self::SimulationFailed(value),
         }
     }
 }
 
+impl From<SimulationError> for EthRpcError {
+    fn from(error: SimulationError) -> Self {
+        match error {
+            SimulationError::Violations(violations) => match violations.into_iter().min() {
+                Some(violation) => violation.into(),
+                None => Self::Internal(anyhow::anyhow!("simulation failed without violations")),
+            },
+            SimulationError::Provider(error) => Self::Internal(error.into()),
+            SimulationError::Cancelled => {
+                Self::Internal(anyhow::anyhow!("simulation was cancelled"))
+            }
+            SimulationError::NodeUnavailable => {
+                Self::Internal(anyhow::anyhow!("node is unavailable"))
+            }
+            SimulationError::ReorgDetected => Self::Internal(anyhow::anyhow!(
+                "simulation block was reorged, please retry"
+            )),
+            SimulationError::Other(error) => Self::Internal(error),
+        }
+    }
+}
+
+/// Serialize a simulation error into the JSON-RPC error object returned by
+/// `eth_sendUserOperation`, with the `code`, `message` and `data` fields the
+/// ERC-4337 bundler spec tests assert on.
+///
+/// This lets rundler's simulation results be compared against the spec test
+/// expectations without running the RPC server. Unlike the RPC response, the
+/// stake too low data has the entity's address keyed by its type alongside
+/// the minimums, e.g. `{"paymaster": "0x..", "minimumStake": ..}`, as the
+/// spec tests expect.
+pub fn spec_error_json(error: SimulationError) -> serde_json::Value {
+    let error: ErrorObjectOwned = EthRpcError::from(error).into();
+    let is_stake_too_low = error.code() == STAKE_TOO_LOW_CODE;
+    let mut json = serde_json::to_value(error).expect("error object should serialize");
+    if is_stake_too_low {
+        if let Some(data) = json.get_mut("data").and_then(|data| data.as_object_mut()) {
+            if let Some(serde_json::Value::Object(entity)) = data.remove("entity") {
+                data.extend(entity);
+            }
+        }
+    }
+    json
+}
+
 impl From<EthRpcError> for ErrorObjectOwned {
     fn from(error: EthRpcError) -> Self {
         let msg = error.to_string();
@@ -290,3 +361,93 @@ impl From<ProviderError> for EthRpcError {
         EthRpcError::Internal(anyhow::anyhow!("provider error: {e:?}"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rundler_sim::ViolationOpCode;
+    use serde_json::json;
+
+    use super::*;
+
+    fn address() -> Address {
+        Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap()
+    }
+
+    #[test]
+    fn test_spec_error_json_banned_opcode() {
+        let error = SimulationError::from(vec![SimulationViolation::UsedForbiddenOpcode(
+            Entity::account(address()),
+            address(),
+            ViolationOpCode(Opcode::GASPRICE),
+            1,
+        )]);
+        assert_eq!(
+            spec_error_json(error),
+            json!({
+                "code": -32502,
+                "message": "account uses banned opcode: GASPRICE",
+            })
+        );
+    }
+
+    #[test]
+    fn test_spec_error_json_entry_point_revert() {
+        let error = SimulationError::from(vec![SimulationViolation::UnintendedRevertWithMessage(
+            EntityType::Account,
+            "AA23 reverted (or OOG)".to_string(),
+            Some(address()),
+        )]);
+        assert_eq!(
+            spec_error_json(error),
+            json!({
+                "code": -32500,
+                "message": "AA23 reverted (or OOG)",
+            })
+        );
+    }
+
+    #[test]
+    fn test_spec_error_json_stake_too_low() {
+        let error = SimulationError::from(vec![SimulationViolation::NotStaked {
+            entity: Entity::paymaster(address()),
+            actual_stake: U256::zero(),
+            required_stake: U256::exp10(18),
+            actual_delay: U256::zero(),
+            required_delay: 86400.into(),
+        }]);
+        assert_eq!(
+            spec_error_json(error),
+            json!({
+                "code": -32505,
+                "message": "entity stake/unstake delay too low",
+                "data": {
+                    "paymaster": "0x8ABB13360b87Be5EEb1B98647A016adD927a136c",
+                    "minimumStake": "0xde0b6b3a7640000",
+                    "minimumUnstakeDelay": "0x15180",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_stake_too_low_rpc_data() {
+        let error: ErrorObjectOwned = EthRpcError::StakeTooLow(StakeTooLowData::new(
+            Entity::paymaster(address()),
+            U256::exp10(18),
+            86400.into(),
+        ))
+        .into();
+        assert_eq!(
+            serde_json::to_value(error.data()).unwrap(),
+            json!({
+                "entity": {
+                    "paymaster": "0x8ABB13360b87Be5EEb1B98647A016adD927a136c",
+                },
+                "minimumStake": "0xde0b6b3a7640000",
+                "minimumUnstakeDelay": "0x15180",
+            })
+        );
+    }
+}
@@ -16,6 +16,7 @@ pub(crate) use api::EthApi;
 pub use api::Settings as EthApiSettings;
 
 mod error;
+pub use error::spec_error_json;
 mod server;
 
 use ethers::types::{Address, H256, U64};
@@ -25,7 +25,7 @@ pub use debug::DebugApiClient;
 mod error;
 
 mod eth;
-pub use eth::{EthApiClient, EthApiSettings};
+pub use eth::{spec_error_json, EthApiClient, EthApiSettings};
 
 mod health;
 mod metrics;
@@ -18,8 +18,13 @@ use ethers::{
     abi::AbiDecode,
     contract::EthCall,
     providers::spoof,
-    types::{Address, Bytes, H256, U256},
+    types::{
+        Address, BlockId, Bytes, CallFrame, Eip1559TransactionRequest, GethDebugBuiltInTracerType,
+        GethDebugTracerType, GethDebugTracingCallOptions, GethDebugTracingOptions, GethTrace,
+        GethTraceFrame, H256, U256,
+    },
 };
+use futures_util::future;
 #[cfg(feature = "test-utils")]
 use mockall::automock;
 use rand::Rng;
@@ -38,7 +43,10 @@ use rundler_types::{
 use rundler_utils::{eth, math};
 use tokio::join;
 
-use super::types::{GasEstimate, Settings, UserOperationOptionalGas};
+use super::types::{
+    GasEstimate, GasEstimationProgress, GasExplanation, GasFrame, Settings,
+    UserOperationOptionalGas,
+};
 use crate::{gas, precheck::MIN_CALL_GAS_LIMIT, utils};
 
 /// Gas estimates will be rounded up to the next multiple of this. Increasing
@@ -65,6 +73,9 @@ const GAS_FEE_TRANSFER_COST: u64 = 30000;
 /// failure will tell you the new value.
 const PROXY_TARGET_OFFSET: usize = 137;
 
+/// Number of frames returned by `GasEstimatorImpl::explain_gas`.
+const EXPLAIN_GAS_TOP_FRAMES: usize = 10;
+
 /// Error type for gas estimation
 #[derive(Debug, thiserror::Error)]
 pub enum GasEstimationError {
@@ -77,6 +88,9 @@ pub enum GasEstimationError {
     /// Call reverted with bytes
     #[error("user operation's call reverted: {0:#x}")]
     RevertInCallWithBytes(Bytes),
+    /// The paymaster's postOp reverted after the call
+    #[error("paymaster's postOp reverted: {0}")]
+    RevertInPostOp(String),
     /// Other error
     #[error(transparent)]
     Other(#[from] anyhow::Error),
@@ -108,6 +122,31 @@ impl<P: Provider, E: EntryPoint> GasEstimator for GasEstimatorImpl<P, E> {
     async fn estimate_op_gas(
         &self,
         op: UserOperationOptionalGas,
+    ) -> Result<GasEstimate, GasEstimationError> {
+        self.estimate_op_gas_with_progress(op, None).await
+    }
+}
+
+impl<P: Provider, E: EntryPoint> GasEstimatorImpl<P, E> {
+    /// Create a new gas estimator
+    pub fn new(chain_id: u64, provider: Arc<P>, entry_point: E, settings: Settings) -> Self {
+        Self {
+            chain_id,
+            provider,
+            entry_point,
+            settings,
+        }
+    }
+
+    /// Same as `GasEstimator::estimate_op_gas`, but calls `progress` after each
+    /// round of the verification gas binary search with the current bounds.
+    ///
+    /// Call gas is searched inside the estimation proxy contract, so its
+    /// rounds are not reported.
+    pub async fn estimate_op_gas_with_progress(
+        &self,
+        op: UserOperationOptionalGas,
+        progress: Option<&mut (dyn FnMut(GasEstimationProgress) + Send)>,
     ) -> Result<GasEstimate, GasEstimationError> {
         let Self {
             provider, settings, ..
@@ -134,7 +173,7 @@ impl<P: Provider, E: EntryPoint> GasEstimator for GasEstimatorImpl<P, E> {
             ..op.into_user_operation(settings)
         };
 
-        let verification_future = self.binary_search_verification_gas(&op, block_hash);
+        let verification_future = self.binary_search_verification_gas(&op, block_hash, progress);
         let call_future = self.estimate_call_gas(&op, block_hash);
 
         // Not try_join! because then the output is nondeterministic if both
@@ -160,23 +199,64 @@ impl<P: Provider, E: EntryPoint> GasEstimator for GasEstimatorImpl<P, E> {
             call_gas_limit: call_gas_limit.clamp(MIN_CALL_GAS_LIMIT, settings.max_call_gas.into()),
         })
     }
-}
 
-impl<P: Provider, E: EntryPoint> GasEstimatorImpl<P, E> {
-    /// Create a new gas estimator
-    pub fn new(chain_id: u64, provider: Arc<P>, entry_point: E, settings: Settings) -> Self {
-        Self {
-            chain_id,
-            provider,
-            entry_point,
-            settings,
-        }
+    /// Trace `simulateHandleOp` for the given operation with the call tracer
+    /// and return the frames that used the most gas themselves.
+    ///
+    /// The operation is simulated as given, so its gas limits should already
+    /// be filled in, e.g. from a prior estimate.
+    pub async fn explain_gas(
+        &self,
+        op: UserOperation,
+        block_id: BlockId,
+    ) -> anyhow::Result<GasExplanation> {
+        let tx = Eip1559TransactionRequest {
+            to: Some(self.entry_point.address().into()),
+            gas: Some(self.settings.max_simulate_handle_ops_gas.into()),
+            data: Some(utils::call_data_of(
+                i_entry_point::SimulateHandleOpCall::selector(),
+                (op, Address::zero(), Bytes::new()),
+            )),
+            ..Default::default()
+        };
+        let trace = self
+            .provider
+            .debug_trace_call(
+                tx.into(),
+                Some(block_id),
+                GethDebugTracingCallOptions {
+                    tracing_options: GethDebugTracingOptions {
+                        tracer: Some(GethDebugTracerType::BuiltInTracer(
+                            GethDebugBuiltInTracerType::CallTracer,
+                        )),
+                        ..Default::default()
+                    },
+                    state_overrides: None,
+                },
+            )
+            .await?;
+        let root = match trace {
+            GethTrace::Known(GethTraceFrame::CallTracer(frame)) => frame,
+            GethTrace::Unknown(value) => serde_json::from_value::<CallFrame>(value)
+                .context("simulateHandleOp trace should be a call frame")?,
+            _ => anyhow::bail!("simulateHandleOp trace should be a call frame"),
+        };
+
+        let mut frames = vec![];
+        collect_gas_frames(&root, 0, &mut frames);
+        frames.sort_by(|a, b| b.self_gas_used.cmp(&a.self_gas_used));
+        frames.truncate(EXPLAIN_GAS_TOP_FRAMES);
+        Ok(GasExplanation {
+            total_gas_used: root.gas_used,
+            frames,
+        })
     }
 
     async fn binary_search_verification_gas(
         &self,
         op: &UserOperation,
         block_hash: H256,
+        mut progress: Option<&mut (dyn FnMut(GasEstimationProgress) + Send)>,
     ) -> Result<U256, GasEstimationError> {
         let timer = std::time::Instant::now();
         let simulation_gas = U256::from(self.settings.max_simulate_handle_ops_gas);
@@ -209,7 +289,7 @@ impl<P: Provider, E: EntryPoint> GasEstimatorImpl<P, E> {
             .decode_simulate_handle_ops_revert(gas_used.result)
             .err()
         {
-            return Err(GasEstimationError::RevertInValidation(message));
+            return Err(revert_before_call(message));
         }
 
         let run_attempt_returning_error = |gas: u64| async move {
@@ -243,6 +323,34 @@ impl<P: Provider, E: EntryPoint> GasEstimatorImpl<P, E> {
         }
         let mut guess = gas_used.gas_used.as_u64() * 2;
         let mut num_rounds = 0;
+        let fanout = self.settings.estimation_fanout;
+        if fanout > 1 {
+            // Bracket the result faster by trying several limits at once in
+            // the first round, then narrow the bounds sequentially.
+            num_rounds += 1;
+            let candidates = fanout_candidates(guess, fanout);
+            let errors = future::try_join_all(
+                candidates
+                    .iter()
+                    .map(|&gas| run_attempt_returning_error(gas)),
+            )
+            .await?;
+            for (gas, error) in candidates.into_iter().zip(errors) {
+                if error.is_some() {
+                    max_failure_gas = max_failure_gas.max(gas);
+                } else {
+                    min_success_gas = min_success_gas.min(gas);
+                }
+            }
+            if let Some(progress) = progress.as_mut() {
+                progress(GasEstimationProgress {
+                    round: num_rounds,
+                    current_lower: max_failure_gas,
+                    current_upper: min_success_gas,
+                });
+            }
+            guess = (max_failure_gas + min_success_gas) / 2;
+        }
         while (min_success_gas as f64) / (max_failure_gas as f64)
             > (1.0 + GAS_ESTIMATION_ERROR_MARGIN)
         {
@@ -253,6 +361,13 @@ impl<P: Provider, E: EntryPoint> GasEstimatorImpl<P, E> {
             } else {
                 min_success_gas = guess;
             }
+            if let Some(progress) = progress.as_mut() {
+                progress(GasEstimationProgress {
+                    round: num_rounds,
+                    current_lower: max_failure_gas,
+                    current_upper: min_success_gas,
+                });
+            }
             guess = (max_failure_gas + min_success_gas) / 2;
         }
 
@@ -324,7 +439,13 @@ impl<P: Provider, E: EntryPoint> GasEstimatorImpl<P, E> {
                     &spoofed_state,
                 )
                 .await?
-                .map_err(GasEstimationError::RevertInCallWithMessage)?
+                .map_err(|message| {
+                    if is_post_op_revert(&message) {
+                        GasEstimationError::RevertInPostOp(message)
+                    } else {
+                        GasEstimationError::RevertInCallWithMessage(message)
+                    }
+                })?
                 .target_result;
             if let Ok(result) = EstimateCallGasResult::decode(&target_revert_data) {
                 num_rounds += result.num_rounds;
@@ -375,11 +496,37 @@ impl<P: Provider, E: EntryPoint> GasEstimatorImpl<P, E> {
             self.entry_point.address(),
             self.provider.clone(),
             self.chain_id,
+            None,
+            self.settings.evm_version,
         )
         .await?)
     }
 }
 
+/// Whether a `FailedOp` reason from `simulateHandleOp` comes from the
+/// paymaster's postOp rather than from validation
+fn is_post_op_revert(message: &str) -> bool {
+    message.starts_with("AA50")
+}
+
+/// Classifies a revert of `simulateHandleOp` run with no call gas, where the
+/// call's own revert is caught by the entry point
+fn revert_before_call(message: String) -> GasEstimationError {
+    if is_post_op_revert(&message) {
+        GasEstimationError::RevertInPostOp(message)
+    } else {
+        GasEstimationError::RevertInValidation(message)
+    }
+}
+
+/// Returns `count` gas limits, each half of the next, ending at `guess`
+fn fanout_candidates(guess: u64, count: usize) -> Vec<u64> {
+    (0..count)
+        .rev()
+        .map(|i| guess.checked_shr(i as u32).unwrap_or_default())
+        .collect()
+}
+
 /// Replaces the address of the proxy target where it appears in the proxy
 /// bytecode so we don't need the same fixed address every time.
 fn estimation_proxy_bytecode_with_target(target: Address) -> Bytes {
@@ -388,8 +535,27 @@ fn estimation_proxy_bytecode_with_target(target: Address) -> Bytes {
     vec.into()
 }
 
+fn collect_gas_frames(frame: &CallFrame, depth: usize, out: &mut Vec<GasFrame>) {
+    let calls = frame.calls.as_deref().unwrap_or_default();
+    let children_gas_used = calls
+        .iter()
+        .fold(U256::zero(), |sum, call| sum.saturating_add(call.gas_used));
+    out.push(GasFrame {
+        depth,
+        to: frame.to.as_ref().and_then(|to| to.as_address()).copied(),
+        selector: frame.input.get(..4).and_then(|s| s.try_into().ok()),
+        gas_used: frame.gas_used,
+        self_gas_used: frame.gas_used.saturating_sub(children_gas_used),
+    });
+    for call in calls {
+        collect_gas_frames(call, depth + 1, out);
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
     use ethers::{
         abi::{AbiEncode, Address},
         providers::JsonRpcError,
@@ -400,6 +566,7 @@ mod tests {
     use rundler_types::contracts::{get_gas_used::GasUsedResult, i_entry_point::ExecutionResult};
 
     use super::*;
+    use crate::{estimation::GasUtilization, simulation::EvmVersion};
 
     // Gas overhead defaults
     const FIXED: u32 = 21000;
@@ -425,6 +592,8 @@ mod tests {
             max_verification_gas: 10000000000,
             max_call_gas: 10000000000,
             max_simulate_handle_ops_gas: 100000000,
+            estimation_fanout: 1,
+            evm_version: EvmVersion::default(),
         };
 
         let estimator: GasEstimatorImpl<MockProvider, MockEntryPoint> =
@@ -518,6 +687,8 @@ mod tests {
             max_verification_gas: 10000000000,
             max_call_gas: 10000000000,
             max_simulate_handle_ops_gas: 100000000,
+            estimation_fanout: 1,
+            evm_version: EvmVersion::default(),
         };
 
         // Chose arbitrum
@@ -555,12 +726,14 @@ mod tests {
         entry.expect_address().return_const(Address::zero());
         provider
             .expect_calc_optimism_l1_gas()
-            .returning(|_a, _b| Ok(U256::from(1000)));
+            .returning(|_a, _b, _c| Ok(U256::from(1000)));
 
         let settings = Settings {
             max_verification_gas: 10000000000,
             max_call_gas: 10000000000,
             max_simulate_handle_ops_gas: 100000000,
+            estimation_fanout: 1,
+            evm_version: EvmVersion::default(),
         };
 
         // Chose OP
@@ -643,7 +816,7 @@ mod tests {
         let (estimator, _) = create_estimator(entry, provider);
         let user_op = demo_user_op();
         let estimation = estimator
-            .binary_search_verification_gas(&user_op, H256::zero())
+            .binary_search_verification_gas(&user_op, H256::zero(), None)
             .await
             .unwrap();
 
@@ -655,6 +828,144 @@ mod tests {
         assert_eq!(pre_op_gas + gas_used, estimation);
     }
 
+    #[tokio::test]
+    async fn test_binary_search_verification_gas_progress() {
+        let (mut entry, mut provider) = create_base_config();
+
+        entry.expect_address().return_const(Address::zero());
+        entry
+            .expect_decode_simulate_handle_ops_revert()
+            .returning(|_a| Ok(ExecutionResult::default()));
+        let attempts = Arc::new(AtomicU32::new(0));
+        let attempts_clone = Arc::clone(&attempts);
+        entry
+            .expect_call_spoofed_simulate_op()
+            .returning(move |op, _b, _c, _d, _e, _f| {
+                attempts_clone.fetch_add(1, Ordering::Relaxed);
+                if op.verification_gas_limit < U256::from(30000) {
+                    Ok(Err("AA13 initCode failed or OOG".to_string()))
+                } else {
+                    Ok(Ok(ExecutionResult::default()))
+                }
+            });
+
+        provider.expect_call().returning(|_a, _b| {
+            let result_data: Bytes = GasUsedResult {
+                gas_used: U256::from(20000),
+                success: false,
+                result: Bytes::new(),
+            }
+            .encode()
+            .into();
+
+            let json_rpc_error = JsonRpcError {
+                code: -32000,
+                message: "execution reverted".to_string(),
+                data: Some(serde_json::Value::String(result_data.to_string())),
+            };
+            Err(ProviderError::JsonRpcError(json_rpc_error))
+        });
+
+        let (estimator, _) = create_estimator(entry, provider);
+        let user_op = demo_user_op();
+        let mut reports = vec![];
+        let mut progress = |p: GasEstimationProgress| reports.push(p);
+        estimator
+            .binary_search_verification_gas(&user_op, H256::zero(), Some(&mut progress))
+            .await
+            .unwrap();
+
+        // one report per round, each with the bounds after that round
+        let rounds = attempts.load(Ordering::Relaxed);
+        assert!(rounds > 1);
+        assert_eq!(
+            reports.iter().map(|p| p.round).collect::<Vec<_>>(),
+            (1..=rounds).collect::<Vec<_>>()
+        );
+        assert!(reports
+            .iter()
+            .all(|p| p.current_lower < 30000 && p.current_upper >= 30000));
+        assert_eq!(
+            reports[0],
+            GasEstimationProgress {
+                round: 1,
+                current_lower: 0,
+                current_upper: 40000,
+            }
+        );
+    }
+
+    // Returns the estimate, the number of attempts and the number of rounds
+    async fn binary_search_verification_gas_with_fanout(fanout: usize) -> (U256, u32, u32) {
+        let (mut entry, mut provider) = create_base_config();
+
+        entry.expect_address().return_const(Address::zero());
+        entry
+            .expect_decode_simulate_handle_ops_revert()
+            .returning(|_a| Ok(ExecutionResult::default()));
+        let attempts = Arc::new(AtomicU32::new(0));
+        let attempts_clone = Arc::clone(&attempts);
+        entry
+            .expect_call_spoofed_simulate_op()
+            .returning(move |op, _b, _c, _d, _e, _f| {
+                attempts_clone.fetch_add(1, Ordering::Relaxed);
+                if op.verification_gas_limit < U256::from(30000) {
+                    Ok(Err("AA13 initCode failed or OOG".to_string()))
+                } else {
+                    Ok(Ok(ExecutionResult::default()))
+                }
+            });
+
+        provider.expect_call().returning(|_a, _b| {
+            let result_data: Bytes = GasUsedResult {
+                gas_used: U256::from(20000),
+                success: false,
+                result: Bytes::new(),
+            }
+            .encode()
+            .into();
+
+            let json_rpc_error = JsonRpcError {
+                code: -32000,
+                message: "execution reverted".to_string(),
+                data: Some(serde_json::Value::String(result_data.to_string())),
+            };
+            Err(ProviderError::JsonRpcError(json_rpc_error))
+        });
+
+        let (_, settings) = create_estimator(MockEntryPoint::new(), MockProvider::new());
+        let estimator = GasEstimatorImpl::new(
+            0,
+            Arc::new(provider),
+            entry,
+            Settings {
+                estimation_fanout: fanout,
+                ..settings
+            },
+        );
+        let mut rounds = 0;
+        let mut progress = |p: GasEstimationProgress| rounds = p.round;
+        let estimation = estimator
+            .binary_search_verification_gas(&demo_user_op(), H256::zero(), Some(&mut progress))
+            .await
+            .unwrap();
+        (estimation, attempts.load(Ordering::Relaxed), rounds)
+    }
+
+    #[tokio::test]
+    async fn test_binary_search_verification_gas_fanout() {
+        let (sequential, sequential_attempts, sequential_rounds) =
+            binary_search_verification_gas_with_fanout(1).await;
+        let (fanned_out, fanned_out_attempts, fanned_out_rounds) =
+            binary_search_verification_gas_with_fanout(4).await;
+
+        assert_eq!(fanned_out, sequential);
+        // The first round tries 5000, 10000, 20000 and 40000 at once, finding
+        // the failure at 20000 that takes the sequential search a second round
+        assert_eq!(fanned_out_rounds, sequential_rounds - 1);
+        assert_eq!(fanned_out_attempts, sequential_attempts + 2);
+    }
+
     #[tokio::test]
     async fn test_binary_search_verification_gas_should_not_overflow() {
         let (mut entry, mut provider) = create_base_config();
@@ -709,7 +1020,7 @@ mod tests {
         let (estimator, _) = create_estimator(entry, provider);
         let user_op = demo_user_op();
         let estimation = estimator
-            .binary_search_verification_gas(&user_op, H256::zero())
+            .binary_search_verification_gas(&user_op, H256::zero(), None)
             .await
             .err();
 
@@ -773,7 +1084,7 @@ mod tests {
         let (estimator, _) = create_estimator(entry, provider);
         let user_op = demo_user_op();
         let estimation = estimator
-            .binary_search_verification_gas(&user_op, H256::zero())
+            .binary_search_verification_gas(&user_op, H256::zero(), None)
             .await;
 
         assert!(estimation.is_err());
@@ -823,12 +1134,52 @@ mod tests {
         let (estimator, _) = create_estimator(entry, provider);
         let user_op = demo_user_op();
         let estimation = estimator
-            .binary_search_verification_gas(&user_op, H256::zero())
+            .binary_search_verification_gas(&user_op, H256::zero(), None)
             .await;
 
         assert!(estimation.is_err());
     }
 
+    #[tokio::test]
+    async fn test_binary_search_verification_gas_post_op_revert() {
+        let (mut entry, mut provider) = create_base_config();
+
+        entry.expect_address().return_const(Address::zero());
+        entry
+            .expect_decode_simulate_handle_ops_revert()
+            .returning(|_a| Err(String::from("AA50 postOp reverted: not enough tokens")));
+
+        provider.expect_call().returning(|_a, _b| {
+            let result_data: Bytes = GasUsedResult {
+                gas_used: U256::from(20000),
+                success: false,
+                result: Bytes::new(),
+            }
+            .encode()
+            .into();
+
+            let json_rpc_error = JsonRpcError {
+                code: -32000,
+                message: "execution reverted".to_string(),
+                data: Some(serde_json::Value::String(result_data.to_string())),
+            };
+            Err(ProviderError::JsonRpcError(json_rpc_error))
+        });
+
+        let (estimator, _) = create_estimator(entry, provider);
+        let user_op = demo_user_op();
+        let estimation = estimator
+            .binary_search_verification_gas(&user_op, H256::zero(), None)
+            .await
+            .err();
+
+        assert!(matches!(
+            estimation,
+            Some(GasEstimationError::RevertInPostOp(message))
+                if message == "AA50 postOp reverted: not enough tokens"
+        ));
+    }
+
     #[tokio::test]
     async fn test_binary_search_verification_gas_invalid_spoof() {
         let (mut entry, mut provider) = create_base_config();
@@ -872,7 +1223,7 @@ mod tests {
         let (estimator, _) = create_estimator(entry, provider);
         let user_op = demo_user_op();
         let estimation = estimator
-            .binary_search_verification_gas(&user_op, H256::zero())
+            .binary_search_verification_gas(&user_op, H256::zero(), None)
             .await;
 
         assert!(estimation.is_err());
@@ -917,7 +1268,7 @@ mod tests {
         let (estimator, _) = create_estimator(entry, provider);
         let user_op = demo_user_op();
         let estimation = estimator
-            .binary_search_verification_gas(&user_op, H256::zero())
+            .binary_search_verification_gas(&user_op, H256::zero(), None)
             .await;
 
         assert!(estimation.is_err());
@@ -999,6 +1350,60 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_estimate_call_gas_post_op_revert() {
+        let (mut entry, mut provider) = create_base_config();
+
+        entry.expect_address().return_const(Address::zero());
+        entry
+            .expect_call_spoofed_simulate_op()
+            .returning(|_a, _b, _c, _d, _e, _f| Ok(Err(String::from("AA50 postOp revert"))));
+
+        provider
+            .expect_get_code()
+            .returning(|_a, _b| Ok(Bytes::new()));
+
+        let (estimator, _) = create_estimator(entry, provider);
+        let user_op = demo_user_op();
+        let estimation = estimator
+            .estimate_call_gas(&user_op, H256::zero())
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(
+            estimation,
+            GasEstimationError::RevertInPostOp(message) if message == "AA50 postOp revert"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_estimate_call_gas_revert_with_message() {
+        let (mut entry, mut provider) = create_base_config();
+
+        entry.expect_address().return_const(Address::zero());
+        entry
+            .expect_call_spoofed_simulate_op()
+            .returning(|_a, _b, _c, _d, _e, _f| Ok(Err(String::from("call failed"))));
+
+        provider
+            .expect_get_code()
+            .returning(|_a, _b| Ok(Bytes::new()));
+
+        let (estimator, _) = create_estimator(entry, provider);
+        let user_op = demo_user_op();
+        let estimation = estimator
+            .estimate_call_gas(&user_op, H256::zero())
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(
+            estimation,
+            GasEstimationError::RevertInCallWithMessage(message) if message == "call failed"
+        ));
+    }
+
     #[tokio::test]
     async fn test_estimate_call_gas_continuation() {
         let (mut entry, mut provider) = create_base_config();
@@ -1185,6 +1590,8 @@ mod tests {
             max_verification_gas: 10,
             max_call_gas: 10,
             max_simulate_handle_ops_gas: 10,
+            estimation_fanout: 1,
+            evm_version: EvmVersion::default(),
         };
 
         let estimator: GasEstimatorImpl<MockProvider, MockEntryPoint> =
@@ -1197,4 +1604,84 @@ mod tests {
             Some(GasEstimationError::RevertInValidation(..))
         ));
     }
+
+    #[test]
+    fn test_gas_utilization() {
+        let estimate = GasEstimate {
+            pre_verification_gas: 50_000.into(),
+            verification_gas_limit: 75_000.into(),
+            call_gas_limit: 99_000.into(),
+        };
+        let op = UserOperation {
+            verification_gas_limit: 300_000.into(),
+            call_gas_limit: 100_000.into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            estimate.utilization(&op),
+            GasUtilization {
+                verification_pct: 25.0,
+                call_pct: 99.0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_explain_gas() {
+        let (mut entry, mut provider) = create_base_config();
+        entry.expect_address().return_const(Address::zero());
+        let account = Address::from_low_u64_be(1);
+        let expensive = Address::from_low_u64_be(2);
+        let cheap = Address::from_low_u64_be(3);
+        let frame = |to: Address, input: &str, gas_used: u64, calls: Vec<CallFrame>| CallFrame {
+            typ: "CALL".to_string(),
+            from: Address::zero(),
+            to: Some(to.into()),
+            gas: U256::from(1_000_000),
+            gas_used: U256::from(gas_used),
+            input: input.parse().unwrap(),
+            output: None,
+            error: None,
+            calls: Some(calls),
+            logs: None,
+            value: None,
+        };
+        let root = frame(
+            Address::zero(),
+            "0xd6383f94",
+            200_000,
+            vec![frame(
+                account,
+                "0xb61d27f6",
+                180_000,
+                vec![
+                    frame(expensive, "0xa9059cbb0000", 150_000, vec![]),
+                    frame(cheap, "0x", 5_000, vec![]),
+                ],
+            )],
+        );
+        provider
+            .expect_debug_trace_call()
+            .returning(move |_, _, _| {
+                Ok(GethTrace::Known(GethTraceFrame::CallTracer(root.clone())))
+            });
+
+        let (estimator, _) = create_estimator(entry, provider);
+        let explanation = estimator
+            .explain_gas(demo_user_op(), BlockId::Number(Default::default()))
+            .await
+            .unwrap();
+
+        assert_eq!(explanation.total_gas_used, U256::from(200_000));
+        assert_eq!(explanation.frames.len(), 4);
+        let top = &explanation.frames[0];
+        assert_eq!(top.to, Some(expensive));
+        assert_eq!(top.selector, Some([0xa9, 0x05, 0x9c, 0xbb]));
+        assert_eq!(top.depth, 2);
+        assert_eq!(top.self_gas_used, U256::from(150_000));
+        let cheapest = explanation.frames.last().unwrap();
+        assert_eq!(cheapest.to, Some(cheap));
+        assert_eq!(cheapest.selector, None);
+    }
 }
@@ -16,4 +16,7 @@ mod estimation;
 pub use estimation::*;
 
 mod types;
-pub use types::{GasEstimate, Settings, UserOperationOptionalGas};
+pub use types::{
+    GasEstimate, GasEstimationProgress, GasExplanation, GasFrame, GasUtilization, Settings,
+    UserOperationOptionalGas,
+};
@@ -16,7 +16,7 @@ use rand::RngCore;
 use rundler_types::UserOperation;
 use serde::{Deserialize, Serialize};
 
-use crate::precheck::MIN_CALL_GAS_LIMIT;
+use crate::{precheck::MIN_CALL_GAS_LIMIT, simulation::EvmVersion};
 
 /// Settings for gas estimation
 #[derive(Clone, Copy, Debug)]
@@ -27,6 +27,13 @@ pub struct Settings {
     pub max_call_gas: u64,
     /// The maximum amount of gas that can be used in a call to `simulateHandleOps`
     pub max_simulate_handle_ops_gas: u64,
+    /// The number of verification gas limits tried concurrently in the first
+    /// round of the binary search, geometrically spaced below the initial
+    /// guess. 1 tries only the initial guess, as a sequential search does.
+    pub estimation_fanout: usize,
+    /// The hardfork the chain follows, which determines how the calldata
+    /// part of the pre-verification gas is priced
+    pub evm_version: EvmVersion,
 }
 
 impl Settings {
@@ -42,6 +49,42 @@ impl Settings {
     }
 }
 
+/// Progress of gas estimation, reported after each round of binary search
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct GasEstimationProgress {
+    /// The number of rounds completed so far, starting at 1
+    pub round: u32,
+    /// The largest gas limit known to fail
+    pub current_lower: u64,
+    /// The smallest gas limit known to succeed
+    pub current_upper: u64,
+}
+
+/// Breakdown of where a user operation's gas goes, from a call trace of
+/// `simulateHandleOp`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GasExplanation {
+    /// Gas used by the whole `simulateHandleOp` call
+    pub total_gas_used: U256,
+    /// The frames that used the most gas themselves, highest first
+    pub frames: Vec<GasFrame>,
+}
+
+/// A single call frame in a `GasExplanation`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GasFrame {
+    /// Depth of the frame in the call tree, where the entry point call is 0
+    pub depth: usize,
+    /// Target of the call, if any
+    pub to: Option<Address>,
+    /// First four bytes of the call data, if it has at least four
+    pub selector: Option<[u8; 4]>,
+    /// Gas used by the frame, including its subcalls
+    pub gas_used: U256,
+    /// Gas used by the frame, excluding its subcalls
+    pub self_gas_used: U256,
+}
+
 /// User operation with optional gas fields for gas estimation
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -222,3 +265,37 @@ pub struct GasEstimate {
     /// Call gas limit estimate
     pub call_gas_limit: U256,
 }
+
+impl GasEstimate {
+    /// How much of the operation's verification and call gas limits the
+    /// estimated gas needs, as percentages. Values near 100 mean a limit is
+    /// dangerously tight, and low values mean it is looser than needed.
+    pub fn utilization(&self, op: &UserOperation) -> GasUtilization {
+        GasUtilization {
+            verification_pct: percent_of(self.verification_gas_limit, op.verification_gas_limit),
+            call_pct: percent_of(self.call_gas_limit, op.call_gas_limit),
+        }
+    }
+}
+
+/// The share of a user operation's gas limits needed by each phase, see
+/// `GasEstimate::utilization`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GasUtilization {
+    /// Estimated verification gas as a percentage of `verificationGasLimit`
+    pub verification_pct: f64,
+    /// Estimated call gas as a percentage of `callGasLimit`
+    pub call_pct: f64,
+}
+
+// A zero limit is fully used by any gas at all
+fn percent_of(used: U256, limit: U256) -> f64 {
+    if limit.is_zero() {
+        return if used.is_zero() { 0.0 } else { f64::INFINITY };
+    }
+    gas_to_f64(used) * 100.0 / gas_to_f64(limit)
+}
+
+fn gas_to_f64(gas: U256) -> f64 {
+    gas.min(u128::MAX.into()).as_u128() as f64
+}
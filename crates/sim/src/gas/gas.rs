@@ -19,6 +19,7 @@ use ethers::{
     types::{Address, Chain, U256},
 };
 use rundler_provider::Provider;
+pub use rundler_types::EntryPointVersion;
 use rundler_types::{
     chain::{ARBITRUM_CHAIN_IDS, OP_BEDROCK_CHAIN_IDS, POLYGON_CHAIN_IDS},
     GasFees, UserOperation,
@@ -27,6 +28,42 @@ use rundler_utils::math;
 use tokio::try_join;
 
 use super::polygon::Polygon;
+use crate::simulation::EvmVersion;
+
+/// Gas constants of the entry point that differ between versions
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GasConstants {
+    /// The fixed gas overhead of the bundle transaction
+    pub fixed_overhead: U256,
+    /// The gas overhead the entry point spends on each user operation
+    pub per_user_op: U256,
+    /// The gas the entry point requires to remain after each user operation's call for its own
+    /// bookkeeping, which the bundle transaction must leave room for
+    pub bundle_transaction_gas_buffer: U256,
+}
+
+impl GasConstants {
+    /// Returns the gas constants for the given entry point version
+    ///
+    /// The fixed and per-operation overheads are the `DefaultGasOverheads` of the reference
+    /// bundler, which are unchanged from v0.6 to v0.7. The buffer is the gas `innerHandleOp`
+    /// requires beyond the operation's limits: 5000 in v0.6 (see the `AA95` check) and
+    /// `INNER_GAS_OVERHEAD` (10000) from v0.7.
+    pub fn for_version(version: EntryPointVersion) -> Self {
+        match version {
+            EntryPointVersion::V0_6 => Self {
+                fixed_overhead: 21_000.into(),
+                per_user_op: 18_300.into(),
+                bundle_transaction_gas_buffer: 5_000.into(),
+            },
+            EntryPointVersion::V0_7 | EntryPointVersion::V0_8 => Self {
+                fixed_overhead: 21_000.into(),
+                per_user_op: 18_300.into(),
+                bundle_transaction_gas_buffer: 10_000.into(),
+            },
+        }
+    }
+}
 
 /// Gas overheads for user operations used in calculating the pre-verification gas. See: https://github.com/eth-infinitism/bundler/blob/main/packages/sdk/src/calcPreVerificationGas.ts
 #[derive(Clone, Copy, Debug)]
@@ -39,21 +76,31 @@ pub struct GasOverheads {
     per_user_op_word: U256,
     zero_byte: U256,
     non_zero_byte: U256,
+    floor_per_token: U256,
 }
 
-impl Default for GasOverheads {
-    fn default() -> Self {
+impl GasOverheads {
+    /// Returns the gas overheads for the given entry point version
+    pub fn for_version(version: EntryPointVersion) -> Self {
+        let constants = GasConstants::for_version(version);
         Self {
-            bundle_transaction_gas_buffer: 5_000.into(),
-            transaction_gas_overhead: 21_000.into(),
-            per_user_op: 18_300.into(),
+            bundle_transaction_gas_buffer: constants.bundle_transaction_gas_buffer,
+            transaction_gas_overhead: constants.fixed_overhead,
+            per_user_op: constants.per_user_op,
             per_user_op_word: 4.into(),
             zero_byte: 4.into(),
             non_zero_byte: 16.into(),
+            floor_per_token: 10.into(),
         }
     }
 }
 
+impl Default for GasOverheads {
+    fn default() -> Self {
+        Self::for_version(EntryPointVersion::default())
+    }
+}
+
 /// Returns the required pre_verification_gas for the given user operation
 ///
 /// `full_op` is either the user operation submitted via `sendUserOperation`
@@ -67,14 +114,25 @@ impl Default for GasOverheads {
 ///
 /// Networks that require dynamic pre_verification_gas are typically those that charge extra calldata fees
 /// that can scale based on dynamic gas prices.
+///
+/// `l1_base_fee_override`, if set, pins the L1 base fee used to calculate the dynamic portion on OP Stack
+/// networks instead of reading it from the gas price oracle, for reproducible estimates.
+///
+/// From Prague, as given by `evm_version`, EIP-7623 charges at least the calldata floor, which is
+/// compared against the standard calldata cost plus execution gas. The operation's
+/// `call_gas_limit` and `verification_gas_limit` are counted as its execution gas, and any part of
+/// the floor that they do not cover is added to the calldata cost.
 pub async fn calc_pre_verification_gas<P: Provider>(
     full_op: &UserOperation,
     random_op: &UserOperation,
     entry_point: Address,
     provider: Arc<P>,
     chain_id: u64,
+    l1_base_fee_override: Option<U256>,
+    evm_version: EvmVersion,
 ) -> anyhow::Result<U256> {
-    let static_gas = calc_static_pre_verification_gas(full_op, true);
+    let static_gas =
+        calc_static_pre_verification_gas(full_op, true, evm_version.has_calldata_floor());
     let dynamic_gas = match chain_id {
         _ if ARBITRUM_CHAIN_IDS.contains(&chain_id) => {
             provider
@@ -85,7 +143,7 @@ pub async fn calc_pre_verification_gas<P: Provider>(
         _ if OP_BEDROCK_CHAIN_IDS.contains(&chain_id) => {
             provider
                 .clone()
-                .calc_optimism_l1_gas(entry_point, random_op.clone())
+                .calc_optimism_l1_gas(entry_point, random_op.clone(), l1_base_fee_override)
                 .await?
         }
         _ => U256::zero(),
@@ -94,12 +152,17 @@ pub async fn calc_pre_verification_gas<P: Provider>(
     Ok(static_gas + dynamic_gas)
 }
 
-/// Compute the gas limit for the bundle composed of the given user operations
-pub fn bundle_gas_limit<'a, I>(iter_ops: I, chain_id: u64) -> U256
+/// Compute the gas limit for the bundle composed of the given user operations, submitted to
+/// an entry point of the given version
+pub fn bundle_gas_limit<'a, I>(
+    iter_ops: I,
+    chain_id: u64,
+    entry_point_version: EntryPointVersion,
+) -> U256
 where
     I: Iterator<Item = &'a UserOperation>,
 {
-    let ov = GasOverheads::default();
+    let ov = GasOverheads::for_version(entry_point_version);
     let mut max_gas = U256::zero();
     let mut gas_spent = U256::zero();
     for op in iter_ops {
@@ -140,7 +203,7 @@ fn user_operation_pre_verification_gas_limit(
     // but this not part of the execution gas limit of the transaction.
     // In such cases we only consider the static portion of the pre_verification_gas in the gas limit.
     if OP_BEDROCK_CHAIN_IDS.contains(&chain_id) | ARBITRUM_CHAIN_IDS.contains(&chain_id) {
-        calc_static_pre_verification_gas(uo, include_fixed_gas_overhead)
+        calc_static_pre_verification_gas(uo, include_fixed_gas_overhead, false)
     } else {
         uo.pre_verification_gas
     }
@@ -148,16 +211,36 @@ fn user_operation_pre_verification_gas_limit(
 
 /// Returns the maximum cost, in wei, of this user operation
 pub fn user_operation_max_gas_cost(uo: &UserOperation) -> U256 {
+    uo.max_fee_per_gas * user_operation_max_gas(uo)
+}
+
+/// Returns the amount, in wei, that the entry point refunds to the payer of
+/// this user operation when it is charged `paid`, e.g. the `paid` field of
+/// the `ExecutionResult` from `simulateHandleOp`.
+///
+/// The entry point prefunds the maximum gas cost of the operation, covering
+/// its pre-verification gas, call gas limit, and verification gas limit
+/// (tripled when a paymaster is used for its `postOp`) at the max fee. After
+/// execution it keeps the net amount paid and refunds the rest, so the gross
+/// cost minus the refund is the net paid.
+pub fn user_operation_gas_refund(uo: &UserOperation, paid: U256) -> U256 {
+    user_operation_max_gas_cost(uo).saturating_sub(paid)
+}
+
+fn user_operation_max_gas(uo: &UserOperation) -> U256 {
     let mul = if uo.paymaster().is_some() { 3 } else { 1 };
-    uo.max_fee_per_gas
-        * (uo.pre_verification_gas + uo.call_gas_limit + uo.verification_gas_limit * mul)
+    uo.pre_verification_gas + uo.call_gas_limit + uo.verification_gas_limit * mul
 }
 
-fn calc_static_pre_verification_gas(op: &UserOperation, include_fixed_gas_overhead: bool) -> U256 {
+fn calc_static_pre_verification_gas(
+    op: &UserOperation,
+    include_fixed_gas_overhead: bool,
+    calldata_floor: bool,
+) -> U256 {
     let ov = GasOverheads::default();
     let encoded_op = op.clone().encode();
     let length_in_words = encoded_op.len() / 32; // size of packed user op is always a multiple of 32 bytes
-    let call_data_cost: U256 = encoded_op
+    let mut call_data_cost: U256 = encoded_op
         .iter()
         .map(|&x| {
             if x == 0 {
@@ -168,6 +251,17 @@ fn calc_static_pre_verification_gas(op: &UserOperation, include_fixed_gas_overhe
         })
         .reduce(|a, b| a + b)
         .unwrap_or_default();
+    if calldata_floor {
+        // EIP-7623 counts a zero byte as one token and a nonzero byte as four,
+        // and only charges the floor when it exceeds calldata plus execution gas
+        let tokens = encoded_op
+            .iter()
+            .map(|&x| if x == 0 { 1 } else { 4 })
+            .sum::<u64>();
+        let execution_gas = op.call_gas_limit + op.verification_gas_limit;
+        call_data_cost =
+            call_data_cost.max((ov.floor_per_token * tokens).saturating_sub(execution_gas));
+    }
 
     call_data_cost
         + ov.per_user_op
@@ -333,6 +427,7 @@ fn is_known_non_eip_1559_chain(chain_id: u64) -> bool {
 #[cfg(test)]
 mod tests {
     use ethers::types::Bytes;
+    use rundler_provider::MockProvider;
 
     use super::*;
 
@@ -355,13 +450,160 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_calc_pre_verification_gas_calldata_floor() {
+        let op = UserOperation {
+            call_data: Bytes::from(vec![0xff; 2_000]),
+            ..Default::default()
+        };
+        let calc = |evm_version| {
+            calc_pre_verification_gas(
+                &op,
+                &op,
+                Address::zero(),
+                Arc::new(MockProvider::new()),
+                1,
+                None,
+                evm_version,
+            )
+        };
+        let standard = calc(EvmVersion::Cancun).await.unwrap();
+        let floored = calc(EvmVersion::Prague).await.unwrap();
+
+        // with no execution gas, a zero byte costs 10 gas at the floor instead
+        // of 4, and a nonzero byte 40 instead of 16
+        let encoded_op = op.clone().encode();
+        let zero_bytes = encoded_op.iter().filter(|&&b| b == 0).count();
+        let nonzero_bytes = encoded_op.len() - zero_bytes;
+        assert_eq!(
+            floored - standard,
+            U256::from(6 * zero_bytes + 24 * nonzero_bytes)
+        );
+        assert!(nonzero_bytes >= 2_000);
+    }
+
+    #[tokio::test]
+    async fn test_calc_pre_verification_gas_calldata_floor_covered_by_execution() {
+        let calc = |op: UserOperation, evm_version| async move {
+            calc_pre_verification_gas(
+                &op,
+                &op,
+                Address::zero(),
+                Arc::new(MockProvider::new()),
+                1,
+                None,
+                evm_version,
+            )
+            .await
+            .unwrap()
+        };
+        let op = UserOperation {
+            call_data: Bytes::from(vec![0xff; 2_000]),
+            call_gas_limit: 100_000.into(),
+            verification_gas_limit: 100_000.into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            calc(op.clone(), EvmVersion::Prague).await,
+            calc(op.clone(), EvmVersion::Cancun).await
+        );
+
+        // execution gas that only covers part of the floor reduces the charge
+        // by that amount; both limits encode to the same bytes
+        let less = UserOperation {
+            call_gas_limit: 10_000.into(),
+            verification_gas_limit: 0.into(),
+            ..op.clone()
+        };
+        let more = UserOperation {
+            call_gas_limit: 0.into(),
+            verification_gas_limit: 20_000.into(),
+            ..op
+        };
+        assert_eq!(
+            calc(less, EvmVersion::Prague).await - calc(more, EvmVersion::Prague).await,
+            U256::from(10_000)
+        );
+    }
+
+    #[test]
+    fn test_user_operation_gas_refund() {
+        let op = UserOperation {
+            max_fee_per_gas: 10.into(),
+            ..create_test_op_with_gas(1_000.into(), 20_000.into(), 30_000.into(), true)
+        };
+        let gross = user_operation_max_gas_cost(&op);
+        assert_eq!(gross, U256::from(10 * (1_000 + 20_000 + 3 * 30_000)));
+
+        let paid = U256::from(400_000);
+        let refund = user_operation_gas_refund(&op, paid);
+        assert_eq!(gross - refund, paid);
+        // nothing is refunded if the full prefund was used
+        assert_eq!(user_operation_gas_refund(&op, gross), U256::zero());
+    }
+
+    #[test]
+    fn test_gas_constants_v0_6() {
+        let ov = GasOverheads::for_version(EntryPointVersion::V0_6);
+        assert_eq!(ov.transaction_gas_overhead, 21_000.into());
+        assert_eq!(ov.per_user_op, 18_300.into());
+        assert_eq!(ov.bundle_transaction_gas_buffer, 5_000.into());
+    }
+
+    #[test]
+    fn test_gas_constants_v0_7() {
+        let ov = GasOverheads::for_version(EntryPointVersion::V0_7);
+        assert_eq!(ov.transaction_gas_overhead, 21_000.into());
+        assert_eq!(ov.per_user_op, 18_300.into());
+        assert_eq!(ov.bundle_transaction_gas_buffer, 10_000.into());
+    }
+
+    #[tokio::test]
+    async fn test_calc_pre_verification_gas_l1_base_fee_override() {
+        let mut provider = MockProvider::new();
+        provider
+            .expect_calc_optimism_l1_gas()
+            .returning(|_, _, l1_base_fee_override| {
+                // the oracle reports an L1 base fee of 1 gwei
+                Ok(l1_base_fee_override.unwrap_or(U256::exp10(9)) / 1_000)
+            });
+        let provider = Arc::new(provider);
+        let op = create_test_op_with_gas(0.into(), 100_000.into(), 100_000.into(), false);
+        let chain_id = Chain::Optimism as u64;
+
+        let from_oracle = calc_pre_verification_gas(
+            &op,
+            &op,
+            Address::zero(),
+            provider.clone(),
+            chain_id,
+            None,
+            EvmVersion::default(),
+        )
+        .await
+        .unwrap();
+        let overridden = calc_pre_verification_gas(
+            &op,
+            &op,
+            Address::zero(),
+            provider,
+            chain_id,
+            Some(U256::exp10(9) * 2),
+            EvmVersion::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(overridden - from_oracle, 1_000_000.into());
+    }
+
     #[tokio::test]
     async fn test_bundle_gas_limit() {
         let op1 = create_test_op_with_gas(100_000.into(), 100_000.into(), 1_000_000.into(), false);
         let op2 = create_test_op_with_gas(100_000.into(), 100_000.into(), 200_000.into(), false);
         let ops = vec![op1.clone(), op2.clone()];
         let chain_id = 1;
-        let gas_limit = bundle_gas_limit(ops.iter(), chain_id);
+        let gas_limit = bundle_gas_limit(ops.iter(), chain_id, EntryPointVersion::V0_6);
 
         // The gas requirement in the first user operation dominates and determines the expected gas limit
         let expected_gas_limit = op1.pre_verification_gas
@@ -379,7 +621,7 @@ mod tests {
         let op2 = create_test_op_with_gas(100_000.into(), 100_000.into(), 200_000.into(), false);
         let ops = vec![op1.clone(), op2.clone()];
         let chain_id = 1;
-        let gas_limit = bundle_gas_limit(ops.iter(), chain_id);
+        let gas_limit = bundle_gas_limit(ops.iter(), chain_id, EntryPointVersion::V0_6);
 
         // The gas requirement in the second user operation dominates and determines the expected gas limit
         let expected_gas_limit = op1.pre_verification_gas
@@ -393,4 +635,19 @@ mod tests {
 
         assert_eq!(gas_limit, expected_gas_limit);
     }
+
+    #[tokio::test]
+    async fn test_bundle_gas_limit_v0_7() {
+        let op = create_test_op_with_gas(100_000.into(), 100_000.into(), 1_000_000.into(), false);
+        let ops = vec![op.clone()];
+        let gas_limit = bundle_gas_limit(ops.iter(), 1, EntryPointVersion::V0_7);
+
+        let expected_gas_limit = op.pre_verification_gas
+            + op.verification_gas_limit * 2
+            + op.call_gas_limit
+            + 21_000
+            + 10_000;
+
+        assert_eq!(gas_limit, expected_gas_limit);
+    }
 }
@@ -29,11 +29,13 @@
 //! ## Feature Flags
 //!
 //! - `test-utils`: Export mocks and utilities for testing.
+//! - `bincode`: Compact binary encoding of simulation results and gas estimates.
 
 mod estimation;
 pub use estimation::{
-    GasEstimate, GasEstimationError, GasEstimator, GasEstimatorImpl,
-    Settings as EstimationSettings, UserOperationOptionalGas,
+    GasEstimate, GasEstimationError, GasEstimationProgress, GasEstimator, GasEstimatorImpl,
+    GasExplanation, GasFrame, GasUtilization, Settings as EstimationSettings,
+    UserOperationOptionalGas,
 };
 
 pub mod gas;
@@ -51,12 +53,22 @@ mod simulation;
 #[cfg(feature = "test-utils")]
 pub use simulation::MockSimulator;
 pub use simulation::{
-    MempoolConfig, Settings as SimulationSettings, SimulateValidationTracer,
-    SimulateValidationTracerImpl, SimulationError, SimulationSuccess, SimulationViolation,
-    Simulator, SimulatorImpl, ViolationOpCode,
+    detect_read_after_write, is_stale, AggregatorFailurePolicy, AggregatorInfo,
+    AggregatorValidator, BlockSource, CurrentBlock, CustomPrecheck, EvmVersion, GasLimitMode,
+    GasOverrides, InMemoryBlockSource, MempoolConfig, MempoolRevalidationResult,
+    PaymasterContextDecoder, PhasedViolation, PoolDecision, ProviderAggregatorValidator,
+    ProviderBlockSource, RecordedSimulateValidationTracer, ReputationChecker, RestrictedOpcodes,
+    Settings as SimulationSettings, SimulateValidationTracer, SimulateValidationTracerImpl,
+    SimulationDiff, SimulationError, SimulationRequest, SimulationSink, SimulationStats,
+    SimulationSuccess, SimulationTracerOutput, SimulationViolation, Simulator, SimulatorImpl,
+    SlotAccess, StakeInfo, StakeResolver, StorageConflict, TokenCharge, UnstakeDelaySource,
+    ValidationOutput, ValidationReturnInfo, ViolationFilter, ViolationOpCode,
 };
 
 mod types;
-pub use types::ExpectedStorage;
+pub use types::{is_valid_replacement, ExpectedStorage};
 
 mod utils;
+
+#[cfg(feature = "bincode")]
+mod wire;
@@ -21,7 +21,7 @@ use mockall::automock;
 use rundler_provider::{EntryPoint, Provider};
 use rundler_types::{GasFees, UserOperation};
 
-use crate::{gas, types::ViolationError};
+use crate::{gas, simulation::EvmVersion, types::ViolationError};
 
 /// The min cost of a `CALL` with nonzero value, as required by the spec.
 pub const MIN_CALL_GAS_LIMIT: U256 = U256([9100, 0, 0, 0]);
@@ -54,8 +54,16 @@ pub struct Settings {
     pub chain_id: u64,
     /// Maximum verification gas allowed for a user operation
     pub max_verification_gas: U256,
+    /// Minimum verification gas limit a user operation must set, as a safety
+    /// floor against limits that only pass simulation on a fast node. Zero
+    /// disables the check.
+    pub min_verification_gas_limit: U256,
     /// Maximum total execution gas allowed for a user operation
     pub max_total_execution_gas: U256,
+    /// Maximum sum of the verification and call gas limits allowed for a
+    /// user operation, as a per-operation policy independent of the bundle
+    /// gas limit. Zero disables the check.
+    pub max_verification_and_call_gas: U256,
     /// Whether to use a bundle priority fee on the bundle transaction.
     /// If `None`, the default is to use a bundle priority fee if the
     /// chain id known to support EIP-1559.
@@ -65,6 +73,19 @@ pub struct Settings {
     pub bundle_priority_fee_overhead_percent: u64,
     /// The priority fee mode to use for calculating required user operation priority fee.
     pub priority_fee_mode: gas::PriorityFeeMode,
+    /// Percentage below the calculated minimum pre-verification gas that is still
+    /// accepted, to allow for small fluctuations in L1 gas prices on L2s.
+    pub pvg_tolerance_percent: u64,
+    /// Minimum entry point deposit required of the payer (the paymaster if
+    /// present, otherwise the sender), regardless of the operation's cost.
+    /// Zero disables the check.
+    pub min_payer_deposit: U256,
+    /// Minimum tip per gas that an operation must pay on top of the current
+    /// base fee for bundling it to be profitable. Zero disables the check.
+    pub min_priority_fee: U256,
+    /// The hardfork the chain follows, which determines how the calldata
+    /// part of the minimum pre-verification gas is priced
+    pub evm_version: EvmVersion,
 }
 
 #[cfg(any(test, feature = "test-utils"))]
@@ -72,11 +93,17 @@ impl Default for Settings {
     fn default() -> Self {
         Self {
             max_verification_gas: 5_000_000.into(),
+            min_verification_gas_limit: U256::zero(),
             use_bundle_priority_fee: None,
             bundle_priority_fee_overhead_percent: 0,
             priority_fee_mode: gas::PriorityFeeMode::BaseFeePercent(0),
             max_total_execution_gas: 10_000_000.into(),
+            max_verification_and_call_gas: U256::zero(),
             chain_id: 1,
+            pvg_tolerance_percent: 0,
+            min_payer_deposit: U256::zero(),
+            min_priority_fee: U256::zero(),
+            evm_version: EvmVersion::default(),
         }
     }
 }
@@ -87,6 +114,7 @@ struct AsyncData {
     sender_exists: bool,
     paymaster_exists: bool,
     payer_funds: U256,
+    payer_deposit: U256,
     bundle_fees: GasFees,
     min_pre_verification_gas: U256,
 }
@@ -96,9 +124,12 @@ impl<P: Provider, E: EntryPoint> Prechecker for PrecheckerImpl<P, E> {
     async fn check(&self, op: &UserOperation) -> Result<(), PrecheckError> {
         let async_data = self.load_async_data(op).await?;
         let mut violations: Vec<PrecheckViolation> = vec![];
+        violations.extend(self.check_encoding(op));
         violations.extend(self.check_init_code(op, async_data));
         violations.extend(self.check_gas(op, async_data));
         violations.extend(self.check_payer(op, async_data));
+        violations.extend(self.check_payer_deposit(op, async_data));
+        violations.extend(self.check_profitability(op, async_data));
         if !violations.is_empty() {
             Err(violations)?
         }
@@ -123,6 +154,12 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
         }
     }
 
+    fn check_encoding(&self, op: &UserOperation) -> Option<PrecheckViolation> {
+        op.validate_encoding()
+            .err()
+            .map(|error| PrecheckViolation::InvalidEncoding(error.to_string()))
+    }
+
     fn check_init_code(
         &self,
         op: &UserOperation,
@@ -160,11 +197,14 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
         &self,
         op: &UserOperation,
         async_data: AsyncData,
-    ) -> ArrayVec<PrecheckViolation, 6> {
+    ) -> ArrayVec<PrecheckViolation, 8> {
         let Settings {
             chain_id,
             max_verification_gas,
+            min_verification_gas_limit,
             max_total_execution_gas,
+            max_verification_and_call_gas,
+            pvg_tolerance_percent,
             ..
         } = self.settings;
         let AsyncData {
@@ -180,6 +220,12 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
                 max_verification_gas,
             ));
         }
+        if op.verification_gas_limit < min_verification_gas_limit {
+            violations.push(PrecheckViolation::VerificationGasLimitTooLow(
+                op.verification_gas_limit,
+                min_verification_gas_limit,
+            ));
+        }
         let total_gas_limit = gas::user_operation_gas_limit(op, chain_id, true);
         if total_gas_limit > max_total_execution_gas {
             violations.push(PrecheckViolation::TotalGasLimitTooHigh(
@@ -187,6 +233,17 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
                 max_total_execution_gas,
             ))
         }
+        let verification_and_call_gas = op.verification_gas_limit + op.call_gas_limit;
+        if !max_verification_and_call_gas.is_zero()
+            && verification_and_call_gas > max_verification_and_call_gas
+        {
+            violations.push(PrecheckViolation::TotalGasLimitExceeded(
+                verification_and_call_gas,
+                max_verification_and_call_gas,
+            ))
+        }
+        let min_pre_verification_gas =
+            min_pre_verification_gas * (100 - pvg_tolerance_percent.min(100)) / 100;
         if op.pre_verification_gas < min_pre_verification_gas {
             violations.push(PrecheckViolation::PreVerificationGasTooLow(
                 op.pre_verification_gas,
@@ -209,7 +266,13 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
             ));
         }
 
-        if op.call_gas_limit < MIN_CALL_GAS_LIMIT {
+        if op.call_gas_limit.is_zero() {
+            // The entry point only makes the execution call if there is call
+            // data, so an operation without any needs no call gas
+            if !op.call_data.is_empty() {
+                violations.push(PrecheckViolation::ZeroCallGasWithCallData);
+            }
+        } else if op.call_gas_limit < MIN_CALL_GAS_LIMIT {
             violations.push(PrecheckViolation::CallGasLimitTooLow(
                 op.call_gas_limit,
                 MIN_CALL_GAS_LIMIT,
@@ -224,7 +287,8 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
             payer_funds,
             ..
         } = async_data;
-        if !op.paymaster_and_data.is_empty() {
+        let has_paymaster = op.classify().has_paymaster;
+        if has_paymaster {
             let Some(paymaster) = op.paymaster() else {
                 return Some(PrecheckViolation::PaymasterTooShort(
                     op.paymaster_and_data.len(),
@@ -234,9 +298,11 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
                 return Some(PrecheckViolation::PaymasterIsNotContract(paymaster));
             }
         }
+        // The entry point requires a prefund at the max fee, whatever the
+        // price the operation ends up paying
         let max_gas_cost = gas::user_operation_max_gas_cost(op);
         if payer_funds < max_gas_cost {
-            if op.paymaster_and_data.is_empty() {
+            if !has_paymaster {
                 return Some(PrecheckViolation::SenderFundsTooLow(
                     payer_funds,
                     max_gas_cost,
@@ -251,19 +317,60 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
         None
     }
 
+    fn check_payer_deposit(
+        &self,
+        op: &UserOperation,
+        async_data: AsyncData,
+    ) -> Option<PrecheckViolation> {
+        let min_payer_deposit = self.settings.min_payer_deposit;
+        if async_data.payer_deposit < min_payer_deposit {
+            return Some(PrecheckViolation::PayerDepositBelowMinimum(
+                payer_address(op),
+                min_payer_deposit,
+            ));
+        }
+        None
+    }
+
+    // Unlike the fee checks in `check_gas`, which ensure the operation can be
+    // included, this ensures the tip the bundler earns is worth including it.
+    fn check_profitability(
+        &self,
+        op: &UserOperation,
+        async_data: AsyncData,
+    ) -> Option<PrecheckViolation> {
+        let min_priority_fee = self.settings.min_priority_fee;
+        if min_priority_fee.is_zero() {
+            return None;
+        }
+        let bundle_fees = async_data.bundle_fees;
+        let base_fee = bundle_fees.max_fee_per_gas - bundle_fees.max_priority_fee_per_gas;
+        let effective_gas_price = op.effective_gas_price(base_fee);
+        let required_gas_price = base_fee.saturating_add(min_priority_fee);
+        if effective_gas_price < required_gas_price {
+            return Some(PrecheckViolation::InsufficientPriorityFee(
+                effective_gas_price,
+                required_gas_price,
+            ));
+        }
+        None
+    }
+
     async fn load_async_data(&self, op: &UserOperation) -> anyhow::Result<AsyncData> {
         let (
             factory_exists,
             sender_exists,
             paymaster_exists,
-            payer_funds,
+            payer_deposit,
+            payer_balance,
             bundle_fees,
             min_pre_verification_gas,
         ) = tokio::try_join!(
             self.is_contract(op.factory()),
             self.is_contract(Some(op.sender)),
             self.is_contract(op.paymaster()),
-            self.get_payer_funds(op),
+            self.get_payer_deposit(op),
+            self.get_payer_balance(op),
             self.get_bundle_fees(),
             self.get_pre_verification_gas(op.clone())
         )?;
@@ -271,7 +378,8 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
             factory_exists,
             sender_exists,
             paymaster_exists,
-            payer_funds,
+            payer_funds: payer_deposit + payer_balance,
+            payer_deposit,
             bundle_fees,
             min_pre_verification_gas,
         })
@@ -289,25 +397,18 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
         Ok(!bytecode.is_empty())
     }
 
-    async fn get_payer_funds(&self, op: &UserOperation) -> anyhow::Result<U256> {
-        let (deposit, balance) =
-            tokio::try_join!(self.get_payer_deposit(op), self.get_payer_balance(op),)?;
-        Ok(deposit + balance)
-    }
-
     async fn get_payer_deposit(&self, op: &UserOperation) -> anyhow::Result<U256> {
-        let payer = match op.paymaster() {
-            Some(paymaster) => paymaster,
-            None => op.sender,
-        };
         self.entry_point
-            .balance_of(payer, None)
+            .balance_of(payer_address(op), None)
             .await
             .context("precheck should get payer balance")
     }
 
+    // When a paymaster is present it is charged the full cost of the
+    // operation, so the sender needs no balance or deposit of its own even
+    // though the operation carries nonzero gas fees.
     async fn get_payer_balance(&self, op: &UserOperation) -> anyhow::Result<U256> {
-        if !op.paymaster_and_data.is_empty() {
+        if op.classify().has_paymaster {
             // Paymasters must deposit eth, and cannot pay with their own.
             return Ok(0.into());
         }
@@ -331,12 +432,19 @@ impl<P: Provider, E: EntryPoint> PrecheckerImpl<P, E> {
             self.entry_point.address(),
             self.provider.clone(),
             self.settings.chain_id,
+            None,
+            self.settings.evm_version,
         )
         .await
         .context("should calculate pre-verification gas")
     }
 }
 
+/// The address whose entry point deposit pays for the operation
+fn payer_address(op: &UserOperation) -> Address {
+    op.paymaster().unwrap_or(op.sender)
+}
+
 /// Precheck violation enumeration
 ///
 /// All possible errors that can be returned from a precheck.
@@ -358,9 +466,17 @@ pub enum PrecheckViolation {
     /// See `gas::user_operation_execution_gas_limit` for calculation.
     #[display("total gas limit is {0} but must be at most {1}")]
     TotalGasLimitTooHigh(U256, U256),
+    /// The sum of the verification and call gas limits of the user operation
+    /// is above the operator's configured maximum.
+    #[display("verificationGasLimit + callGasLimit is {0} but must be at most {1}")]
+    TotalGasLimitExceeded(U256, U256),
     /// The verification gas limit of the user operation is too high.
     #[display("verificationGasLimit is {0} but must be at most {1}")]
     VerificationGasLimitTooHigh(U256, U256),
+    /// The verification gas limit of the user operation is below the
+    /// operator's configured minimum.
+    #[display("verificationGasLimit is {0} but must be at least {1}")]
+    VerificationGasLimitTooLow(U256, U256),
     /// The pre-verification gas of the user operation is too low.
     #[display("preVerificationGas is {0} but must be at least {1}")]
     PreVerificationGasTooLow(U256, U256),
@@ -377,6 +493,9 @@ pub enum PrecheckViolation {
     /// (when not using a paymaster)
     #[display("sender balance and deposit together is {0} but must be at least {1} to pay for this operation")]
     SenderFundsTooLow(U256, U256),
+    /// The payer's entry point deposit is below the operator's configured minimum.
+    #[display("payer {0:?} has an entry point deposit below the required minimum of {1}")]
+    PayerDepositBelowMinimum(Address, U256),
     /// The provided max fee per gas is too low based on the current network rate.
     #[display("maxFeePerGas is {0} but must be at least {1}, which is based on the current block base fee")]
     MaxFeePerGasTooLow(U256, U256),
@@ -386,6 +505,17 @@ pub enum PrecheckViolation {
     /// The call gas limit is too low to account for any possible call.
     #[display("callGasLimit is {0} but must be at least {1}")]
     CallGasLimitTooLow(U256, U256),
+    /// The call gas limit is zero, but the call data is nonempty so the
+    /// execution call would run out of gas.
+    #[display("callGasLimit is 0 but callData is nonempty")]
+    ZeroCallGasWithCallData,
+    /// The user operation does not round trip through the entry point ABI encoding.
+    #[display("user operation has an invalid encoding: {0}")]
+    InvalidEncoding(String),
+    /// The operation's effective gas price at the current base fee does not
+    /// include the operator's required minimum tip.
+    #[display("effective gas price is {0} but must be at least {1} to be profitable to bundle")]
+    InsufficientPriorityFee(U256, U256),
 }
 
 #[cfg(test)]
@@ -407,6 +537,7 @@ mod tests {
             sender_exists: true,
             paymaster_exists: true,
             payer_funds: 5_000_000.into(),
+            payer_deposit: 1_000_000.into(),
             bundle_fees: GasFees {
                 max_fee_per_gas: 5_000.into(),
                 max_priority_fee_per_gas: 1_000.into(),
@@ -451,10 +582,16 @@ mod tests {
         let test_settings = Settings {
             chain_id: 1,
             max_verification_gas: 5_000_000.into(),
+            min_verification_gas_limit: U256::zero(),
             max_total_execution_gas: 10_000_000.into(),
+            max_verification_and_call_gas: U256::zero(),
             use_bundle_priority_fee: None,
             bundle_priority_fee_overhead_percent: 0,
             priority_fee_mode: gas::PriorityFeeMode::BaseFeePercent(100),
+            pvg_tolerance_percent: 0,
+            min_payer_deposit: U256::zero(),
+            min_priority_fee: U256::zero(),
+            evm_version: EvmVersion::default(),
         };
         let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, test_settings);
         let op = UserOperation {
@@ -475,7 +612,7 @@ mod tests {
 
         assert_eq!(
             res,
-            ArrayVec::<PrecheckViolation, 6>::from([
+            ArrayVec::<PrecheckViolation, 8>::from_iter([
                 PrecheckViolation::VerificationGasLimitTooHigh(10_000_000.into(), 5_000_000.into(),),
                 PrecheckViolation::TotalGasLimitTooHigh(20_009_000.into(), 10_000_000.into(),),
                 PrecheckViolation::PreVerificationGasTooLow(0.into(), 1_000.into(),),
@@ -486,6 +623,153 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_check_zero_call_gas_without_call_data() {
+        let (provider, entry_point) = create_base_config();
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, Settings::default());
+        let op = UserOperation {
+            call_gas_limit: 0.into(),
+            ..pvg_test_op(1_000.into())
+        };
+
+        let res = prechecker.check_gas(&op, get_test_async_data());
+        assert!(res.is_empty(), "unexpected violations: {res:?}");
+    }
+
+    #[tokio::test]
+    async fn test_check_zero_call_gas_with_call_data() {
+        let (provider, entry_point) = create_base_config();
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, Settings::default());
+        let op = UserOperation {
+            call_gas_limit: 0.into(),
+            call_data: Bytes::from(vec![0xb6, 0x1d, 0x27, 0xf6]),
+            ..pvg_test_op(1_000.into())
+        };
+
+        let res = prechecker.check_gas(&op, get_test_async_data());
+        assert_eq!(
+            res,
+            ArrayVec::<PrecheckViolation, 8>::from_iter([
+                PrecheckViolation::ZeroCallGasWithCallData
+            ])
+        );
+    }
+
+    fn pvg_test_op(pre_verification_gas: U256) -> UserOperation {
+        UserOperation {
+            sender: Address::from_str("0x3f8a2b6c4d5e1079286fa1b3c0d4e5f6902b7c8d").unwrap(),
+            nonce: 100.into(),
+            init_code: Bytes::default(),
+            call_data: Bytes::default(),
+            call_gas_limit: 10_000.into(),
+            verification_gas_limit: 100_000.into(),
+            pre_verification_gas,
+            max_fee_per_gas: 5_000.into(),
+            max_priority_fee_per_gas: 1_000.into(),
+            paymaster_and_data: Bytes::default(),
+            signature: Bytes::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_pre_verification_gas_sufficient() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            pvg_tolerance_percent: 10,
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        // exactly the minimum, and within the 10% tolerance below it
+        for pre_verification_gas in [1_000, 900] {
+            let res = prechecker.check_gas(
+                &pvg_test_op(pre_verification_gas.into()),
+                get_test_async_data(),
+            );
+            assert!(res.is_empty(), "unexpected violations: {res:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_pre_verification_gas_insufficient() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            pvg_tolerance_percent: 10,
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        let res = prechecker.check_gas(&pvg_test_op(899.into()), get_test_async_data());
+        assert_eq!(
+            res,
+            ArrayVec::<PrecheckViolation, 8>::from_iter([
+                PrecheckViolation::PreVerificationGasTooLow(899.into(), 900.into())
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_verification_gas_limit_at_minimum() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            min_verification_gas_limit: 100_000.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        let res = prechecker.check_gas(&pvg_test_op(1_000.into()), get_test_async_data());
+        assert!(res.is_empty(), "unexpected violations: {res:?}");
+    }
+
+    #[tokio::test]
+    async fn test_check_verification_gas_limit_below_minimum() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            min_verification_gas_limit: 100_001.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        let res = prechecker.check_gas(&pvg_test_op(1_000.into()), get_test_async_data());
+        assert_eq!(
+            res,
+            ArrayVec::<PrecheckViolation, 8>::from_iter([
+                PrecheckViolation::VerificationGasLimitTooLow(100_000.into(), 100_001.into())
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_verification_and_call_gas_at_maximum() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            max_verification_and_call_gas: 110_000.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        let res = prechecker.check_gas(&pvg_test_op(1_000.into()), get_test_async_data());
+        assert!(res.is_empty(), "unexpected violations: {res:?}");
+    }
+
+    #[tokio::test]
+    async fn test_check_verification_and_call_gas_above_maximum() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            max_verification_and_call_gas: 109_999.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        let res = prechecker.check_gas(&pvg_test_op(1_000.into()), get_test_async_data());
+        assert_eq!(
+            res,
+            ArrayVec::<PrecheckViolation, 8>::from_iter([
+                PrecheckViolation::TotalGasLimitExceeded(110_000.into(), 109_999.into())
+            ])
+        );
+    }
+
     #[tokio::test]
     async fn test_check_payer_paymaster_deposit_too_low() {
         let (provider, entry_point) = create_base_config();
@@ -516,4 +800,166 @@ mod tests {
             ))
         );
     }
+
+    #[tokio::test]
+    async fn test_check_payer_uses_max_fee() {
+        let (provider, entry_point) = create_base_config();
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, Settings::default());
+        // Would only pay 4_000 base fee + 100 priority fee, but the entry point
+        // requires a prefund of the 10_000 max fee for all 1_000_000 gas
+        let op = UserOperation {
+            sender: Address::from_str("0x3f8a2b6c4d5e1079286fa1b3c0d4e5f6902b7c8d").unwrap(),
+            call_gas_limit: 500_000.into(),
+            verification_gas_limit: 500_000.into(),
+            max_fee_per_gas: 10_000.into(),
+            max_priority_fee_per_gas: 100.into(),
+            ..Default::default()
+        };
+        let async_data = AsyncData {
+            payer_funds: 10_000_000_000u64.into(),
+            ..get_test_async_data()
+        };
+
+        assert_eq!(prechecker.check_payer(&op, async_data), None);
+        assert_eq!(
+            prechecker.check_payer(
+                &op,
+                AsyncData {
+                    payer_funds: 5_000_000_000u64.into(),
+                    ..async_data
+                }
+            ),
+            Some(PrecheckViolation::SenderFundsTooLow(
+                5_000_000_000u64.into(),
+                10_000_000_000u64.into(),
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_payer_deposit_account_below_minimum() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            min_payer_deposit: 2_000_000.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+        let op = pvg_test_op(1_000.into());
+
+        let res = prechecker.check_payer_deposit(&op, get_test_async_data());
+        assert_eq!(
+            res,
+            Some(PrecheckViolation::PayerDepositBelowMinimum(
+                op.sender,
+                2_000_000.into(),
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_payer_deposit_paymaster_below_minimum() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            min_payer_deposit: 2_000_000.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+        let op = UserOperation {
+            paymaster_and_data: Bytes::from_str("0xa4b2c8f0351d60729e4f0a12345678d9b1c3e5f2")
+                .unwrap(),
+            ..pvg_test_op(1_000.into())
+        };
+
+        let res = prechecker.check_payer_deposit(&op, get_test_async_data());
+        assert_eq!(
+            res,
+            Some(PrecheckViolation::PayerDepositBelowMinimum(
+                Address::from_str("0xa4b2c8f0351d60729e4f0a12345678d9b1c3e5f2").unwrap(),
+                2_000_000.into(),
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_payer_deposit_above_minimum() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            min_payer_deposit: 1_000_000.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        let res = prechecker.check_payer_deposit(&pvg_test_op(1_000.into()), get_test_async_data());
+        assert_eq!(res, None);
+    }
+
+    #[tokio::test]
+    async fn test_check_profitability_above_margin() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            min_priority_fee: 1_000.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        let res = prechecker.check_profitability(&pvg_test_op(1_000.into()), get_test_async_data());
+        assert_eq!(res, None);
+    }
+
+    #[tokio::test]
+    async fn test_check_profitability_below_margin() {
+        let (provider, entry_point) = create_base_config();
+        let settings = Settings {
+            min_priority_fee: 1_500.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+
+        // Base fee is 4,000, so the op's effective gas price is capped at its
+        // max fee of 5,000 while 5,500 is required
+        let res = prechecker.check_profitability(&pvg_test_op(1_000.into()), get_test_async_data());
+        assert_eq!(
+            res,
+            Some(PrecheckViolation::InsufficientPriorityFee(
+                5_000.into(),
+                5_500.into()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_payer_sponsored_op() {
+        let (provider, mut entry_point) = create_base_config();
+        let paymaster = Address::from_str("0xa4b2c8f0351d60729e4f0a12345678d9b1c3e5f2").unwrap();
+        // Only the paymaster's deposit is loaded, and the sender's balance is
+        // never requested
+        entry_point
+            .expect_balance_of()
+            .withf(move |&address, _| address == paymaster)
+            .returning(|_, _| Ok(2_000_000_000u64.into()));
+        let settings = Settings {
+            min_payer_deposit: 1_000_000.into(),
+            ..Default::default()
+        };
+        let prechecker = PrecheckerImpl::new(Arc::new(provider), entry_point, settings);
+        let op = UserOperation {
+            sender: Address::from_str("0x3f8a2b6c4d5e1079286fa1b3c0d4e5f6902b7c8d").unwrap(),
+            call_gas_limit: 500_000.into(),
+            verification_gas_limit: 500_000.into(),
+            max_fee_per_gas: 1_000.into(),
+            paymaster_and_data: paymaster.as_bytes().to_vec().into(),
+            ..Default::default()
+        };
+
+        let payer_deposit = prechecker.get_payer_deposit(&op).await.unwrap();
+        let payer_balance = prechecker.get_payer_balance(&op).await.unwrap();
+        let async_data = AsyncData {
+            payer_funds: payer_deposit + payer_balance,
+            payer_deposit,
+            ..get_test_async_data()
+        };
+        assert_eq!(payer_balance, U256::zero());
+        assert_eq!(prechecker.check_payer(&op, async_data), None);
+        assert_eq!(prechecker.check_payer_deposit(&op, async_data), None);
+    }
 }
@@ -0,0 +1,69 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::{fmt::Debug, sync::Arc};
+
+use async_trait::async_trait;
+use ethers::types::Address;
+use rundler_provider::{AggregatorOut, Provider};
+use rundler_types::UserOperation;
+
+/// Validates a user operation's signature with its aggregator during
+/// simulation.
+///
+/// Replacing the default lets the aggregator path of simulation be exercised,
+/// e.g. in tests or on development chains, without deploying an aggregator.
+#[async_trait]
+pub trait AggregatorValidator: Debug + Send + Sync + 'static {
+    /// Validate the operation's signature with the given aggregator
+    async fn validate_user_op_signature(
+        &self,
+        aggregator_address: Address,
+        op: UserOperation,
+        gas_cap: u64,
+    ) -> anyhow::Result<AggregatorOut>;
+}
+
+/// Aggregator validator that calls `validateUserOpSignature` on the
+/// aggregator contract through the provider
+pub struct ProviderAggregatorValidator<P> {
+    provider: Arc<P>,
+}
+
+impl<P> ProviderAggregatorValidator<P> {
+    /// Create an aggregator validator backed by the given provider
+    pub fn new(provider: Arc<P>) -> Self {
+        Self { provider }
+    }
+}
+
+impl<P> Debug for ProviderAggregatorValidator<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderAggregatorValidator")
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<P: Provider> AggregatorValidator for ProviderAggregatorValidator<P> {
+    async fn validate_user_op_signature(
+        &self,
+        aggregator_address: Address,
+        op: UserOperation,
+        gas_cap: u64,
+    ) -> anyhow::Result<AggregatorOut> {
+        Ok(Arc::clone(&self.provider)
+            .validate_user_op_signature(aggregator_address, op, gas_cap)
+            .await?)
+    }
+}
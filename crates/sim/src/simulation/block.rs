@@ -0,0 +1,109 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::{fmt::Debug, sync::Arc};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use ethers::types::{Block, BlockNumber, H256, U256};
+use parking_lot::RwLock;
+use rundler_provider::Provider;
+
+/// The head of the chain, as seen by a `BlockSource`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CurrentBlock {
+    /// Hash of the block
+    pub hash: H256,
+    /// Number of the block
+    pub number: u64,
+    /// Timestamp of the block
+    pub timestamp: U256,
+    /// Gas limit of the block
+    pub gas_limit: U256,
+    /// Base fee of the block, if the chain supports EIP-1559
+    pub base_fee: Option<U256>,
+}
+
+impl TryFrom<Block<H256>> for CurrentBlock {
+    type Error = anyhow::Error;
+
+    fn try_from(block: Block<H256>) -> anyhow::Result<Self> {
+        Ok(Self {
+            hash: block.hash.context("block should have a hash")?,
+            number: block.number.context("block should have a number")?.as_u64(),
+            timestamp: block.timestamp,
+            gas_limit: block.gas_limit,
+            base_fee: block.base_fee_per_gas,
+        })
+    }
+}
+
+/// Source of the current head of the chain, used by simulation when no block
+/// is given.
+#[async_trait]
+pub trait BlockSource: Debug + Send + Sync + 'static {
+    /// Return the current head of the chain
+    async fn current_block(&self) -> anyhow::Result<CurrentBlock>;
+}
+
+/// Block source that loads the latest block from the provider on every call
+pub struct ProviderBlockSource<P> {
+    provider: Arc<P>,
+}
+
+impl<P> ProviderBlockSource<P> {
+    /// Create a block source backed by the given provider
+    pub fn new(provider: Arc<P>) -> Self {
+        Self { provider }
+    }
+}
+
+impl<P> Debug for ProviderBlockSource<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderBlockSource")
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<P: Provider> BlockSource for ProviderBlockSource<P> {
+    async fn current_block(&self) -> anyhow::Result<CurrentBlock> {
+        self.provider
+            .get_block(BlockNumber::Latest)
+            .await?
+            .context("latest block should exist")?
+            .try_into()
+    }
+}
+
+/// Block source that serves the head most recently given to it, for
+/// bundlers that already track new heads through a subscription.
+#[derive(Debug, Default)]
+pub struct InMemoryBlockSource {
+    head: RwLock<Option<CurrentBlock>>,
+}
+
+impl InMemoryBlockSource {
+    /// Record a new head of the chain, to be called as each new head
+    /// arrives from the subscription
+    pub fn update(&self, block: CurrentBlock) {
+        *self.head.write() = Some(block);
+    }
+}
+
+#[async_trait]
+impl BlockSource for InMemoryBlockSource {
+    async fn current_block(&self) -> anyhow::Result<CurrentBlock> {
+        self.head.read().context("no head has been received yet")
+    }
+}
@@ -0,0 +1,140 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use ethers::types::H256;
+use indexmap::IndexMap;
+
+use super::SimulationSuccess;
+
+/// Successful simulation results keyed by operation hash and block hash, so
+/// that simulating the same operation at the same block again is free.
+///
+/// Entries are evicted once the cache holds more than `capacity` of them,
+/// least recently used first, and once their block is more than `ttl_blocks`
+/// behind the newest block the cache has seen.
+#[derive(Debug)]
+pub(crate) struct SimulationCache {
+    capacity: usize,
+    ttl_blocks: u64,
+    newest_block: u64,
+    // Ordered from least to most recently used
+    entries: IndexMap<(H256, H256), SimulationSuccess>,
+}
+
+impl SimulationCache {
+    pub(crate) fn new(capacity: usize, ttl_blocks: u64) -> Self {
+        Self {
+            capacity,
+            ttl_blocks,
+            newest_block: 0,
+            entries: IndexMap::new(),
+        }
+    }
+
+    /// Return the result for the operation at the block, marking it as most
+    /// recently used
+    pub(crate) fn get(&mut self, op_hash: H256, block_hash: H256) -> Option<SimulationSuccess> {
+        let key = (op_hash, block_hash);
+        let success = self.entries.shift_remove(&key)?;
+        self.entries.insert(key, success.clone());
+        Some(success)
+    }
+
+    /// Store the result for the operation at the block it was simulated at,
+    /// evicting entries that are too old or least recently used
+    pub(crate) fn insert(&mut self, op_hash: H256, success: SimulationSuccess) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.newest_block = self.newest_block.max(success.block_number);
+        let min_block = self.newest_block.saturating_sub(self.ttl_blocks);
+        if success.block_number < min_block {
+            return;
+        }
+        self.entries
+            .retain(|_, entry| entry.block_number >= min_block);
+
+        let key = (op_hash, success.block_hash);
+        self.entries.shift_remove(&key);
+        self.entries.insert(key, success);
+        while self.entries.len() > self.capacity {
+            self.entries.shift_remove_index(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn success_at(block_number: u64) -> SimulationSuccess {
+        SimulationSuccess {
+            block_hash: H256::from_low_u64_be(block_number),
+            block_number,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hit() {
+        let mut cache = SimulationCache::new(10, 2);
+        let op_hash = H256::random();
+        let success = success_at(100);
+        cache.insert(op_hash, success.clone());
+
+        let cached = cache.get(op_hash, success.block_hash).unwrap();
+        assert_eq!(cached.block_number, 100);
+        assert!(cache.get(H256::random(), success.block_hash).is_none());
+    }
+
+    #[test]
+    fn test_miss_on_new_block() {
+        let mut cache = SimulationCache::new(10, 2);
+        let op_hash = H256::random();
+        cache.insert(op_hash, success_at(100));
+
+        assert!(cache.get(op_hash, success_at(101).block_hash).is_none());
+    }
+
+    #[test]
+    fn test_block_age_ttl() {
+        let mut cache = SimulationCache::new(10, 2);
+        let old_op = H256::random();
+        cache.insert(old_op, success_at(100));
+        cache.insert(H256::random(), success_at(102));
+        assert!(cache.get(old_op, success_at(100).block_hash).is_some());
+
+        cache.insert(H256::random(), success_at(103));
+        assert!(cache.get(old_op, success_at(100).block_hash).is_none());
+
+        // Results older than the TTL are not stored at all
+        cache.insert(old_op, success_at(100));
+        assert!(cache.get(old_op, success_at(100).block_hash).is_none());
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut cache = SimulationCache::new(2, 2);
+        let block_hash = success_at(100).block_hash;
+        let [first, second, third] = [H256::random(), H256::random(), H256::random()];
+        cache.insert(first, success_at(100));
+        cache.insert(second, success_at(100));
+        // Using the first entry makes the second the least recently used
+        assert!(cache.get(first, block_hash).is_some());
+        cache.insert(third, success_at(100));
+
+        assert!(cache.get(first, block_hash).is_some());
+        assert!(cache.get(second, block_hash).is_none());
+        assert!(cache.get(third, block_hash).is_some());
+    }
+}
@@ -0,0 +1,28 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::fmt::Debug;
+
+use rundler_types::UserOperation;
+
+use super::SimulationViolation;
+
+/// A deployment-specific check run on each operation before it is traced.
+///
+/// Prechecks are run in the order they were added to the simulator, and the
+/// first failure rejects the operation without tracing it.
+pub trait CustomPrecheck: Debug + Send + Sync + 'static {
+    /// Return the violation to reject the operation with, if any
+    #[allow(clippy::result_large_err)]
+    fn check(&self, op: &UserOperation) -> Result<(), SimulationViolation>;
+}
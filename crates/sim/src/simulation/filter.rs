@@ -0,0 +1,26 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::fmt::Debug;
+
+use super::SimulationViolation;
+
+/// Post-processes the violations found during simulation before they are
+/// checked against the mempool rules.
+///
+/// This lets operators drop, replace or add violations based on rules that
+/// can't be expressed in the simulation settings.
+pub trait ViolationFilter: Debug + Send + Sync + 'static {
+    /// Return the violations that should be used in place of those found
+    fn filter(&self, violations: Vec<SimulationViolation>) -> Vec<SimulationViolation>;
+}
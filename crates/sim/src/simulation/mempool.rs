@@ -29,6 +29,24 @@ pub struct MempoolConfig {
     allowlist: Vec<AllowlistEntry>,
 }
 
+impl MempoolConfig {
+    /// Check if the allowlist allows the given violation. A storage access
+    /// violation is allowed only if every slot it reports is allowed by some
+    /// entry.
+    fn is_allowed(&self, violation: &SimulationViolation) -> bool {
+        if let SimulationViolation::InvalidStorageAccess(entity, address, slots) = violation {
+            return slots.iter().all(|slot| {
+                self.allowlist
+                    .iter()
+                    .any(|entry| entry.is_storage_slot_allowed(entity, *address, *slot))
+            });
+        }
+        self.allowlist
+            .iter()
+            .any(|entry| entry.is_allowed(violation))
+    }
+}
+
 /// The entity allowed by an allowlist entry.
 #[derive(Debug, Copy, Clone)]
 enum AllowEntity {
@@ -111,6 +129,7 @@ impl AllowlistEntry {
                     violation_entity,
                     violation_contract,
                     violation_opcode,
+                    _,
                 ) = violation
                 {
                     self.entity.is_allowed(violation_entity)
@@ -137,13 +156,16 @@ impl AllowlistEntry {
                     false
                 }
             }
-            AllowRule::InvalidStorageAccess { contract, slot } => {
-                if let SimulationViolation::InvalidStorageAccess(violation_entity, violation_slot) =
-                    violation
+            AllowRule::InvalidStorageAccess { .. } => {
+                if let SimulationViolation::InvalidStorageAccess(
+                    violation_entity,
+                    violation_address,
+                    violation_slots,
+                ) = violation
                 {
-                    self.entity.is_allowed(violation_entity)
-                        && *contract == violation_slot.address
-                        && *slot == violation_slot.slot
+                    violation_slots.iter().all(|slot| {
+                        self.is_storage_slot_allowed(violation_entity, *violation_address, *slot)
+                    })
                 } else {
                     false
                 }
@@ -156,7 +178,11 @@ impl AllowlistEntry {
                 }
             }
             AllowRule::NotStaked => {
-                if let SimulationViolation::NotStaked(violation_entity, _, _) = violation {
+                if let SimulationViolation::NotStaked {
+                    entity: violation_entity,
+                    ..
+                } = violation
+                {
                     self.entity.is_allowed(violation_entity)
                 } else {
                     false
@@ -164,6 +190,18 @@ impl AllowlistEntry {
             }
         }
     }
+
+    /// Check if the allowlist entry allows the entity to access the given
+    /// storage slot.
+    fn is_storage_slot_allowed(&self, entity: &Entity, address: Address, slot: U256) -> bool {
+        match &self.rule {
+            AllowRule::InvalidStorageAccess {
+                contract,
+                slot: allowed_slot,
+            } => self.entity.is_allowed(entity) && *contract == address && *allowed_slot == slot,
+            _ => false,
+        }
+    }
 }
 
 /// Return value for matching mempools
@@ -184,12 +222,7 @@ pub(crate) fn match_mempools(
 ) -> MempoolMatchResult {
     let mut candidate_pools: Vec<H256> = mempools.keys().cloned().collect();
     for (i, violation) in violations.iter().enumerate() {
-        candidate_pools.retain(|p| {
-            mempools[p]
-                .allowlist
-                .iter()
-                .any(|r| r.is_allowed(violation))
-        });
+        candidate_pools.retain(|p| mempools[p].is_allowed(violation));
         if candidate_pools.is_empty() {
             return MempoolMatchResult::NoMatch(i);
         }
@@ -200,7 +233,6 @@ pub(crate) fn match_mempools(
 #[cfg(test)]
 mod tests {
     use ethers::types::U256;
-    use rundler_types::StorageSlot;
 
     use super::*;
     use crate::simulation::ViolationOpCode;
@@ -293,6 +325,7 @@ mod tests {
             },
             contract,
             ViolationOpCode(Opcode::GAS),
+            1,
         );
         assert!(entry.is_allowed(&violation));
 
@@ -303,6 +336,7 @@ mod tests {
             },
             contract,
             ViolationOpCode(Opcode::BLOCKHASH),
+            1,
         );
         assert!(!entry.is_allowed(&violation));
     }
@@ -356,10 +390,6 @@ mod tests {
         let entity_addr = Address::random();
         let slot_addr = Address::random();
         let slot_slot = U256::from(1234567890);
-        let slot = StorageSlot {
-            address: slot_addr,
-            slot: slot_slot,
-        };
         let entry = AllowlistEntry::new(
             AllowEntity::Address(entity_addr),
             AllowRule::InvalidStorageAccess {
@@ -373,7 +403,8 @@ mod tests {
                 kind: EntityType::Account,
                 address: entity_addr,
             },
-            slot,
+            slot_addr,
+            vec![slot_slot],
         );
         assert!(entry.is_allowed(&violation));
 
@@ -382,7 +413,8 @@ mod tests {
                 kind: EntityType::Account,
                 address: Address::random(),
             },
-            slot,
+            slot_addr,
+            vec![slot_slot],
         );
         assert!(!entry.is_allowed(&violation));
 
@@ -391,10 +423,8 @@ mod tests {
                 kind: EntityType::Account,
                 address: entity_addr,
             },
-            StorageSlot {
-                address: slot_addr,
-                slot: U256::from(0),
-            },
+            slot_addr,
+            vec![U256::from(0)],
         );
         assert!(!entry.is_allowed(&violation));
 
@@ -403,12 +433,36 @@ mod tests {
                 kind: EntityType::Account,
                 address: entity_addr,
             },
-            StorageSlot {
-                address: Address::random(),
-                slot: U256::from(1234567890),
+            Address::random(),
+            vec![slot_slot],
+        );
+        assert!(!entry.is_allowed(&violation));
+
+        // the rule covers only one of the slots accessed at the address
+        let violation = SimulationViolation::InvalidStorageAccess(
+            Entity {
+                kind: EntityType::Account,
+                address: entity_addr,
             },
+            slot_addr,
+            vec![slot_slot, U256::from(0)],
         );
         assert!(!entry.is_allowed(&violation));
+
+        // the slots are all covered once another rule allows the other slot
+        let config = MempoolConfig {
+            allowlist: vec![
+                entry,
+                AllowlistEntry::new(
+                    AllowEntity::Address(entity_addr),
+                    AllowRule::InvalidStorageAccess {
+                        contract: slot_addr,
+                        slot: U256::from(0),
+                    },
+                ),
+            ],
+        };
+        assert!(config.is_allowed(&violation));
     }
 
     #[test]
@@ -435,24 +489,28 @@ mod tests {
         let entity_addr = Address::random();
         let entry = AllowlistEntry::new(AllowEntity::Address(entity_addr), AllowRule::NotStaked);
 
-        let violation = SimulationViolation::NotStaked(
-            Entity {
+        let violation = SimulationViolation::NotStaked {
+            entity: Entity {
                 kind: EntityType::Account,
                 address: entity_addr,
             },
-            U256::from(0),
-            U256::from(0),
-        );
+            actual_stake: U256::from(0),
+            required_stake: U256::from(0),
+            actual_delay: U256::from(0),
+            required_delay: U256::from(0),
+        };
         assert!(entry.is_allowed(&violation));
 
-        let violation = SimulationViolation::NotStaked(
-            Entity {
+        let violation = SimulationViolation::NotStaked {
+            entity: Entity {
                 kind: EntityType::Account,
                 address: Address::random(),
             },
-            U256::from(0),
-            U256::from(0),
-        );
+            actual_stake: U256::from(0),
+            required_stake: U256::from(0),
+            actual_delay: U256::from(0),
+            required_delay: U256::from(0),
+        };
         assert!(!entry.is_allowed(&violation));
     }
 
@@ -481,6 +539,7 @@ mod tests {
             },
             contract,
             ViolationOpCode(Opcode::BLOCKHASH),
+            1,
         );
         assert_eq!(
             match_mempools(&mempools, &[violation]),
@@ -514,6 +573,7 @@ mod tests {
                 },
                 contract,
                 ViolationOpCode(Opcode::GAS),
+                1,
             ),
             SimulationViolation::UsedForbiddenOpcode(
                 Entity {
@@ -522,6 +582,7 @@ mod tests {
                 },
                 contract,
                 ViolationOpCode(Opcode::BLOCKHASH),
+                1,
             ),
         ];
         assert_eq!(
@@ -557,6 +618,7 @@ mod tests {
             },
             contract,
             ViolationOpCode(Opcode::GAS),
+            1,
         )];
         assert_eq!(
             match_mempools(&mempools, &violations),
@@ -630,6 +692,7 @@ mod tests {
                 },
                 contract,
                 ViolationOpCode(Opcode::GAS),
+                1,
             ),
             SimulationViolation::UsedForbiddenOpcode(
                 Entity {
@@ -638,6 +701,7 @@ mod tests {
                 },
                 contract,
                 ViolationOpCode(Opcode::BASEFEE),
+                1,
             ),
         ];
 
@@ -16,14 +16,55 @@ mod simulation;
 #[cfg(feature = "test-utils")]
 pub use simulation::MockSimulator;
 pub use simulation::{
-    Settings, SimulationError, SimulationSuccess, SimulationViolation, Simulator, SimulatorImpl,
-    ViolationOpCode,
+    detect_read_after_write, is_stale, AggregatorFailurePolicy, EvmVersion, GasLimitMode,
+    GasOverrides, MempoolRevalidationResult, PhasedViolation, RestrictedOpcodes, Settings,
+    SimulationDiff, SimulationError, SimulationRequest, SimulationSuccess, SimulationViolation,
+    Simulator, SimulatorImpl, SlotAccess, StorageConflict, ViolationOpCode,
 };
 
+mod aggregator;
+pub use aggregator::{AggregatorValidator, ProviderAggregatorValidator};
+
+mod block;
+pub use block::{BlockSource, CurrentBlock, InMemoryBlockSource, ProviderBlockSource};
+
+mod cache;
+
+mod custom_precheck;
+pub use custom_precheck::CustomPrecheck;
+
+mod filter;
+pub use filter::ViolationFilter;
+
 mod mempool;
 pub use mempool::MempoolConfig;
 
+#[cfg(feature = "otel")]
+mod otel;
+
+mod paymaster;
+pub use paymaster::{PaymasterContextDecoder, TokenCharge};
+
+mod pool_decision;
+pub use pool_decision::{PoolDecision, ReputationChecker};
+
+mod sink;
+pub use sink::SimulationSink;
+
+mod stake;
+pub use stake::StakeResolver;
+
+mod stats;
+pub use stats::SimulationStats;
+
 mod tracer;
-pub use tracer::{SimulateValidationTracer, SimulateValidationTracerImpl};
+pub use tracer::{
+    RecordedSimulateValidationTracer, SimulateValidationTracer, SimulateValidationTracerImpl,
+    SimulationTracerOutput,
+};
+
+mod unstake_delay;
+pub use unstake_delay::UnstakeDelaySource;
 
 mod validation_results;
+pub use validation_results::{AggregatorInfo, StakeInfo, ValidationOutput, ValidationReturnInfo};
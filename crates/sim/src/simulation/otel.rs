@@ -0,0 +1,164 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use opentelemetry::{trace::get_active_span, KeyValue};
+use rundler_types::{Entity, EntityType};
+
+use super::{SimulationViolation, ViolationOpCode};
+
+/// Record each violation as a `violation` event on the active span.
+pub(crate) fn record_violations(violations: &[SimulationViolation]) {
+    get_active_span(|span| {
+        for violation in violations {
+            span.add_event("violation", violation_attributes(violation));
+        }
+    });
+}
+
+fn violation_attributes(violation: &SimulationViolation) -> Vec<KeyValue> {
+    let mut attributes = vec![KeyValue::new("violation", violation.to_string())];
+    if let Some(rule_id) = violation.rule_id() {
+        attributes.push(KeyValue::new("rule_id", rule_id));
+    }
+    match violation {
+        SimulationViolation::UsedForbiddenOpcode(entity, _, opcode, _)
+        | SimulationViolation::RestrictedOpcode(entity, opcode) => {
+            add_entity(&mut attributes, entity);
+            add_opcode(&mut attributes, opcode);
+        }
+        SimulationViolation::InvalidStorageAccess(entity, address, slots) => {
+            add_entity(&mut attributes, entity);
+            attributes.push(KeyValue::new("slot_address", format!("{address:?}")));
+            attributes.push(KeyValue::new(
+                "slots",
+                slots
+                    .iter()
+                    .map(|slot| format!("{slot:#x}"))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ));
+        }
+        SimulationViolation::UsedForbiddenPrecompile(entity, _, _)
+        | SimulationViolation::AccessedUndeployedContract(entity, _)
+        | SimulationViolation::TransientStorageAccess(entity)
+        | SimulationViolation::UsedBlockHash(entity)
+        | SimulationViolation::CalledHandleOps(entity)
+        | SimulationViolation::CalledBannedEntryPointMethod(entity)
+        | SimulationViolation::CallHadValue(entity)
+        | SimulationViolation::NotStaked { entity, .. }
+        | SimulationViolation::OutOfGas(entity) => add_entity(&mut attributes, entity),
+        SimulationViolation::FactoryCalledCreate2Twice(address)
+        | SimulationViolation::FactoryNotDeployed(address)
+        | SimulationViolation::FactoryDenied(address) => {
+            add_entity(&mut attributes, &Entity::factory(*address))
+        }
+        SimulationViolation::SenderDenied(address) => {
+            add_entity(&mut attributes, &Entity::account(*address))
+        }
+        SimulationViolation::PaymasterDenied(address) => {
+            add_entity(&mut attributes, &Entity::paymaster(*address))
+        }
+        SimulationViolation::AggregatorReturnedEmptySignature(address) => {
+            add_entity(&mut attributes, &Entity::aggregator(*address))
+        }
+        SimulationViolation::UnintendedRevertWithMessage(kind, _, address) => {
+            add_entity_type(&mut attributes, *kind);
+            if let Some(address) = address {
+                attributes.push(KeyValue::new("address", format!("{address:?}")));
+            }
+        }
+        SimulationViolation::UnintendedRevert(kind) => add_entity_type(&mut attributes, *kind),
+        SimulationViolation::InvalidSignature
+        | SimulationViolation::CodeHashChanged(_)
+        | SimulationViolation::DidNotRevert(_)
+        | SimulationViolation::WrongNumberOfPhases(_)
+        | SimulationViolation::TooManyAccessedAddresses(_)
+        | SimulationViolation::OpGasExceedsBlockLimit(_, _)
+        | SimulationViolation::AggregatorValidationFailed
+        | SimulationViolation::CustomPrecheckFailed(_)
+        | SimulationViolation::TruncatedViolations(_) => {}
+    }
+    attributes
+}
+
+fn add_entity(attributes: &mut Vec<KeyValue>, entity: &Entity) {
+    add_entity_type(attributes, entity.kind);
+    attributes.push(KeyValue::new("address", format!("{:?}", entity.address)));
+}
+
+fn add_entity_type(attributes: &mut Vec<KeyValue>, kind: EntityType) {
+    attributes.push(KeyValue::new("entity", kind.to_string()));
+}
+
+fn add_opcode(attributes: &mut Vec<KeyValue>, opcode: &ViolationOpCode) {
+    attributes.push(KeyValue::new("opcode", opcode.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Address, Opcode, U256};
+    use opentelemetry::trace::{Tracer, TracerProvider as _};
+    use opentelemetry_sdk::{testing::trace::new_test_exporter, trace::TracerProvider};
+
+    use super::*;
+
+    #[test]
+    fn test_record_violations() {
+        let (exporter, spans, _) = new_test_exporter();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter)
+            .build();
+        let tracer = provider.tracer("test");
+
+        let account = Address::random();
+        let paymaster = Address::random();
+        tracer.in_span("simulation", |_| {
+            record_violations(&[
+                SimulationViolation::UsedForbiddenOpcode(
+                    Entity::account(account),
+                    account,
+                    ViolationOpCode(Opcode::GASPRICE),
+                    1,
+                ),
+                SimulationViolation::InvalidStorageAccess(
+                    Entity::paymaster(paymaster),
+                    account,
+                    vec![U256::from(1)],
+                ),
+            ])
+        });
+
+        let span = spans.recv().unwrap();
+        let events: Vec<_> = span.events.iter().collect();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.name == "violation"));
+        assert!(events[0]
+            .attributes
+            .contains(&KeyValue::new("entity", "account")));
+        assert!(events[0]
+            .attributes
+            .contains(&KeyValue::new("address", format!("{account:?}"))));
+        assert!(events[0]
+            .attributes
+            .contains(&KeyValue::new("opcode", "GASPRICE")));
+        assert!(events[1]
+            .attributes
+            .contains(&KeyValue::new("entity", "paymaster")));
+        assert!(events[1]
+            .attributes
+            .contains(&KeyValue::new("slot_address", format!("{account:?}"))));
+        assert!(events[1]
+            .attributes
+            .contains(&KeyValue::new("slots", "0x1")));
+    }
+}
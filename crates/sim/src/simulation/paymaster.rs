@@ -0,0 +1,36 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::fmt::Debug;
+
+use ethers::types::{Address, Bytes, U256};
+
+/// The token a paymaster will charge for an operation, and the amount charged
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TokenCharge {
+    /// Address of the ERC-20 token charged by the paymaster
+    pub token: Address,
+    /// Amount of the token charged
+    pub amount: U256,
+}
+
+/// Decodes the context returned by a paymaster during validation into the
+/// token charge for the operation.
+///
+/// Paymasters that charge in ERC-20 tokens each use their own context layout,
+/// so operators provide a decoder for the paymasters they want to report on.
+pub trait PaymasterContextDecoder: Debug + Send + Sync + 'static {
+    /// Decode the paymaster context, returning `None` if the paymaster is not
+    /// recognized or the context does not describe a token charge.
+    fn decode(&self, paymaster: Address, context: &Bytes) -> Option<TokenCharge>;
+}
@@ -0,0 +1,50 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::fmt::Debug;
+
+use ethers::types::H256;
+use rundler_types::Entity;
+
+use super::{SimulationError, SimulationSuccess};
+
+/// Outcome of `Simulator::evaluate_for_pool`, telling the pool what to do
+/// with an incoming operation
+#[derive(Debug)]
+pub enum PoolDecision {
+    /// The operation passed simulation and should be added to the pool
+    Accept(SimulationSuccess),
+    /// The operation should not be added to the pool
+    Reject(SimulationError),
+    /// The operation passed simulation and replaces the pool's operation
+    /// with the given hash, which should be removed
+    Replace(SimulationSuccess, H256),
+    /// The operation has the sender and nonce of the pool's operation with
+    /// the given hash, but does not raise its fees enough to replace it, so
+    /// it was not simulated
+    ReplacementUnderpriced(H256),
+    /// The operation uses an entity that is currently throttled, so it was
+    /// not simulated
+    Throttle(Entity),
+}
+
+/// Checks the reputation of the entities used by an operation before it is
+/// simulated for the pool.
+///
+/// This is implemented by the pool, which tracks the reputation of each
+/// entity from the operations it has seen and included.
+pub trait ReputationChecker: Debug + Send + Sync + 'static {
+    /// Return true if operations using the entity should not currently be
+    /// accepted
+    fn is_throttled(&self, entity: &Entity) -> bool;
+}
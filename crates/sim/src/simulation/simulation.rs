@@ -12,36 +12,76 @@
 // If not, see https://www.gnu.org/licenses/.
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    future::Future,
     mem,
     ops::Deref,
+    path::PathBuf,
+    str::FromStr,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
+use anyhow::Context;
 use async_trait::async_trait;
 use ethers::{
-    abi::AbiDecode,
-    types::{Address, BlockId, Opcode, H256, U256},
+    abi::{self, AbiDecode, AbiEncode, Token},
+    types::{
+        spoof, Address, BlockId, BlockNumber, Bytes, DiffMode, Eip1559TransactionRequest,
+        GethDebugBuiltInTracerConfig, GethDebugBuiltInTracerType, GethDebugTracerConfig,
+        GethDebugTracerType, GethDebugTracingCallOptions, GethDebugTracingOptions, GethTrace,
+        GethTraceFrame, Opcode, PreStateConfig, PreStateFrame, H256, U256,
+    },
+    utils::keccak256,
 };
-use indexmap::IndexSet;
+use futures_util::future;
+use indexmap::IndexMap;
 #[cfg(feature = "test-utils")]
 use mockall::automock;
+use parking_lot::Mutex;
 use rundler_provider::{AggregatorOut, AggregatorSimOut, Provider};
 use rundler_types::{
-    contracts::i_entry_point::FailedOp, Entity, EntityType, StorageSlot, UserOperation,
+    contracts::{
+        i_entry_point::{FailedOp, GetDepositInfoCall, GetDepositInfoReturn, HandleOpsCall},
+        simple_account::{ExecuteBatchCall, ExecuteCall},
+    },
+    Entity, EntityType, EntryPointVersion, OpKind, StorageSlot, Timestamp, UserOperation,
     ValidTimeRange,
 };
+use rundler_utils::math;
+use tokio::{
+    fs,
+    sync::{OnceCell, Semaphore},
+};
+use tokio_util::sync::CancellationToken;
 
+#[cfg(feature = "otel")]
+use super::otel;
+#[cfg(any(test, feature = "test-utils"))]
+use super::tracer::RecordedSimulateValidationTracer;
 use super::{
+    aggregator::{AggregatorValidator, ProviderAggregatorValidator},
+    block::{BlockSource, CurrentBlock, ProviderBlockSource},
+    cache::SimulationCache,
+    custom_precheck::CustomPrecheck,
+    filter::ViolationFilter,
     mempool::{match_mempools, MempoolConfig, MempoolMatchResult},
+    paymaster::{PaymasterContextDecoder, TokenCharge},
+    pool_decision::{PoolDecision, ReputationChecker},
+    sink::SimulationSink,
+    stake::StakeResolver,
+    stats::{SimulationCounters, SimulationStats},
     tracer::{
         parse_combined_tracer_str, AssociatedSlotsByAddress, SimulateValidationTracer,
         SimulationTracerOutput, StorageAccess,
     },
-    validation_results::{StakeInfo, ValidationOutput, ValidationReturnInfo},
+    unstake_delay::UnstakeDelaySource,
+    validation_results::{
+        valid_time_range_from_validation_data, StakeInfo, ValidationOutput, ValidationReturnInfo,
+    },
 };
 use crate::{
-    types::{ExpectedStorage, ViolationError},
+    types::{is_valid_replacement, ExpectedStorage, ViolationError},
     utils,
 };
 
@@ -52,25 +92,86 @@ pub struct SimulationSuccess {
     pub mempools: Vec<H256>,
     /// Block hash this operation was simulated against
     pub block_hash: H256,
+    /// Number of the block this operation was simulated against
+    pub block_number: u64,
     /// Gas used in the pre-op phase of simulation measured
     /// by the entry point
     pub pre_op_gas: U256,
-    /// The time range for which this operation is valid
+    /// The time range for which this operation is valid, the intersection of
+    /// the account's and paymaster's ranges
     pub valid_time_range: ValidTimeRange,
+    /// The time range returned by the account's validation
+    pub account_valid_range: ValidTimeRange,
+    /// The time range returned by the paymaster's validation, if the
+    /// operation uses a paymaster
+    pub paymaster_valid_range: Option<ValidTimeRange>,
+    /// True if the paymaster's time range ends within
+    /// `Settings::min_validity_window_secs` of the simulation block, so the
+    /// operation may expire before it can be bundled. This is informational,
+    /// the operation is still valid.
+    pub paymaster_window_ending_soon: bool,
     /// If using an aggregator, the result of the aggregation
     /// simulation
     pub aggregator: Option<AggregatorSimOut>,
     /// Code hash of all accessed contracts
     pub code_hash: H256,
+    /// Code hash of each accessed contract, if requested with
+    /// `SimulationRequest::want_code_hashes`
+    pub code_hashes: HashMap<Address, H256>,
     /// List of used entities that need to be staked for this operation
     /// to be valid
     pub entities_needing_stake: Vec<EntityType>,
     /// Whether the sender account is staked
     pub account_is_staked: bool,
+    /// The sender's nonce on the entry point after this operation, i.e. the
+    /// nonce of the sender's next operation with the same key
+    pub sender_nonce_after: U256,
+    /// The key of the operation's nonce, see `UserOperation::nonce_key`
+    pub nonce_key: U256,
     /// List of all addresses accessed during validation
     pub accessed_addresses: HashSet<Address>,
     /// Expected storage values for all accessed slots during validation
     pub expected_storage: ExpectedStorage,
+    /// Addresses of all contracts created during validation, by `CREATE` or
+    /// `CREATE2` in any phase. For a valid operation this is at most the
+    /// sender, created by the factory.
+    pub created_contracts: Vec<Address>,
+    /// If a paymaster context decoder is configured and recognizes the
+    /// paymaster's context, the token charged by the paymaster
+    pub token_charge: Option<TokenCharge>,
+    /// True if the code hash and aggregator checks did not finish before the
+    /// soft deadline. In that case `code_hash` is zero, `code_hashes` is empty,
+    /// `aggregator` is `None` and the expected code hash has not been checked.
+    pub partial: bool,
+    /// Violations that were allowed by the settings but are still worth
+    /// surfacing, e.g. a failed aggregator signature check under
+    /// `AggregatorFailurePolicy::Warn`
+    pub warnings: Vec<SimulationViolation>,
+    /// Storage slots read and written by each contract during each validation
+    /// phase, in phase order: factory, account, then paymaster. Accesses made
+    /// by the entry point itself are not included.
+    pub storage_accesses: Vec<HashMap<Address, SlotAccess>>,
+    /// Opcodes subject to the validation rules that were used by any entity
+    /// during validation, e.g. restricted opcodes allowed by the settings
+    pub opcodes_used: BTreeSet<Opcode>,
+}
+
+/// Storage slots of a single contract accessed during validation
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SlotAccess {
+    /// Slots loaded with `SLOAD`
+    pub reads: HashSet<U256>,
+    /// Slots stored to with `SSTORE`
+    pub writes: HashSet<U256>,
+}
+
+impl From<&StorageAccess> for SlotAccess {
+    fn from(access: &StorageAccess) -> Self {
+        Self {
+            reads: access.read_slots.iter().copied().collect(),
+            writes: access.written_slots.iter().copied().collect(),
+        }
+    }
 }
 
 impl SimulationSuccess {
@@ -78,11 +179,367 @@ impl SimulationSuccess {
     pub fn aggregator_address(&self) -> Option<Address> {
         self.aggregator.as_ref().map(|agg| agg.address)
     }
+
+    /// Classify the simulated operation, including whether validation
+    /// selected an aggregator for it
+    pub fn op_kind(&self, op: &UserOperation) -> OpKind {
+        OpKind {
+            is_aggregated: self.aggregator.is_some(),
+            ..op.classify()
+        }
+    }
+
+    /// Storage slots read and written by each contract across all validation
+    /// phases. A slot that was both loaded and stored appears in both sets.
+    pub fn storage_access_report(&self) -> HashMap<Address, SlotAccess> {
+        let mut report = HashMap::<Address, SlotAccess>::new();
+        for (&address, access) in self.storage_accesses.iter().flatten() {
+            let entry = report.entry(address).or_default();
+            entry.reads.extend(&access.reads);
+            entry.writes.extend(&access.writes);
+        }
+        report
+    }
+
+    /// Hash of the operation's validation behavior: the addresses it
+    /// accessed, the entities that needed stake and the opcodes it used.
+    ///
+    /// The components are sorted before hashing, so two operations that
+    /// behave identically during validation share a fingerprint regardless
+    /// of the block, the order of accesses or any other field.
+    pub fn behavior_fingerprint(&self) -> H256 {
+        let mut addresses: Vec<_> = self.accessed_addresses.iter().copied().collect();
+        addresses.sort();
+        let mut entities: Vec<_> = self
+            .entities_needing_stake
+            .iter()
+            .map(|kind| kind.to_string())
+            .collect();
+        entities.sort();
+        entities.dedup();
+        let encoded = abi::encode(&[
+            Token::Array(addresses.into_iter().map(Token::Address).collect()),
+            Token::Array(entities.into_iter().map(Token::String).collect()),
+            Token::Bytes(self.opcodes_used.iter().map(|&op| op as u8).collect()),
+        ]);
+        H256(keccak256(encoded))
+    }
+
+    /// Compare this result with a later simulation of the same operation,
+    /// reporting how the validation behavior changed.
+    pub fn diff(&self, other: &SimulationSuccess) -> SimulationDiff {
+        SimulationDiff {
+            added_accessed_addresses: other
+                .accessed_addresses
+                .difference(&self.accessed_addresses)
+                .copied()
+                .collect(),
+            removed_accessed_addresses: self
+                .accessed_addresses
+                .difference(&other.accessed_addresses)
+                .copied()
+                .collect(),
+            added_entities_needing_stake: other
+                .entities_needing_stake
+                .iter()
+                .filter(|kind| !self.entities_needing_stake.contains(kind))
+                .copied()
+                .collect(),
+            removed_entities_needing_stake: self
+                .entities_needing_stake
+                .iter()
+                .filter(|kind| !other.entities_needing_stake.contains(kind))
+                .copied()
+                .collect(),
+            changed_expected_storage_slots: self
+                .expected_storage
+                .changed_slots(&other.expected_storage)
+                .into_iter()
+                .map(|(address, slot)| StorageSlot {
+                    address,
+                    slot: U256::from(slot.as_bytes()),
+                })
+                .collect(),
+            code_hash_changed: self.code_hash != other.code_hash,
+            changed_code_hash_addresses: changed_code_hash_addresses(
+                &self.code_hashes,
+                &other.code_hashes,
+            ),
+            account_staked_changed: self.account_is_staked != other.account_is_staked,
+        }
+    }
+}
+
+/// Hash of the ABI-encoded operation, including its signature. Keys the
+/// result cache and the traces written to `Settings::trace_dump_dir`, so that
+/// operations differing only in their signature are never confused.
+fn encoded_op_key(op: &UserOperation) -> H256 {
+    H256(keccak256(op.clone().encode()))
+}
+
+/// Key of an operation's results in the result cache: the hash of the
+/// ABI-encoded operation and the chain it is validated for.
+fn result_cache_key(op: &UserOperation, chain_id: u64) -> H256 {
+    let mut encoded = op.clone().encode();
+    encoded.extend_from_slice(&chain_id.to_be_bytes());
+    H256(keccak256(encoded))
+}
+
+/// Returns true if `success` was simulated against a block more than
+/// `max_age_blocks` blocks behind `current_block`.
+pub fn is_stale(success: &SimulationSuccess, current_block: u64, max_age_blocks: u64) -> bool {
+    current_block.saturating_sub(success.block_number) > max_age_blocks
+}
+
+/// A storage slot written during the validation of one operation in a
+/// bundle and read during the validation of a later one, so that whether the
+/// later operation is valid depends on the order of the bundle
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StorageConflict {
+    /// Index in the bundle of the operation that writes the slot
+    pub writer_index: usize,
+    /// Sender of the operation that writes the slot
+    pub writer: Address,
+    /// Index in the bundle of the later operation that reads the slot
+    pub reader_index: usize,
+    /// Sender of the operation that reads the slot
+    pub reader: Address,
+    /// The slot
+    pub slot: StorageSlot,
+}
+
+/// Find the slots that an operation of a bundle reads during validation
+/// after an earlier operation of the bundle writes them, so that the builder
+/// can reorder or drop operations. `successes` are the simulation results of
+/// `ops`, in bundle order, and an error is returned if their lengths differ.
+///
+/// Conflicts are sorted by reader, then writer, then slot.
+pub fn detect_read_after_write(
+    successes: &[SimulationSuccess],
+    ops: &[UserOperation],
+) -> anyhow::Result<Vec<StorageConflict>> {
+    anyhow::ensure!(
+        successes.len() == ops.len(),
+        "got {} simulation results for {} operations",
+        successes.len(),
+        ops.len()
+    );
+    let reports: Vec<_> = successes
+        .iter()
+        .zip(ops)
+        .map(|(success, op)| (op.sender, success.storage_access_report()))
+        .collect();
+    let mut conflicts = vec![];
+    for (reader_index, (reader, reads)) in reports.iter().enumerate() {
+        for (writer_index, (writer, writes)) in reports[..reader_index].iter().enumerate() {
+            for (&address, write_access) in writes {
+                let Some(read_access) = reads.get(&address) else {
+                    continue;
+                };
+                for &slot in write_access.writes.intersection(&read_access.reads) {
+                    conflicts.push(StorageConflict {
+                        writer_index,
+                        writer: *writer,
+                        reader_index,
+                        reader: *reader,
+                        slot: StorageSlot { address, slot },
+                    });
+                }
+            }
+        }
+    }
+    conflicts.sort_by_key(|conflict| (conflict.reader_index, conflict.writer_index, conflict.slot));
+    Ok(conflicts)
+}
+
+/// The result of revalidating the operations in a mempool at a new block
+#[derive(Debug, Default)]
+pub struct MempoolRevalidationResult {
+    /// Operations that are still valid, with their new simulation results
+    pub valid: Vec<(UserOperation, SimulationSuccess)>,
+    /// Operations that now violate the validation rules
+    pub rejected: Vec<(UserOperation, SimulationError)>,
+    /// Operations that could not be simulated, e.g. because the node returned
+    /// an error, or whose simulation only returned a partial result. Their
+    /// previous results are stale and they should be revalidated again later.
+    pub stale: Vec<UserOperation>,
+}
+
+/// The changes between two simulations of the same user operation
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SimulationDiff {
+    /// Addresses accessed only by the later simulation
+    pub added_accessed_addresses: HashSet<Address>,
+    /// Addresses accessed only by the earlier simulation
+    pub removed_accessed_addresses: HashSet<Address>,
+    /// Entities that need stake only in the later simulation
+    pub added_entities_needing_stake: Vec<EntityType>,
+    /// Entities that need stake only in the earlier simulation
+    pub removed_entities_needing_stake: Vec<EntityType>,
+    /// Storage slots whose expected values differ between the simulations
+    pub changed_expected_storage_slots: Vec<StorageSlot>,
+    /// Whether the code hash of the accessed contracts changed
+    pub code_hash_changed: bool,
+    /// Accessed contracts whose code changed, if both simulations were
+    /// run with `SimulationRequest::want_code_hashes`
+    pub changed_code_hash_addresses: Vec<Address>,
+    /// Whether the staking status of the sender account changed
+    pub account_staked_changed: bool,
+}
+
+impl SimulationDiff {
+    /// Returns true if the simulations did not differ
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+// The contracts with a code hash in both maps that differs between them,
+// sorted so that the result is deterministic
+fn changed_code_hash_addresses(
+    before: &HashMap<Address, H256>,
+    after: &HashMap<Address, H256>,
+) -> Vec<Address> {
+    let mut addresses: Vec<_> = before
+        .iter()
+        .filter(|(address, hash)| {
+            after
+                .get(address)
+                .is_some_and(|after_hash| after_hash != *hash)
+        })
+        .map(|(address, _)| *address)
+        .collect();
+    addresses.sort();
+    addresses
 }
 
 /// The result of a failed simulation
 pub type SimulationError = ViolationError<SimulationViolation>;
 
+/// Controls which of the optional checks are run after the validation rules
+/// have been evaluated.
+///
+/// Skipping a check saves a round-trip to the node, at the cost of the
+/// corresponding fields of `SimulationSuccess` not being populated.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SimulationRequest {
+    /// Compute the code hash of all accessed contracts. If false,
+    /// `SimulationSuccess::code_hash` is zero and the expected code hash is not checked.
+    pub want_code_hash: bool,
+    /// Validate the signature with the aggregator, if one is used. If false,
+    /// `SimulationSuccess::aggregator` is `None`.
+    pub want_aggregator_sig: bool,
+    /// Compute the code hash of each accessed contract, so that a changed
+    /// contract can be identified. If false, `SimulationSuccess::code_hashes`
+    /// is empty.
+    pub want_code_hashes: bool,
+    /// The code hash of each contract accessed by an earlier simulation, as
+    /// returned in its `SimulationSuccess::code_hashes`. If the expected code
+    /// hash no longer matches, `SimulationViolation::CodeHashChanged` names
+    /// the contracts whose code differs from these.
+    pub expected_code_hashes: HashMap<Address, H256>,
+    /// The gas limits the operation is traced with
+    pub gas_limits: GasLimitMode,
+    /// Ignore the prewarm cache and the head served by the block source, so
+    /// that the block, trace and code hash are always loaded fresh from the
+    /// node and reflect the current chain state. This is useful when auditing
+    /// or debugging a simulation result.
+    pub bypass_caches: bool,
+    /// The chain the operation is validated for, in place of
+    /// `Settings::chain_id`, e.g. for an account that validates operations
+    /// for several chains. The operation is hashed for this chain when
+    /// reporting it to the `SimulationSink`. Unless
+    /// `Settings::allow_chain_id_override` is set, it must match the node's
+    /// chain ID.
+    pub chain_id: Option<u64>,
+    /// Only trace the operation for its gas and validity window, skipping the
+    /// validation rules, the block gas limit check and the code hash and
+    /// aggregator checks. This speeds up gas estimation, but does NOT check
+    /// that the operation is admissible: `SimulationSuccess::mempools`,
+    /// `entities_needing_stake` and `accessed_addresses` are empty.
+    pub gas_only: bool,
+}
+
+/// Which gas limits the operation is traced with during simulation
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum GasLimitMode {
+    /// Trace with the operation's own gas limits, as the entry point will
+    /// when the operation is bundled. Validation that needs more gas than the
+    /// operation's verification gas limit fails, so this is the mode to use
+    /// when admitting operations.
+    #[default]
+    AsProvided,
+    /// Trace with the verification gas limit raised to
+    /// `Settings::max_verification_gas`, so that validation is not limited by
+    /// the operation's own limit and `SimulationSuccess::pre_op_gas` reflects
+    /// the gas validation actually needs. This suits gas estimation. The
+    /// required prefund grows with the limit, so operations should have zero
+    /// fees or enough deposit to cover it. The call gas limit is left as
+    /// provided since the call is not executed during validation.
+    Maximal,
+}
+
+impl GasLimitMode {
+    fn apply(self, op: UserOperation, settings: &Settings) -> UserOperation {
+        match self {
+            Self::AsProvided => op,
+            Self::Maximal => UserOperation {
+                verification_gas_limit: settings.max_verification_gas.into(),
+                ..op
+            },
+        }
+    }
+}
+
+/// Replacement gas fields for `SimulatorImpl::simulate_with_gas_overrides`.
+/// Fields left as `None` keep the operation's own value.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct GasOverrides {
+    /// Replacement call gas limit
+    pub call_gas_limit: Option<U256>,
+    /// Replacement verification gas limit
+    pub verification_gas_limit: Option<U256>,
+    /// Replacement pre-verification gas
+    pub pre_verification_gas: Option<U256>,
+    /// Replacement max fee per gas
+    pub max_fee_per_gas: Option<U256>,
+    /// Replacement max priority fee per gas
+    pub max_priority_fee_per_gas: Option<U256>,
+}
+
+impl GasOverrides {
+    /// Return `op` with the overridden gas fields replaced
+    pub fn apply(self, op: UserOperation) -> UserOperation {
+        UserOperation {
+            call_gas_limit: self.call_gas_limit.unwrap_or(op.call_gas_limit),
+            verification_gas_limit: self
+                .verification_gas_limit
+                .unwrap_or(op.verification_gas_limit),
+            pre_verification_gas: self.pre_verification_gas.unwrap_or(op.pre_verification_gas),
+            max_fee_per_gas: self.max_fee_per_gas.unwrap_or(op.max_fee_per_gas),
+            max_priority_fee_per_gas: self
+                .max_priority_fee_per_gas
+                .unwrap_or(op.max_priority_fee_per_gas),
+            ..op
+        }
+    }
+}
+
+impl Default for SimulationRequest {
+    fn default() -> Self {
+        Self {
+            want_code_hash: true,
+            want_aggregator_sig: true,
+            want_code_hashes: false,
+            expected_code_hashes: HashMap::new(),
+            gas_limits: GasLimitMode::AsProvided,
+            bypass_caches: false,
+            chain_id: None,
+            gas_only: false,
+        }
+    }
+}
+
 /// Simulator trait for running user operation simulations
 #[cfg_attr(feature = "test-utils", automock)]
 #[async_trait::async_trait]
@@ -95,6 +552,67 @@ pub trait Simulator: Send + Sync + 'static {
         block_hash: Option<H256>,
         expected_code_hash: Option<H256>,
     ) -> Result<SimulationSuccess, SimulationError>;
+
+    /// Simulate a user operation, only running the optional checks
+    /// enabled in `request`.
+    async fn simulate_validation_with_request(
+        &self,
+        op: UserOperation,
+        block_hash: Option<H256>,
+        expected_code_hash: Option<H256>,
+        request: SimulationRequest,
+    ) -> Result<SimulationSuccess, SimulationError>;
+
+    /// Simulate a user operation like `simulate_validation_with_request`,
+    /// aborting as soon as `cancel` is cancelled, e.g. because the client that
+    /// requested the simulation disconnected. In-flight calls to the node are
+    /// dropped and `SimulationError::Cancelled` is returned.
+    async fn simulate_validation_with_cancellation(
+        &self,
+        op: UserOperation,
+        block_hash: Option<H256>,
+        expected_code_hash: Option<H256>,
+        request: SimulationRequest,
+        cancel: CancellationToken,
+    ) -> Result<SimulationSuccess, SimulationError>;
+
+    /// Trace the validation of the given operations and compute the code hashes
+    /// of the contracts they access, caching the results so that simulating the
+    /// operations at the same block does not need to fetch them again.
+    async fn prewarm(&self, ops: &[UserOperation], block_id: BlockId) -> anyhow::Result<()>;
+
+    /// Simulate all of the given operations concurrently at the given block,
+    /// as the pool does for its operations on each new block, partitioning
+    /// them by whether they are still valid.
+    async fn revalidate_mempool(
+        &self,
+        ops: Vec<UserOperation>,
+        block_id: BlockId,
+    ) -> MempoolRevalidationResult;
+
+    /// Decide whether the pool should accept `op`, checking the reputation
+    /// of its entities, whether it validly replaces `existing`, the pool's
+    /// operation with the same sender and nonce, and then simulating it at
+    /// the given block. The operations are validated and hashed for
+    /// `chain_id` as in `SimulationRequest::chain_id`. An `existing`
+    /// operation with a different sender or nonce is an error, and a partial
+    /// simulation result is rejected.
+    async fn evaluate_for_pool(
+        &self,
+        op: UserOperation,
+        existing: Option<UserOperation>,
+        block_id: BlockId,
+        chain_id: Option<u64>,
+    ) -> PoolDecision;
+
+    /// Simulate a user operation at the given block and return the entry
+    /// point's decoded validation output, including the stakes of its
+    /// entities, without checking the validation rules.
+    async fn simulate_validation_raw(
+        &self,
+        op: UserOperation,
+        block_id: BlockId,
+    ) -> Result<ValidationOutput, SimulationError>;
 }
 
 /// Simulator implementation.
@@ -113,8 +631,27 @@ pub struct SimulatorImpl<P: Provider, T: SimulateValidationTracer> {
     provider: Arc<P>,
     entry_point_address: Address,
     simulate_validation_tracer: T,
+    // Keys the prewarmed traces, so that they are only reused by the tracer
+    // that produced them
+    tracer_hash: Option<H256>,
     sim_settings: Settings,
     mempool_configs: HashMap<H256, MempoolConfig>,
+    paymaster_context_decoder: Option<Arc<dyn PaymasterContextDecoder>>,
+    stake_resolver: Option<Arc<dyn StakeResolver>>,
+    violation_filter: Option<Arc<dyn ViolationFilter>>,
+    block_source: Arc<dyn BlockSource>,
+    aggregator_validator: Arc<dyn AggregatorValidator>,
+    unstake_delay_source: Option<Arc<dyn UnstakeDelaySource>>,
+    reputation_checker: Option<Arc<dyn ReputationChecker>>,
+    custom_prechecks: Vec<Arc<dyn CustomPrecheck>>,
+    sink: Option<Arc<dyn SimulationSink>>,
+    cache: Mutex<PrewarmCache>,
+    result_cache: Mutex<SimulationCache>,
+    counters: SimulationCounters,
+    rpc_permits: Option<Semaphore>,
+    node_chain_id: OnceCell<u64>,
+    // The head most recently read from the block source
+    head: Mutex<Option<CurrentBlock>>,
 }
 
 impl<P, T> SimulatorImpl<P, T>
@@ -135,33 +672,312 @@ where
         mempool_configs: HashMap<H256, MempoolConfig>,
     ) -> Self {
         Self {
+            block_source: Arc::new(ProviderBlockSource::new(Arc::clone(&provider))),
+            aggregator_validator: Arc::new(ProviderAggregatorValidator::new(Arc::clone(&provider))),
             provider,
             entry_point_address,
+            tracer_hash: simulate_validation_tracer.tracer_hash(),
             simulate_validation_tracer,
-            sim_settings,
             mempool_configs,
+            paymaster_context_decoder: None,
+            stake_resolver: None,
+            violation_filter: None,
+            unstake_delay_source: None,
+            reputation_checker: None,
+            custom_prechecks: vec![],
+            sink: None,
+            cache: Mutex::default(),
+            result_cache: Mutex::new(SimulationCache::new(
+                sim_settings.simulation_cache_size,
+                sim_settings.simulation_cache_ttl_blocks,
+            )),
+            counters: SimulationCounters::default(),
+            rpc_permits: sim_settings.max_rpc_connections.map(Semaphore::new),
+            node_chain_id: OnceCell::new(),
+            head: Mutex::default(),
+            sim_settings,
         }
     }
 
-    /// Return the associated settings
+    /// Use the given decoder to report the token charged by paymasters
+    /// in `SimulationSuccess::token_charge`
+    pub fn with_paymaster_context_decoder(
+        mut self,
+        decoder: Arc<dyn PaymasterContextDecoder>,
+    ) -> Self {
+        self.paymaster_context_decoder = Some(decoder);
+        self
+    }
+
+    /// Use the given resolver to choose the address whose stake is checked
+    /// for each entity
+    pub fn with_stake_resolver(mut self, resolver: Arc<dyn StakeResolver>) -> Self {
+        self.stake_resolver = Some(resolver);
+        self
+    }
+
+    /// Use the given filter to post-process the violations found during
+    /// simulation. Without a filter, violations are used as found.
+    pub fn with_violation_filter(mut self, filter: Arc<dyn ViolationFilter>) -> Self {
+        self.violation_filter = Some(filter);
+        self
+    }
+
+    /// Use the given source for the current block when simulating without a
+    /// block hash. Without a source, the latest block is loaded from the
+    /// provider on each simulation.
+    pub fn with_block_source(mut self, source: Arc<dyn BlockSource>) -> Self {
+        self.block_source = source;
+        self
+    }
+
+    /// Use the given validator to check signatures with the operation's
+    /// aggregator. Without one, the aggregator contract is called through
+    /// the provider.
+    pub fn with_aggregator_validator(mut self, validator: Arc<dyn AggregatorValidator>) -> Self {
+        self.aggregator_validator = validator;
+        self
+    }
+
+    /// Read the minimum unstake delay from the given source, once per block,
+    /// instead of using `Settings::min_unstake_delay`. If the read fails, the
+    /// static setting is used.
+    pub fn with_unstake_delay_source(mut self, source: Arc<dyn UnstakeDelaySource>) -> Self {
+        self.unstake_delay_source = Some(source);
+        self
+    }
+
+    /// Use the given checker in `Simulator::evaluate_for_pool` to throttle
+    /// operations whose entities have a poor reputation. Without one, no
+    /// operation is throttled.
+    pub fn with_reputation_checker(mut self, checker: Arc<dyn ReputationChecker>) -> Self {
+        self.reputation_checker = Some(checker);
+        self
+    }
+
+    /// Run the given check on each operation before it is traced, after any
+    /// prechecks added before it. An operation failing a precheck is rejected
+    /// with the violation it returns.
+    pub fn with_custom_precheck(mut self, precheck: Arc<dyn CustomPrecheck>) -> Self {
+        self.custom_prechecks.push(precheck);
+        self
+    }
+
+    /// Report the result of each simulation run to the given sink, without
+    /// waiting for it. Results served from the result cache are not reported
+    /// again. Without a sink, results are not reported.
+    pub fn with_sink(mut self, sink: Arc<dyn SimulationSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Return the settings the simulator is running with, including any
+    /// restricted opcode overrides
     pub fn settings(&self) -> &Settings {
         &self.sim_settings
     }
 
+    /// Return a snapshot of the simulator's counters, for reporting its health
+    pub fn stats(&self) -> SimulationStats {
+        self.counters.snapshot()
+    }
+
+    /// Simulate validation as if the sender's code were `sender_code`,
+    /// regardless of what is deployed at the sender address.
+    ///
+    /// This lets account developers test their validation logic against a
+    /// reference implementation without deploying it. The code is replaced
+    /// with a state override on the traced call, so the result is never
+    /// served from or stored in the prewarm cache.
+    pub async fn simulate_with_sender_code(
+        &self,
+        op: UserOperation,
+        sender_code: Bytes,
+        block_hash: Option<H256>,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        let mut state_overrides = spoof::State::default();
+        state_overrides.account(op.sender).code(sender_code);
+        self.simulate_validation_inner(
+            op,
+            block_hash,
+            None,
+            SimulationRequest::default(),
+            Some(state_overrides),
+        )
+        .await
+    }
+
+    /// Simulate validation of `op` with its gas fields replaced by
+    /// `overrides`, e.g. to preview whether raising an operation's fees
+    /// leaves its sender or paymaster able to pay the prefund.
+    ///
+    /// The signature is not updated, so accounts that sign over the gas
+    /// fields will report a signature failure for the overridden operation.
+    pub async fn simulate_with_gas_overrides(
+        &self,
+        op: UserOperation,
+        overrides: GasOverrides,
+        block_id: BlockId,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        let block_hash = self.block_hash_of(block_id).await?;
+        self.simulate_validation(overrides.apply(op), Some(block_hash), None)
+            .await
+    }
+
+    /// Simulate validation of `op` at each of the given block numbers
+    /// concurrently, returning the results in the same order.
+    ///
+    /// An operation whose validity depends on changing state can pass at one
+    /// block and fail at the next. Comparing the results, e.g. with
+    /// `SimulationSuccess::diff`, shows whether it validates consistently.
+    pub async fn simulate_validation_across_blocks(
+        &self,
+        op: UserOperation,
+        block_numbers: Vec<u64>,
+    ) -> Vec<Result<SimulationSuccess, SimulationError>> {
+        future::join_all(block_numbers.into_iter().map(|block_number| {
+            let op = op.clone();
+            async move {
+                let block_hash = self
+                    .block_hash_of(BlockNumber::Number(block_number.into()).into())
+                    .await?;
+                self.simulate_validation(op, Some(block_hash), None).await
+            }
+        }))
+        .await
+    }
+
+    /// Simulate the validation of `op` against the state left behind by
+    /// `prior_ops`, as if they were executed before it in the same bundle.
+    ///
+    /// The prior operations are traced as a single `handleOps` call, and the
+    /// state it changes is applied to the traced call as state overrides.
+    /// This lets an operation that depends on an earlier one in the bundle,
+    /// such as a second operation from the same sender, be validated
+    /// correctly. Prior operations are traced without their aggregators, and
+    /// the result is never served from or stored in the prewarm cache.
+    pub async fn simulate_validation_after(
+        &self,
+        prior_ops: &[UserOperation],
+        op: UserOperation,
+        block_hash: Option<H256>,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        let block_hash = match block_hash {
+            Some(block_hash) => block_hash,
+            None => self.current_block(false).await?.hash,
+        };
+        let state_overrides = if prior_ops.is_empty() {
+            None
+        } else {
+            Some(self.trace_state_after(prior_ops, block_hash.into()).await?)
+        };
+        self.simulate_validation_inner(
+            op,
+            Some(block_hash),
+            None,
+            SimulationRequest::default(),
+            state_overrides,
+        )
+        .await
+    }
+
+    /// Simulate the validation of each operation in turn at the same block,
+    /// returning a result for each in order.
+    ///
+    /// Once `Settings::batch_node_failure_limit` operations in a row fail
+    /// with a provider error, the node is assumed to be down and the
+    /// remaining operations fail with `SimulationError::NodeUnavailable`
+    /// without being simulated.
+    pub async fn simulate_validation_batch(
+        &self,
+        ops: Vec<UserOperation>,
+        block_hash: Option<H256>,
+    ) -> Vec<Result<SimulationSuccess, SimulationError>> {
+        let mut results = Vec::with_capacity(ops.len());
+        let mut consecutive_node_failures = 0;
+        for op in ops {
+            if consecutive_node_failures >= self.sim_settings.batch_node_failure_limit {
+                results.push(Err(SimulationError::NodeUnavailable));
+                continue;
+            }
+            let result = self.simulate_validation(op, block_hash, None).await;
+            if matches!(result, Err(SimulationError::Provider(_))) {
+                consecutive_node_failures += 1;
+            } else {
+                consecutive_node_failures = 0;
+            }
+            results.push(result);
+        }
+        results
+    }
+
+    // Trace `handleOps` on the given operations and return the state it
+    // changes as state overrides.
+    async fn trace_state_after(
+        &self,
+        ops: &[UserOperation],
+        block_id: BlockId,
+    ) -> anyhow::Result<spoof::State> {
+        let tx = Eip1559TransactionRequest {
+            to: Some(self.entry_point_address.into()),
+            gas: Some(self.sim_settings.max_simulate_handle_ops_gas.into()),
+            data: Some(
+                HandleOpsCall {
+                    ops: ops.to_vec(),
+                    beneficiary: Address::zero(),
+                }
+                .encode()
+                .into(),
+            ),
+            ..Default::default()
+        };
+        let trace = self
+            .with_rpc_permit(self.provider.debug_trace_call(
+                tx.into(),
+                Some(block_id),
+                GethDebugTracingCallOptions {
+                    tracing_options: GethDebugTracingOptions {
+                        tracer: Some(GethDebugTracerType::BuiltInTracer(
+                            GethDebugBuiltInTracerType::PreStateTracer,
+                        )),
+                        tracer_config: Some(GethDebugTracerConfig::BuiltInTracer(
+                            GethDebugBuiltInTracerConfig::PreStateTracer(PreStateConfig {
+                                diff_mode: Some(true),
+                            }),
+                        )),
+                        ..Default::default()
+                    },
+                    state_overrides: None,
+                },
+            ))
+            .await?;
+        let diff = match trace {
+            GethTrace::Known(GethTraceFrame::PreStateTracer(PreStateFrame::Diff(diff))) => diff,
+            GethTrace::Unknown(value) => serde_json::from_value::<DiffMode>(value)
+                .context("prior ops trace should be a prestate diff")?,
+            _ => anyhow::bail!("prior ops trace should be a prestate diff"),
+        };
+        state_overrides_from_diff(diff)
+    }
+
     // Run the tracer and transform the output.
     // Any violations during this stage are errors.
     async fn create_context(
         &self,
         op: UserOperation,
         block_id: BlockId,
+        state_overrides: Option<spoof::State>,
+        bypass_caches: bool,
     ) -> Result<ValidationContext, SimulationError> {
         let factory_address = op.factory();
         let sender_address = op.sender;
         let paymaster_address = op.paymaster();
-        let tracer_out = self
-            .simulate_validation_tracer
-            .trace_simulate_validation(op.clone(), block_id, self.sim_settings.max_verification_gas)
-            .await?;
+        let (tracer_out, resolved_stake_infos, min_unstake_delay) = tokio::join!(
+            self.trace_simulate_validation(op.clone(), block_id, state_overrides, bypass_caches),
+            self.resolve_stake_infos(&op, block_id),
+            self.min_unstake_delay(block_id)
+        );
+        let tracer_out = tracer_out?;
         let num_phases = tracer_out.phases.len() as u32;
         // Check if there are too many phases here, then check too few at the
         // end. We are detecting cases where the entry point is broken. Too many
@@ -172,7 +988,9 @@ where
             Err(vec![SimulationViolation::WrongNumberOfPhases(num_phases)])?
         }
         let Some(ref revert_data) = tracer_out.revert_data else {
-            Err(vec![SimulationViolation::DidNotRevert])?
+            let return_data = Bytes::from_str(&tracer_out.return_data)
+                .context("tracer should return valid return data")?;
+            Err(vec![SimulationViolation::DidNotRevert(return_data)])?
         };
         let last_entity = entity_type_from_simulation_phase(tracer_out.phases.len() - 1).unwrap();
 
@@ -189,15 +1007,16 @@ where
                 entity_addr,
             )])?
         }
-        let Ok(entry_point_out) = ValidationOutput::decode_hex(revert_data) else {
+        let Ok(mut entry_point_out) = ValidationOutput::decode_hex(revert_data) else {
             Err(vec![SimulationViolation::UnintendedRevert(last_entity)])?
         };
+        resolved_stake_infos?.apply(&mut entry_point_out);
         let entity_infos = EntityInfos::new(
             factory_address,
             sender_address,
             paymaster_address,
             &entry_point_out,
-            self.sim_settings,
+            |info| self.is_staked(info, min_unstake_delay),
         );
         let is_unstaked_wallet_creation = entity_infos
             .get(EntityType::Factory)
@@ -214,50 +1033,483 @@ where
             is_unstaked_wallet_creation,
             entities_needing_stake: vec![],
             accessed_addresses: HashSet::new(),
+            min_unstake_delay,
         })
     }
 
-    async fn validate_aggregator_signature(
+    // The minimum unstake delay at the given block, from the unstake delay
+    // source if one is configured
+    async fn min_unstake_delay(&self, block_id: BlockId) -> u32 {
+        let Some(source) = &self.unstake_delay_source else {
+            return self.sim_settings.min_unstake_delay;
+        };
+        if let Some(delay) = self
+            .cache
+            .lock()
+            .get(block_id)
+            .and_then(|cache| cache.min_unstake_delay)
+        {
+            return delay;
+        }
+        match self
+            .with_rpc_permit(source.min_unstake_delay(block_id))
+            .await
+        {
+            Ok(delay) => {
+                if let BlockId::Hash(block_hash) = block_id {
+                    self.cache.lock().at_block(block_hash).min_unstake_delay = Some(delay);
+                }
+                delay
+            }
+            Err(error) => {
+                tracing::warn!("failed to read min unstake delay, using static setting: {error:#}");
+                self.sim_settings.min_unstake_delay
+            }
+        }
+    }
+
+    // Use the tracer output from prewarming if available, otherwise run the
+    // tracer. Prewarmed traces were run without state overrides, so they are
+    // only used when there are none.
+    async fn trace_simulate_validation(
         &self,
         op: UserOperation,
-        aggregator_address: Option<Address>,
-        gas_cap: u64,
-    ) -> anyhow::Result<AggregatorOut> {
-        let Some(aggregator_address) = aggregator_address else {
-            return Ok(AggregatorOut::NotNeeded);
+        block_id: BlockId,
+        state_overrides: Option<spoof::State>,
+        bypass_caches: bool,
+    ) -> anyhow::Result<SimulationTracerOutput> {
+        if state_overrides.is_none() && !bypass_caches {
+            let cached = self
+                .cache
+                .lock()
+                .get(block_id)
+                .and_then(|cache| cache.tracer_outs.remove(&(self.tracer_hash, op.clone())));
+            self.counters.record_trace_cache_lookup(cached.is_some());
+            if let Some(tracer_out) = cached {
+                self.dump_trace(&op, &tracer_out).await;
+                return Ok(tracer_out);
+            }
+        }
+        let start = Instant::now();
+        let tracer_out = self
+            .with_rpc_permit(self.simulate_validation_tracer.trace_simulate_validation(
+                op.clone(),
+                block_id,
+                self.sim_settings.max_verification_gas,
+                state_overrides,
+            ))
+            .await?;
+        self.counters.record_trace(start.elapsed());
+        self.dump_trace(&op, &tracer_out).await;
+        Ok(tracer_out)
+    }
+
+    // Write the tracer output to the dump directory, if configured. Failures
+    // are logged rather than failing the simulation.
+    async fn dump_trace(&self, op: &UserOperation, tracer_out: &SimulationTracerOutput) {
+        let Some(dir) = &self.sim_settings.trace_dump_dir else {
+            return;
+        };
+        let path = dir.join(format!("{:?}.json", encoded_op_key(op)));
+        let result = match serde_json::to_vec_pretty(tracer_out) {
+            Ok(json) => fs::write(&path, json)
+                .await
+                .context("should write tracer output"),
+            Err(error) => Err(error).context("should serialize tracer output"),
         };
+        if let Err(error) = result {
+            tracing::warn!("failed to dump trace to {}: {error:#}", path.display());
+        }
+    }
 
-        Ok(self
-            .provider
-            .clone()
-            .validate_user_op_signature(aggregator_address, op, gas_cap)
-            .await?)
+    // Use the code hash from prewarming if available, otherwise compute it
+    async fn get_code_hash(
+        &self,
+        mut addresses: Vec<Address>,
+        block_id: BlockId,
+        bypass_caches: bool,
+    ) -> anyhow::Result<H256> {
+        addresses.sort();
+        let cached = if bypass_caches {
+            None
+        } else {
+            self.cache
+                .lock()
+                .get(block_id)
+                .and_then(|cache| cache.code_hashes.get(&addresses).copied())
+        };
+        match cached {
+            Some(code_hash) => Ok(code_hash),
+            None => {
+                self.with_rpc_permit(utils::get_code_hash(
+                    self.provider.deref(),
+                    addresses,
+                    Some(block_id),
+                ))
+                .await
+            }
+        }
     }
 
-    // Parse the output from tracing and return a list of violations.
-    // Most violations found during this stage are allowlistable and can be added
-    // to the list of allowlisted violations on a given mempool.
-    fn gather_context_violations(
+    async fn get_code_hashes(
         &self,
-        context: &mut ValidationContext,
-    ) -> anyhow::Result<Vec<SimulationViolation>> {
-        let &mut ValidationContext {
-            ref entity_infos,
-            ref tracer_out,
-            ref entry_point_out,
-            is_unstaked_wallet_creation,
-            ref mut entities_needing_stake,
-            ref mut accessed_addresses,
-            ..
-        } = context;
+        addresses: &[Address],
+        block_hash: H256,
+    ) -> anyhow::Result<HashMap<Address, H256>> {
+        let codes = future::try_join_all(addresses.iter().map(|&address| {
+            self.with_rpc_permit(self.provider.get_code(address, Some(block_hash)))
+        }))
+        .await
+        .context("should load code of accessed contracts")?;
+        Ok(addresses
+            .iter()
+            .zip(codes)
+            .map(|(&address, code)| (address, H256(keccak256(code))))
+            .collect())
+    }
 
-        let mut violations = vec![];
+    // The configured chain ID, or the node's if none is configured
+    async fn chain_id(&self) -> Result<u64, SimulationError> {
+        if self.sim_settings.chain_id != 0 {
+            return Ok(self.sim_settings.chain_id);
+        }
+        Ok(*self
+            .node_chain_id
+            .get_or_try_init(|| self.with_rpc_permit(self.provider.get_chain_id()))
+            .await?)
+    }
 
-        if entry_point_out.return_info.sig_failed {
-            violations.push(SimulationViolation::InvalidSignature);
+    // The chain ID given with a request, or the default one
+    async fn request_chain_id(&self, chain_id: Option<u64>) -> Result<u64, SimulationError> {
+        match chain_id {
+            Some(chain_id) => Ok(chain_id),
+            None => self.chain_id().await,
         }
+    }
 
-        let sender_address = entity_infos.sender_address();
+    // Simulate with the default request for the given chain, reusing the
+    // result of an earlier simulation of the same operation at the same block
+    async fn simulate_validation_cached(
+        &self,
+        op: UserOperation,
+        block_hash: Option<H256>,
+        expected_code_hash: Option<H256>,
+        chain_id: Option<u64>,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        let request = SimulationRequest {
+            chain_id,
+            ..Default::default()
+        };
+        if self.sim_settings.simulation_cache_size == 0 {
+            return self
+                .simulate_validation_with_request(op, block_hash, expected_code_hash, request)
+                .await;
+        }
+        let block_hash = match block_hash {
+            Some(block_hash) => block_hash,
+            None => self.current_block(false).await?.hash,
+        };
+        let op_key = result_cache_key(&op, self.request_chain_id(chain_id).await?);
+        let cached = self.result_cache.lock().get(op_key, block_hash);
+        self.counters.record_result_cache_lookup(cached.is_some());
+        // A cached result with a different code hash is simulated again so
+        // that the mismatch is reported as usual
+        if let Some(success) = cached.filter(|success| {
+            expected_code_hash.map_or(true, |code_hash| code_hash == success.code_hash)
+        }) {
+            return Ok(success);
+        }
+        let result = self
+            .simulate_validation_with_request(op, Some(block_hash), expected_code_hash, request)
+            .await;
+        if let Ok(success) = &result {
+            if !success.partial {
+                self.result_cache.lock().insert(op_key, success.clone());
+            }
+        }
+        result
+    }
+
+    // Check that a chain ID given with the request is the node's, unless
+    // overrides are allowed
+    async fn check_chain_id(&self, chain_id: Option<u64>) -> Result<(), SimulationError> {
+        let Some(chain_id) = chain_id else {
+            return Ok(());
+        };
+        if self.sim_settings.allow_chain_id_override {
+            return Ok(());
+        }
+        let node_chain_id = *self
+            .node_chain_id
+            .get_or_try_init(|| self.with_rpc_permit(self.provider.get_chain_id()))
+            .await?;
+        if chain_id != node_chain_id {
+            return Err(anyhow::anyhow!(
+                "chain id {chain_id} does not match the node's chain id {node_chain_id}"
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // Check that the block simulated at is still the chain's block at its
+    // height
+    async fn check_reorg(
+        &self,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Result<(), SimulationError> {
+        let canonical_hash = self
+            .with_rpc_permit(
+                self.provider
+                    .get_block(BlockNumber::Number(block_number.into())),
+            )
+            .await?
+            .and_then(|block| block.hash);
+        if canonical_hash != Some(block_hash) {
+            return Err(SimulationError::ReorgDetected);
+        }
+        Ok(())
+    }
+
+    // Check that the factory creating the sender has code at the block, as
+    // tracing a creation with an undeployed factory can only fail with a
+    // revert that doesn't say why
+    async fn check_factory_deployed(
+        &self,
+        op: &UserOperation,
+        block_hash: H256,
+    ) -> Result<Option<SimulationViolation>, SimulationError> {
+        let Some(factory) = op.factory() else {
+            return Ok(None);
+        };
+        let code = self
+            .with_rpc_permit(self.provider.get_code(factory, Some(block_hash)))
+            .await?;
+        Ok(code
+            .is_empty()
+            .then_some(SimulationViolation::FactoryNotDeployed(factory)))
+    }
+
+    // Read the head from the block source, remembering it so that a
+    // simulation at that block doesn't load it again. When bypassing caches,
+    // the latest block is loaded from the node instead, as the block source
+    // may serve a head it has cached.
+    async fn current_block(&self, bypass_caches: bool) -> anyhow::Result<CurrentBlock> {
+        let head = if bypass_caches {
+            self.with_rpc_permit(self.provider.get_block(BlockNumber::Latest))
+                .await?
+                .context("latest block should exist")?
+                .try_into()?
+        } else {
+            self.with_rpc_permit(self.block_source.current_block())
+                .await?
+        };
+        *self.head.lock() = Some(head);
+        Ok(head)
+    }
+
+    // The block with the given hash, taken from the last head read if it is
+    // that block, otherwise loaded from the node
+    async fn block_at(
+        &self,
+        block_hash: H256,
+        bypass_caches: bool,
+    ) -> anyhow::Result<CurrentBlock> {
+        if let Some(head) = self
+            .head
+            .lock()
+            .filter(|head| !bypass_caches && head.hash == block_hash)
+        {
+            return Ok(head);
+        }
+        self.with_rpc_permit(self.provider.get_block(block_hash))
+            .await?
+            .context("simulation block should exist")?
+            .try_into()
+    }
+
+    // The hash of the operation for the configured entry point version
+    fn op_hash(&self, op: &UserOperation, chain_id: u64) -> H256 {
+        op.op_hash_for_version(
+            self.sim_settings.entry_point_version,
+            self.entry_point_address,
+            chain_id,
+        )
+    }
+
+    async fn block_hash_of(&self, block_id: BlockId) -> anyhow::Result<H256> {
+        if let BlockId::Hash(block_hash) = block_id {
+            return Ok(block_hash);
+        }
+        self.with_rpc_permit(self.provider.get_block(block_id))
+            .await?
+            .context("block should exist")?
+            .hash
+            .context("block should have a hash")
+    }
+
+    async fn prewarm_op(&self, op: UserOperation, block_hash: H256) -> anyhow::Result<()> {
+        let block_id = block_hash.into();
+        let tracer_out = self
+            .with_rpc_permit(self.simulate_validation_tracer.trace_simulate_validation(
+                op.clone(),
+                block_id,
+                self.sim_settings.max_verification_gas,
+                None,
+            ))
+            .await?;
+        let mut addresses = tracer_out.accessed_contract_addresses.clone();
+        addresses.sort();
+        let code_hash = self
+            .with_rpc_permit(utils::get_code_hash(
+                self.provider.deref(),
+                addresses.clone(),
+                Some(block_id),
+            ))
+            .await?;
+
+        let mut cache = self.cache.lock();
+        let cache = cache.at_block(block_hash);
+        cache.tracer_outs.insert((self.tracer_hash, op), tracer_out);
+        cache.code_hashes.insert(addresses, code_hash);
+        Ok(())
+    }
+
+    // Wait until fewer than `Settings::max_rpc_connections` calls to the
+    // node are in flight, then make the call while holding a permit
+    async fn with_rpc_permit<F: Future>(&self, call: F) -> F::Output {
+        let _permit = match &self.rpc_permits {
+            Some(permits) => Some(
+                permits
+                    .acquire()
+                    .await
+                    .expect("semaphore should not be closed"),
+            ),
+            None => None,
+        };
+        call.await
+    }
+
+    // Load the stake info of the address the stake resolver remaps each
+    // entity to. This only depends on the operation, so it runs concurrently
+    // with the trace.
+    async fn resolve_stake_infos(
+        &self,
+        op: &UserOperation,
+        block_id: BlockId,
+    ) -> anyhow::Result<ResolvedStakeInfos> {
+        let Some(resolver) = &self.stake_resolver else {
+            return Ok(ResolvedStakeInfos::default());
+        };
+        let resolve = |entity: Option<Entity>| async move {
+            let Some(entity) = entity else {
+                return Ok(None);
+            };
+            match resolver.resolve(entity) {
+                Some(address) if address != entity.address => {
+                    self.get_stake_info(address, block_id).await.map(Some)
+                }
+                _ => Ok(None),
+            }
+        };
+        let (factory, sender, paymaster) = tokio::try_join!(
+            resolve(op.factory().map(Entity::factory)),
+            resolve(Some(Entity::account(op.sender))),
+            resolve(op.paymaster().map(Entity::paymaster)),
+        )?;
+        Ok(ResolvedStakeInfos {
+            factory,
+            sender,
+            paymaster,
+        })
+    }
+
+    async fn get_stake_info(
+        &self,
+        address: Address,
+        block_id: BlockId,
+    ) -> anyhow::Result<StakeInfo> {
+        let tx = Eip1559TransactionRequest {
+            to: Some(self.entry_point_address.into()),
+            data: Some(GetDepositInfoCall { account: address }.encode().into()),
+            ..Default::default()
+        };
+        let out = self
+            .with_rpc_permit(self.provider.call(&tx.into(), Some(block_id)))
+            .await?;
+        let GetDepositInfoReturn { info } = GetDepositInfoReturn::decode(out)?;
+        Ok(info.into())
+    }
+
+    async fn validate_aggregator_signature(
+        &self,
+        op: UserOperation,
+        aggregator_address: Option<Address>,
+        gas_cap: u64,
+    ) -> anyhow::Result<AggregatorOut> {
+        let Some(aggregator_address) = aggregator_address else {
+            return Ok(AggregatorOut::NotNeeded);
+        };
+
+        self.with_rpc_permit(self.aggregator_validator.validate_user_op_signature(
+            aggregator_address,
+            op,
+            gas_cap,
+        ))
+        .await
+    }
+
+    // Parse the output from tracing and return a list of violations.
+    // Most violations found during this stage are allowlistable and can be added
+    // to the list of allowlisted violations on a given mempool.
+    fn gather_context_violations(
+        &self,
+        context: &mut ValidationContext,
+    ) -> anyhow::Result<Vec<SimulationViolation>> {
+        Ok(self
+            .gather_phased_violations(context)?
+            .into_iter()
+            .map(|phased| phased.violation)
+            .collect())
+    }
+
+    // Like `gather_context_violations`, but keeps the index of the phase
+    // each violation was found in
+    fn gather_phased_violations(
+        &self,
+        context: &mut ValidationContext,
+    ) -> anyhow::Result<Vec<PhasedViolation>> {
+        let &mut ValidationContext {
+            ref entity_infos,
+            ref tracer_out,
+            ref entry_point_out,
+            is_unstaked_wallet_creation,
+            ref mut entities_needing_stake,
+            ref mut accessed_addresses,
+            min_unstake_delay,
+            ..
+        } = context;
+
+        let mut violations = vec![];
+        // The phase index of each violation, filled in as each phase is done
+        let mut phase_indexes = vec![];
+
+        if entry_point_out.return_info.sig_failed {
+            violations.push(SimulationViolation::InvalidSignature);
+        }
+        phase_indexes.resize(violations.len(), None);
+
+        let sender_address = entity_infos.sender_address();
+        let evm_version = self.sim_settings.evm_version;
+        let max_accessed_addresses = self.sim_settings.max_accessed_addresses;
+        let mut too_many_accessed_addresses = false;
+        // The entry point deploys the sender through its SenderCreator, whose
+        // own execution in the factory phase isn't the factory's behavior
+        let is_sender_creator = |address: Address| {
+            !self.sim_settings.strict_spec_mode
+                && self.sim_settings.sender_creator_address == Some(address)
+        };
 
         for (index, phase) in tracer_out.phases.iter().enumerate().take(3) {
             let kind = entity_type_from_simulation_phase(index).unwrap();
@@ -268,55 +1520,119 @@ where
                 kind,
                 address: entity_info.address,
             };
-            for opcode in &phase.forbidden_opcodes_used {
-                let (contract, opcode) = parse_combined_tracer_str(opcode)?;
+            for combined in &phase.forbidden_opcodes_used {
+                let (contract, opcode) = parse_combined_tracer_str(combined)?;
+                if !evm_version.has_opcode(opcode) || is_sender_creator(contract) {
+                    continue;
+                }
                 violations.push(SimulationViolation::UsedForbiddenOpcode(
                     entity,
                     contract,
                     ViolationOpCode(opcode),
+                    phase.opcode_count(combined),
                 ));
             }
 
+            for combined in &phase.restrictable_opcodes_used {
+                let (contract, opcode) = parse_combined_tracer_str(combined)?;
+                if !evm_version.has_opcode(opcode) || is_sender_creator(contract) {
+                    continue;
+                }
+                match self
+                    .sim_settings
+                    .restricted_opcodes
+                    .is_allowed(kind, opcode)
+                {
+                    Some(true) => {}
+                    Some(false) => {
+                        violations.push(SimulationViolation::RestrictedOpcode(
+                            entity,
+                            ViolationOpCode(opcode),
+                        ));
+                    }
+                    None => {
+                        if !RestrictedOpcodes::allowed_by_default(opcode) {
+                            violations.push(SimulationViolation::UsedForbiddenOpcode(
+                                entity,
+                                contract,
+                                ViolationOpCode(opcode),
+                                phase.opcode_count(combined),
+                            ));
+                        }
+                    }
+                }
+            }
+
+            // A factory may check whether the sender or the entry point is
+            // already deployed before creating the sender.
+            let allow_existence_checks = kind == EntityType::Factory
+                && self.sim_settings.allow_factory_existence_checks
+                && !self.sim_settings.strict_spec_mode;
             for (addr, opcode) in &phase.ext_code_access_info {
-                if *addr == self.entry_point_address {
+                if *addr == self.entry_point_address
+                    && !(allow_existence_checks && *opcode == Opcode::EXTCODESIZE)
+                    && evm_version.has_opcode(*opcode)
+                {
                     violations.push(SimulationViolation::UsedForbiddenOpcode(
                         entity,
                         *addr,
                         ViolationOpCode(*opcode),
+                        1,
                     ));
                 }
             }
 
             for precompile in &phase.forbidden_precompiles_used {
                 let (contract, precompile) = parse_combined_tracer_str(precompile)?;
+                if is_sender_creator(contract) {
+                    continue;
+                }
                 violations.push(SimulationViolation::UsedForbiddenPrecompile(
                     entity, contract, precompile,
                 ));
             }
             let mut needs_stake = entity.kind == EntityType::Paymaster
                 && !entry_point_out.return_info.paymaster_context.is_empty();
-            let mut banned_slots_accessed = IndexSet::<StorageSlot>::new();
-            for StorageAccess { address, slots } in &phase.storage_accesses {
+            // Keyed by address so that an entity touching many banned slots
+            // of one contract reports a single violation
+            let mut banned_slots_accessed = IndexMap::<Address, Vec<U256>>::new();
+            for StorageAccess { address, slots, .. } in &phase.storage_accesses {
                 let address = *address;
-                accessed_addresses.insert(address);
+                let is_allowlisted_token = entity.kind == EntityType::Paymaster
+                    && !self.sim_settings.strict_spec_mode
+                    && self
+                        .sim_settings
+                        .token_paymaster_allowlist
+                        .contains(&(entity_info.address, address));
+                // Stop accumulating once the cap is reached to bound memory use
+                if accessed_addresses.len() < max_accessed_addresses {
+                    accessed_addresses.insert(address);
+                } else if !accessed_addresses.contains(&address) {
+                    too_many_accessed_addresses = true;
+                }
                 for slot in slots {
                     let restriction = get_storage_restriction(GetStorageRestrictionArgs {
                         slots_by_address: &tracer_out.associated_slots_by_address,
                         is_unstaked_wallet_creation,
+                        allow_sender_deposit_during_creation: self
+                            .sim_settings
+                            .allow_sender_deposit_during_creation
+                            && !self.sim_settings.strict_spec_mode,
                         entry_point_address: self.entry_point_address,
                         entity_address: entity_info.address,
                         sender_address,
                         accessed_address: address,
+                        is_allowlisted_token,
                         slot: *slot,
                     });
                     match restriction {
                         StorageRestriction::Allowed => {}
                         StorageRestriction::NeedsStake => needs_stake = true,
                         StorageRestriction::Banned => {
-                            banned_slots_accessed.insert(StorageSlot {
-                                address,
-                                slot: *slot,
-                            });
+                            let banned_slots = banned_slots_accessed.entry(address).or_default();
+                            if !banned_slots.contains(slot) {
+                                banned_slots.push(*slot);
+                            }
                         }
                     }
                 }
@@ -324,15 +1640,33 @@ where
             if needs_stake {
                 entities_needing_stake.push(entity.kind);
                 if !entity_info.is_staked {
-                    violations.push(SimulationViolation::NotStaked(
+                    violations.push(not_staked(
                         entity,
-                        self.sim_settings.min_stake_value.into(),
-                        self.sim_settings.min_unstake_delay.into(),
+                        entity_info.stake_info,
+                        self.sim_settings.min_stake_value,
+                        min_unstake_delay,
                     ));
                 }
             }
-            for slot in banned_slots_accessed {
-                violations.push(SimulationViolation::InvalidStorageAccess(entity, slot));
+            for (address, slots) in banned_slots_accessed {
+                violations.push(SimulationViolation::InvalidStorageAccess(
+                    entity, address, slots,
+                ));
+            }
+            // Before Cancun, TLOAD and TSTORE are undefined and abort the
+            // call instead of accessing transient storage
+            if phase.used_transient_storage
+                && evm_version.has_transient_storage()
+                && !entity_info.is_staked
+            {
+                violations.push(SimulationViolation::TransientStorageAccess(entity));
+            }
+            if phase.used_block_hash
+                && !(entity_info.is_staked
+                    && self.sim_settings.allow_staked_block_hash
+                    && !self.sim_settings.strict_spec_mode)
+            {
+                violations.push(SimulationViolation::UsedBlockHash(entity));
             }
             let non_sender_called_with_value = phase
                 .addresses_calling_with_value
@@ -341,6 +1675,9 @@ where
             if non_sender_called_with_value || phase.called_non_entry_point_with_value {
                 violations.push(SimulationViolation::CallHadValue(entity));
             }
+            if phase.called_handle_ops {
+                violations.push(SimulationViolation::CalledHandleOps(entity));
+            }
             if phase.called_banned_entry_point_method {
                 violations.push(SimulationViolation::CalledBannedEntryPointMethod(entity));
             }
@@ -350,19 +1687,26 @@ where
                 violations.push(SimulationViolation::OutOfGas(entity));
             }
             for &address in &phase.undeployed_contract_accesses {
+                if (allow_existence_checks && address == sender_address)
+                    || is_sender_creator(address)
+                {
+                    continue;
+                }
                 violations.push(SimulationViolation::AccessedUndeployedContract(
                     entity, address,
                 ))
             }
+            phase_indexes.resize(violations.len(), Some(index));
         }
 
         if let Some(aggregator_info) = entry_point_out.aggregator_info {
             entities_needing_stake.push(EntityType::Aggregator);
-            if !is_staked(aggregator_info.stake_info, self.sim_settings) {
-                violations.push(SimulationViolation::NotStaked(
+            if !self.is_staked(aggregator_info.stake_info, min_unstake_delay) {
+                violations.push(not_staked(
                     Entity::aggregator(aggregator_info.address),
-                    self.sim_settings.min_stake_value.into(),
-                    self.sim_settings.min_unstake_delay.into(),
+                    aggregator_info.stake_info,
+                    self.sim_settings.min_stake_value,
+                    min_unstake_delay,
                 ));
             }
         }
@@ -383,19 +1727,43 @@ where
                 }
             }
         }
+        if too_many_accessed_addresses {
+            violations.push(SimulationViolation::TooManyAccessedAddresses(
+                max_accessed_addresses,
+            ));
+        }
+        phase_indexes.resize(violations.len(), None);
 
-        Ok(violations)
+        Ok(violations
+            .into_iter()
+            .zip(phase_indexes)
+            .map(|(violation, phase_index)| PhasedViolation {
+                violation,
+                phase_index,
+            })
+            .collect())
     }
 
     // Check the code hash of the entities associated with the user operation
     // if needed, validate that the signature is valid for the aggregator.
+    // Checks not enabled in the request are skipped.
     // Violations during this stage are always errors.
     async fn check_contracts(
         &self,
         op: UserOperation,
+        block_hash: H256,
         context: &mut ValidationContext,
         expected_code_hash: Option<H256>,
-    ) -> Result<(H256, Option<AggregatorSimOut>), SimulationError> {
+        request: SimulationRequest,
+    ) -> Result<
+        (
+            H256,
+            HashMap<Address, H256>,
+            Option<AggregatorSimOut>,
+            Vec<SimulationViolation>,
+        ),
+        SimulationError,
+    > {
         let &mut ValidationContext {
             block_id,
             ref mut tracer_out,
@@ -406,83 +1774,330 @@ where
         // collect a vector of violations to ensure a deterministic error message
         let mut violations = vec![];
 
-        let aggregator_address = entry_point_out.aggregator_info.map(|info| info.address);
-        let code_hash_future = utils::get_code_hash(
-            self.provider.deref(),
-            mem::take(&mut tracer_out.accessed_contract_addresses),
-            Some(block_id),
-        );
+        let aggregator_address = entry_point_out
+            .aggregator_info
+            .map(|info| info.address)
+            .filter(|_| request.want_aggregator_sig);
+        let accessed_contract_addresses = mem::take(&mut tracer_out.accessed_contract_addresses);
+        let code_hashes_future = async {
+            if !request.want_code_hashes {
+                return Ok(HashMap::new());
+            }
+            self.get_code_hashes(&accessed_contract_addresses, block_hash)
+                .await
+        };
+        let code_hash_future = async {
+            if !request.want_code_hash {
+                return Ok(H256::zero());
+            }
+            self.get_code_hash(
+                accessed_contract_addresses.clone(),
+                block_id,
+                request.bypass_caches,
+            )
+            .await
+        };
         let aggregator_signature_future = self.validate_aggregator_signature(
             op,
             aggregator_address,
             self.sim_settings.max_verification_gas,
         );
 
-        let (code_hash, aggregator_out) =
-            tokio::try_join!(code_hash_future, aggregator_signature_future)?;
+        let (code_hash, code_hashes, aggregator_out) = tokio::try_join!(
+            code_hash_future,
+            code_hashes_future,
+            aggregator_signature_future
+        )?;
 
-        if let Some(expected_code_hash) = expected_code_hash {
+        if let Some(expected_code_hash) = expected_code_hash.filter(|_| request.want_code_hash) {
             if expected_code_hash != code_hash {
-                violations.push(SimulationViolation::CodeHashChanged)
+                // Only load the code of each contract to find the changed
+                // ones when there are earlier hashes to compare against
+                let changed = if request.expected_code_hashes.is_empty() {
+                    vec![]
+                } else if request.want_code_hashes {
+                    changed_code_hash_addresses(&request.expected_code_hashes, &code_hashes)
+                } else {
+                    let code_hashes = self
+                        .get_code_hashes(&accessed_contract_addresses, block_hash)
+                        .await?;
+                    changed_code_hash_addresses(&request.expected_code_hashes, &code_hashes)
+                };
+                violations.push(SimulationViolation::CodeHashChanged(changed))
             }
         }
+        let mut warnings = vec![];
         let aggregator = match aggregator_out {
             AggregatorOut::NotNeeded => None,
+            AggregatorOut::SuccessWithInfo(info) if info.signature.is_empty() => {
+                violations.push(SimulationViolation::AggregatorReturnedEmptySignature(
+                    info.address,
+                ));
+                None
+            }
             AggregatorOut::SuccessWithInfo(info) => Some(info),
             AggregatorOut::ValidationReverted => {
-                violations.push(SimulationViolation::AggregatorValidationFailed);
+                match self.sim_settings.aggregator_failure_policy {
+                    AggregatorFailurePolicy::Reject => {
+                        violations.push(SimulationViolation::AggregatorValidationFailed)
+                    }
+                    AggregatorFailurePolicy::Warn => {
+                        warnings.push(SimulationViolation::AggregatorValidationFailed)
+                    }
+                }
                 None
             }
         };
 
+        let violations = self.filter_violations(violations);
         if !violations.is_empty() {
             return Err(violations.into());
         }
 
-        Ok((code_hash, aggregator))
+        Ok((code_hash, code_hashes, aggregator, warnings))
     }
-}
 
-#[async_trait]
-impl<P, T> Simulator for SimulatorImpl<P, T>
-where
-    P: Provider,
-    T: SimulateValidationTracer,
-{
-    async fn simulate_validation(
+    // Sort violations by priority, keeping at most the configured number and
+    // marking the list as truncated if any were dropped
+    fn cap_violations(&self, mut violations: Vec<SimulationViolation>) -> Vec<SimulationViolation> {
+        violations.sort();
+        let total = violations.len();
+        let max = self.sim_settings.max_violations_returned;
+        if total > max {
+            violations.truncate(max);
+            violations.push(SimulationViolation::TruncatedViolations(total));
+        }
+        violations
+    }
+
+    // The validity windows returned by the account and paymaster, which the
+    // entry point only reports as their intersection
+    fn validity_windows(
+        &self,
+        tracer_out: &SimulationTracerOutput,
+        has_paymaster: bool,
+        block_timestamp: Timestamp,
+    ) -> ValidityWindows {
+        let phase_range = |phase: usize| {
+            tracer_out
+                .phases
+                .get(phase)
+                .and_then(|phase| phase.validation_data)
+                .map(valid_time_range_from_validation_data)
+                .unwrap_or_default()
+        };
+        let paymaster = has_paymaster.then(|| phase_range(2));
+        let min_valid_until =
+            block_timestamp + Duration::from_secs(self.sim_settings.min_validity_window_secs);
+        ValidityWindows {
+            account: phase_range(1),
+            paymaster,
+            paymaster_ending_soon: paymaster
+                .is_some_and(|range| range.valid_until < min_valid_until),
+        }
+    }
+
+    fn filter_violations(&self, violations: Vec<SimulationViolation>) -> Vec<SimulationViolation> {
+        match &self.violation_filter {
+            Some(filter) => filter.filter(violations),
+            None => violations,
+        }
+    }
+
+    // Drop the opcode and storage rule violations of an operation whose
+    // sender is on the trusted app allowlist
+    fn skip_trusted_app_violations(
+        &self,
+        op: &UserOperation,
+        violations: Vec<SimulationViolation>,
+    ) -> Vec<SimulationViolation> {
+        if self.sim_settings.strict_spec_mode || !self.is_trusted_app_call(op) {
+            return violations;
+        }
+        violations
+            .into_iter()
+            .filter(|violation| !is_opcode_or_storage_violation(violation))
+            .collect()
+    }
+
+    // Whether every target the operation's callData calls is allowlisted as
+    // a trusted app for its sender. callData that is not a recognized
+    // `execute` or `executeBatch` call is never trusted.
+    fn is_trusted_app_call(&self, op: &UserOperation) -> bool {
+        let targets = if let Ok(call) = ExecuteCall::decode(&op.call_data) {
+            vec![call.dest]
+        } else if let Ok(call) = ExecuteBatchCall::decode(&op.call_data) {
+            call.dest
+        } else {
+            return false;
+        };
+        !targets.is_empty()
+            && targets.into_iter().all(|target| {
+                self.sim_settings
+                    .trusted_app_allowlist
+                    .contains(&(op.sender, target))
+            })
+    }
+
+    // Check that the operation's summed gas limits fit within the configured
+    // percentage of the block gas limit
+    fn check_block_gas_limit(
+        &self,
+        op: &UserOperation,
+        block_gas_limit: U256,
+    ) -> Option<SimulationViolation> {
+        let total_gas = op
+            .verification_gas_limit
+            .saturating_add(op.call_gas_limit)
+            .saturating_add(op.pre_verification_gas);
+        let max_gas = math::percent(block_gas_limit, self.sim_settings.max_block_gas_percent);
+        (total_gas > max_gas).then_some(SimulationViolation::OpGasExceedsBlockLimit(
+            total_gas,
+            block_gas_limit,
+        ))
+    }
+
+    // Decode the token charged by the paymaster, if a decoder is configured
+    fn decode_token_charge(
+        &self,
+        paymaster_address: Option<Address>,
+        paymaster_context: &Bytes,
+    ) -> Option<TokenCharge> {
+        let decoder = self.paymaster_context_decoder.as_ref()?;
+        let paymaster_address = paymaster_address?;
+        if paymaster_context.is_empty() {
+            return None;
+        }
+        decoder.decode(paymaster_address, paymaster_context)
+    }
+
+    fn check_denied_entities(&self, op: &UserOperation) -> Vec<SimulationViolation> {
+        let mut violations = vec![];
+        if self.sim_settings.denied_senders.contains(&op.sender) {
+            violations.push(SimulationViolation::SenderDenied(op.sender));
+        }
+        if let Some(factory) = op
+            .factory()
+            .filter(|factory| self.sim_settings.denied_factories.contains(factory))
+        {
+            violations.push(SimulationViolation::FactoryDenied(factory));
+        }
+        if let Some(paymaster) = op
+            .paymaster()
+            .filter(|paymaster| self.sim_settings.denied_paymasters.contains(paymaster))
+        {
+            violations.push(SimulationViolation::PaymasterDenied(paymaster));
+        }
+        violations
+    }
+
+    fn is_staked(&self, info: StakeInfo, min_unstake_delay: u32) -> bool {
+        self.sim_settings.assume_all_entities_staked
+            || is_staked(info, self.sim_settings.min_stake_value, min_unstake_delay)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn run_custom_prechecks(&self, op: &UserOperation) -> Result<(), SimulationViolation> {
+        self.custom_prechecks
+            .iter()
+            .try_for_each(|precheck| precheck.check(op))
+    }
+
+    // Run the full simulation, returning any violations as an error
+    async fn simulate_validation_inner(
         &self,
         op: UserOperation,
         block_hash: Option<H256>,
         expected_code_hash: Option<H256>,
+        request: SimulationRequest,
+        state_overrides: Option<spoof::State>,
     ) -> Result<SimulationSuccess, SimulationError> {
+        let violations = self.check_denied_entities(&op);
+        if !violations.is_empty() {
+            return Err(violations.into());
+        }
+        if let Err(violation) = self.run_custom_prechecks(&op) {
+            return Err(vec![violation].into());
+        }
+        self.check_chain_id(request.chain_id).await?;
         let block_hash = match block_hash {
             Some(block_hash) => block_hash,
-            None => self
-                .provider
-                .get_latest_block_hash()
-                .await
-                .map_err(anyhow::Error::from)?,
+            None => self.current_block(request.bypass_caches).await?.hash,
         };
+        if state_overrides.is_none() {
+            if let Some(violation) = self.check_factory_deployed(&op, block_hash).await? {
+                return Err(vec![violation].into());
+            }
+        }
         let block_id = block_hash.into();
-        let mut context = match self.create_context(op.clone(), block_id).await {
+        let paymaster_address = op.paymaster();
+        // Validation consumes the nonce, incrementing the sequence of its key
+        let sender_nonce_after = op.nonce.saturating_add(U256::one());
+        let nonce_key = op.nonce_key();
+        let traced_op = request.gas_limits.apply(op.clone(), &self.sim_settings);
+        let (context, block) = tokio::join!(
+            self.create_context(traced_op, block_id, state_overrides, request.bypass_caches),
+            self.block_at(block_hash, request.bypass_caches)
+        );
+        let mut context = match context {
             Ok(context) => context,
             error @ Err(_) => error?,
         };
+        let block = block?;
+        let block_number = block.number;
+        let validity_windows = self.validity_windows(
+            &context.tracer_out,
+            paymaster_address.is_some(),
+            Timestamp::new(block.timestamp.low_u64()),
+        );
+        let (mempools, code_hash, code_hashes, aggregator, warnings, partial) = if request.gas_only
+        {
+            (vec![], H256::zero(), HashMap::new(), None, vec![], false)
+        } else {
+            if let Some(violation) = self.check_block_gas_limit(&op, block.gas_limit) {
+                return Err(vec![violation].into());
+            }
 
-        // Gather all violations from the tracer
-        let mut violations = self.gather_context_violations(&mut context)?;
-        // Sort violations so that the final error message is deterministic
-        violations.sort();
-        // Check violations against mempool rules, find supporting mempools, error if none found
-        let mempools = match match_mempools(&self.mempool_configs, &violations) {
-            MempoolMatchResult::Matches(pools) => pools,
-            MempoolMatchResult::NoMatch(i) => return Err(vec![violations[i].clone()].into()),
-        };
+            // Gather all violations from the tracer
+            let violations = self.gather_context_violations(&mut context)?;
+            let violations = self.skip_trusted_app_violations(&op, violations);
+            let mut violations = self.filter_violations(violations);
+            // Sort violations so that the final error message is deterministic
+            violations.sort();
+            // Check every violation against mempool rules, find supporting
+            // mempools, error if none found. Only the error is capped, so that
+            // violations past the cap are still matched.
+            let mempools = match match_mempools(&self.mempool_configs, &violations) {
+                MempoolMatchResult::Matches(pools) => pools,
+                MempoolMatchResult::NoMatch(i) => {
+                    return Err(self.cap_violations(vec![violations[i].clone()]).into())
+                }
+            };
 
-        // Check code hash and aggregator signature, these can't fail
-        let (code_hash, aggregator) = self
-            .check_contracts(op, &mut context, expected_code_hash)
-            .await?;
+            // Check code hash and aggregator signature, these can't fail.
+            // If they take longer than the soft deadline, return a partial result.
+            let check_contracts_future =
+                self.check_contracts(op, block_hash, &mut context, expected_code_hash, request);
+            match with_soft_deadline(self.sim_settings.soft_deadline_ms, check_contracts_future)
+                .await
+            {
+                Some(result) => {
+                    let (code_hash, code_hashes, aggregator, warnings) = result?;
+                    (
+                        mempools,
+                        code_hash,
+                        code_hashes,
+                        aggregator,
+                        warnings,
+                        false,
+                    )
+                }
+                None => (mempools, H256::zero(), HashMap::new(), None, vec![], true),
+            }
+        };
+        if self.sim_settings.detect_reorgs {
+            self.check_reorg(block_number, block_hash).await?;
+        }
 
         // Transform outputs into success struct
         let ValidationContext {
@@ -491,6 +2106,7 @@ where
             is_unstaked_wallet_creation: _,
             entities_needing_stake,
             accessed_addresses,
+            min_unstake_delay,
             ..
         } = context;
         let ValidationOutput {
@@ -498,84 +2114,465 @@ where
             sender_info,
             ..
         } = entry_point_out;
-        let account_is_staked = is_staked(sender_info, self.sim_settings);
+        let account_is_staked = self.is_staked(sender_info, min_unstake_delay);
         let ValidationReturnInfo {
             pre_op_gas,
             valid_after,
             valid_until,
+            paymaster_context,
             ..
         } = return_info;
+        let token_charge = self.decode_token_charge(paymaster_address, &paymaster_context);
+        let storage_accesses = tracer_out
+            .phases
+            .iter()
+            .map(|phase| {
+                phase
+                    .storage_accesses
+                    .iter()
+                    .map(|access| (access.address, SlotAccess::from(access)))
+                    .collect()
+            })
+            .collect();
+        let mut opcodes_used = BTreeSet::new();
+        for phase in tracer_out.phases.iter().take(3) {
+            for combined in phase
+                .forbidden_opcodes_used
+                .iter()
+                .chain(&phase.restrictable_opcodes_used)
+            {
+                let (_, opcode): (Address, Opcode) = parse_combined_tracer_str(combined)?;
+                opcodes_used.insert(opcode);
+            }
+        }
         Ok(SimulationSuccess {
             mempools,
             block_hash,
+            block_number,
             pre_op_gas,
             valid_time_range: ValidTimeRange::new(valid_after, valid_until),
+            account_valid_range: validity_windows.account,
+            paymaster_valid_range: validity_windows.paymaster,
+            paymaster_window_ending_soon: validity_windows.paymaster_ending_soon,
             aggregator,
             code_hash,
+            code_hashes,
             entities_needing_stake,
             account_is_staked,
+            sender_nonce_after,
+            nonce_key,
             accessed_addresses,
             expected_storage: tracer_out.expected_storage,
+            created_contracts: tracer_out.created_contracts,
+            token_charge,
+            partial,
+            warnings,
+            storage_accesses,
+            opcodes_used,
         })
     }
 }
 
-/// All possible simulation violations
-#[derive(Clone, Debug, parse_display::Display, Ord, Eq, PartialOrd, PartialEq)]
-pub enum SimulationViolation {
-    // Make sure to maintain the order here based on the importance
-    // of the violation for converting to an JSON RPC error
-    /// The user operation signature is invalid
-    #[display("invalid signature")]
-    InvalidSignature,
-    /// The user operation used an opcode that is not allowed
-    #[display("{0.kind} uses banned opcode: {2} in contract {1:?}")]
-    UsedForbiddenOpcode(Entity, Address, ViolationOpCode),
-    /// The user operation used a precompile that is not allowed
-    #[display("{0.kind} uses banned precompile: {2:?} in contract {1:?}")]
-    UsedForbiddenPrecompile(Entity, Address, Address),
-    /// The user operation accessed a contract that has not been deployed
-    #[display(
-        "{0.kind} tried to access code at {1} during validation, but that address is not a contract"
-    )]
-    AccessedUndeployedContract(Entity, Address),
-    /// The user operation factory entity called CREATE2 more than once during initialization
-    #[display("factory may only call CREATE2 once during initialization")]
-    FactoryCalledCreate2Twice(Address),
-    /// The user operation accessed a storage slot that is not allowed
-    #[display("{0.kind} accessed forbidden storage at address {1:?} during validation")]
-    InvalidStorageAccess(Entity, StorageSlot),
-    /// The user operation called an entry point method that is not allowed
-    #[display("{0.kind} called entry point method other than depositTo")]
-    CalledBannedEntryPointMethod(Entity),
-    /// The user operation made a call that contained value to a contract other than the entrypoint
-    /// during validation
-    #[display("{0.kind} must not send ETH during validation (except from account to entry point)")]
-    CallHadValue(Entity),
-    /// The code hash of accessed contracts changed on the second simulation
-    #[display("code accessed by validation has changed since the last time validation was run")]
-    CodeHashChanged,
-    /// The user operation contained an entity that accessed storage without being staked
-    #[display("{0.kind} must be staked")]
-    NotStaked(Entity, U256, U256),
-    /// Simulation reverted with an unintended reason, containing a message
-    #[display("reverted while simulating {0} validation: {1}")]
-    UnintendedRevertWithMessage(EntityType, String, Option<Address>),
-    /// Simulation reverted with an unintended reason
-    #[display("reverted while simulating {0} validation")]
-    UnintendedRevert(EntityType),
-    /// Simulation did not revert, a revert is always expected
-    #[display("simulateValidation did not revert. Make sure your EntryPoint is valid")]
-    DidNotRevert,
-    /// Simulation had the wrong number of phases
-    #[display("simulateValidation should have 3 parts but had {0} instead. Make sure your EntryPoint is valid")]
-    WrongNumberOfPhases(u32),
-    /// The user operation ran out of gas during validation
-    #[display("ran out of gas during {0.kind} validation")]
-    OutOfGas(Entity),
-    /// The user operation aggregator signature validation failed
+#[cfg(any(test, feature = "test-utils"))]
+impl<P: Provider> SimulatorImpl<P, RecordedSimulateValidationTracer> {
+    /// Create a simulator that replays a trace previously written to
+    /// `Settings::trace_dump_dir` instead of tracing against a node.
+    ///
+    /// Use `recorded_violations` to run the validation rules on the trace.
+    pub fn from_recorded_trace(
+        provider: Arc<P>,
+        entry_point_address: Address,
+        trace: SimulationTracerOutput,
+        sim_settings: Settings,
+    ) -> Self {
+        Self::new(
+            provider,
+            entry_point_address,
+            RecordedSimulateValidationTracer::new(trace),
+            sim_settings,
+            HashMap::new(),
+        )
+    }
+
+    /// Run the validation rules against the recorded trace for the given
+    /// operation, returning the violations found.
+    pub async fn recorded_violations(
+        &self,
+        op: UserOperation,
+    ) -> Result<Vec<SimulationViolation>, SimulationError> {
+        let mut context = self
+            .create_context(op, BlockNumber::Latest.into(), None, false)
+            .await?;
+        let violations = self.gather_context_violations(&mut context)?;
+        Ok(self.cap_violations(violations))
+    }
+
+    /// Run the validation rules against the recorded trace for the given
+    /// operation, returning every violation found with the index of the
+    /// trace phase it was found in, in the order they were found.
+    pub async fn recorded_phased_violations(
+        &self,
+        op: UserOperation,
+    ) -> Result<Vec<PhasedViolation>, SimulationError> {
+        let mut context = self
+            .create_context(op, BlockNumber::Latest.into(), None, false)
+            .await?;
+        Ok(self.gather_phased_violations(&mut context)?)
+    }
+}
+
+#[async_trait]
+impl<P, T> Simulator for SimulatorImpl<P, T>
+where
+    P: Provider,
+    T: SimulateValidationTracer,
+{
+    async fn simulate_validation(
+        &self,
+        op: UserOperation,
+        block_hash: Option<H256>,
+        expected_code_hash: Option<H256>,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        self.simulate_validation_cached(op, block_hash, expected_code_hash, None)
+            .await
+    }
+
+    async fn simulate_validation_with_request(
+        &self,
+        op: UserOperation,
+        block_hash: Option<H256>,
+        expected_code_hash: Option<H256>,
+        request: SimulationRequest,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        let op_hash = match &self.sink {
+            Some(_) => Some(self.op_hash(&op, self.request_chain_id(request.chain_id).await?)),
+            None => None,
+        };
+        let result = self
+            .simulate_validation_inner(op, block_hash, expected_code_hash, request, None)
+            .await;
+        self.counters.record_simulation(&result);
+        #[cfg(feature = "otel")]
+        if let Err(SimulationError::Violations(violations)) = &result {
+            otel::record_violations(violations);
+        }
+        if let (Some(sink), Some(op_hash)) = (&self.sink, op_hash) {
+            let sink = Arc::clone(sink);
+            let result = result.clone();
+            tokio::spawn(async move { sink.on_simulation(op_hash, result).await });
+        }
+        result
+    }
+
+    async fn simulate_validation_with_cancellation(
+        &self,
+        op: UserOperation,
+        block_hash: Option<H256>,
+        expected_code_hash: Option<H256>,
+        request: SimulationRequest,
+        cancel: CancellationToken,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(SimulationError::Cancelled),
+            result = self.simulate_validation_with_request(
+                op,
+                block_hash,
+                expected_code_hash,
+                request,
+            ) => result,
+        }
+    }
+
+    async fn prewarm(&self, ops: &[UserOperation], block_id: BlockId) -> anyhow::Result<()> {
+        // Simulation looks up the cache by block hash, so a block given by
+        // number or tag is resolved first
+        let block_hash = self.block_hash_of(block_id).await?;
+        future::try_join_all(ops.iter().map(|op| self.prewarm_op(op.clone(), block_hash))).await?;
+        Ok(())
+    }
+
+    async fn revalidate_mempool(
+        &self,
+        ops: Vec<UserOperation>,
+        block_id: BlockId,
+    ) -> MempoolRevalidationResult {
+        let block_hash = match self.block_hash_of(block_id).await {
+            Ok(block_hash) => block_hash,
+            Err(error) => {
+                tracing::warn!("failed to load block to revalidate mempool: {error:#}");
+                return MempoolRevalidationResult {
+                    stale: ops,
+                    ..Default::default()
+                };
+            }
+        };
+        // Trace the operations and load their code hashes as a batch. Any
+        // operation that fails here is retried by its own simulation.
+        if let Err(error) = self.prewarm(&ops, block_hash.into()).await {
+            tracing::debug!("failed to prewarm mempool revalidation: {error:#}");
+        }
+        let results = future::join_all(ops.into_iter().map(|op| async move {
+            let result = self
+                .simulate_validation(op.clone(), Some(block_hash), None)
+                .await;
+            (op, result)
+        }))
+        .await;
+
+        let mut revalidation = MempoolRevalidationResult::default();
+        for (op, result) in results {
+            match result {
+                // A partial result skipped the code hash and aggregator
+                // checks, so it is retried rather than counted as valid
+                Ok(success) if success.partial => revalidation.stale.push(op),
+                Ok(success) => revalidation.valid.push((op, success)),
+                Err(error @ SimulationError::Violations(_)) => {
+                    revalidation.rejected.push((op, error))
+                }
+                Err(_) => revalidation.stale.push(op),
+            }
+        }
+        revalidation
+    }
+
+    async fn evaluate_for_pool(
+        &self,
+        op: UserOperation,
+        existing: Option<UserOperation>,
+        block_id: BlockId,
+        chain_id: Option<u64>,
+    ) -> PoolDecision {
+        if let Some(checker) = &self.reputation_checker {
+            if let Some(entity) = op.entities().find(|entity| checker.is_throttled(entity)) {
+                return PoolDecision::Throttle(entity);
+            }
+        }
+        let hash_chain_id = match self.request_chain_id(chain_id).await {
+            Ok(hash_chain_id) => hash_chain_id,
+            Err(error) => return PoolDecision::Reject(error),
+        };
+        if let Some(existing) = &existing {
+            if existing.sender != op.sender || existing.nonce != op.nonce {
+                return PoolDecision::Reject(SimulationError::Other(anyhow::anyhow!(
+                    "existing operation with sender {:?} and nonce {} is not replaced by the operation",
+                    existing.sender,
+                    existing.nonce
+                )));
+            }
+            if !is_valid_replacement(
+                existing,
+                &op,
+                self.sim_settings.min_replacement_fee_increase_percentage,
+            ) {
+                return PoolDecision::ReplacementUnderpriced(self.op_hash(existing, hash_chain_id));
+            }
+        }
+        let block_hash = match self.block_hash_of(block_id).await {
+            Ok(block_hash) => block_hash,
+            Err(error) => return PoolDecision::Reject(error.into()),
+        };
+        let result = self
+            .simulate_validation_cached(op, Some(block_hash), None, chain_id)
+            .await;
+        match result {
+            // A partial result skipped the code hash and aggregator checks,
+            // which the pool requires
+            Ok(success) if success.partial => PoolDecision::Reject(SimulationError::Other(
+                anyhow::anyhow!("simulation checks did not finish before the soft deadline"),
+            )),
+            Ok(success) => match existing {
+                Some(existing) => {
+                    PoolDecision::Replace(success, self.op_hash(&existing, hash_chain_id))
+                }
+                None => PoolDecision::Accept(success),
+            },
+            Err(error) => PoolDecision::Reject(error),
+        }
+    }
+
+    async fn simulate_validation_raw(
+        &self,
+        op: UserOperation,
+        block_id: BlockId,
+    ) -> Result<ValidationOutput, SimulationError> {
+        let block_hash = self.block_hash_of(block_id).await?;
+        let context = self
+            .create_context(op, block_hash.into(), None, false)
+            .await?;
+        Ok(context.entry_point_out)
+    }
+}
+
+/// All possible simulation violations
+#[derive(Clone, Debug, parse_display::Display, Ord, Eq, PartialOrd, PartialEq)]
+pub enum SimulationViolation {
+    // Make sure to maintain the order here based on the importance
+    // of the violation for converting to an JSON RPC error
+    /// The user operation signature is invalid
+    #[display("invalid signature")]
+    InvalidSignature,
+    /// The user operation used an opcode that is not allowed, the given
+    /// number of times
+    #[display("{0.kind} uses banned opcode: {2} in contract {1:?} ({3} times)")]
+    UsedForbiddenOpcode(Entity, Address, ViolationOpCode, usize),
+    /// The user operation used an opcode that the operator has restricted for this entity
+    #[display("{0.kind} uses restricted opcode: {1}")]
+    RestrictedOpcode(Entity, ViolationOpCode),
+    /// The user operation used a precompile that is not allowed
+    #[display("{0.kind} uses banned precompile: {2:?} in contract {1:?}")]
+    UsedForbiddenPrecompile(Entity, Address, Address),
+    /// The user operation accessed a contract that has not been deployed
+    #[display(
+        "{0.kind} tried to access code at {1} during validation, but that address is not a contract"
+    )]
+    AccessedUndeployedContract(Entity, Address),
+    /// The user operation factory entity called CREATE2 more than once during initialization
+    #[display("factory may only call CREATE2 once during initialization")]
+    FactoryCalledCreate2Twice(Address),
+    /// The user operation creates its sender with a factory that has no code
+    /// at the simulation block, so wallet creation can't succeed
+    #[display("factory {0:?} in initCode is not a deployed contract")]
+    FactoryNotDeployed(Address),
+    /// The user operation accessed storage slots that are not allowed. Holds
+    /// the address and every such slot at it, in the order first accessed.
+    #[display("{0.kind} accessed forbidden storage at address {1:?} during validation")]
+    InvalidStorageAccess(Entity, Address, Vec<U256>),
+    /// The user operation accessed transient storage with TLOAD or TSTORE
+    /// from an unstaked entity
+    #[display("{0.kind} accessed transient storage during validation but is not staked")]
+    TransientStorageAccess(Entity),
+    /// The user operation used the BLOCKHASH opcode, which is only allowed
+    /// for staked entities with `Settings::allow_staked_block_hash`
+    #[display("{0.kind} uses BLOCKHASH during validation")]
+    UsedBlockHash(Entity),
+    /// The user operation called `handleOps` or `handleAggregatedOps` on the entry point
+    #[display("{0.kind} called handleOps or handleAggregatedOps during validation")]
+    CalledHandleOps(Entity),
+    /// The user operation called an entry point method that is not allowed
+    #[display("{0.kind} called entry point method other than depositTo")]
+    CalledBannedEntryPointMethod(Entity),
+    /// The user operation made a call that contained value to a contract other than the entrypoint
+    /// during validation
+    #[display("{0.kind} must not send ETH during validation (except from account to entry point)")]
+    CallHadValue(Entity),
+    /// The user operation accessed the storage of more addresses than allowed
+    #[display("validation accessed more than the maximum of {0} addresses")]
+    TooManyAccessedAddresses(usize),
+    /// The summed gas limits of the user operation exceed the allowed portion
+    /// of the block gas limit
+    #[display("total gas limit of {0} exceeds the allowed portion of the block gas limit of {1}")]
+    OpGasExceedsBlockLimit(U256, U256),
+    /// The code hash of accessed contracts changed on the second simulation.
+    /// Holds the contracts whose code changed, if their earlier hashes were
+    /// given in `SimulationRequest::expected_code_hashes`
+    #[display("code accessed by validation has changed since the last time validation was run")]
+    CodeHashChanged(Vec<Address>),
+    /// The user operation contained an entity that accessed storage without being staked
+    #[display("{entity.kind} must be staked")]
+    NotStaked {
+        /// The entity that must be staked
+        entity: Entity,
+        /// The entity's stake on the entry point, in wei
+        actual_stake: U256,
+        /// The minimum stake, in wei
+        required_stake: U256,
+        /// The entity's unstake delay on the entry point, in seconds
+        actual_delay: U256,
+        /// The minimum unstake delay, in seconds
+        required_delay: U256,
+    },
+    /// Simulation reverted with an unintended reason, containing a message
+    #[display("reverted while simulating {0} validation: {1}")]
+    UnintendedRevertWithMessage(EntityType, String, Option<Address>),
+    /// Simulation reverted with an unintended reason
+    #[display("reverted while simulating {0} validation")]
+    UnintendedRevert(EntityType),
+    /// Simulation did not revert, a revert is always expected
+    #[display(
+        "simulateValidation did not revert and returned {0}. Make sure your EntryPoint is valid"
+    )]
+    DidNotRevert(Bytes),
+    /// Simulation had the wrong number of phases
+    #[display("simulateValidation should have 3 parts but had {0} instead. Make sure your EntryPoint is valid")]
+    WrongNumberOfPhases(u32),
+    /// The user operation ran out of gas during validation
+    #[display("ran out of gas during {0.kind} validation")]
+    OutOfGas(Entity),
+    /// The user operation aggregator signature validation failed
     #[display("aggregator signature validation failed")]
     AggregatorValidationFailed,
+    /// The aggregator's signature validation succeeded but returned an empty
+    /// signature, which cannot be aggregated into a bundle
+    #[display("aggregator {0:?} returned an empty signature")]
+    AggregatorReturnedEmptySignature(Address),
+    /// The user operation's sender is on the operator's denylist
+    #[display("sender {0:?} is denied")]
+    SenderDenied(Address),
+    /// The user operation's factory is on the operator's denylist
+    #[display("factory {0:?} is denied")]
+    FactoryDenied(Address),
+    /// The user operation's paymaster is on the operator's denylist
+    #[display("paymaster {0:?} is denied")]
+    PaymasterDenied(Address),
+    /// The user operation was rejected by one of the operator's custom
+    /// prechecks, with the given reason
+    #[display("operation rejected by precheck: {0}")]
+    CustomPrecheckFailed(String),
+    /// More violations were found than are returned, containing the total
+    /// number of violations found
+    #[display("{0} violations found during validation, only the highest priority are returned")]
+    TruncatedViolations(usize),
+}
+
+impl SimulationViolation {
+    /// The id of the ERC-7562 validation rule this violation breaks, if any.
+    ///
+    /// Violations of operator-configured limits, and failures that are not
+    /// rule violations such as reverts, have no rule id.
+    pub fn rule_id(&self) -> Option<&'static str> {
+        match self {
+            Self::UsedForbiddenOpcode(..) | Self::UsedBlockHash(_) => Some("OP-011"),
+            Self::OutOfGas(_) => Some("OP-020"),
+            Self::FactoryCalledCreate2Twice(_) => Some("OP-031"),
+            Self::AccessedUndeployedContract(..) => Some("OP-041"),
+            Self::CalledHandleOps(_) | Self::CalledBannedEntryPointMethod(_) => Some("OP-054"),
+            Self::CallHadValue(_) => Some("OP-061"),
+            Self::UsedForbiddenPrecompile(..) => Some("OP-062"),
+            Self::TransientStorageAccess(_) => Some("OP-070"),
+            Self::NotStaked { .. } => Some("STO-031"),
+            Self::InvalidStorageAccess(..) => Some("STO-033"),
+            Self::CodeHashChanged(_) => Some("COD-010"),
+            Self::InvalidSignature
+            | Self::RestrictedOpcode(..)
+            | Self::TooManyAccessedAddresses(_)
+            | Self::OpGasExceedsBlockLimit(..)
+            | Self::UnintendedRevertWithMessage(..)
+            | Self::UnintendedRevert(_)
+            | Self::DidNotRevert(_)
+            | Self::WrongNumberOfPhases(_)
+            | Self::AggregatorValidationFailed
+            | Self::AggregatorReturnedEmptySignature(_)
+            | Self::SenderDenied(_)
+            | Self::FactoryNotDeployed(_)
+            | Self::FactoryDenied(_)
+            | Self::PaymasterDenied(_)
+            | Self::CustomPrecheckFailed(_)
+            | Self::TruncatedViolations(_) => None,
+        }
+    }
+}
+
+/// A violation found while checking the trace of a simulation, with the
+/// phase of the trace it was found in
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PhasedViolation {
+    /// The violation
+    pub violation: SimulationViolation,
+    /// The index of the phase the violation was found in (0 for the factory,
+    /// 1 for the account and 2 for the paymaster), or `None` if it wasn't
+    /// found in a single phase
+    pub phase_index: Option<usize>,
 }
 
 /// A wrapper around Opcode that implements extra traits
@@ -598,6 +2595,38 @@ impl Ord for ViolationOpCode {
     }
 }
 
+// Await the future, returning `None` if it does not complete before the deadline
+async fn with_soft_deadline<F: Future>(deadline_ms: Option<u64>, future: F) -> Option<F::Output> {
+    match deadline_ms {
+        Some(deadline_ms) => tokio::time::timeout(Duration::from_millis(deadline_ms), future)
+            .await
+            .ok(),
+        None => Some(future.await),
+    }
+}
+
+// The post state of a prestate diff only includes the fields that changed, so
+// each is applied as an override on top of the block's state.
+fn state_overrides_from_diff(diff: DiffMode) -> anyhow::Result<spoof::State> {
+    let mut state_overrides = spoof::State::default();
+    for (address, post) in diff.post {
+        let account = state_overrides.account(address);
+        if let Some(balance) = post.balance {
+            account.balance(balance);
+        }
+        if let Some(nonce) = post.nonce {
+            account.nonce(nonce.as_u64().into());
+        }
+        if let Some(code) = post.code {
+            account.code(Bytes::from_str(&code).context("prior ops trace should have hex code")?);
+        }
+        for (slot, value) in post.storage.unwrap_or_default() {
+            account.store(slot, value);
+        }
+    }
+    Ok(state_overrides)
+}
+
 fn entity_type_from_simulation_phase(i: usize) -> Option<EntityType> {
     match i {
         0 => Some(EntityType::Factory),
@@ -616,12 +2645,92 @@ struct ValidationContext {
     is_unstaked_wallet_creation: bool,
     entities_needing_stake: Vec<EntityType>,
     accessed_addresses: HashSet<Address>,
+    min_unstake_delay: u32,
+}
+
+// Stake infos loaded for the entities that the stake resolver remaps
+#[derive(Debug, Default)]
+struct ResolvedStakeInfos {
+    factory: Option<StakeInfo>,
+    sender: Option<StakeInfo>,
+    paymaster: Option<StakeInfo>,
+}
+
+impl ResolvedStakeInfos {
+    // Replace the stake info of each remapped entity with the stake info of
+    // the address it resolves to
+    fn apply(self, entry_point_out: &mut ValidationOutput) {
+        if let Some(info) = self.factory {
+            entry_point_out.factory_info = info;
+        }
+        if let Some(info) = self.sender {
+            entry_point_out.sender_info = info;
+        }
+        if let Some(info) = self.paymaster {
+            entry_point_out.paymaster_info = info;
+        }
+    }
+}
+
+// Validity windows returned separately by the account and paymaster
+#[derive(Debug)]
+struct ValidityWindows {
+    account: ValidTimeRange,
+    paymaster: Option<ValidTimeRange>,
+    paymaster_ending_soon: bool,
+}
+
+// Number of blocks whose prewarm results are kept, so that looking up an
+// older block does not discard the results for the newest one
+const PREWARM_CACHE_BLOCKS: usize = 4;
+
+// Results of prewarming, keyed by the hash of the block they were computed at.
+#[derive(Debug, Default)]
+struct PrewarmCache {
+    // Ordered from least to most recently used
+    blocks: IndexMap<H256, BlockPrewarm>,
+}
+
+// Results of prewarming at a single block
+#[derive(Debug, Default)]
+struct BlockPrewarm {
+    // Keyed by the hash of the tracer that produced them and the operation
+    tracer_outs: HashMap<(Option<H256>, UserOperation), SimulationTracerOutput>,
+    code_hashes: HashMap<Vec<Address>, H256>,
+    min_unstake_delay: Option<u32>,
+}
+
+impl PrewarmCache {
+    // Get the results for the given block, if it is given by hash and has any
+    fn get(&mut self, block_id: BlockId) -> Option<&mut BlockPrewarm> {
+        let BlockId::Hash(block_hash) = block_id else {
+            return None;
+        };
+        let index = self.blocks.get_index_of(&block_hash)?;
+        let last = self.blocks.len() - 1;
+        self.blocks.move_index(index, last);
+        self.blocks.get_index_mut(last).map(|(_, cache)| cache)
+    }
+
+    // Get the results for the given block, creating them if needed and
+    // evicting the least recently used block if the cache is full
+    fn at_block(&mut self, block_hash: H256) -> &mut BlockPrewarm {
+        if self.get(block_hash.into()).is_none() {
+            self.blocks.insert(block_hash, BlockPrewarm::default());
+            while self.blocks.len() > PREWARM_CACHE_BLOCKS {
+                self.blocks.shift_remove_index(0);
+            }
+        }
+        let last = self.blocks.len() - 1;
+        &mut self.blocks[last]
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 struct EntityInfo {
     address: Address,
     is_staked: bool,
+    stake_info: StakeInfo,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -637,19 +2746,22 @@ impl EntityInfos {
         sender_address: Address,
         paymaster_address: Option<Address>,
         entry_point_out: &ValidationOutput,
-        sim_settings: Settings,
+        is_staked: impl Fn(StakeInfo) -> bool,
     ) -> Self {
         let factory = factory_address.map(|address| EntityInfo {
             address,
-            is_staked: is_staked(entry_point_out.factory_info, sim_settings),
+            is_staked: is_staked(entry_point_out.factory_info),
+            stake_info: entry_point_out.factory_info,
         });
         let sender = EntityInfo {
             address: sender_address,
-            is_staked: is_staked(entry_point_out.sender_info, sim_settings),
+            is_staked: is_staked(entry_point_out.sender_info),
+            stake_info: entry_point_out.sender_info,
         };
         let paymaster = paymaster_address.map(|address| EntityInfo {
             address,
-            is_staked: is_staked(entry_point_out.paymaster_info, sim_settings),
+            is_staked: is_staked(entry_point_out.paymaster_info),
+            stake_info: entry_point_out.paymaster_info,
         });
         Self {
             factory,
@@ -672,9 +2784,40 @@ impl EntityInfos {
     }
 }
 
-fn is_staked(info: StakeInfo, sim_settings: Settings) -> bool {
-    info.stake >= sim_settings.min_stake_value.into()
-        && info.unstake_delay_sec >= sim_settings.min_unstake_delay.into()
+fn is_staked(info: StakeInfo, min_stake_value: u128, min_unstake_delay: u32) -> bool {
+    info.stake >= min_stake_value.into() && info.unstake_delay_sec >= min_unstake_delay.into()
+}
+
+// Violations of the opcode and storage rules, as opposed to failures of the
+// operation's validation itself
+fn is_opcode_or_storage_violation(violation: &SimulationViolation) -> bool {
+    matches!(
+        violation,
+        SimulationViolation::UsedForbiddenOpcode(..)
+            | SimulationViolation::RestrictedOpcode(..)
+            | SimulationViolation::UsedForbiddenPrecompile(..)
+            | SimulationViolation::AccessedUndeployedContract(..)
+            | SimulationViolation::FactoryCalledCreate2Twice(_)
+            | SimulationViolation::InvalidStorageAccess(..)
+            | SimulationViolation::TransientStorageAccess(_)
+            | SimulationViolation::UsedBlockHash(_)
+            | SimulationViolation::TooManyAccessedAddresses(_)
+    )
+}
+
+fn not_staked(
+    entity: Entity,
+    info: StakeInfo,
+    min_stake_value: u128,
+    min_unstake_delay: u32,
+) -> SimulationViolation {
+    SimulationViolation::NotStaked {
+        entity,
+        actual_stake: info.stake,
+        required_stake: min_stake_value.into(),
+        actual_delay: info.unstake_delay_sec,
+        required_delay: min_unstake_delay.into(),
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -688,10 +2831,12 @@ enum StorageRestriction {
 struct GetStorageRestrictionArgs<'a> {
     slots_by_address: &'a AssociatedSlotsByAddress,
     is_unstaked_wallet_creation: bool,
+    allow_sender_deposit_during_creation: bool,
     entry_point_address: Address,
     entity_address: Address,
     sender_address: Address,
     accessed_address: Address,
+    is_allowlisted_token: bool,
     slot: U256,
 }
 
@@ -699,10 +2844,12 @@ fn get_storage_restriction(args: GetStorageRestrictionArgs<'_>) -> StorageRestri
     let GetStorageRestrictionArgs {
         slots_by_address,
         is_unstaked_wallet_creation,
+        allow_sender_deposit_during_creation,
         entry_point_address,
         entity_address,
         sender_address,
         accessed_address,
+        is_allowlisted_token,
         slot,
         ..
     } = args;
@@ -710,14 +2857,22 @@ fn get_storage_restriction(args: GetStorageRestrictionArgs<'_>) -> StorageRestri
         StorageRestriction::Allowed
     } else if slots_by_address.is_associated_slot(sender_address, slot) {
         // Allow entities to access the sender's associated storage unless its during an unstaked wallet creation
-        // Can always access the entry point's associated storage (note only depositTo is allowed to be called)
-        if accessed_address == entry_point_address || !is_unstaked_wallet_creation {
+        // Can access the entry point's associated storage during an unstaked wallet creation, if allowed
+        // (note only depositTo is allowed to be called)
+        if !is_unstaked_wallet_creation {
             StorageRestriction::Allowed
+        } else if accessed_address == entry_point_address {
+            if allow_sender_deposit_during_creation {
+                StorageRestriction::Allowed
+            } else {
+                StorageRestriction::Banned
+            }
         } else {
             StorageRestriction::NeedsStake
         }
     } else if accessed_address == entity_address
         || slots_by_address.is_associated_slot(entity_address, slot)
+        || is_allowlisted_token
     {
         StorageRestriction::NeedsStake
     } else {
@@ -725,8 +2880,113 @@ fn get_storage_restriction(args: GetStorageRestrictionArgs<'_>) -> StorageRestri
     }
 }
 
+/// Per-entity overrides for the "gray area" opcodes that some mempools
+/// restrict: `ADDRESS`, `CHAINID`, `SELFBALANCE` and `ORIGIN`.
+///
+/// Opcodes without an override fall back to the default rules, under which
+/// `ADDRESS` and `CHAINID` are allowed and `SELFBALANCE` and `ORIGIN` are
+/// forbidden. Overrides for any other opcode are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RestrictedOpcodes(BTreeMap<(EntityType, Opcode), bool>);
+
+impl RestrictedOpcodes {
+    /// Allow the given entity type to use the opcode during validation
+    pub fn allow(&mut self, kind: EntityType, opcode: Opcode) -> &mut Self {
+        self.0.insert((kind, opcode), true);
+        self
+    }
+
+    /// Deny the given entity type from using the opcode during validation
+    pub fn deny(&mut self, kind: EntityType, opcode: Opcode) -> &mut Self {
+        self.0.insert((kind, opcode), false);
+        self
+    }
+
+    /// Returns the override for the entity type and opcode, if one is set
+    pub fn is_allowed(&self, kind: EntityType, opcode: Opcode) -> Option<bool> {
+        self.0.get(&(kind, opcode)).copied()
+    }
+
+    fn allowed_by_default(opcode: Opcode) -> bool {
+        matches!(opcode, Opcode::ADDRESS | Opcode::CHAINID)
+    }
+}
+
+/// The hardfork whose EVM rules the chain follows, which determines the
+/// opcodes that exist during simulation and how calldata is priced.
+///
+/// An opcode that the hardfork does not define aborts the call that executes
+/// it, so the validation rules ignore its use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EvmVersion {
+    /// Paris, before `PUSH0`
+    Paris,
+    /// Shanghai, adding `PUSH0`
+    Shanghai,
+    /// Cancun, adding `TLOAD`, `TSTORE`, `MCOPY`, `BLOBHASH` and `BLOBBASEFEE`
+    #[default]
+    Cancun,
+    /// Prague, adding the EIP-7623 floor on calldata cost
+    Prague,
+}
+
+impl FromStr for EvmVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "paris" => Ok(Self::Paris),
+            "shanghai" => Ok(Self::Shanghai),
+            "cancun" => Ok(Self::Cancun),
+            "prague" => Ok(Self::Prague),
+            _ => Err(format!("unknown EVM version: {s}")),
+        }
+    }
+}
+
+impl EvmVersion {
+    /// Returns true if the opcode exists in this version of the EVM
+    pub fn has_opcode(self, opcode: Opcode) -> bool {
+        self.has_opcode_byte(opcode as u8)
+    }
+
+    /// Returns true if `TLOAD` and `TSTORE` exist in this version of the EVM
+    pub fn has_transient_storage(self) -> bool {
+        self.has_opcode_byte(0x5c) && self.has_opcode_byte(0x5d)
+    }
+
+    /// Returns true if transactions pay at least the EIP-7623 floor cost for
+    /// their calldata
+    pub fn has_calldata_floor(self) -> bool {
+        self >= Self::Prague
+    }
+
+    fn has_opcode_byte(self, opcode: u8) -> bool {
+        match opcode {
+            // PUSH0
+            0x5f => self >= Self::Shanghai,
+            // BLOBHASH, BLOBBASEFEE, TLOAD, TSTORE, MCOPY
+            0x49 | 0x4a | 0x5c | 0x5d | 0x5e => self >= Self::Cancun,
+            _ => true,
+        }
+    }
+}
+
+/// How simulation treats an operation whose aggregator rejects its signature
+/// off-chain
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AggregatorFailurePolicy {
+    /// Reject the operation with `SimulationViolation::AggregatorValidationFailed`
+    #[default]
+    Reject,
+    /// Accept the operation and report the violation in
+    /// `SimulationSuccess::warnings`, leaving on-chain aggregation to
+    /// reject it. Useful for optimistic aggregation.
+    Warn,
+}
+
 /// Simulation Settings
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Settings {
     /// The minimum amount of time that a staked entity must have configured as
     /// their unstake delay on the entry point contract in order to be considered staked.
@@ -738,6 +2998,128 @@ pub struct Settings {
     pub max_simulate_handle_ops_gas: u64,
     /// The maximum amount of verification gas that can be used during the simulation call
     pub max_verification_gas: u64,
+    /// Per-entity overrides for restrictable opcodes
+    pub restricted_opcodes: RestrictedOpcodes,
+    /// If set, the maximum time in milliseconds to wait for the code hash and
+    /// aggregator checks before returning a partial simulation result. The
+    /// pool and builder reject partial results, since they skipped these
+    /// checks.
+    pub soft_deadline_ms: Option<u64>,
+    /// Whether the sender's associated storage on the entry point may be accessed
+    /// during an unstaked wallet creation, so that the account can call `depositTo()`
+    pub allow_sender_deposit_during_creation: bool,
+    /// The maximum number of distinct addresses whose storage may be accessed
+    /// during validation
+    pub max_accessed_addresses: usize,
+    /// The maximum percentage of the block gas limit that the summed gas
+    /// limits of an operation may use. Operations above this can never
+    /// be bundled.
+    pub max_block_gas_percent: u64,
+    /// If set, the tracer output of each simulation is written to this
+    /// directory as JSON, to be replayed with
+    /// `SimulatorImpl::from_recorded_trace`
+    pub trace_dump_dir: Option<PathBuf>,
+    /// Whether the factory may use EXTCODESIZE on the entry point and access
+    /// the sender it is creating, e.g. to check if the sender is already
+    /// deployed. Other EXTCODE* opcodes on the entry point stay banned.
+    pub allow_factory_existence_checks: bool,
+    /// Whether staked entities may use BLOCKHASH during validation. It makes
+    /// validation depend on recent blocks, so a reorg can invalidate
+    /// operations that were already accepted.
+    pub allow_staked_block_hash: bool,
+    /// The maximum number of violations kept from a simulation. Beyond this,
+    /// only the highest priority violations are kept and a
+    /// `TruncatedViolations` marker is appended, so the operation is rejected.
+    pub max_violations_returned: usize,
+    /// The minimum number of seconds after the simulation block that a
+    /// paymaster's validity window should remain open, below which
+    /// `SimulationSuccess::paymaster_window_ending_soon` is set
+    pub min_validity_window_secs: u64,
+    /// The hardfork whose opcodes are available on the chain
+    pub evm_version: EvmVersion,
+    /// If set, the gas given to the traced `simulateValidation` call in place
+    /// of the maximum verification gas, to match the node's tracing gas cap
+    pub trace_gas_cap: Option<U256>,
+    /// How to treat an aggregator rejecting the operation's signature
+    pub aggregator_failure_policy: AggregatorFailurePolicy,
+    /// Number of operations in a row that may fail with a provider error
+    /// in `SimulatorImpl::simulate_validation_batch` before the rest of the
+    /// batch is skipped
+    pub batch_node_failure_limit: usize,
+    /// Maximum number of calls the simulator may have in flight to the node
+    /// at the same time, bounding the connections held open to it when many
+    /// operations are simulated concurrently. `None` for no limit.
+    pub max_rpc_connections: Option<usize>,
+    /// Senders whose operations are rejected before simulation
+    pub denied_senders: HashSet<Address>,
+    /// Factories whose operations are rejected before simulation
+    pub denied_factories: HashSet<Address>,
+    /// Paymasters whose operations are rejected before simulation
+    pub denied_paymasters: HashSet<Address>,
+    /// (paymaster, token) pairs for ERC-20 paymasters that pull an allowance
+    /// during validation. The paymaster may access any storage of the token,
+    /// which then requires it to be staked instead of being banned.
+    pub token_paymaster_allowlist: HashSet<(Address, Address)>,
+    /// The chain ID used to hash operations, e.g. the operation being
+    /// replaced in `Simulator::evaluate_for_pool`. If 0, the node's chain ID
+    /// is used.
+    pub chain_id: u64,
+    /// The version of the entry point, which determines how operations are
+    /// hashed for the simulation sink and in `Simulator::evaluate_for_pool`
+    pub entry_point_version: EntryPointVersion,
+    /// The minimum percentage by which both fees of an operation must exceed
+    /// those of the operation it replaces in `Simulator::evaluate_for_pool`
+    pub min_replacement_fee_increase_percentage: u64,
+    /// Maximum number of successful results kept by `simulate_validation`,
+    /// keyed by operation hash and block hash, so that an operation
+    /// simulated again at the same block is not traced again. Zero disables
+    /// the cache.
+    pub simulation_cache_size: usize,
+    /// Number of blocks after which a cached result is evicted, counted back
+    /// from the newest block a result has been cached at
+    pub simulation_cache_ttl_blocks: u64,
+    /// Address of the entry point's `SenderCreator` helper, if it deploys
+    /// senders through one as v0.7 does. Opcodes and precompiles used by its
+    /// own code during the factory phase are not attributed to the factory,
+    /// and accessing it is not reported as accessing undeployed code.
+    pub sender_creator_address: Option<Address>,
+    /// Whether `SimulationRequest::chain_id` may differ from the node's chain
+    /// ID
+    pub allow_chain_id_override: bool,
+    /// Whether to check, once a simulation succeeds, that its block is still
+    /// the chain's block at that height. If it was reorged out, the results
+    /// of the node calls may be inconsistent and
+    /// `SimulationError::ReorgDetected` is returned so that the caller
+    /// retries.
+    pub detect_reorgs: bool,
+    /// Whether to follow the validation rules exactly as specified, for
+    /// compatibility testing. This overrides the settings for rundler's
+    /// intentional deviations from the spec:
+    /// `allow_sender_deposit_during_creation`,
+    /// `allow_factory_existence_checks`, `token_paymaster_allowlist`,
+    /// `allow_staked_block_hash`, `sender_creator_address` and
+    /// `trusted_app_allowlist`. The tracer must be created with the same
+    /// mode, as it also bans calling the entry point with no calldata.
+    pub strict_spec_mode: bool,
+    /// FOR LOCAL TESTING ONLY, NEVER ENABLE IN PRODUCTION. Treats every
+    /// entity as staked whatever its stake on the entry point, so that
+    /// developers can see the storage and opcode violations of their
+    /// contracts without staking first. A bundler running with this accepts
+    /// operations that other bundlers reject and that can be used to grief
+    /// it.
+    pub assume_all_entities_staked: bool,
+    /// Pairs of sender and callData target of trusted apps whose operations
+    /// skip the opcode and storage rules, for closed bundlers that serve
+    /// specific apps. An operation is trusted only if its callData is an
+    /// `execute` or `executeBatch` call whose targets are all listed for its
+    /// sender. Trusted operations are still simulated, and rejected if
+    /// validation fails, runs out of gas, calls the entry point or is
+    /// unstaked. Ignored in `strict_spec_mode`. SECURITY: the rules are what
+    /// keep an operation's validation from being invalidated by other
+    /// operations or by state changes, so an allowlisted app can make the
+    /// bundler submit bundles that revert and pay for them. Only list apps
+    /// whose validation you trust.
+    pub trusted_app_allowlist: HashSet<(Address, Address)>,
 }
 
 impl Settings {
@@ -753,6 +3135,36 @@ impl Settings {
             min_stake_value,
             max_simulate_handle_ops_gas,
             max_verification_gas,
+            restricted_opcodes: RestrictedOpcodes::default(),
+            soft_deadline_ms: None,
+            allow_sender_deposit_during_creation: true,
+            max_accessed_addresses: 1_000,
+            max_block_gas_percent: 100,
+            trace_dump_dir: None,
+            allow_factory_existence_checks: false,
+            allow_staked_block_hash: false,
+            max_violations_returned: 100,
+            min_validity_window_secs: 60,
+            evm_version: EvmVersion::default(),
+            trace_gas_cap: None,
+            aggregator_failure_policy: AggregatorFailurePolicy::default(),
+            batch_node_failure_limit: 2,
+            max_rpc_connections: None,
+            denied_senders: HashSet::new(),
+            denied_factories: HashSet::new(),
+            denied_paymasters: HashSet::new(),
+            token_paymaster_allowlist: HashSet::new(),
+            chain_id: 0,
+            entry_point_version: EntryPointVersion::default(),
+            min_replacement_fee_increase_percentage: 10,
+            simulation_cache_size: 0,
+            simulation_cache_ttl_blocks: 2,
+            sender_creator_address: None,
+            allow_chain_id_override: false,
+            detect_reorgs: false,
+            strict_spec_mode: false,
+            assume_all_entities_staked: false,
+            trusted_app_allowlist: HashSet::new(),
         }
     }
 }
@@ -768,27 +3180,72 @@ impl Default for Settings {
             // 550 million gas: currently the defaults for Alchemy eth_call
             max_simulate_handle_ops_gas: 550_000_000,
             max_verification_gas: 5_000_000,
+            restricted_opcodes: RestrictedOpcodes::default(),
+            soft_deadline_ms: None,
+            allow_sender_deposit_during_creation: true,
+            max_accessed_addresses: 1_000,
+            max_block_gas_percent: 100,
+            trace_dump_dir: None,
+            allow_factory_existence_checks: false,
+            allow_staked_block_hash: false,
+            max_violations_returned: 100,
+            min_validity_window_secs: 60,
+            evm_version: EvmVersion::default(),
+            trace_gas_cap: None,
+            aggregator_failure_policy: AggregatorFailurePolicy::default(),
+            batch_node_failure_limit: 2,
+            max_rpc_connections: None,
+            denied_senders: HashSet::new(),
+            denied_factories: HashSet::new(),
+            denied_paymasters: HashSet::new(),
+            token_paymaster_allowlist: HashSet::new(),
+            chain_id: 0,
+            entry_point_version: EntryPointVersion::default(),
+            min_replacement_fee_increase_percentage: 10,
+            simulation_cache_size: 0,
+            simulation_cache_ttl_blocks: 2,
+            sender_creator_address: None,
+            allow_chain_id_override: false,
+            detect_reorgs: false,
+            strict_spec_mode: false,
+            assume_all_entities_staked: false,
+            trusted_app_allowlist: HashSet::new(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{
+        str::FromStr,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
 
+    use anyhow::anyhow;
     use ethers::{
         abi::AbiEncode,
         providers::JsonRpcError,
-        types::{Address, BlockNumber, Bytes},
+        types::{Address, Block, BlockNumber, Bytes},
         utils::hex,
     };
     use rundler_provider::{AggregatorOut, MockProvider, ProviderError};
+    use rundler_types::contracts::{
+        i_entry_point::{ValidationResult, ValidationResultWithAggregation},
+        shared_types::DepositInfo,
+    };
+    use tokio::sync::Notify;
 
     use super::*;
-    use crate::simulation::tracer::{MockSimulateValidationTracer, Phase};
+    use crate::simulation::{
+        tracer::{MockSimulateValidationTracer, Phase},
+        validation_results::AggregatorInfo,
+        InMemoryBlockSource,
+    };
 
     fn create_base_config() -> (MockProvider, MockSimulateValidationTracer) {
-        (MockProvider::new(), MockSimulateValidationTracer::new())
+        let mut tracer = MockSimulateValidationTracer::new();
+        tracer.expect_tracer_hash().return_const(None);
+        (MockProvider::new(), tracer)
     }
 
     fn get_test_tracer_output() -> SimulationTracerOutput {
@@ -812,6 +3269,7 @@ mod tests {
             }
             "#).unwrap(),
             factory_called_create2_twice: false,
+            created_contracts: vec![],
             expected_storage: serde_json::from_str(r#"
             {
                 "0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789": {
@@ -824,10 +3282,16 @@ mod tests {
                 Phase {
                     addresses_calling_with_value: vec![],
                     called_banned_entry_point_method: false,
+                    called_handle_ops: false,
+                    used_transient_storage: false,
+                    used_block_hash: false,
                     called_non_entry_point_with_value: false,
                     forbidden_opcodes_used: vec![],
+                    restrictable_opcodes_used: vec![],
+                    opcode_counts: HashMap::new(),
                     forbidden_precompiles_used: vec![],
                     ran_out_of_gas: false,
+                    validation_data: None,
                     storage_accesses: vec![],
                     undeployed_contract_accesses: vec![],
                     ext_code_access_info: HashMap::new(),
@@ -835,10 +3299,16 @@ mod tests {
                 Phase {
                     addresses_calling_with_value: vec![Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()],
                     called_banned_entry_point_method: false,
+                    called_handle_ops: false,
+                    used_transient_storage: false,
+                    used_block_hash: false,
                     called_non_entry_point_with_value: false,
                     forbidden_opcodes_used: vec![],
+                    restrictable_opcodes_used: vec![],
+                    opcode_counts: HashMap::new(),
                     forbidden_precompiles_used: vec![],
                     ran_out_of_gas: false,
+                    validation_data: None,
                     storage_accesses: vec![
                         StorageAccess {
                             address: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
@@ -846,12 +3316,16 @@ mod tests {
                                 U256::from_str("0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc").unwrap(),
                                 U256::from_str("0x0000000000000000000000000000000000000000000000000000000000000000").unwrap()
                             ],
+                            read_slots: vec![],
+                            written_slots: vec![],
                         },
                         StorageAccess {
                             address: Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
                             slots: vec![
                                 U256::from_str("0xf5357e1da3acf909ceaed3492183cbad85a3c9e1f0076495f66d3eed05219bd5").unwrap()
                             ],
+                            read_slots: vec![],
+                            written_slots: vec![],
                         }
                     ],
                     undeployed_contract_accesses: vec![],
@@ -860,19 +3334,53 @@ mod tests {
                 Phase {
                     addresses_calling_with_value: vec![],
                     called_banned_entry_point_method: false,
+                    called_handle_ops: false,
+                    used_transient_storage: false,
+                    used_block_hash: false,
                     called_non_entry_point_with_value: false,
                     forbidden_opcodes_used: vec![],
+                    restrictable_opcodes_used: vec![],
+                    opcode_counts: HashMap::new(),
                     forbidden_precompiles_used: vec![],
                     ran_out_of_gas: false,
+                    validation_data: None,
                     storage_accesses: vec![],
                     undeployed_contract_accesses: vec![],
                     ext_code_access_info: HashMap::new(),
                 }
             ],
             revert_data: Some("0xe0cff05f00000000000000000000000000000000000000000000000000000000000000e00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000014eff00000000000000000000000000000000000000000000000000000b7679c50c24000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ffffffffffff00000000000000000000000000000000000000000000000000000000000000c00000000000000000000000000000000000000000000000000000000000000000".into()),
+            return_data: "0x".into(),
         }
     }
 
+    fn expect_get_block(provider: &mut MockProvider) {
+        provider.expect_get_block::<H256>().returning(|hash| {
+            Ok(Some(Block {
+                hash: Some(hash),
+                number: Some(100.into()),
+                gas_limit: 30_000_000.into(),
+                ..Default::default()
+            }))
+        });
+    }
+
+    fn expect_get_latest_block(provider: &mut MockProvider) {
+        provider.expect_get_block::<BlockNumber>().returning(|_| {
+            Ok(Some(Block {
+                hash: Some(
+                    H256::from_str(
+                        "0x38138f1cb4653ab6ab1c89ae3a6acc8705b54bd16a997d880c4421014ed66c3d",
+                    )
+                    .unwrap(),
+                ),
+                number: Some(100.into()),
+                gas_limit: 30_000_000.into(),
+                ..Default::default()
+            }))
+        });
+    }
+
     fn create_simulator(
         provider: MockProvider,
         simulate_validation_tracer: MockSimulateValidationTracer,
@@ -896,197 +3404,2378 @@ mod tests {
         simulator
     }
 
+    #[test]
+    fn test_settings() {
+        let (provider, tracer) = create_base_config();
+        let mut settings = Settings {
+            soft_deadline_ms: Some(500),
+            ..Settings::new(100, 200, 300, 400)
+        };
+        settings
+            .restricted_opcodes
+            .allow(EntityType::Account, Opcode::ORIGIN);
+
+        let simulator = SimulatorImpl::new(
+            Arc::new(provider),
+            Address::zero(),
+            tracer,
+            settings.clone(),
+            HashMap::new(),
+        );
+        assert_eq!(simulator.settings(), &settings);
+    }
+
+    fn simulation_user_operation() -> UserOperation {
+        UserOperation {
+        sender: Address::from_str("b856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+        nonce: U256::from(264),
+        init_code: Bytes::from_str("0x").unwrap(),
+        call_data: Bytes::from_str("0xb61d27f6000000000000000000000000b856dbd4fa1a79a46d426f537455e7d3e79ab7c4000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000004d087d28800000000000000000000000000000000000000000000000000000000").unwrap(),
+        call_gas_limit: U256::from(9100),
+        verification_gas_limit: U256::from(64805),
+        pre_verification_gas: U256::from(46128),
+        max_fee_per_gas: U256::from(105000100),
+        max_priority_fee_per_gas: U256::from(105000000),
+        paymaster_and_data: Bytes::from_str("0x").unwrap(),
+        signature: Bytes::from_str("0x98f89993ce573172635b44ef3b0741bd0c19dd06909d3539159f6d66bef8c0945550cc858b1cf5921dfce0986605097ba34c2cf3fc279154dd25e161ea7b3d0f1c").unwrap(),
+    }
+    }
+
     #[tokio::test]
     async fn test_simulate_validation() {
         let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
 
-        provider.expect_get_latest_block_hash().returning(|| {
-            Ok(
-                H256::from_str(
-                    "0x38138f1cb4653ab6ab1c89ae3a6acc8705b54bd16a997d880c4421014ed66c3d",
-                )
-                .unwrap(),
-            )
-        });
+        expect_get_latest_block(&mut provider);
 
         tracer
             .expect_trace_simulate_validation()
-            .returning(move |_, _, _| Ok(get_test_tracer_output()));
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
 
         // The underlying eth_call when getting the code hash in check_contracts
-        provider.expect_call().returning(|_, _| {
-            let json_rpc_error = JsonRpcError {
-                code: -32000,
-                message: "execution reverted".to_string(),
-                data: Some(serde_json::Value::String(
-                    "0x091cd005abf68e7b82c951a8619f065986132f67a0945153533cfcdd93b6895f33dbc0c7"
-                        .to_string(),
-                )),
-            };
-            Err(ProviderError::JsonRpcError(json_rpc_error))
-        });
+        expect_code_hash_call(&mut provider, ..);
 
         provider
             .expect_validate_user_op_signature()
             .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
 
-        let user_operation = UserOperation {
-            sender: Address::from_str("b856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
-            nonce: U256::from(264),
-            init_code: Bytes::from_str("0x").unwrap(),
-            call_data: Bytes::from_str("0xb61d27f6000000000000000000000000b856dbd4fa1a79a46d426f537455e7d3e79ab7c4000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000004d087d28800000000000000000000000000000000000000000000000000000000").unwrap(),
-            call_gas_limit: U256::from(9100),
-            verification_gas_limit: U256::from(64805),
-            pre_verification_gas: U256::from(46128),
-            max_fee_per_gas: U256::from(105000100),
-            max_priority_fee_per_gas: U256::from(105000000),
-            paymaster_and_data: Bytes::from_str("0x").unwrap(),
-            signature: Bytes::from_str("0x98f89993ce573172635b44ef3b0741bd0c19dd06909d3539159f6d66bef8c0945550cc858b1cf5921dfce0986605097ba34c2cf3fc279154dd25e161ea7b3d0f1c").unwrap(),
-        };
+        let user_operation = simulation_user_operation();
 
         let simulator = create_simulator(provider, tracer);
         let res = simulator
             .simulate_validation(user_operation, None, None)
-            .await;
-        assert!(res.is_ok());
+            .await
+            .unwrap();
+        assert_eq!(res.block_number, 100);
     }
 
     #[tokio::test]
-    async fn test_create_context_two_phases_unintended_revert() {
-        let (provider, mut tracer) = create_base_config();
+    async fn test_simulate_validation_with_block_source() {
+        // no block is loaded from the provider, only the head given by the
+        // source
+        let (mut provider, mut tracer) = create_base_config();
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        let head = CurrentBlock {
+            hash: H256::random(),
+            number: 100,
+            gas_limit: 30_000_000.into(),
+            ..Default::default()
+        };
+        let block_source = Arc::new(InMemoryBlockSource::default());
+        block_source.update(head);
+        let simulator = create_simulator(provider, tracer).with_block_source(block_source);
+        let res = simulator
+            .simulate_validation(simulation_user_operation(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(res.block_hash, head.hash);
+        assert_eq!(res.block_number, head.number);
+    }
+
+    #[derive(Debug, Default)]
+    struct FixedUnstakeDelay {
+        delay: Option<u32>,
+        reads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl UnstakeDelaySource for FixedUnstakeDelay {
+        async fn min_unstake_delay(&self, _block_id: BlockId) -> anyhow::Result<u32> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.delay.context("entry point has no unstake delay")
+        }
+    }
 
+    async fn account_is_staked_with(source: Arc<FixedUnstakeDelay>) -> bool {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
         tracer
             .expect_trace_simulate_validation()
-            .returning(|_, _, _| {
+            .returning(move |_, _, _, _| {
                 let mut tracer_output = get_test_tracer_output();
+                // the sender is staked with a one minute unstake delay
                 tracer_output.revert_data = Some(hex::encode(
-                    FailedOp {
-                        op_index: U256::from(100),
-                        reason: "AA23 reverted (or OOG)".to_string(),
+                    ValidationResult {
+                        return_info: (
+                            U256::from(1000),
+                            U256::default(),
+                            false,
+                            0,
+                            u64::MAX >> 16,
+                            Bytes::default(),
+                        ),
+                        sender_info: (U256::exp10(18), U256::from(60)),
+                        factory_info: (U256::default(), U256::default()),
+                        paymaster_info: (U256::default(), U256::default()),
                     }
                     .encode(),
                 ));
                 Ok(tracer_output)
             });
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
 
-        let user_operation = UserOperation {
-            sender: Address::from_str("b856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
-            nonce: U256::from(264),
-            init_code: Bytes::from_str("0x").unwrap(),
-            call_data: Bytes::from_str("0xb61d27f6000000000000000000000000b856dbd4fa1a79a46d426f537455e7d3e79ab7c4000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000600000000000000000000000000000000000000000000000000000000000000004d087d28800000000000000000000000000000000000000000000000000000000").unwrap(),
-            call_gas_limit: U256::from(9100),
-            verification_gas_limit: U256::from(64805),
-            pre_verification_gas: U256::from(46128),
-            max_fee_per_gas: U256::from(105000100),
-            max_priority_fee_per_gas: U256::from(105000000),
-            paymaster_and_data: Bytes::from_str("0x").unwrap(),
-            signature: Bytes::from_str("0x98f89993ce573172635b44ef3b0741bd0c19dd06909d3539159f6d66bef8c0945550cc858b1cf5921dfce0986605097ba34c2cf3fc279154dd25e161ea7b3d0f1c").unwrap(),
+        let simulator = create_simulator(provider, tracer).with_unstake_delay_source(source);
+        let mut is_staked = vec![];
+        for _ in 0..2 {
+            let res = simulator
+                .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+                .await
+                .unwrap();
+            is_staked.push(res.account_is_staked);
+        }
+        assert_eq!(is_staked[0], is_staked[1]);
+        is_staked[0]
+    }
+
+    #[tokio::test]
+    async fn test_unstake_delay_source() {
+        // the chain requires less than the static one day delay
+        let source = Arc::new(FixedUnstakeDelay {
+            delay: Some(60),
+            ..Default::default()
+        });
+        assert!(account_is_staked_with(Arc::clone(&source)).await);
+        // read once for the block
+        assert_eq!(source.reads.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unstake_delay_source_falls_back_to_settings() {
+        let source = Arc::new(FixedUnstakeDelay::default());
+        assert!(!account_is_staked_with(Arc::clone(&source)).await);
+        // failed reads are retried
+        assert_eq!(source.reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_revalidate_mempool() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let valid_op = simulation_user_operation();
+        let invalid_op = UserOperation {
+            nonce: valid_op.nonce + 1,
+            ..valid_op.clone()
         };
+        let stale_op = UserOperation {
+            nonce: valid_op.nonce + 2,
+            ..valid_op.clone()
+        };
+        let (invalid_nonce, stale_nonce) = (invalid_op.nonce, stale_op.nonce);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |op, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                if op.nonce == invalid_nonce {
+                    // the account now uses a forbidden opcode
+                    tracer_output.phases[1].forbidden_opcodes_used =
+                        vec!["0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:GASPRICE".to_string()];
+                } else if op.nonce == stale_nonce {
+                    return Err(anyhow!("node unavailable"));
+                }
+                Ok(tracer_output)
+            });
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
 
         let simulator = create_simulator(provider, tracer);
-        let res = simulator
-            .create_context(user_operation, BlockId::Number(BlockNumber::Latest))
+        let result = simulator
+            .revalidate_mempool(
+                vec![valid_op.clone(), invalid_op.clone(), stale_op.clone()],
+                H256::random().into(),
+            )
             .await;
 
+        assert_eq!(result.valid.len(), 1);
+        assert_eq!(result.valid[0].0, valid_op);
+        assert_eq!(result.rejected.len(), 1);
+        assert_eq!(result.rejected[0].0, invalid_op);
         assert!(matches!(
-            res,
-            Err(ViolationError::Violations(violations)) if matches!(
-                violations.get(0),
-                Some(&SimulationViolation::UnintendedRevertWithMessage(
-                    EntityType::Paymaster,
-                    ref reason,
-                    _
-                )) if reason == "AA23 reverted (or OOG)"
+            &result.rejected[0].1,
+            ViolationError::Violations(violations) if matches!(
+                violations[0],
+                SimulationViolation::UsedForbiddenOpcode(_, _, ViolationOpCode(Opcode::GASPRICE), 1)
             )
         ));
+        assert_eq!(result.stale, vec![stale_op]);
     }
 
     #[tokio::test]
-    async fn test_gather_context_violations() {
-        let (provider, tracer) = create_base_config();
+    async fn test_stats() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let valid_op = simulation_user_operation();
+        let invalid_op = UserOperation {
+            nonce: valid_op.nonce + 1,
+            ..valid_op.clone()
+        };
+        let failing_op = UserOperation {
+            nonce: valid_op.nonce + 2,
+            ..valid_op.clone()
+        };
+        let (invalid_nonce, failing_nonce) = (invalid_op.nonce, failing_op.nonce);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |op, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                if op.nonce == invalid_nonce {
+                    tracer_output.phases[1].forbidden_opcodes_used =
+                        vec!["0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:GASPRICE".to_string()];
+                } else if op.nonce == failing_nonce {
+                    return Err(anyhow!("node unavailable"));
+                }
+                Ok(tracer_output)
+            });
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
 
-        let mut tracer_output = get_test_tracer_output();
+        let simulator = create_simulator(provider, tracer);
+        for op in [valid_op, invalid_op, failing_op] {
+            let _ = simulator
+                .simulate_validation(op, Some(H256::zero()), None)
+                .await;
+        }
 
-        // add forbidden opcodes and precompiles
-        tracer_output.phases[1].forbidden_opcodes_used = vec![
-            String::from("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:GASPRICE"),
-            String::from("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:COINBASE"),
-        ];
-        tracer_output.phases[1].forbidden_precompiles_used = vec![String::from(
-            "0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:0x0000000000000000000000000000000000000019",
-        )];
+        let stats = simulator.stats();
+        assert_eq!(stats.simulations, 3);
+        assert_eq!(stats.successes, 1);
+        assert_eq!(stats.violations, 1);
+        assert_eq!(stats.errors, 1);
+        // Nothing was prewarmed, so every lookup misses
+        assert_eq!(stats.trace_cache_hits, 0);
+        assert_eq!(stats.trace_cache_misses, 3);
+        assert_eq!(stats.trace_cache_hit_rate(), 0.0);
+        // The failed trace is not counted
+        assert_eq!(stats.traces, 2);
+    }
 
-        // add a storage access for a random unrelated address
+    #[tokio::test]
+    async fn test_created_contracts() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let sender = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                // the factory deployed the sender
+                tracer_output.created_contracts = vec![sender];
+                Ok(tracer_output)
+            });
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await
+            .unwrap();
+        assert_eq!(res.created_contracts, vec![sender]);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_validation_batch_stops_when_node_fails() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        // the node goes down after the first two traces
+        let traces = Arc::new(AtomicUsize::new(0));
+        let traces_clone = Arc::clone(&traces);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| {
+                if traces_clone.fetch_add(1, Ordering::Relaxed) < 2 {
+                    Ok(get_test_tracer_output())
+                } else {
+                    Err(ProviderError::Other(anyhow!("connection refused")).into())
+                }
+            });
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.batch_node_failure_limit = 1;
+        let results = simulator
+            .simulate_validation_batch(vec![simulation_user_operation(); 5], Some(H256::zero()))
+            .await;
+
+        assert_eq!(results.len(), 5);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_ok());
+        assert!(matches!(results[2], Err(SimulationError::Provider(_))));
+        assert!(matches!(results[3], Err(SimulationError::NodeUnavailable)));
+        assert!(matches!(results[4], Err(SimulationError::NodeUnavailable)));
+        // no traces were attempted after the node failed
+        assert_eq!(traces.load(Ordering::Relaxed), 3);
+    }
+
+    // Tracer that records the most traces it has had in flight at once
+    #[derive(Debug, Default)]
+    struct ConcurrencyCountingTracer {
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl SimulateValidationTracer for ConcurrencyCountingTracer {
+        async fn trace_simulate_validation(
+            &self,
+            _op: UserOperation,
+            _block_id: BlockId,
+            _max_validation_gas: u64,
+            _state_overrides: Option<spoof::State>,
+        ) -> anyhow::Result<SimulationTracerOutput> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(get_test_tracer_output())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_rpc_connections() {
+        let (mut provider, _) = create_base_config();
+        expect_get_block(&mut provider);
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+        let tracer = ConcurrencyCountingTracer::default();
+        let max_in_flight = Arc::clone(&tracer.max_in_flight);
+        let simulator = SimulatorImpl::new(
+            Arc::new(provider),
+            Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+            tracer,
+            Settings {
+                max_rpc_connections: Some(2),
+                ..Default::default()
+            },
+            HashMap::from([(H256::zero(), MempoolConfig::default())]),
+        );
+
+        let results = future::join_all((0..8).map(|_| {
+            simulator.simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+        }))
+        .await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_rpc_connections_single() {
+        // Each simulation calls the node concurrently, which must not wait on
+        // a permit it already holds
+        let (mut provider, _) = create_base_config();
+        expect_get_block(&mut provider);
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+        let tracer = ConcurrencyCountingTracer::default();
+        let max_in_flight = Arc::clone(&tracer.max_in_flight);
+        let simulator = SimulatorImpl::new(
+            Arc::new(provider),
+            Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+            tracer,
+            Settings {
+                max_rpc_connections: Some(1),
+                ..Default::default()
+            },
+            HashMap::from([(H256::zero(), MempoolConfig::default())]),
+        );
+
+        let results = future::join_all((0..8).map(|_| {
+            simulator.simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+        }))
+        .await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_simulation_cache() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        // Traced once for the first block, again for the new block and again
+        // for a different signature
+        tracer
+            .expect_trace_simulate_validation()
+            .times(3)
+            .returning(|_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+        let simulator = SimulatorImpl::new(
+            Arc::new(provider),
+            Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+            tracer,
+            Settings {
+                simulation_cache_size: 10,
+                chain_id: 1,
+                ..Default::default()
+            },
+            HashMap::from([(H256::zero(), MempoolConfig::default())]),
+        );
+
+        let first = simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await
+            .unwrap();
+        let cached = simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await
+            .unwrap();
+        assert_eq!(cached.behavior_fingerprint(), first.behavior_fingerprint());
+        simulator
+            .simulate_validation(
+                simulation_user_operation(),
+                Some(H256::from_low_u64_be(1)),
+                None,
+            )
+            .await
+            .unwrap();
+        // The op hash excludes the signature, but the cache key must not
+        let resigned = UserOperation {
+            signature: Bytes::from(vec![1; 65]),
+            ..simulation_user_operation()
+        };
+        simulator
+            .simulate_validation(resigned, Some(H256::zero()), None)
+            .await
+            .unwrap();
+
+        let stats = simulator.stats();
+        assert_eq!(stats.result_cache_hits, 1);
+        assert_eq!(stats.result_cache_misses, 3);
+    }
+
+    #[tokio::test]
+    async fn test_gas_only() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        // the account writes to storage of an unrelated contract
+        let mut tracer_output = get_test_tracer_output();
         tracer_output.phases[1]
             .storage_accesses
             .push(StorageAccess {
-                address: Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap(),
-                slots: vec![U256::from_str(
-                    "0xa3f946b7ed2f016739c6be6031c5579a53d3784a471c3b5f9c2a1f8706c65a4b",
-                )
-                .unwrap()],
+                address: Address::random(),
+                slots: vec![1.into()],
+                read_slots: vec![],
+                written_slots: vec![1.into()],
             });
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| Ok(tracer_output.clone()));
+        let simulator = create_simulator(provider, tracer);
 
-        let mut validation_context = ValidationContext {
-            block_id: BlockId::Number(BlockNumber::Latest),
-            entity_infos: EntityInfos::new(
-                Some(Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap()),
-                Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
-                Some(Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap()),
-                &ValidationOutput {
-                    return_info: ValidationReturnInfo::from((
-                        U256::default(),
-                        U256::default(),
-                        false,
-                        0,
-                        0,
-                        Bytes::default(),
-                    )),
-                    sender_info: StakeInfo::from((U256::default(), U256::default())),
-                    factory_info: StakeInfo::from((U256::default(), U256::default())),
-                    paymaster_info: StakeInfo::from((U256::default(), U256::default())),
-                    aggregator_info: None,
+        let res = simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await;
+        assert!(matches!(
+            res.unwrap_err(),
+            SimulationError::Violations(violations)
+                if matches!(violations[..], [SimulationViolation::InvalidStorageAccess(..)])
+        ));
+
+        let res = simulator
+            .simulate_validation_with_request(
+                simulation_user_operation(),
+                Some(H256::zero()),
+                None,
+                SimulationRequest {
+                    gas_only: true,
+                    ..Default::default()
                 },
-                Settings::default(),
-            ),
-            tracer_out: tracer_output,
-            entry_point_out: ValidationOutput {
-                return_info: ValidationReturnInfo::from((
-                    U256::default(),
-                    U256::default(),
-                    true,
-                    0,
-                    0,
-                    Bytes::default(),
-                )),
-                sender_info: StakeInfo::from((U256::default(), U256::default())),
-                factory_info: StakeInfo::from((U256::default(), U256::default())),
-                paymaster_info: StakeInfo::from((U256::default(), U256::default())),
-                aggregator_info: None,
-            },
-            is_unstaked_wallet_creation: false,
+            )
+            .await
+            .unwrap();
+        // the gas reported by the entry point in the test trace
+        assert_eq!(res.pre_op_gas, 85_759.into());
+        assert!(res.mempools.is_empty());
+    }
 
-            entities_needing_stake: vec![],
-            accessed_addresses: HashSet::new(),
+    #[tokio::test]
+    async fn test_detect_reorgs() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        expect_passing_contract_checks(&mut provider);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(|_, _, _, _| Ok(get_test_tracer_output()));
+        // by the end of the simulation another block is at the same height
+        provider
+            .expect_get_block::<BlockNumber>()
+            .withf(|number| *number == BlockNumber::Number(100.into()))
+            .returning(|_| {
+                Ok(Some(Block {
+                    hash: Some(H256::from_low_u64_be(1)),
+                    number: Some(100.into()),
+                    ..Default::default()
+                }))
+            });
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.detect_reorgs = true;
+
+        let res = simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await;
+        assert!(matches!(res, Err(SimulationError::ReorgDetected)));
+
+        let res = simulator
+            .simulate_validation(
+                simulation_user_operation(),
+                Some(H256::from_low_u64_be(1)),
+                None,
+            )
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_override() {
+        let mut simulator = create_pool_simulator(get_test_tracer_output());
+        simulator.sim_settings.allow_chain_id_override = true;
+        let request = SimulationRequest {
+            chain_id: Some(10),
+            ..Default::default()
         };
 
+        let res = simulator
+            .simulate_validation_with_request(
+                simulation_user_operation(),
+                Some(H256::zero()),
+                None,
+                request,
+            )
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_must_match_node() {
+        let (mut provider, mut tracer) = create_base_config();
+        provider.expect_get_chain_id().times(1).returning(|| Ok(1));
+        tracer.expect_trace_simulate_validation().never();
         let simulator = create_simulator(provider, tracer);
-        let res = simulator.gather_context_violations(&mut validation_context);
+        let request = SimulationRequest {
+            chain_id: Some(10),
+            ..Default::default()
+        };
 
-        assert_eq!(
-            res.unwrap(),
-            vec![
-                SimulationViolation::InvalidSignature,
-                SimulationViolation::UsedForbiddenOpcode(
-                    Entity {
-                        kind: EntityType::Account,
-                        address: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4")
-                            .unwrap()
-                    },
-                    Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
-                    ViolationOpCode(Opcode::GASPRICE),
-                ),
-                SimulationViolation::UsedForbiddenOpcode(
+        for _ in 0..2 {
+            let res = simulator
+                .simulate_validation_with_request(
+                    simulation_user_operation(),
+                    Some(H256::zero()),
+                    None,
+                    request.clone(),
+                )
+                .await;
+            assert!(matches!(res, Err(SimulationError::Other(_))));
+        }
+    }
+
+    #[derive(Debug)]
+    struct ThrottledEntities(Vec<Entity>);
+
+    impl ReputationChecker for ThrottledEntities {
+        fn is_throttled(&self, entity: &Entity) -> bool {
+            self.0.contains(entity)
+        }
+    }
+
+    fn create_pool_simulator(
+        tracer_output: SimulationTracerOutput,
+    ) -> SimulatorImpl<MockProvider, MockSimulateValidationTracer> {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        provider.expect_get_chain_id().returning(|| Ok(1337));
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| Ok(tracer_output.clone()));
+        expect_passing_contract_checks(&mut provider);
+        create_simulator(provider, tracer)
+    }
+
+    // The eth_call that computes the code hash of the accessed contracts,
+    // expected the given number of times
+    fn expect_code_hash_call(provider: &mut MockProvider, times: impl Into<mockall::TimesRange>) {
+        provider.expect_call().times(times).returning(|_, _| {
+            let json_rpc_error = JsonRpcError {
+                code: -32000,
+                message: "execution reverted".to_string(),
+                data: Some(serde_json::Value::String(
+                    "0x091cd005abf68e7b82c951a8619f065986132f67a0945153533cfcdd93b6895f33dbc0c7"
+                        .to_string(),
+                )),
+            };
+            Err(ProviderError::JsonRpcError(json_rpc_error))
+        });
+    }
+
+    // The code hash and aggregator checks that follow tracing succeed
+    fn expect_passing_contract_checks(provider: &mut MockProvider) {
+        expect_code_hash_call(provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_accept() {
+        let simulator = create_pool_simulator(get_test_tracer_output());
+        let decision = simulator
+            .evaluate_for_pool(simulation_user_operation(), None, H256::zero().into(), None)
+            .await;
+        assert!(matches!(decision, PoolDecision::Accept(_)));
+    }
+
+    #[tokio::test]
+    async fn test_trusted_app_allowlist() {
+        // the account reads an unassociated slot of another contract
+        let mut tracer_output = get_test_tracer_output();
+        tracer_output.phases[1]
+            .storage_accesses
+            .push(StorageAccess {
+                address: Address::random(),
+                slots: vec![U256::one()],
+                read_slots: vec![U256::one()],
+                written_slots: vec![],
+            });
+        let op = simulation_user_operation();
+
+        let simulator = create_pool_simulator(tracer_output.clone());
+        let res = simulator
+            .simulate_validation(op.clone(), Some(H256::zero()), None)
+            .await;
+        assert!(matches!(
+            res.unwrap_err(),
+            SimulationError::Violations(violations)
+                if matches!(violations[..], [SimulationViolation::InvalidStorageAccess(..)])
+        ));
+
+        // The op's callData calls `execute` on the sender itself
+        let target = op.sender;
+        let simulate_with = |allowlist: HashSet<(Address, Address)>, strict_spec_mode: bool| {
+            let mut simulator = create_pool_simulator(tracer_output.clone());
+            simulator.sim_settings.trusted_app_allowlist = allowlist;
+            simulator.sim_settings.strict_spec_mode = strict_spec_mode;
+            let op = op.clone();
+            async move {
+                simulator
+                    .simulate_validation(op, Some(H256::zero()), None)
+                    .await
+            }
+        };
+
+        let res = simulate_with(HashSet::from([(op.sender, target)]), false).await;
+        assert!(res.is_ok(), "unexpected error: {res:?}");
+        // Another target of the same sender is not trusted
+        let res = simulate_with(HashSet::from([(op.sender, Address::random())]), false).await;
+        assert!(res.is_err());
+        // Nor is anything in strict spec mode
+        let res = simulate_with(HashSet::from([(op.sender, target)]), true).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_reject() {
+        let mut tracer_output = get_test_tracer_output();
+        tracer_output.phases[1].forbidden_opcodes_used = vec![String::from(
+            "0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:GASPRICE",
+        )];
+        let simulator = create_pool_simulator(tracer_output);
+
+        let decision = simulator
+            .evaluate_for_pool(simulation_user_operation(), None, H256::zero().into(), None)
+            .await;
+        assert!(matches!(
+            decision,
+            PoolDecision::Reject(SimulationError::Violations(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_replace() {
+        let simulator = create_pool_simulator(get_test_tracer_output());
+        let existing = simulation_user_operation();
+        let op = UserOperation {
+            max_fee_per_gas: existing.max_fee_per_gas * 2,
+            max_priority_fee_per_gas: existing.max_priority_fee_per_gas * 2,
+            ..existing.clone()
+        };
+
+        let decision = simulator
+            .evaluate_for_pool(op, Some(existing.clone()), H256::zero().into(), None)
+            .await;
+        let PoolDecision::Replace(_, replaced) = decision else {
+            panic!("expected a replacement, got {decision:?}");
+        };
+        assert_eq!(
+            replaced,
+            existing.op_hash(simulator.entry_point_address, 1337)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_replace_for_chain() {
+        let mut simulator = create_pool_simulator(get_test_tracer_output());
+        simulator.sim_settings.allow_chain_id_override = true;
+        let existing = simulation_user_operation();
+        let op = UserOperation {
+            max_fee_per_gas: existing.max_fee_per_gas * 2,
+            max_priority_fee_per_gas: existing.max_priority_fee_per_gas * 2,
+            ..existing.clone()
+        };
+
+        let decision = simulator
+            .evaluate_for_pool(op, Some(existing.clone()), H256::zero().into(), Some(10))
+            .await;
+        let PoolDecision::Replace(_, replaced) = decision else {
+            panic!("expected a replacement, got {decision:?}");
+        };
+        assert_eq!(
+            replaced,
+            existing.op_hash(simulator.entry_point_address, 10)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_rejects_underpriced_replacement() {
+        let simulator = create_pool_simulator(get_test_tracer_output());
+        let existing = simulation_user_operation();
+
+        let decision = simulator
+            .evaluate_for_pool(
+                existing.clone(),
+                Some(existing.clone()),
+                H256::zero().into(),
+                None,
+            )
+            .await;
+        let PoolDecision::ReplacementUnderpriced(replaced) = decision else {
+            panic!("expected an underpriced replacement, got {decision:?}");
+        };
+        assert_eq!(
+            replaced,
+            existing.op_hash(simulator.entry_point_address, 1337)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_entry_point_version() {
+        let mut simulator = create_pool_simulator(get_test_tracer_output());
+        simulator.sim_settings.entry_point_version = EntryPointVersion::V0_7;
+        let existing = simulation_user_operation();
+        let expected = existing.op_hash_for_version(
+            EntryPointVersion::V0_7,
+            simulator.entry_point_address,
+            1337,
+        );
+
+        let decision = simulator
+            .evaluate_for_pool(
+                existing.clone(),
+                Some(existing.clone()),
+                H256::zero().into(),
+                None,
+            )
+            .await;
+        let PoolDecision::ReplacementUnderpriced(replaced) = decision else {
+            panic!("expected an underpriced replacement, got {decision:?}");
+        };
+        assert_eq!(replaced, expected);
+
+        let op = UserOperation {
+            max_fee_per_gas: existing.max_fee_per_gas * 2,
+            max_priority_fee_per_gas: existing.max_priority_fee_per_gas * 2,
+            ..existing.clone()
+        };
+        let decision = simulator
+            .evaluate_for_pool(op, Some(existing), H256::zero().into(), None)
+            .await;
+        let PoolDecision::Replace(_, replaced) = decision else {
+            panic!("expected a replacement, got {decision:?}");
+        };
+        assert_eq!(replaced, expected);
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_rejects_mismatched_existing() {
+        let simulator = create_pool_simulator(get_test_tracer_output());
+        let existing = simulation_user_operation();
+        let op = UserOperation {
+            nonce: existing.nonce + 1,
+            max_fee_per_gas: existing.max_fee_per_gas * 2,
+            max_priority_fee_per_gas: existing.max_priority_fee_per_gas * 2,
+            ..existing.clone()
+        };
+
+        let decision = simulator
+            .evaluate_for_pool(op, Some(existing), H256::zero().into(), None)
+            .await;
+        assert!(matches!(
+            decision,
+            PoolDecision::Reject(SimulationError::Other(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_throttle() {
+        let (provider, tracer) = create_base_config();
+        let op = simulation_user_operation();
+        let sender = Entity::account(op.sender);
+        let simulator = create_simulator(provider, tracer)
+            .with_reputation_checker(Arc::new(ThrottledEntities(vec![sender])));
+
+        let decision = simulator
+            .evaluate_for_pool(op, None, H256::zero().into(), None)
+            .await;
+        assert!(matches!(decision, PoolDecision::Throttle(entity) if entity == sender));
+    }
+
+    #[test]
+    fn test_behavior_fingerprint() {
+        let paymaster = Address::random();
+        let token = Address::random();
+        let success = SimulationSuccess {
+            block_hash: H256::random(),
+            entities_needing_stake: vec![EntityType::Paymaster, EntityType::Account],
+            accessed_addresses: HashSet::from([paymaster, token]),
+            opcodes_used: BTreeSet::from([Opcode::SELFBALANCE]),
+            ..Default::default()
+        };
+        // same behavior at a different block with stake reported in another order
+        let same = SimulationSuccess {
+            block_hash: H256::random(),
+            block_number: 100,
+            entities_needing_stake: vec![EntityType::Account, EntityType::Paymaster],
+            ..success.clone()
+        };
+        assert_eq!(success.behavior_fingerprint(), same.behavior_fingerprint());
+
+        let other_address = SimulationSuccess {
+            accessed_addresses: HashSet::from([paymaster, Address::random()]),
+            ..success.clone()
+        };
+        let other_stake = SimulationSuccess {
+            entities_needing_stake: vec![EntityType::Paymaster],
+            ..success.clone()
+        };
+        let other_opcode = SimulationSuccess {
+            opcodes_used: BTreeSet::from([Opcode::ORIGIN]),
+            ..success.clone()
+        };
+        for other in [other_address, other_stake, other_opcode] {
+            assert_ne!(success.behavior_fingerprint(), other.behavior_fingerprint());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_access_report() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let sender = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        let implementation_slot =
+            U256::from_str("0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc")
+                .unwrap();
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                // the account loads both of its slots and stores to slot 0
+                let access = &mut tracer_output.phases[1].storage_accesses[0];
+                access.read_slots = vec![implementation_slot, U256::zero()];
+                access.written_slots = vec![U256::zero()];
+                Ok(tracer_output)
+            });
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await
+            .unwrap();
+        let report = res.storage_access_report();
+        assert_eq!(
+            report[&sender],
+            SlotAccess {
+                reads: HashSet::from([implementation_slot, U256::zero()]),
+                writes: HashSet::from([U256::zero()]),
+            }
+        );
+    }
+
+    async fn denied_violations(settings: impl FnOnce(&mut Settings)) -> Vec<SimulationViolation> {
+        let (provider, mut tracer) = create_base_config();
+        // denied operations are rejected before tracing
+        tracer.expect_trace_simulate_validation().never();
+        let mut simulator = create_simulator(provider, tracer);
+        settings(&mut simulator.sim_settings);
+        let factory = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let paymaster = Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap();
+        let op = UserOperation {
+            init_code: factory.as_bytes().to_vec().into(),
+            paymaster_and_data: paymaster.as_bytes().to_vec().into(),
+            ..simulation_user_operation()
+        };
+        match simulator
+            .simulate_validation(op, Some(H256::zero()), None)
+            .await
+        {
+            Err(ViolationError::Violations(violations)) => violations,
+            res => panic!("expected violations, got {res:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_denied_sender() {
+        let sender = simulation_user_operation().sender;
+        let violations = denied_violations(|settings| {
+            settings.denied_senders.insert(sender);
+        })
+        .await;
+        assert_eq!(violations, vec![SimulationViolation::SenderDenied(sender)]);
+    }
+
+    #[tokio::test]
+    async fn test_denied_factory() {
+        let factory = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let violations = denied_violations(|settings| {
+            settings.denied_factories.insert(factory);
+        })
+        .await;
+        assert_eq!(
+            violations,
+            vec![SimulationViolation::FactoryDenied(factory)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_denied_paymaster() {
+        let paymaster = Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap();
+        let violations = denied_violations(|settings| {
+            settings.denied_paymasters.insert(paymaster);
+        })
+        .await;
+        assert_eq!(
+            violations,
+            vec![SimulationViolation::PaymasterDenied(paymaster)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_factory_not_deployed() {
+        let (mut provider, mut tracer) = create_base_config();
+        let factory = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        provider
+            .expect_get_code()
+            .withf(move |address, block_hash| {
+                *address == factory && *block_hash == Some(H256::zero())
+            })
+            .returning(|_, _| Ok(Bytes::new()));
+        // the doomed creation is not traced
+        tracer.expect_trace_simulate_validation().never();
+        let simulator = create_simulator(provider, tracer);
+        let op = UserOperation {
+            init_code: factory.as_bytes().to_vec().into(),
+            ..simulation_user_operation()
+        };
+
+        match simulator
+            .simulate_validation(op, Some(H256::zero()), None)
+            .await
+        {
+            Err(ViolationError::Violations(violations)) => assert_eq!(
+                violations,
+                vec![SimulationViolation::FactoryNotDeployed(factory)]
+            ),
+            res => panic!("expected violations, got {res:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_factory_deployed_at_simulation_block() {
+        let (mut provider, mut tracer) = create_base_config();
+        let factory = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let deploy_block = H256::from_low_u64_be(100);
+        // the factory is deployed in the block simulated against, so the
+        // latest block may not have it yet
+        provider
+            .expect_get_code()
+            .returning(move |_, block_hash| match block_hash {
+                Some(block_hash) if block_hash == deploy_block => Ok(vec![0x60].into()),
+                _ => Ok(Bytes::new()),
+            });
+        expect_get_block(&mut provider);
+        expect_passing_contract_checks(&mut provider);
+        tracer
+            .expect_trace_simulate_validation()
+            .times(1)
+            .returning(|_, _, _, _| Ok(get_test_tracer_output()));
+        let simulator = create_simulator(provider, tracer);
+        let op = UserOperation {
+            init_code: factory.as_bytes().to_vec().into(),
+            ..simulation_user_operation()
+        };
+
+        let res = simulator
+            .simulate_validation(op.clone(), Some(deploy_block), None)
+            .await;
+        assert!(res.is_ok(), "{res:?}");
+
+        let res = simulator
+            .simulate_validation(op, Some(H256::from_low_u64_be(99)), None)
+            .await;
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations))
+                if violations == vec![SimulationViolation::FactoryNotDeployed(factory)]
+        ));
+    }
+
+    #[derive(Debug)]
+    struct DropCodeHashChanged;
+
+    impl ViolationFilter for DropCodeHashChanged {
+        fn filter(&self, violations: Vec<SimulationViolation>) -> Vec<SimulationViolation> {
+            violations
+                .into_iter()
+                .filter(|v| !matches!(v, SimulationViolation::CodeHashChanged(_)))
+                .collect()
+        }
+    }
+
+    fn create_code_hash_simulator() -> SimulatorImpl<MockProvider, MockSimulateValidationTracer> {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        // the code hash returned does not match the expected zero hash
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+        create_simulator(provider, tracer)
+    }
+
+    #[tokio::test]
+    async fn test_violation_filter_drops_code_hash_changed() {
+        let user_operation = UserOperation {
+            sender: Address::from_str("b856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+            ..Default::default()
+        };
+
+        let res = create_code_hash_simulator()
+            .simulate_validation(
+                user_operation.clone(),
+                Some(H256::zero()),
+                Some(H256::zero()),
+            )
+            .await;
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations))
+                if violations == vec![SimulationViolation::CodeHashChanged(vec![])]
+        ));
+
+        let res = create_code_hash_simulator()
+            .with_violation_filter(Arc::new(DropCodeHashChanged))
+            .simulate_validation(user_operation, Some(H256::zero()), Some(H256::zero()))
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_code_hash_changed_names_contracts() {
+        let changed = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_get_code()
+            .returning(|address, _| Ok(Bytes::from(address.as_bytes().to_vec())));
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+        let simulator = create_simulator(provider, tracer);
+
+        // every contract but one has the code it had before
+        let expected_code_hashes = get_test_tracer_output()
+            .accessed_contract_addresses
+            .into_iter()
+            .map(|address| {
+                let hash = if address == changed {
+                    H256::random()
+                } else {
+                    H256(keccak256(address.as_bytes()))
+                };
+                (address, hash)
+            })
+            .collect();
+        let res = simulator
+            .simulate_validation_with_request(
+                UserOperation {
+                    sender: Address::from_str("b856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+                    ..Default::default()
+                },
+                Some(H256::zero()),
+                Some(H256::zero()),
+                SimulationRequest {
+                    expected_code_hashes,
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations))
+                if violations == vec![SimulationViolation::CodeHashChanged(vec![changed])]
+        ));
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let success = SimulationSuccess {
+            block_number: 100,
+            ..Default::default()
+        };
+        assert!(!is_stale(&success, 100, 5));
+        assert!(!is_stale(&success, 105, 5));
+        assert!(is_stale(&success, 106, 5));
+        // a head behind the simulation block is never stale
+        assert!(!is_stale(&success, 90, 5));
+    }
+
+    #[test]
+    fn test_detect_read_after_write() {
+        let token = Address::random();
+        let accesses = |reads: &[u64], writes: &[u64]| SimulationSuccess {
+            storage_accesses: vec![HashMap::from([(
+                token,
+                SlotAccess {
+                    reads: reads.iter().map(|&slot| slot.into()).collect(),
+                    writes: writes.iter().map(|&slot| slot.into()).collect(),
+                },
+            )])],
+            ..Default::default()
+        };
+        let ops: Vec<_> = (0..3)
+            .map(|_| UserOperation {
+                sender: Address::random(),
+                ..Default::default()
+            })
+            .collect();
+        // op 0 writes slot 1, op 1 reads it and op 2 only reads other slots
+        let successes = [
+            accesses(&[], &[1]),
+            accesses(&[1], &[]),
+            accesses(&[2], &[]),
+        ];
+
+        assert_eq!(
+            detect_read_after_write(&successes, &ops).unwrap(),
+            vec![StorageConflict {
+                writer_index: 0,
+                writer: ops[0].sender,
+                reader_index: 1,
+                reader: ops[1].sender,
+                slot: StorageSlot {
+                    address: token,
+                    slot: 1.into(),
+                },
+            }]
+        );
+        // a read before the write is not a hazard
+        let successes = [accesses(&[1], &[]), accesses(&[], &[1])];
+        assert!(detect_read_after_write(&successes, &ops[..2])
+            .unwrap()
+            .is_empty());
+        // every operation needs a simulation result
+        assert!(detect_read_after_write(&successes, &ops).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sender_nonce_after() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        // nonce with key 5 and sequence 3
+        let key = U256::from(5) << 64;
+        let user_operation = UserOperation {
+            sender: Address::from_str("b856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+            nonce: key + 3,
+            ..Default::default()
+        };
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .simulate_validation(user_operation, Some(H256::zero()), None)
+            .await
+            .unwrap();
+        assert_eq!(res.sender_nonce_after, key + 4);
+        assert_eq!(res.nonce_key, 5.into());
+    }
+
+    #[tokio::test]
+    async fn test_prewarm() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let block_hash =
+            H256::from_str("0x38138f1cb4653ab6ab1c89ae3a6acc8705b54bd16a997d880c4421014ed66c3d")
+                .unwrap();
+
+        // Both the trace and the code hash are fetched once, during prewarming
+        tracer
+            .expect_trace_simulate_validation()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, 1);
+
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        let user_operation = simulation_user_operation();
+
+        let simulator = create_simulator(provider, tracer);
+        simulator
+            .prewarm(&[user_operation.clone()], block_hash.into())
+            .await
+            .unwrap();
+        let res = simulator
+            .simulate_validation(user_operation, Some(block_hash), None)
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_prewarm_by_number() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let block_hash =
+            H256::from_str("0x38138f1cb4653ab6ab1c89ae3a6acc8705b54bd16a997d880c4421014ed66c3d")
+                .unwrap();
+        provider
+            .expect_get_block::<BlockId>()
+            .times(1)
+            .returning(move |_| {
+                Ok(Some(Block {
+                    hash: Some(block_hash),
+                    number: Some(100.into()),
+                    ..Default::default()
+                }))
+            });
+
+        // The block number is resolved to its hash, so simulating by hash
+        // uses the prewarmed trace and code hash
+        tracer
+            .expect_trace_simulate_validation()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, 1);
+
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        let user_operation = simulation_user_operation();
+        let simulator = create_simulator(provider, tracer);
+        simulator
+            .prewarm(
+                &[user_operation.clone()],
+                BlockNumber::Number(100.into()).into(),
+            )
+            .await
+            .unwrap();
+        // A lookup at another block keeps the prewarmed results
+        assert!(simulator.cache.lock().get(H256::zero().into()).is_none());
+        let res = simulator
+            .simulate_validation(user_operation, Some(block_hash), None)
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_prewarm_bypass_caches() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let block_hash =
+            H256::from_str("0x38138f1cb4653ab6ab1c89ae3a6acc8705b54bd16a997d880c4421014ed66c3d")
+                .unwrap();
+
+        // The prewarmed trace and code hash are ignored, so both are fetched
+        // again during simulation
+        tracer
+            .expect_trace_simulate_validation()
+            .times(2)
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, 2);
+
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        let user_operation = simulation_user_operation();
+
+        let simulator = create_simulator(provider, tracer);
+        simulator
+            .prewarm(&[user_operation.clone()], block_hash.into())
+            .await
+            .unwrap();
+        let res = simulator
+            .simulate_validation_with_request(
+                user_operation,
+                Some(block_hash),
+                None,
+                SimulationRequest {
+                    bypass_caches: true,
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bypass_caches_ignores_block_source() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_latest_block(&mut provider);
+        expect_get_block(&mut provider);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        // the source serves a head the node has moved past
+        let stale_head = CurrentBlock {
+            hash: H256::random(),
+            number: 99,
+            gas_limit: 30_000_000.into(),
+            ..Default::default()
+        };
+        let block_source = Arc::new(InMemoryBlockSource::default());
+        block_source.update(stale_head);
+        let simulator = create_simulator(provider, tracer).with_block_source(block_source);
+        let res = simulator
+            .simulate_validation_with_request(
+                simulation_user_operation(),
+                None,
+                None,
+                SimulationRequest {
+                    bypass_caches: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            res.block_hash,
+            H256::from_str("0x38138f1cb4653ab6ab1c89ae3a6acc8705b54bd16a997d880c4421014ed66c3d")
+                .unwrap()
+        );
+        assert_eq!(res.block_number, 100);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_validation_provider_error() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+
+        // The tracer fails to reach the node
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(|_, _, _, _| {
+                Err(
+                    ProviderError::Other(anyhow!("error sending request: connection refused"))
+                        .into(),
+                )
+            });
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .simulate_validation(UserOperation::default(), Some(H256::zero()), None)
+            .await;
+        assert!(matches!(
+            res,
+            Err(SimulationError::Provider(ProviderError::Other(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_validation_skips_optional_checks() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+
+        expect_get_latest_block(&mut provider);
+
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                // use a staked aggregator so that the aggregator signature check would be needed
+                tracer_output.revert_data = Some(hex::encode(
+                    ValidationResultWithAggregation {
+                        return_info: (
+                            U256::from(1000),
+                            U256::default(),
+                            false,
+                            0,
+                            u64::MAX >> 16,
+                            Bytes::default(),
+                        ),
+                        sender_info: (U256::default(), U256::default()),
+                        factory_info: (U256::default(), U256::default()),
+                        paymaster_info: (U256::default(), U256::default()),
+                        aggregator_info: (
+                            Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6")
+                                .unwrap(),
+                            (U256::exp10(18), U256::from(84600)),
+                        ),
+                    }
+                    .encode(),
+                ));
+                Ok(tracer_output)
+            });
+
+        // Neither the code hash nor the aggregator signature should be requested
+        provider.expect_call().never();
+        provider.expect_validate_user_op_signature().never();
+
+        let user_operation = simulation_user_operation();
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .simulate_validation_with_request(
+                user_operation,
+                None,
+                Some(H256::random()),
+                SimulationRequest {
+                    want_code_hash: false,
+                    want_aggregator_sig: false,
+                    want_code_hashes: false,
+                    expected_code_hashes: HashMap::new(),
+                    gas_limits: GasLimitMode::AsProvided,
+                    bypass_caches: false,
+                    chain_id: None,
+                    gas_only: false,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.code_hash, H256::zero());
+        assert!(res.aggregator.is_none());
+        assert_eq!(res.entities_needing_stake, vec![EntityType::Aggregator]);
+    }
+
+    fn aggregated_op_simulator(
+        mut provider: MockProvider,
+    ) -> SimulatorImpl<MockProvider, MockSimulateValidationTracer> {
+        let mut tracer = MockSimulateValidationTracer::new();
+        tracer.expect_tracer_hash().return_const(None);
+        expect_get_block(&mut provider);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                tracer_output.revert_data = Some(hex::encode(
+                    ValidationResultWithAggregation {
+                        return_info: (
+                            U256::from(1000),
+                            U256::default(),
+                            false,
+                            0,
+                            u64::MAX >> 16,
+                            Bytes::default(),
+                        ),
+                        sender_info: (U256::default(), U256::default()),
+                        factory_info: (U256::default(), U256::default()),
+                        paymaster_info: (U256::default(), U256::default()),
+                        aggregator_info: (
+                            Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6")
+                                .unwrap(),
+                            (U256::exp10(18), U256::from(84600)),
+                        ),
+                    }
+                    .encode(),
+                ));
+                Ok(tracer_output)
+            });
+        expect_code_hash_call(&mut provider, ..);
+        create_simulator(provider, tracer)
+    }
+
+    async fn simulate_with_rejecting_aggregator(
+        policy: AggregatorFailurePolicy,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        let mut provider = MockProvider::new();
+        // the aggregator rejects the signature
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::ValidationReverted));
+
+        let mut simulator = aggregated_op_simulator(provider);
+        simulator.sim_settings.aggregator_failure_policy = policy;
+        simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_failure_policy_reject() {
+        let res = simulate_with_rejecting_aggregator(AggregatorFailurePolicy::Reject).await;
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations))
+                if violations == vec![SimulationViolation::AggregatorValidationFailed]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_failure_policy_warn() {
+        let res = simulate_with_rejecting_aggregator(AggregatorFailurePolicy::Warn)
+            .await
+            .unwrap();
+        assert!(res.aggregator.is_none());
+        assert_eq!(
+            res.warnings,
+            vec![SimulationViolation::AggregatorValidationFailed]
+        );
+    }
+
+    #[derive(Debug)]
+    struct CannedAggregatorValidator(AggregatorOut);
+
+    #[async_trait]
+    impl AggregatorValidator for CannedAggregatorValidator {
+        async fn validate_user_op_signature(
+            &self,
+            _aggregator_address: Address,
+            _op: UserOperation,
+            _gas_cap: u64,
+        ) -> anyhow::Result<AggregatorOut> {
+            Ok(self.0.clone())
+        }
+    }
+
+    async fn simulate_with_canned_aggregator(
+        out: AggregatorOut,
+    ) -> Result<SimulationSuccess, SimulationError> {
+        let mut provider = MockProvider::new();
+        // the aggregator contract is never called
+        provider.expect_validate_user_op_signature().never();
+        aggregated_op_simulator(provider)
+            .with_aggregator_validator(Arc::new(CannedAggregatorValidator(out)))
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_validator_success() {
+        let aggregator = AggregatorSimOut {
+            address: Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap(),
+            signature: Bytes::from_str("0x1234").unwrap(),
+        };
+        let res =
+            simulate_with_canned_aggregator(AggregatorOut::SuccessWithInfo(aggregator.clone()))
+                .await
+                .unwrap();
+        let res_aggregator = res.aggregator.unwrap();
+        assert_eq!(res_aggregator.address, aggregator.address);
+        assert_eq!(res_aggregator.signature, aggregator.signature);
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_validator_not_needed() {
+        let res = simulate_with_canned_aggregator(AggregatorOut::NotNeeded)
+            .await
+            .unwrap();
+        assert!(res.aggregator.is_none());
+        assert!(res.warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_validator_empty_signature() {
+        let address = Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap();
+        let res =
+            simulate_with_canned_aggregator(AggregatorOut::SuccessWithInfo(AggregatorSimOut {
+                address,
+                signature: Bytes::new(),
+            }))
+            .await;
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations))
+                if violations == vec![SimulationViolation::AggregatorReturnedEmptySignature(address)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_validator_reverted() {
+        let res = simulate_with_canned_aggregator(AggregatorOut::ValidationReverted).await;
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations))
+                if violations == vec![SimulationViolation::AggregatorValidationFailed]
+        ));
+    }
+
+    async fn traced_verification_gas_limit(gas_limits: GasLimitMode) -> U256 {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let traced_limit = Arc::new(Mutex::new(U256::zero()));
+        let traced_limit_clone = Arc::clone(&traced_limit);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |op, _, _, _| {
+                *traced_limit_clone.lock() = op.verification_gas_limit;
+                Ok(get_test_tracer_output())
+            });
+
+        let user_operation = UserOperation {
+            sender: Address::from_str("b856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+            verification_gas_limit: U256::from(64805),
+            ..Default::default()
+        };
+        let simulator = create_simulator(provider, tracer);
+        simulator
+            .simulate_validation_with_request(
+                user_operation,
+                Some(H256::zero()),
+                None,
+                SimulationRequest {
+                    want_code_hash: false,
+                    want_aggregator_sig: false,
+                    want_code_hashes: false,
+                    expected_code_hashes: HashMap::new(),
+                    gas_limits,
+                    bypass_caches: false,
+                    chain_id: None,
+                    gas_only: false,
+                },
+            )
+            .await
+            .unwrap();
+        let limit = *traced_limit.lock();
+        limit
+    }
+
+    #[tokio::test]
+    async fn test_gas_limit_mode_as_provided() {
+        assert_eq!(
+            traced_verification_gas_limit(GasLimitMode::AsProvided).await,
+            U256::from(64805)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gas_limit_mode_maximal() {
+        assert_eq!(
+            traced_verification_gas_limit(GasLimitMode::Maximal).await,
+            U256::from(Settings::default().max_verification_gas)
+        );
+    }
+
+    #[test]
+    fn test_violation_rule_id() {
+        let entity = Entity::account(Address::zero());
+        let opcode = ViolationOpCode(Opcode::GAS);
+        let cases = [
+            (
+                SimulationViolation::UsedForbiddenOpcode(
+                    entity,
+                    Address::zero(),
+                    opcode.clone(),
+                    1,
+                ),
+                Some("OP-011"),
+            ),
+            (SimulationViolation::UsedBlockHash(entity), Some("OP-011")),
+            (SimulationViolation::OutOfGas(entity), Some("OP-020")),
+            (
+                SimulationViolation::FactoryCalledCreate2Twice(Address::zero()),
+                Some("OP-031"),
+            ),
+            (
+                SimulationViolation::AccessedUndeployedContract(entity, Address::zero()),
+                Some("OP-041"),
+            ),
+            (SimulationViolation::CalledHandleOps(entity), Some("OP-054")),
+            (
+                SimulationViolation::CalledBannedEntryPointMethod(entity),
+                Some("OP-054"),
+            ),
+            (SimulationViolation::CallHadValue(entity), Some("OP-061")),
+            (
+                SimulationViolation::UsedForbiddenPrecompile(
+                    entity,
+                    Address::zero(),
+                    Address::zero(),
+                ),
+                Some("OP-062"),
+            ),
+            (
+                SimulationViolation::TransientStorageAccess(entity),
+                Some("OP-070"),
+            ),
+            (
+                SimulationViolation::NotStaked {
+                    entity,
+                    actual_stake: U256::zero(),
+                    required_stake: U256::zero(),
+                    actual_delay: U256::zero(),
+                    required_delay: U256::zero(),
+                },
+                Some("STO-031"),
+            ),
+            (
+                SimulationViolation::InvalidStorageAccess(
+                    entity,
+                    Address::zero(),
+                    vec![U256::zero()],
+                ),
+                Some("STO-033"),
+            ),
+            (
+                SimulationViolation::CodeHashChanged(vec![]),
+                Some("COD-010"),
+            ),
+            (SimulationViolation::InvalidSignature, None),
+            (SimulationViolation::RestrictedOpcode(entity, opcode), None),
+            (SimulationViolation::TooManyAccessedAddresses(1), None),
+            (
+                SimulationViolation::OpGasExceedsBlockLimit(U256::zero(), U256::zero()),
+                None,
+            ),
+            (
+                SimulationViolation::UnintendedRevertWithMessage(
+                    EntityType::Account,
+                    String::new(),
+                    None,
+                ),
+                None,
+            ),
+            (
+                SimulationViolation::UnintendedRevert(EntityType::Account),
+                None,
+            ),
+            (SimulationViolation::DidNotRevert(Bytes::default()), None),
+            (SimulationViolation::WrongNumberOfPhases(2), None),
+            (SimulationViolation::AggregatorValidationFailed, None),
+        ];
+        for (violation, rule_id) in cases {
+            assert_eq!(violation.rule_id(), rule_id, "{violation:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_context_two_phases_unintended_revert() {
+        let (provider, mut tracer) = create_base_config();
+
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(|_, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                tracer_output.revert_data = Some(hex::encode(
+                    FailedOp {
+                        op_index: U256::from(100),
+                        reason: "AA23 reverted (or OOG)".to_string(),
+                    }
+                    .encode(),
+                ));
+                Ok(tracer_output)
+            });
+
+        let user_operation = simulation_user_operation();
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .create_context(
+                user_operation,
+                BlockId::Number(BlockNumber::Latest),
+                None,
+                false,
+            )
+            .await;
+
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations)) if matches!(
+                violations.get(0),
+                Some(&SimulationViolation::UnintendedRevertWithMessage(
+                    EntityType::Paymaster,
+                    ref reason,
+                    _
+                )) if reason == "AA23 reverted (or OOG)"
+            )
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_with_sender_code() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let sender = Address::from_str("b856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        // PUSH1 0 PUSH1 0 REVERT
+        let sender_code = Bytes::from_str("0x60006000fd").unwrap();
+        let mut expected_overrides = spoof::State::default();
+        expected_overrides.account(sender).code(sender_code.clone());
+
+        // the account reverts during validation under the overridden code
+        tracer
+            .expect_trace_simulate_validation()
+            .withf(move |_, _, _, state_overrides| {
+                state_overrides.as_ref() == Some(&expected_overrides)
+            })
+            .returning(|_, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                tracer_output.phases.truncate(2);
+                tracer_output.revert_data = Some(hex::encode(
+                    FailedOp {
+                        op_index: U256::zero(),
+                        reason: "AA23 reverted (or OOG)".to_string(),
+                    }
+                    .encode(),
+                ));
+                Ok(tracer_output)
+            });
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .simulate_with_sender_code(
+                UserOperation {
+                    sender,
+                    ..Default::default()
+                },
+                sender_code,
+                Some(H256::zero()),
+            )
+            .await;
+
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations)) if matches!(
+                violations.get(0),
+                Some(&SimulationViolation::UnintendedRevertWithMessage(
+                    EntityType::Account,
+                    ref reason,
+                    Some(address),
+                )) if reason == "AA23 reverted (or OOG)" && address == sender
+            )
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_with_gas_overrides() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        expect_passing_contract_checks(&mut provider);
+        let op = simulation_user_operation();
+        let deposit_covers_fee = op.max_fee_per_gas;
+
+        // the sender's deposit only covers the prefund at the operation's own fee
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |op, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                if op.max_fee_per_gas > deposit_covers_fee {
+                    tracer_output.phases.truncate(2);
+                    tracer_output.revert_data = Some(hex::encode(
+                        FailedOp {
+                            op_index: U256::zero(),
+                            reason: "AA21 didn't pay prefund".to_string(),
+                        }
+                        .encode(),
+                    ));
+                }
+                Ok(tracer_output)
+            });
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .simulate_with_gas_overrides(op.clone(), GasOverrides::default(), H256::zero().into())
+            .await;
+        assert!(res.is_ok());
+
+        let overrides = GasOverrides {
+            max_fee_per_gas: Some(op.max_fee_per_gas * 2),
+            max_priority_fee_per_gas: Some(op.max_priority_fee_per_gas * 2),
+            ..Default::default()
+        };
+        let res = simulator
+            .simulate_with_gas_overrides(op, overrides, H256::zero().into())
+            .await;
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations)) if matches!(
+                violations.get(0),
+                Some(&SimulationViolation::UnintendedRevertWithMessage(
+                    EntityType::Account,
+                    ref reason,
+                    _,
+                )) if reason == "AA21 didn't pay prefund"
+            )
+        ));
+    }
+
+    #[derive(Debug)]
+    struct MaxCallDataSize(usize);
+
+    impl CustomPrecheck for MaxCallDataSize {
+        fn check(&self, op: &UserOperation) -> Result<(), SimulationViolation> {
+            if op.call_data.len() > self.0 {
+                return Err(SimulationViolation::CustomPrecheckFailed(format!(
+                    "call data is {} bytes but must be at most {}",
+                    op.call_data.len(),
+                    self.0
+                )));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_precheck() {
+        let op = simulation_user_operation();
+        let call_data_len = op.call_data.len();
+
+        let simulator = create_pool_simulator(get_test_tracer_output())
+            .with_custom_precheck(Arc::new(MaxCallDataSize(call_data_len)));
+        let res = simulator
+            .simulate_validation(op.clone(), Some(H256::zero()), None)
+            .await;
+        assert!(res.is_ok(), "{res:?}");
+
+        let (provider, mut tracer) = create_base_config();
+        // the operation is rejected before it is traced
+        tracer.expect_trace_simulate_validation().never();
+        let simulator = create_simulator(provider, tracer)
+            .with_custom_precheck(Arc::new(MaxCallDataSize(call_data_len - 1)));
+        let res = simulator
+            .simulate_validation(op, Some(H256::zero()), None)
+            .await;
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations)) if violations == vec![
+                SimulationViolation::CustomPrecheckFailed(format!(
+                    "call data is {call_data_len} bytes but must be at most {}",
+                    call_data_len - 1
+                ))
+            ]
+        ));
+    }
+
+    #[derive(Debug)]
+    struct ChannelSink(tokio::sync::mpsc::UnboundedSender<(H256, bool)>);
+
+    #[async_trait]
+    impl SimulationSink for ChannelSink {
+        async fn on_simulation(
+            &self,
+            op_hash: H256,
+            result: Result<SimulationSuccess, SimulationError>,
+        ) {
+            self.0.send((op_hash, result.is_ok())).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulation_sink() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let simulator = create_pool_simulator(get_test_tracer_output())
+            .with_sink(Arc::new(ChannelSink(tx)))
+            .with_custom_precheck(Arc::new(MaxCallDataSize(0)));
+        let accepted = UserOperation {
+            call_data: Bytes::new(),
+            ..simulation_user_operation()
+        };
+        let rejected = simulation_user_operation();
+        let hash_of = |op: &UserOperation| op.op_hash(simulator.entry_point_address, 1337);
+
+        let res = simulator
+            .simulate_validation(accepted.clone(), Some(H256::zero()), None)
+            .await;
+        assert!(res.is_ok(), "{res:?}");
+        assert_eq!(rx.recv().await, Some((hash_of(&accepted), true)));
+
+        let res = simulator
+            .simulate_validation(rejected.clone(), Some(H256::zero()), None)
+            .await;
+        assert!(res.is_err());
+        assert_eq!(rx.recv().await, Some((hash_of(&rejected), false)));
+    }
+
+    #[tokio::test]
+    async fn test_simulation_sink_entry_point_version() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut simulator = create_pool_simulator(get_test_tracer_output())
+            .with_sink(Arc::new(ChannelSink(tx)))
+            .with_custom_precheck(Arc::new(MaxCallDataSize(0)));
+        simulator.sim_settings.entry_point_version = EntryPointVersion::V0_7;
+        let op = simulation_user_operation();
+
+        let res = simulator
+            .simulate_validation(op.clone(), Some(H256::zero()), None)
+            .await;
+        assert!(res.is_err());
+        let expected =
+            op.op_hash_for_version(EntryPointVersion::V0_7, simulator.entry_point_address, 1337);
+        assert_ne!(expected, op.op_hash(simulator.entry_point_address, 1337));
+        assert_eq!(rx.recv().await, Some((expected, false)));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_validation_raw() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(|_, _, _, _| Ok(get_test_tracer_output()));
+        let simulator = create_simulator(provider, tracer);
+
+        let out = simulator
+            .simulate_validation_raw(simulation_user_operation(), H256::zero().into())
+            .await
+            .unwrap();
+        assert_eq!(out.return_info.pre_op_gas, 85_759.into());
+        assert_eq!(out.return_info.prefund, 12_603_477_003_300_u64.into());
+        assert!(!out.return_info.sig_failed);
+        assert_eq!(out.return_info.valid_after, Timestamp::MIN);
+        assert_eq!(
+            out.return_info.valid_until,
+            Timestamp::from((1_u64 << 48) - 1)
+        );
+        assert!(out.return_info.paymaster_context.is_empty());
+        assert!(out.sender_info.stake.is_zero());
+        assert!(out.factory_info.stake.is_zero());
+        assert!(out.paymaster_info.stake.is_zero());
+        assert!(out.aggregator_info.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_simulate_validation_across_blocks() {
+        let (mut provider, mut tracer) = create_base_config();
+        provider
+            .expect_get_block::<BlockId>()
+            .returning(|block_id| match block_id {
+                BlockId::Number(BlockNumber::Number(number)) => Ok(Some(Block {
+                    hash: Some(H256::from_low_u64_be(number.as_u64())),
+                    number: Some(number),
+                    ..Default::default()
+                })),
+                _ => panic!("blocks should be loaded by number"),
+            });
+        expect_get_block(&mut provider);
+        expect_passing_contract_checks(&mut provider);
+        // the account's validation reverts at block 99 only
+        let unstable_block = H256::from_low_u64_be(99);
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, block_id, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                if block_id == unstable_block.into() {
+                    tracer_output.phases.truncate(2);
+                    tracer_output.revert_data = Some(hex::encode(
+                        FailedOp {
+                            op_index: U256::zero(),
+                            reason: "AA23 reverted (or OOG)".to_string(),
+                        }
+                        .encode(),
+                    ));
+                }
+                Ok(tracer_output)
+            });
+        let simulator = create_simulator(provider, tracer);
+
+        let results = simulator
+            .simulate_validation_across_blocks(simulation_user_operation(), vec![98, 99, 100])
+            .await;
+
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(ViolationError::Violations(ref violations)) if matches!(
+                violations.get(0),
+                Some(SimulationViolation::UnintendedRevertWithMessage(
+                    EntityType::Account,
+                    reason,
+                    _,
+                )) if reason == "AA23 reverted (or OOG)"
+            )
+        ));
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn test_gas_overrides_apply() {
+        let op = simulation_user_operation();
+        let overrides = GasOverrides {
+            call_gas_limit: Some(1.into()),
+            pre_verification_gas: Some(2.into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            overrides.apply(op.clone()),
+            UserOperation {
+                call_gas_limit: 1.into(),
+                pre_verification_gas: 2.into(),
+                ..op.clone()
+            }
+        );
+        assert_eq!(GasOverrides::default().apply(op.clone()), op);
+    }
+
+    #[tokio::test]
+    async fn test_simulate_validation_after() {
+        let (mut provider, mut tracer) = create_base_config();
+        expect_get_block(&mut provider);
+        let entry_point = Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap();
+        let op_b = UserOperation {
+            nonce: 1.into(),
+            ..simulation_user_operation()
+        };
+        let op_a = UserOperation {
+            nonce: 0.into(),
+            ..op_b.clone()
+        };
+        // nonceSequenceNumber[sender][0], a nested mapping at slot 1
+        let inner = keccak256((op_a.sender, U256::one()).encode());
+        let nonce_slot = H256(keccak256((U256::zero(), H256(inner)).encode()));
+
+        // executing op A increments the sender's nonce on the entry point
+        provider
+            .expect_debug_trace_call()
+            .returning(move |_, _, _| {
+                Ok(GethTrace::Unknown(serde_json::json!({
+                    "pre": { format!("{entry_point:?}"): { "storage": {} } },
+                    "post": {
+                        format!("{entry_point:?}"): {
+                            "storage": { format!("{nonce_slot:?}"): format!("{:?}", H256::from_low_u64_be(1)) }
+                        }
+                    }
+                })))
+            });
+        // op B's validation only passes the nonce check after op A
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, state_overrides| {
+                let mut tracer_output = get_test_tracer_output();
+                let nonce = state_overrides
+                    .and_then(|mut state| state.account(entry_point).storage.take())
+                    .and_then(|storage| storage.get(&nonce_slot).copied())
+                    .unwrap_or_default();
+                if nonce != H256::from_low_u64_be(1) {
+                    tracer_output.phases.truncate(2);
+                    tracer_output.revert_data = Some(hex::encode(
+                        FailedOp {
+                            op_index: U256::zero(),
+                            reason: "AA25 invalid account nonce".to_string(),
+                        }
+                        .encode(),
+                    ));
+                }
+                Ok(tracer_output)
+            });
+        expect_code_hash_call(&mut provider, ..);
+        provider
+            .expect_validate_user_op_signature()
+            .returning(|_, _, _| Ok(AggregatorOut::NotNeeded));
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .simulate_validation_after(&[op_a], op_b.clone(), Some(H256::zero()))
+            .await
+            .unwrap();
+        assert_eq!(res.sender_nonce_after, 2.into());
+
+        // without op A, op B is rejected
+        let res = simulator
+            .simulate_validation(op_b, Some(H256::zero()), None)
+            .await;
+        assert!(matches!(res, Err(ViolationError::Violations(_))));
+    }
+
+    #[tokio::test]
+    async fn test_create_context_did_not_revert() {
+        let (provider, mut tracer) = create_base_config();
+
+        // the entry point returned data instead of reverting
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(|_, _, _, _| {
+                let mut tracer_output = get_test_tracer_output();
+                tracer_output.revert_data = None;
+                tracer_output.return_data = "0x1234".into();
+                Ok(tracer_output)
+            });
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator
+            .create_context(
+                UserOperation::default(),
+                BlockId::Number(BlockNumber::Latest),
+                None,
+                false,
+            )
+            .await;
+
+        assert!(matches!(
+            res,
+            Err(ViolationError::Violations(violations)) if violations == vec![
+                SimulationViolation::DidNotRevert(Bytes::from_str("0x1234").unwrap())
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_gather_context_violations() {
+        let (provider, tracer) = create_base_config();
+
+        let mut tracer_output = get_test_tracer_output();
+
+        // add forbidden opcodes and precompiles
+        tracer_output.phases[1].forbidden_opcodes_used = vec![
+            String::from("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:GASPRICE"),
+            String::from("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:COINBASE"),
+        ];
+        tracer_output.phases[1].forbidden_precompiles_used = vec![String::from(
+            "0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:0x0000000000000000000000000000000000000019",
+        )];
+
+        // add a storage access for a random unrelated address
+        tracer_output.phases[1]
+            .storage_accesses
+            .push(StorageAccess {
+                address: Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap(),
+                slots: vec![U256::from_str(
+                    "0xa3f946b7ed2f016739c6be6031c5579a53d3784a471c3b5f9c2a1f8706c65a4b",
+                )
+                .unwrap()],
+                read_slots: vec![],
+                written_slots: vec![],
+            });
+
+        let mut validation_context = ValidationContext {
+            block_id: BlockId::Number(BlockNumber::Latest),
+            entity_infos: EntityInfos::new(
+                Some(Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap()),
+                Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+                Some(Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap()),
+                &ValidationOutput {
+                    return_info: ValidationReturnInfo::from((
+                        U256::default(),
+                        U256::default(),
+                        false,
+                        0,
+                        0,
+                        Bytes::default(),
+                    )),
+                    sender_info: StakeInfo::from((U256::default(), U256::default())),
+                    factory_info: StakeInfo::from((U256::default(), U256::default())),
+                    paymaster_info: StakeInfo::from((U256::default(), U256::default())),
+                    aggregator_info: None,
+                },
+                default_is_staked,
+            ),
+            tracer_out: tracer_output,
+            entry_point_out: ValidationOutput {
+                return_info: ValidationReturnInfo::from((
+                    U256::default(),
+                    U256::default(),
+                    true,
+                    0,
+                    0,
+                    Bytes::default(),
+                )),
+                sender_info: StakeInfo::from((U256::default(), U256::default())),
+                factory_info: StakeInfo::from((U256::default(), U256::default())),
+                paymaster_info: StakeInfo::from((U256::default(), U256::default())),
+                aggregator_info: None,
+            },
+            is_unstaked_wallet_creation: false,
+
+            entities_needing_stake: vec![],
+            accessed_addresses: HashSet::new(),
+            min_unstake_delay: Settings::default().min_unstake_delay,
+        };
+
+        let simulator = create_simulator(provider, tracer);
+        let res = simulator.gather_context_violations(&mut validation_context);
+
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                SimulationViolation::InvalidSignature,
+                SimulationViolation::UsedForbiddenOpcode(
+                    Entity {
+                        kind: EntityType::Account,
+                        address: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4")
+                            .unwrap()
+                    },
+                    Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+                    ViolationOpCode(Opcode::GASPRICE),
+                    1,
+                ),
+                SimulationViolation::UsedForbiddenOpcode(
                     Entity {
                         kind: EntityType::Account,
                         address: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4")
@@ -1094,6 +5783,7 @@ mod tests {
                     },
                     Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
                     ViolationOpCode(Opcode::COINBASE),
+                    1,
                 ),
                 SimulationViolation::UsedForbiddenPrecompile(
                     Entity {
@@ -1110,16 +5800,1413 @@ mod tests {
                         address: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4")
                             .unwrap()
                     },
-                    StorageSlot {
-                        address: Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6")
-                            .unwrap(),
-                        slot: U256::from_str(
-                            "0xa3f946b7ed2f016739c6be6031c5579a53d3784a471c3b5f9c2a1f8706c65a4b"
-                        )
-                        .unwrap()
-                    }
+                    Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap(),
+                    vec![U256::from_str(
+                        "0xa3f946b7ed2f016739c6be6031c5579a53d3784a471c3b5f9c2a1f8706c65a4b"
+                    )
+                    .unwrap()]
+                )
+            ]
+        );
+    }
+
+    fn default_is_staked(info: StakeInfo) -> bool {
+        is_staked(
+            info,
+            Settings::default().min_stake_value,
+            Settings::default().min_unstake_delay,
+        )
+    }
+
+    fn create_validation_context(tracer_output: SimulationTracerOutput) -> ValidationContext {
+        let entry_point_out = ValidationOutput {
+            return_info: ValidationReturnInfo::from((
+                U256::default(),
+                U256::default(),
+                false,
+                0,
+                0,
+                Bytes::default(),
+            )),
+            sender_info: StakeInfo::from((U256::default(), U256::default())),
+            factory_info: StakeInfo::from((U256::default(), U256::default())),
+            paymaster_info: StakeInfo::from((U256::default(), U256::default())),
+            aggregator_info: None,
+        };
+        ValidationContext {
+            block_id: BlockId::Number(BlockNumber::Latest),
+            entity_infos: EntityInfos::new(
+                None,
+                Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+                None,
+                &entry_point_out,
+                default_is_staked,
+            ),
+            tracer_out: tracer_output,
+            entry_point_out,
+            is_unstaked_wallet_creation: false,
+            entities_needing_stake: vec![],
+            accessed_addresses: HashSet::new(),
+            min_unstake_delay: Settings::default().min_unstake_delay,
+        }
+    }
+
+    fn tracer_output_with_account_opcodes(opcodes: &[&str]) -> SimulationTracerOutput {
+        let mut tracer_output = get_test_tracer_output();
+        // strip the unrelated storage accesses so that only opcode violations are reported
+        tracer_output.phases[1].storage_accesses = vec![];
+        tracer_output.phases[1].addresses_calling_with_value = vec![];
+        tracer_output.phases[1].restrictable_opcodes_used = opcodes
+            .iter()
+            .map(|opcode| format!("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:{opcode}"))
+            .collect();
+        tracer_output
+    }
+
+    fn create_wallet_creation_deposit_context() -> ValidationContext {
+        let mut tracer_output = get_test_tracer_output();
+        // the account only accesses its own storage and its deposit on the entry point
+        tracer_output.phases[1].addresses_calling_with_value = vec![];
+        let mut context = create_validation_context(tracer_output);
+        context.is_unstaked_wallet_creation = true;
+        context
+    }
+
+    #[test]
+    fn test_sender_deposit_during_creation_allowed() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        let mut context = create_wallet_creation_deposit_context();
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_sender_deposit_during_creation_disallowed() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.allow_sender_deposit_during_creation = false;
+        let mut context = create_wallet_creation_deposit_context();
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::InvalidStorageAccess(
+                Entity::account(
+                    Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()
+                ),
+                Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+                vec![U256::from_str(
+                    "0xf5357e1da3acf909ceaed3492183cbad85a3c9e1f0076495f66d3eed05219bd5"
+                )
+                .unwrap()]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_sender_deposit_during_creation_strict_spec() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.strict_spec_mode = true;
+        let mut context = create_wallet_creation_deposit_context();
+
+        assert!(simulator.sim_settings.allow_sender_deposit_during_creation);
+        assert!(matches!(
+            simulator.gather_context_violations(&mut context).unwrap()[..],
+            [SimulationViolation::InvalidStorageAccess(..)]
+        ));
+    }
+
+    #[test]
+    fn test_invalid_storage_access_deduped_by_address() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        // the account reads many unassociated slots of a single contract
+        let contract = Address::random();
+        let slots: Vec<U256> = (1..=20).map(U256::from).collect();
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].storage_accesses = vec![StorageAccess {
+            address: contract,
+            slots: slots.clone(),
+            read_slots: slots.clone(),
+            written_slots: vec![],
+        }];
+        let mut context = create_validation_context(tracer_output);
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::InvalidStorageAccess(
+                Entity::account(
+                    Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()
+                ),
+                contract,
+                slots
+            )]
+        );
+    }
+
+    #[test]
+    fn test_token_paymaster_allowlist() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        let paymaster = Address::random();
+        let token = Address::random();
+        // the paymaster reads the token's allowance mapping, whose slot is
+        // not associated with any entity
+        let allowance_slot = U256::from(H256::random().as_bytes());
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[2].storage_accesses = vec![StorageAccess {
+            address: token,
+            slots: vec![allowance_slot],
+            read_slots: vec![allowance_slot],
+            written_slots: vec![],
+        }];
+        let mut context = create_validation_context(tracer_output);
+        context.entity_infos.paymaster = Some(EntityInfo {
+            address: paymaster,
+            is_staked: true,
+            stake_info: StakeInfo::from((U256::zero(), U256::zero())),
+        });
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::InvalidStorageAccess(
+                Entity::paymaster(paymaster),
+                token,
+                vec![allowance_slot]
+            )]
+        );
+
+        simulator
+            .sim_settings
+            .token_paymaster_allowlist
+            .insert((paymaster, token));
+        let mut context = create_validation_context(context.tracer_out.clone());
+        context.entity_infos.paymaster = Some(EntityInfo {
+            address: paymaster,
+            is_staked: true,
+            stake_info: StakeInfo::from((U256::zero(), U256::zero())),
+        });
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![]
+        );
+        assert_eq!(context.entities_needing_stake, vec![EntityType::Paymaster]);
+    }
+
+    #[test]
+    fn test_forbidden_opcode_count() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        let account = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        // GASPRICE executed three times and COINBASE once in the account phase
+        let gas_price = format!("{account:?}:GASPRICE");
+        let coinbase = format!("{account:?}:COINBASE");
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].forbidden_opcodes_used = vec![gas_price.clone(), coinbase.clone()];
+        tracer_output.phases[1].opcode_counts = HashMap::from([(gas_price, 3), (coinbase, 1)]);
+        let mut context = create_validation_context(tracer_output);
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![
+                SimulationViolation::UsedForbiddenOpcode(
+                    Entity::account(account),
+                    account,
+                    ViolationOpCode(Opcode::GASPRICE),
+                    3
+                ),
+                SimulationViolation::UsedForbiddenOpcode(
+                    Entity::account(account),
+                    account,
+                    ViolationOpCode(Opcode::COINBASE),
+                    1
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_transient_storage_access() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        // the account uses TLOAD/TSTORE during validation
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].used_transient_storage = true;
+        let mut context = create_validation_context(tracer_output);
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::TransientStorageAccess(
+                Entity::account(
+                    Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()
                 )
+            )]
+        );
+    }
+
+    #[test]
+    fn test_transient_storage_access_before_cancun() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.evm_version = EvmVersion::Shanghai;
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].used_transient_storage = true;
+        let mut context = create_validation_context(tracer_output);
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_push0_availability() {
+        // the account checks the entry point's code size and follows it
+        // with PUSH0
+        let entry_point = Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap();
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1]
+            .ext_code_access_info
+            .insert(entry_point, Opcode::PUSH0);
+
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.evm_version = EvmVersion::Shanghai;
+        let mut context = create_validation_context(tracer_output.clone());
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::UsedForbiddenOpcode(
+                Entity::account(
+                    Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()
+                ),
+                entry_point,
+                ViolationOpCode(Opcode::PUSH0),
+                1,
+            )]
+        );
+
+        // before Shanghai PUSH0 is undefined and aborts the call
+        simulator.sim_settings.evm_version = EvmVersion::Paris;
+        let mut context = create_validation_context(tracer_output);
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![]
+        );
+    }
+
+    fn validation_data(valid_after: u64, valid_until: u64) -> U256 {
+        (U256::from(valid_after) << 208) | (U256::from(valid_until) << 160)
+    }
+
+    #[test]
+    fn test_validity_windows_mismatched() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        let mut tracer_output = get_test_tracer_output();
+        tracer_output.phases[1].validation_data = Some(validation_data(100, 0));
+        tracer_output.phases[2].validation_data = Some(validation_data(500, 1_030));
+
+        let windows = simulator.validity_windows(&tracer_output, true, Timestamp::new(1_000));
+        assert_eq!(
+            windows.account,
+            ValidTimeRange::new(100.into(), 0xffff_ffff_ffff.into())
+        );
+        assert_eq!(
+            windows.paymaster,
+            Some(ValidTimeRange::new(500.into(), 1_030.into()))
+        );
+        // the paymaster's window closes 30 seconds after the block
+        assert!(windows.paymaster_ending_soon);
+
+        let windows = simulator.validity_windows(&tracer_output, true, Timestamp::new(900));
+        assert!(!windows.paymaster_ending_soon);
+
+        let windows = simulator.validity_windows(&tracer_output, false, Timestamp::new(1_000));
+        assert_eq!(windows.paymaster, None);
+        assert!(!windows.paymaster_ending_soon);
+    }
+
+    #[test]
+    fn test_max_violations_returned() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.max_violations_returned = 2;
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].forbidden_opcodes_used =
+            ["GASPRICE", "GASLIMIT", "TIMESTAMP", "ORIGIN"]
+                .iter()
+                .map(|opcode| format!("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:{opcode}"))
+                .collect();
+        let mut context = create_validation_context(tracer_output);
+
+        let violations = simulator.gather_context_violations(&mut context).unwrap();
+        assert_eq!(violations.len(), 4);
+        let mut expected = violations.clone();
+        expected.sort();
+        expected.truncate(2);
+        expected.push(SimulationViolation::TruncatedViolations(4));
+
+        assert_eq!(simulator.cap_violations(violations), expected);
+    }
+
+    #[tokio::test]
+    async fn test_mempool_allowlists_violations_past_cap() {
+        let opcodes = ["GASPRICE", "GASLIMIT", "TIMESTAMP", "ORIGIN"];
+        let mut tracer_output = get_test_tracer_output();
+        tracer_output.phases[1].forbidden_opcodes_used = opcodes
+            .iter()
+            .map(|opcode| format!("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:{opcode}"))
+            .collect();
+        let mut simulator = create_pool_simulator(tracer_output);
+        simulator.sim_settings.max_violations_returned = 2;
+        let allowlist: Vec<_> = opcodes
+            .iter()
+            .map(|opcode| {
+                serde_json::json!({
+                    "entity": "*",
+                    "rule": "forbiddenOpcode",
+                    "contract": "0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4",
+                    "opcode": opcode,
+                })
+            })
+            .collect();
+        let mempool_config: MempoolConfig =
+            serde_json::from_value(serde_json::json!({ "allowlist": allowlist })).unwrap();
+        let mempool_id = H256::random();
+        simulator.mempool_configs = HashMap::from([(mempool_id, mempool_config)]);
+
+        let res = simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await
+            .unwrap();
+        assert_eq!(res.mempools, vec![mempool_id]);
+    }
+
+    fn factory_existence_check_context(opcode: Opcode) -> ValidationContext {
+        let entry_point = Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap();
+        let sender = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        let factory = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        // the factory checks the code of the entry point and of the sender
+        // before the sender is deployed
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[0].ext_code_access_info = HashMap::from([(entry_point, opcode)]);
+        tracer_output.phases[0].undeployed_contract_accesses = vec![sender];
+        let mut context = create_validation_context(tracer_output);
+        context.entity_infos = EntityInfos::new(
+            Some(factory),
+            sender,
+            None,
+            &context.entry_point_out,
+            default_is_staked,
+        );
+        context
+    }
+
+    #[test]
+    fn test_factory_existence_checks_allowed() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.allow_factory_existence_checks = true;
+        let mut context = factory_existence_check_context(Opcode::EXTCODESIZE);
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![]
+        );
+
+        // Only the code size of the entry point may be checked
+        let mut context = factory_existence_check_context(Opcode::EXTCODEHASH);
+        let factory = Entity::factory(
+            Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap(),
+        );
+        let entry_point = Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap();
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::UsedForbiddenOpcode(
+                factory,
+                entry_point,
+                ViolationOpCode(Opcode::EXTCODEHASH),
+                1
+            )]
+        );
+    }
+
+    #[test]
+    fn test_factory_existence_checks_disallowed() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        let mut context = factory_existence_check_context(Opcode::EXTCODESIZE);
+        let factory = Entity::factory(
+            Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap(),
+        );
+        let entry_point = Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap();
+        let sender = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![
+                SimulationViolation::UsedForbiddenOpcode(
+                    factory,
+                    entry_point,
+                    ViolationOpCode(Opcode::EXTCODESIZE),
+                    1
+                ),
+                SimulationViolation::AccessedUndeployedContract(factory, sender),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sender_creator_allowed() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        let sender_creator =
+            Address::from_str("0xefc2c1444ebcc4db75e7613d20c6a62ff67a167c").unwrap();
+        let sender = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        let factory = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        // v0.7 routes creation through SenderCreator, which calls the factory
+        // with GAS as the entry point would
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[0].forbidden_opcodes_used = vec![format!("{sender_creator:?}:GAS")];
+        tracer_output.phases[0]
+            .opcode_counts
+            .insert(format!("{sender_creator:?}:GAS"), 1);
+        let mut context = create_validation_context(tracer_output);
+        context.entity_infos = EntityInfos::new(
+            Some(factory),
+            sender,
+            None,
+            &context.entry_point_out,
+            default_is_staked,
+        );
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::UsedForbiddenOpcode(
+                Entity::factory(factory),
+                sender_creator,
+                ViolationOpCode(Opcode::GAS),
+                1
+            )]
+        );
+
+        simulator.sim_settings.sender_creator_address = Some(sender_creator);
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![]
+        );
+
+        simulator.sim_settings.strict_spec_mode = true;
+        assert_eq!(
+            simulator
+                .gather_context_violations(&mut context)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_used_block_hash_unstaked() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        // the account reads BLOCKHASH during validation
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].used_block_hash = true;
+        let mut context = create_validation_context(tracer_output);
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::UsedBlockHash(Entity::account(
+                Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_not_staked_reports_shortfall() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        let aggregator = Address::random();
+        let mut context = create_validation_context(tracer_output_with_account_opcodes(&[]));
+        context.entry_point_out.aggregator_info = Some(AggregatorInfo {
+            address: aggregator,
+            stake_info: StakeInfo::from((U256::exp10(17), 3_600.into())),
+        });
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::NotStaked {
+                entity: Entity::aggregator(aggregator),
+                actual_stake: U256::exp10(17),
+                required_stake: U256::exp10(18),
+                actual_delay: 3_600.into(),
+                required_delay: 84_600.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_assume_all_entities_staked() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.assume_all_entities_staked = true;
+        // the account reads an unassociated slot and the aggregator is unstaked
+        let contract = Address::random();
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].storage_accesses = vec![StorageAccess {
+            address: contract,
+            slots: vec![U256::one()],
+            read_slots: vec![U256::one()],
+            written_slots: vec![],
+        }];
+        let mut context = create_validation_context(tracer_output);
+        context.entry_point_out.aggregator_info = Some(AggregatorInfo {
+            address: Address::random(),
+            stake_info: StakeInfo::from((U256::zero(), U256::zero())),
+        });
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::InvalidStorageAccess(
+                Entity::account(
+                    Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()
+                ),
+                contract,
+                vec![U256::one()]
+            )]
+        );
+        assert!(simulator.is_staked(StakeInfo::from((U256::zero(), U256::zero())), 84_600));
+    }
+
+    #[test]
+    fn test_phased_violations() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].forbidden_opcodes_used = vec![String::from(
+            "0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4:GASPRICE",
+        )];
+        let mut context = create_validation_context(tracer_output);
+        context.entry_point_out.return_info.sig_failed = true;
+
+        let violations = simulator.gather_phased_violations(&mut context).unwrap();
+        assert!(matches!(
+            violations[..],
+            [
+                PhasedViolation {
+                    violation: SimulationViolation::InvalidSignature,
+                    phase_index: None,
+                },
+                PhasedViolation {
+                    violation: SimulationViolation::UsedForbiddenOpcode(..),
+                    phase_index: Some(1),
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_used_block_hash_staked() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].used_block_hash = true;
+        let mut context = create_validation_context(tracer_output);
+        context.entity_infos.sender.is_staked = true;
+
+        // Staked entities are only exempt when allowed
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::UsedBlockHash(Entity::account(
+                Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()
+            ))]
+        );
+        simulator.sim_settings.allow_staked_block_hash = true;
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![]
+        );
+        simulator.sim_settings.strict_spec_mode = true;
+        assert_eq!(
+            simulator
+                .gather_context_violations(&mut context)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_too_many_accessed_addresses() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.max_accessed_addresses = 1;
+        // the account accesses the storage of itself and the entry point
+        let mut context = create_validation_context(get_test_tracer_output());
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::TooManyAccessedAddresses(1)]
+        );
+        assert_eq!(context.accessed_addresses.len(), 1);
+    }
+
+    #[test]
+    fn test_restricted_opcodes_default() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        let mut context =
+            create_validation_context(tracer_output_with_account_opcodes(&["ORIGIN", "ADDRESS"]));
+
+        let account = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::UsedForbiddenOpcode(
+                Entity::account(account),
+                account,
+                ViolationOpCode(Opcode::ORIGIN),
+                1,
+            )]
+        );
+    }
+
+    #[test]
+    fn test_restricted_opcodes_allow_origin_for_account() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator
+            .sim_settings
+            .restricted_opcodes
+            .allow(EntityType::Account, Opcode::ORIGIN);
+        let mut context =
+            create_validation_context(tracer_output_with_account_opcodes(&["ORIGIN"]));
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_restricted_opcodes_allow_origin_for_other_entity() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator
+            .sim_settings
+            .restricted_opcodes
+            .allow(EntityType::Paymaster, Opcode::ORIGIN);
+        let mut context =
+            create_validation_context(tracer_output_with_account_opcodes(&["ORIGIN"]));
+
+        let account = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::UsedForbiddenOpcode(
+                Entity::account(account),
+                account,
+                ViolationOpCode(Opcode::ORIGIN),
+                1,
+            )]
+        );
+    }
+
+    #[test]
+    fn test_restricted_opcodes_deny_for_account() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator
+            .sim_settings
+            .restricted_opcodes
+            .deny(EntityType::Account, Opcode::ORIGIN)
+            .deny(EntityType::Account, Opcode::ADDRESS);
+        let mut context = create_validation_context(tracer_output_with_account_opcodes(&[
+            "ORIGIN", "ADDRESS", "CHAINID",
+        ]));
+
+        let account = Entity::account(
+            Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+        );
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![
+                SimulationViolation::RestrictedOpcode(account, ViolationOpCode(Opcode::ORIGIN)),
+                SimulationViolation::RestrictedOpcode(account, ViolationOpCode(Opcode::ADDRESS)),
             ]
         );
     }
+
+    // Sample layout: abi.encode(address token, uint256 amount)
+    #[derive(Debug)]
+    struct TestContextDecoder {
+        paymaster: Address,
+    }
+
+    impl PaymasterContextDecoder for TestContextDecoder {
+        fn decode(&self, paymaster: Address, context: &Bytes) -> Option<TokenCharge> {
+            if paymaster != self.paymaster {
+                return None;
+            }
+            let tokens = ethers::abi::decode(
+                &[
+                    ethers::abi::ParamType::Address,
+                    ethers::abi::ParamType::Uint(256),
+                ],
+                context,
+            )
+            .ok()?;
+            Some(TokenCharge {
+                token: tokens[0].clone().into_address()?,
+                amount: tokens[1].clone().into_uint()?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_decode_token_charge() {
+        let (provider, tracer) = create_base_config();
+        let paymaster = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let token = Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap();
+        let context: Bytes = ethers::abi::encode(&[
+            ethers::abi::Token::Address(token),
+            ethers::abi::Token::Uint(U256::from(1_000_000)),
+        ])
+        .into();
+
+        let simulator = create_simulator(provider, tracer);
+        assert_eq!(
+            simulator.decode_token_charge(Some(paymaster), &context),
+            None
+        );
+
+        let simulator =
+            simulator.with_paymaster_context_decoder(Arc::new(TestContextDecoder { paymaster }));
+        assert_eq!(
+            simulator.decode_token_charge(Some(paymaster), &context),
+            Some(TokenCharge {
+                token,
+                amount: U256::from(1_000_000),
+            })
+        );
+        assert_eq!(simulator.decode_token_charge(None, &context), None);
+        assert_eq!(
+            simulator.decode_token_charge(Some(paymaster), &Bytes::default()),
+            None
+        );
+        assert_eq!(
+            simulator.decode_token_charge(Some(Address::random()), &context),
+            None
+        );
+    }
+
+    #[test]
+    fn test_called_handle_ops() {
+        let (provider, tracer) = create_base_config();
+        let simulator = create_simulator(provider, tracer);
+        let mut tracer_output = tracer_output_with_account_opcodes(&[]);
+        tracer_output.phases[1].called_handle_ops = true;
+        let mut context = create_validation_context(tracer_output);
+
+        assert_eq!(
+            simulator.gather_context_violations(&mut context).unwrap(),
+            vec![SimulationViolation::CalledHandleOps(Entity::account(
+                Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap()
+            ))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_soft_deadline() {
+        let slow_code_hash = async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            H256::random()
+        };
+        assert_eq!(with_soft_deadline(Some(10), slow_code_hash).await, None);
+
+        let code_hash = H256::random();
+        assert_eq!(
+            with_soft_deadline(Some(1000), async { code_hash }).await,
+            Some(code_hash)
+        );
+        assert_eq!(
+            with_soft_deadline(None, async { code_hash }).await,
+            Some(code_hash)
+        );
+    }
+
+    #[derive(Debug)]
+    struct SlowAggregatorValidator;
+
+    #[async_trait]
+    impl AggregatorValidator for SlowAggregatorValidator {
+        async fn validate_user_op_signature(
+            &self,
+            _aggregator_address: Address,
+            _op: UserOperation,
+            _gas_cap: u64,
+        ) -> anyhow::Result<AggregatorOut> {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(AggregatorOut::NotNeeded)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulate_validation_soft_deadline() {
+        let mut simulator = aggregated_op_simulator(MockProvider::new())
+            .with_aggregator_validator(Arc::new(SlowAggregatorValidator));
+        simulator.sim_settings.soft_deadline_ms = Some(10);
+
+        let res = simulator
+            .simulate_validation(simulation_user_operation(), Some(H256::zero()), None)
+            .await
+            .unwrap();
+        assert!(res.partial);
+        assert_eq!(res.code_hash, H256::zero());
+        assert!(res.aggregator.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_revalidate_mempool_partial_is_stale() {
+        let mut simulator = aggregated_op_simulator(MockProvider::new())
+            .with_aggregator_validator(Arc::new(SlowAggregatorValidator));
+        simulator.sim_settings.soft_deadline_ms = Some(10);
+
+        let op = simulation_user_operation();
+        let result = simulator
+            .revalidate_mempool(vec![op.clone()], H256::zero().into())
+            .await;
+        assert!(result.valid.is_empty());
+        assert!(result.rejected.is_empty());
+        assert_eq!(result.stale, vec![op]);
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_for_pool_rejects_partial() {
+        let mut provider = MockProvider::new();
+        provider.expect_get_chain_id().returning(|| Ok(1337));
+        let mut simulator = aggregated_op_simulator(provider)
+            .with_aggregator_validator(Arc::new(SlowAggregatorValidator));
+        simulator.sim_settings.soft_deadline_ms = Some(10);
+
+        let decision = simulator
+            .evaluate_for_pool(simulation_user_operation(), None, H256::zero().into(), None)
+            .await;
+        assert!(matches!(
+            decision,
+            PoolDecision::Reject(SimulationError::Other(_))
+        ));
+    }
+
+    #[derive(Debug)]
+    struct TestStakeResolver {
+        paymaster: Address,
+        stake_holder: Address,
+    }
+
+    impl StakeResolver for TestStakeResolver {
+        fn resolve(&self, entity: Entity) -> Option<Address> {
+            (entity == Entity::paymaster(self.paymaster)).then_some(self.stake_holder)
+        }
+    }
+
+    // Tracer that only finishes once the stake read has been made, so it can
+    // only complete if the two run concurrently
+    #[derive(Debug)]
+    struct WaitForStakeTracer {
+        stake_loaded: Arc<Notify>,
+    }
+
+    #[async_trait]
+    impl SimulateValidationTracer for WaitForStakeTracer {
+        async fn trace_simulate_validation(
+            &self,
+            _op: UserOperation,
+            _block_id: BlockId,
+            _max_validation_gas: u64,
+            _state_overrides: Option<spoof::State>,
+        ) -> anyhow::Result<SimulationTracerOutput> {
+            self.stake_loaded.notified().await;
+            Ok(get_test_tracer_output())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_stake_infos_concurrent_with_trace() {
+        let (mut provider, _) = create_base_config();
+        let paymaster = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let stake_holder = Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap();
+        let stake_loaded = Arc::new(Notify::new());
+
+        let notify = Arc::clone(&stake_loaded);
+        provider.expect_call().times(1).returning(move |_, _| {
+            notify.notify_one();
+            Ok(GetDepositInfoReturn {
+                info: DepositInfo {
+                    stake: 1_000_000_000_000_000_000,
+                    unstake_delay_sec: 84600,
+                    ..Default::default()
+                },
+            }
+            .encode()
+            .into())
+        });
+
+        let simulator = SimulatorImpl::new(
+            Arc::new(provider),
+            Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+            WaitForStakeTracer { stake_loaded },
+            Settings::default(),
+            HashMap::new(),
+        )
+        .with_stake_resolver(Arc::new(TestStakeResolver {
+            paymaster,
+            stake_holder,
+        }));
+        let op = UserOperation {
+            sender: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+            paymaster_and_data: paymaster.as_bytes().to_vec().into(),
+            ..Default::default()
+        };
+
+        let context = tokio::time::timeout(
+            Duration::from_secs(5),
+            simulator.create_context(op, BlockId::Number(BlockNumber::Latest), None, false),
+        )
+        .await
+        .expect("trace should not wait for the stake read to finish first")
+        .unwrap();
+        assert!(
+            context
+                .entity_infos
+                .get(EntityType::Paymaster)
+                .unwrap()
+                .is_staked
+        );
+    }
+
+    // Sets its flag when dropped, to detect that a future holding it was dropped
+    struct SetOnDrop(Arc<AtomicBool>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Tracer whose trace never finishes
+    #[derive(Debug)]
+    struct HangingTracer {
+        trace_started: Arc<Notify>,
+        trace_dropped: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl SimulateValidationTracer for HangingTracer {
+        async fn trace_simulate_validation(
+            &self,
+            _op: UserOperation,
+            _block_id: BlockId,
+            _max_validation_gas: u64,
+            _state_overrides: Option<spoof::State>,
+        ) -> anyhow::Result<SimulationTracerOutput> {
+            let _guard = SetOnDrop(Arc::clone(&self.trace_dropped));
+            self.trace_started.notify_one();
+            future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulate_validation_with_cancellation() {
+        let (mut provider, _) = create_base_config();
+        expect_get_block(&mut provider);
+        let trace_started = Arc::new(Notify::new());
+        let trace_dropped = Arc::new(AtomicBool::new(false));
+        let simulator = SimulatorImpl::new(
+            Arc::new(provider),
+            Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+            HangingTracer {
+                trace_started: Arc::clone(&trace_started),
+                trace_dropped: Arc::clone(&trace_dropped),
+            },
+            Settings::default(),
+            HashMap::new(),
+        );
+        let cancel = CancellationToken::new();
+
+        let (res, _) = tokio::join!(
+            simulator.simulate_validation_with_cancellation(
+                UserOperation::default(),
+                Some(H256::zero()),
+                None,
+                SimulationRequest::default(),
+                cancel.clone(),
+            ),
+            async {
+                trace_started.notified().await;
+                cancel.cancel();
+            }
+        );
+
+        assert!(matches!(res, Err(SimulationError::Cancelled)));
+        assert!(trace_dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_stake_infos() {
+        let (mut provider, tracer) = create_base_config();
+        let paymaster = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let stake_holder = Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap();
+
+        provider
+            .expect_call()
+            .withf(move |tx, _| {
+                tx.data()
+                    == Some(
+                        &GetDepositInfoCall {
+                            account: stake_holder,
+                        }
+                        .encode()
+                        .into(),
+                    )
+            })
+            .times(1)
+            .returning(|_, _| {
+                Ok(GetDepositInfoReturn {
+                    info: DepositInfo {
+                        stake: 1_000_000_000_000_000_000,
+                        unstake_delay_sec: 84600,
+                        ..Default::default()
+                    },
+                }
+                .encode()
+                .into())
+            });
+
+        let simulator =
+            create_simulator(provider, tracer).with_stake_resolver(Arc::new(TestStakeResolver {
+                paymaster,
+                stake_holder,
+            }));
+        let op = UserOperation {
+            sender: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+            paymaster_and_data: paymaster.as_bytes().to_vec().into(),
+            ..Default::default()
+        };
+        let mut entry_point_out = ValidationOutput {
+            return_info: ValidationReturnInfo::from((
+                U256::default(),
+                U256::default(),
+                false,
+                0,
+                0,
+                Bytes::default(),
+            )),
+            sender_info: StakeInfo::from((U256::default(), U256::default())),
+            factory_info: StakeInfo::from((U256::default(), U256::default())),
+            paymaster_info: StakeInfo::from((U256::default(), U256::default())),
+            aggregator_info: None,
+        };
+
+        simulator
+            .resolve_stake_infos(&op, BlockId::Number(BlockNumber::Latest))
+            .await
+            .unwrap()
+            .apply(&mut entry_point_out);
+        let entity_infos = EntityInfos::new(
+            op.factory(),
+            op.sender,
+            op.paymaster(),
+            &entry_point_out,
+            default_is_staked,
+        );
+
+        assert!(entity_infos.get(EntityType::Paymaster).unwrap().is_staked);
+        assert!(!entity_infos.get(EntityType::Account).unwrap().is_staked);
+    }
+
+    #[test]
+    fn test_simulation_success_diff() {
+        let address1 = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        let address2 = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let address3 = Address::from_str("0x1c0e100fcf093c64cdaa545b425ad7ed8e8a0db6").unwrap();
+        let before = SimulationSuccess {
+            code_hash: H256::random(),
+            accessed_addresses: [address1, address2].into(),
+            entities_needing_stake: vec![EntityType::Paymaster],
+            expected_storage: serde_json::from_str(r#"
+            {
+                "0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4": {
+                    "0x0000000000000000000000000000000000000000000000000000000000000001": "0x0000000000000000000000000000000000000000000000000000000000000001"
+                }
+            }
+            "#).unwrap(),
+            ..Default::default()
+        };
+        let after = SimulationSuccess {
+            code_hash: H256::random(),
+            accessed_addresses: [address1, address3].into(),
+            ..before.clone()
+        };
+
+        assert!(before.diff(&before).is_empty());
+        assert_eq!(
+            before.diff(&after),
+            SimulationDiff {
+                added_accessed_addresses: [address3].into(),
+                removed_accessed_addresses: [address2].into(),
+                code_hash_changed: true,
+                ..Default::default()
+            }
+        );
+
+        let after = SimulationSuccess {
+            entities_needing_stake: vec![EntityType::Factory],
+            expected_storage: serde_json::from_str(r#"
+            {
+                "0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4": {
+                    "0x0000000000000000000000000000000000000000000000000000000000000001": "0x0000000000000000000000000000000000000000000000000000000000000002"
+                }
+            }
+            "#).unwrap(),
+            account_is_staked: true,
+            ..before.clone()
+        };
+        assert_eq!(
+            before.diff(&after),
+            SimulationDiff {
+                added_entities_needing_stake: vec![EntityType::Factory],
+                removed_entities_needing_stake: vec![EntityType::Paymaster],
+                changed_expected_storage_slots: vec![StorageSlot {
+                    address: address1,
+                    slot: U256::one(),
+                }],
+                account_staked_changed: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_code_hashes() {
+        let address1 = Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap();
+        let address2 = Address::from_str("0x8abb13360b87be5eeb1b98647a016add927a136c").unwrap();
+        let code1 = Bytes::from_str("0x6001").unwrap();
+        let code2 = Bytes::from_str("0x6002").unwrap();
+        let code2_changed = Bytes::from_str("0x6003").unwrap();
+
+        let (mut provider, tracer) = create_base_config();
+        let block_hash = H256::random();
+        let later_block_hash = H256::random();
+        let (code1_clone, code2_clone, code2_changed_clone) =
+            (code1.clone(), code2.clone(), code2_changed.clone());
+        provider.expect_get_code().returning(move |address, block| {
+            Ok(if address == address1 {
+                code1_clone.clone()
+            } else if block == Some(block_hash) {
+                code2_clone.clone()
+            } else {
+                code2_changed_clone.clone()
+            })
+        });
+        let simulator = create_simulator(provider, tracer);
+
+        let before = SimulationSuccess {
+            code_hashes: simulator
+                .get_code_hashes(&[address1, address2], block_hash)
+                .await
+                .unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            before.code_hashes,
+            HashMap::from([
+                (address1, H256(keccak256(&code1))),
+                (address2, H256(keccak256(&code2))),
+            ])
+        );
+
+        let after = SimulationSuccess {
+            code_hashes: simulator
+                .get_code_hashes(&[address1, address2], later_block_hash)
+                .await
+                .unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(after.code_hashes[&address1], before.code_hashes[&address1]);
+        assert_eq!(
+            after.code_hashes[&address2],
+            H256(keccak256(&code2_changed))
+        );
+        assert_eq!(
+            before.diff(&after).changed_code_hash_addresses,
+            vec![address2]
+        );
+    }
+
+    #[test]
+    fn test_simulation_success_op_kind() {
+        let op = UserOperation {
+            init_code: Bytes::from_str("0x0123456789abcdef0123456789abcdef01234567").unwrap(),
+            ..Default::default()
+        };
+
+        let success = SimulationSuccess::default();
+        assert_eq!(
+            success.op_kind(&op),
+            OpKind {
+                creates_wallet: true,
+                has_paymaster: false,
+                is_aggregated: false,
+            }
+        );
+
+        let success = SimulationSuccess {
+            aggregator: Some(AggregatorSimOut::default()),
+            ..Default::default()
+        };
+        assert_eq!(
+            success.op_kind(&op),
+            OpKind {
+                creates_wallet: true,
+                has_paymaster: false,
+                is_aggregated: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_check_block_gas_limit() {
+        let (provider, tracer) = create_base_config();
+        let mut simulator = create_simulator(provider, tracer);
+        simulator.sim_settings.max_block_gas_percent = 50;
+        let block_gas_limit = U256::from(30_000_000);
+        let op_with_total_gas = |total: u64| UserOperation {
+            verification_gas_limit: 1_000_000.into(),
+            call_gas_limit: (total - 1_100_000).into(),
+            pre_verification_gas: 100_000.into(),
+            ..Default::default()
+        };
+
+        // below and at half of the block gas limit
+        assert_eq!(
+            simulator.check_block_gas_limit(&op_with_total_gas(14_999_999), block_gas_limit),
+            None
+        );
+        assert_eq!(
+            simulator.check_block_gas_limit(&op_with_total_gas(15_000_000), block_gas_limit),
+            None
+        );
+        // above half of the block gas limit
+        assert_eq!(
+            simulator.check_block_gas_limit(&op_with_total_gas(15_000_001), block_gas_limit),
+            Some(SimulationViolation::OpGasExceedsBlockLimit(
+                15_000_001.into(),
+                block_gas_limit
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trace_dump_round_trip() {
+        let dump_dir =
+            std::env::temp_dir().join(format!("rundler-trace-dump-{:x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dump_dir).unwrap();
+
+        let (provider, mut tracer) = create_base_config();
+        // the account uses a restricted opcode, so the replay has a violation to find
+        let tracer_output = tracer_output_with_account_opcodes(&["ORIGIN"]);
+        let recorded_output = tracer_output.clone();
+        tracer
+            .expect_trace_simulate_validation()
+            .returning(move |_, _, _, _| Ok(recorded_output.clone()));
+        let provider = Arc::new(provider);
+        let settings = Settings {
+            trace_dump_dir: Some(dump_dir.clone()),
+            ..Default::default()
+        };
+        let simulator = SimulatorImpl::new(
+            Arc::clone(&provider),
+            Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+            tracer,
+            settings,
+            HashMap::new(),
+        );
+
+        let op = UserOperation {
+            sender: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+            ..Default::default()
+        };
+        let mut context = simulator
+            .create_context(op.clone(), BlockNumber::Latest.into(), None, false)
+            .await
+            .unwrap();
+        let expected_violations = simulator.gather_context_violations(&mut context).unwrap();
+        assert!(!expected_violations.is_empty());
+
+        let path = dump_dir.join(format!("{:?}.json", encoded_op_key(&op)));
+        let trace: SimulationTracerOutput =
+            serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dump_dir).unwrap();
+
+        let replay = SimulatorImpl::from_recorded_trace(
+            provider,
+            Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+            trace,
+            Settings::default(),
+        );
+        assert_eq!(
+            replay.recorded_violations(op).await.unwrap(),
+            expected_violations
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trace_dump_prewarmed() {
+        let dump_dir =
+            std::env::temp_dir().join(format!("rundler-trace-dump-{:x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dump_dir).unwrap();
+
+        let (mut provider, mut tracer) = create_base_config();
+        // the trace is only run once, during prewarming
+        tracer
+            .expect_trace_simulate_validation()
+            .times(1)
+            .returning(move |_, _, _, _| Ok(get_test_tracer_output()));
+        expect_code_hash_call(&mut provider, ..);
+        let settings = Settings {
+            trace_dump_dir: Some(dump_dir.clone()),
+            ..Default::default()
+        };
+        let simulator = SimulatorImpl::new(
+            Arc::new(provider),
+            Address::from_str("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789").unwrap(),
+            tracer,
+            settings,
+            HashMap::new(),
+        );
+
+        let op = UserOperation {
+            sender: Address::from_str("0xb856dbd4fa1a79a46d426f537455e7d3e79ab7c4").unwrap(),
+            ..Default::default()
+        };
+        let block_id = BlockId::from(H256::zero());
+        simulator.prewarm(&[op.clone()], block_id).await.unwrap();
+        simulator
+            .trace_simulate_validation(op.clone(), block_id, None, false)
+            .await
+            .unwrap();
+
+        let path = dump_dir.join(format!("{:?}.json", encoded_op_key(&op)));
+        let dumped = std::fs::read(path);
+        std::fs::remove_dir_all(&dump_dir).unwrap();
+        let trace: SimulationTracerOutput = serde_json::from_slice(&dumped.unwrap()).unwrap();
+        assert_eq!(
+            trace.accessed_contract_addresses,
+            get_test_tracer_output().accessed_contract_addresses
+        );
+    }
 }
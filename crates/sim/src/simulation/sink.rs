@@ -0,0 +1,34 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use ethers::types::H256;
+
+use super::{SimulationError, SimulationSuccess};
+
+/// Receives the outcome of each simulation the simulator runs, e.g. to post
+/// it to a webhook or push it to a queue.
+///
+/// The sink is called on a spawned task, so a slow sink does not delay
+/// simulation, and its outcome is not observed by the simulator.
+#[async_trait]
+pub trait SimulationSink: Debug + Send + Sync + 'static {
+    /// Handle the result of simulating the operation with the given hash
+    async fn on_simulation(
+        &self,
+        op_hash: H256,
+        result: Result<SimulationSuccess, SimulationError>,
+    );
+}
@@ -0,0 +1,28 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::fmt::Debug;
+
+use ethers::types::Address;
+use rundler_types::Entity;
+
+/// Resolves the address whose stake on the entry point should be used when
+/// deciding whether an entity is staked.
+///
+/// This supports entities, such as paymasters deployed behind a proxy, whose
+/// stake is held by a different address than the one used in the operation.
+pub trait StakeResolver: Debug + Send + Sync + 'static {
+    /// Return the address whose stake should be checked for the entity, or
+    /// `None` to use the entity's own stake.
+    fn resolve(&self, entity: Entity) -> Option<Address>;
+}
@@ -0,0 +1,127 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use super::SimulationError;
+
+/// Snapshot of the simulator's counters since it was created
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SimulationStats {
+    /// Number of simulations run
+    pub simulations: u64,
+    /// Number of simulations that succeeded
+    pub successes: u64,
+    /// Number of simulations rejected with violations
+    pub violations: u64,
+    /// Number of simulations that failed with a provider or other error
+    pub errors: u64,
+    /// Number of traces served from the prewarm cache
+    pub trace_cache_hits: u64,
+    /// Number of traces that could have been served from the prewarm cache
+    /// but were not found in it
+    pub trace_cache_misses: u64,
+    /// Number of traces run against the node
+    pub traces: u64,
+    /// Average time taken by a trace run against the node
+    pub avg_trace_latency: Duration,
+    /// Number of `simulate_validation` calls served from the result cache
+    pub result_cache_hits: u64,
+    /// Number of `simulate_validation` calls that were not found in the
+    /// result cache
+    pub result_cache_misses: u64,
+}
+
+impl SimulationStats {
+    /// Fraction of cacheable traces that were served from the prewarm cache,
+    /// or zero if no trace could have been
+    pub fn trace_cache_hit_rate(&self) -> f64 {
+        let lookups = self.trace_cache_hits + self.trace_cache_misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.trace_cache_hits as f64 / lookups as f64
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SimulationCounters {
+    simulations: AtomicU64,
+    successes: AtomicU64,
+    violations: AtomicU64,
+    errors: AtomicU64,
+    trace_cache_hits: AtomicU64,
+    trace_cache_misses: AtomicU64,
+    traces: AtomicU64,
+    trace_latency_micros: AtomicU64,
+    result_cache_hits: AtomicU64,
+    result_cache_misses: AtomicU64,
+}
+
+impl SimulationCounters {
+    pub(crate) fn record_simulation<T>(&self, result: &Result<T, SimulationError>) {
+        self.simulations.fetch_add(1, Ordering::Relaxed);
+        let counter = match result {
+            Ok(_) => &self.successes,
+            Err(SimulationError::Violations(_)) => &self.violations,
+            Err(_) => &self.errors,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_trace_cache_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.trace_cache_hits
+        } else {
+            &self.trace_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_result_cache_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.result_cache_hits
+        } else {
+            &self.result_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_trace(&self, latency: Duration) {
+        self.traces.fetch_add(1, Ordering::Relaxed);
+        self.trace_latency_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> SimulationStats {
+        let traces = self.traces.load(Ordering::Relaxed);
+        let trace_latency_micros = self.trace_latency_micros.load(Ordering::Relaxed);
+        SimulationStats {
+            simulations: self.simulations.load(Ordering::Relaxed),
+            successes: self.successes.load(Ordering::Relaxed),
+            violations: self.violations.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            trace_cache_hits: self.trace_cache_hits.load(Ordering::Relaxed),
+            trace_cache_misses: self.trace_cache_misses.load(Ordering::Relaxed),
+            traces,
+            avg_trace_latency: Duration::from_micros(
+                trace_latency_micros.checked_div(traces).unwrap_or_default(),
+            ),
+            result_cache_hits: self.result_cache_hits.load(Ordering::Relaxed),
+            result_cache_misses: self.result_cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
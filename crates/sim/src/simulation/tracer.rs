@@ -20,9 +20,12 @@ use std::{
 
 use anyhow::{bail, Context};
 use async_trait::async_trait;
-use ethers::types::{
-    Address, BlockId, GethDebugTracerType, GethDebugTracingCallOptions, GethDebugTracingOptions,
-    GethTrace, Opcode, U256,
+use ethers::{
+    types::{
+        spoof, Address, BlockId, GethDebugTracerConfig, GethDebugTracerType,
+        GethDebugTracingCallOptions, GethDebugTracingOptions, GethTrace, Opcode, H256, U256,
+    },
+    utils::keccak256,
 };
 #[cfg(test)]
 use mockall::automock;
@@ -32,14 +35,18 @@ use serde::{Deserialize, Serialize};
 
 use crate::ExpectedStorage;
 
+/// Output of the validation tracer for a single simulation
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SimulationTracerOutput {
     pub(crate) phases: Vec<Phase>,
     pub(crate) revert_data: Option<String>,
+    pub(crate) return_data: String,
     pub(crate) accessed_contract_addresses: Vec<Address>,
     pub(crate) associated_slots_by_address: AssociatedSlotsByAddress,
     pub(crate) factory_called_create2_twice: bool,
+    #[serde(default)]
+    pub(crate) created_contracts: Vec<Address>,
     pub(crate) expected_storage: ExpectedStorage,
 }
 
@@ -59,14 +66,29 @@ impl TryFrom<GethTrace> for SimulationTracerOutput {
 #[serde(rename_all = "camelCase")]
 pub(crate) struct Phase {
     pub(crate) forbidden_opcodes_used: Vec<String>,
+    pub(crate) restrictable_opcodes_used: Vec<String>,
+    #[serde(default)]
+    pub(crate) opcode_counts: HashMap<String, usize>,
     pub(crate) forbidden_precompiles_used: Vec<String>,
     pub(crate) storage_accesses: Vec<StorageAccess>,
     pub(crate) called_banned_entry_point_method: bool,
+    pub(crate) called_handle_ops: bool,
+    pub(crate) used_transient_storage: bool,
+    pub(crate) used_block_hash: bool,
     pub(crate) addresses_calling_with_value: Vec<Address>,
     pub(crate) called_non_entry_point_with_value: bool,
     pub(crate) ran_out_of_gas: bool,
     pub(crate) undeployed_contract_accesses: Vec<Address>,
     pub(crate) ext_code_access_info: HashMap<Address, Opcode>,
+    pub(crate) validation_data: Option<U256>,
+}
+
+impl Phase {
+    /// Number of times the forbidden or restrictable opcode with the given
+    /// combined key was executed, or 1 for traces recorded without counts
+    pub(crate) fn opcode_count(&self, combined: &str) -> usize {
+        self.opcode_counts.get(combined).copied().unwrap_or(1)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -74,6 +96,10 @@ pub(crate) struct Phase {
 pub(crate) struct StorageAccess {
     pub(crate) address: Address,
     pub(crate) slots: Vec<U256>,
+    #[serde(default)]
+    pub(crate) read_slots: Vec<U256>,
+    #[serde(default)]
+    pub(crate) written_slots: Vec<U256>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -98,13 +124,22 @@ impl AssociatedSlotsByAddress {
 #[cfg_attr(test, automock)]
 #[async_trait]
 pub trait SimulateValidationTracer: Send + Sync + 'static {
-    /// Traces the simulation of a user operation.
+    /// Traces the simulation of a user operation, optionally with the given
+    /// state overrides applied for the duration of the call.
     async fn trace_simulate_validation(
         &self,
         op: UserOperation,
         block_id: BlockId,
         max_validation_gas: u64,
+        state_overrides: Option<spoof::State>,
     ) -> anyhow::Result<SimulationTracerOutput>;
+
+    /// Returns the hash of the JS tracer source and config this tracer runs,
+    /// for keying cached traces, or `None` if it does not trace against a
+    /// node.
+    fn tracer_hash(&self) -> Option<H256> {
+        None
+    }
 }
 
 /// Tracer implementation for the bundler's custom tracer.
@@ -116,6 +151,10 @@ where
 {
     provider: Arc<P>,
     entry_point: E,
+    gas_cap: Option<U256>,
+    strict_spec_mode: bool,
+    tracer_js: &'static str,
+    tracer_hash: H256,
 }
 
 /// Runs the bundler's custom tracer on the entry point's `simulateValidation`
@@ -132,11 +171,15 @@ where
         op: UserOperation,
         block_id: BlockId,
         max_validation_gas: u64,
+        state_overrides: Option<spoof::State>,
     ) -> anyhow::Result<SimulationTracerOutput> {
-        let tx = self
+        let mut tx = self
             .entry_point
             .simulate_validation(op, max_validation_gas)
             .await?;
+        if let Some(gas_cap) = self.gas_cap {
+            tx.set_gas(gas_cap);
+        }
 
         SimulationTracerOutput::try_from(
             self.provider
@@ -145,17 +188,23 @@ where
                     Some(block_id),
                     GethDebugTracingCallOptions {
                         tracing_options: GethDebugTracingOptions {
-                            tracer: Some(GethDebugTracerType::JsTracer(
-                                validation_tracer_js().to_string(),
+                            // The request needs an owned copy of the source
+                            tracer: Some(GethDebugTracerType::JsTracer(self.tracer_js.to_owned())),
+                            tracer_config: Some(GethDebugTracerConfig::JsTracer(
+                                serde_json::json!({ "strictSpecMode": self.strict_spec_mode }),
                             )),
                             ..Default::default()
                         },
-                        ..Default::default()
+                        state_overrides,
                     },
                 )
                 .await?,
         )
     }
+
+    fn tracer_hash(&self) -> Option<H256> {
+        Some(self.tracer_hash)
+    }
 }
 
 impl<P, E> SimulateValidationTracerImpl<P, E>
@@ -163,19 +212,81 @@ where
     P: Provider,
     E: EntryPoint,
 {
-    /// Creates a new instance of the bundler's custom tracer.
+    /// Creates a new instance of the bundler's custom tracer. The tracer
+    /// source is hashed here, once, and reused for every trace.
     pub fn new(provider: Arc<P>, entry_point: E) -> Self {
+        Self::with_tracer_js(provider, entry_point, validation_tracer_js())
+    }
+
+    // Creates a tracer running the given source, so that tests can tell which
+    // source is sent to the node
+    fn with_tracer_js(provider: Arc<P>, entry_point: E, tracer_js: &'static str) -> Self {
         Self {
             provider,
             entry_point,
+            gas_cap: None,
+            strict_spec_mode: false,
+            tracer_js,
+            tracer_hash: tracer_hash(tracer_js, false),
         }
     }
+
+    /// Sets the gas given to the traced call, overriding the gas computed from
+    /// the maximum verification gas. This should match the gas cap that the
+    /// node applies to `debug_traceCall`.
+    pub fn with_gas_cap(mut self, gas_cap: Option<U256>) -> Self {
+        self.gas_cap = gas_cap;
+        self
+    }
+
+    /// Sets whether the tracer disables its deviations from the validation
+    /// rules, matching `Settings::strict_spec_mode`.
+    pub fn with_strict_spec_mode(mut self, strict_spec_mode: bool) -> Self {
+        self.strict_spec_mode = strict_spec_mode;
+        self.tracer_hash = tracer_hash(self.tracer_js, strict_spec_mode);
+        self
+    }
+}
+
+/// Tracer that returns a previously recorded trace instead of tracing
+/// against a node, for replaying simulations.
+#[derive(Debug)]
+pub struct RecordedSimulateValidationTracer {
+    trace: SimulationTracerOutput,
+}
+
+impl RecordedSimulateValidationTracer {
+    /// Creates a tracer that always returns the given trace.
+    pub fn new(trace: SimulationTracerOutput) -> Self {
+        Self { trace }
+    }
+}
+
+#[async_trait]
+impl SimulateValidationTracer for RecordedSimulateValidationTracer {
+    async fn trace_simulate_validation(
+        &self,
+        _op: UserOperation,
+        _block_id: BlockId,
+        _max_validation_gas: u64,
+        _state_overrides: Option<spoof::State>,
+    ) -> anyhow::Result<SimulationTracerOutput> {
+        Ok(self.trace.clone())
+    }
 }
 
 fn validation_tracer_js() -> &'static str {
     include_str!("../../tracer/dist/validationTracer.js").trim_end_matches(";export{};")
 }
 
+// The hash of the tracer source and its config, which together determine the
+// tracer's output
+fn tracer_hash(tracer_js: &str, strict_spec_mode: bool) -> H256 {
+    let mut hashed = tracer_js.as_bytes().to_vec();
+    hashed.push(strict_spec_mode.into());
+    keccak256(hashed).into()
+}
+
 pub(crate) fn parse_combined_tracer_str<A, B>(combined: &str) -> anyhow::Result<(A, B)>
 where
     A: std::str::FromStr,
@@ -188,3 +299,180 @@ where
         .context("tracer combined should contain two parts")?;
     Ok((a.parse()?, b.parse()?))
 }
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        contract::EthCall,
+        types::{transaction::eip2718::TypedTransaction, BlockNumber, Eip1559TransactionRequest},
+        utils::hex,
+    };
+    use rundler_provider::{MockEntryPoint, MockProvider};
+    use rundler_types::contracts::i_entry_point::{HandleAggregatedOpsCall, HandleOpsCall};
+    use serde_json::json;
+
+    use super::*;
+
+    const NODE_REQUIRED_GAS: u64 = 3_000_000;
+
+    fn trace_with_gas(gas: U256) -> GethTrace {
+        GethTrace::Unknown(json!({
+            "phases": [{
+                "forbiddenOpcodesUsed": [],
+                "restrictableOpcodesUsed": [],
+                "forbiddenPrecompilesUsed": [],
+                "storageAccesses": [],
+                "calledBannedEntryPointMethod": false,
+                "calledHandleOps": false,
+                "usedTransientStorage": false,
+                "usedBlockHash": false,
+                "addressesCallingWithValue": [],
+                "calledNonEntryPointWithValue": false,
+                "ranOutOfGas": gas < NODE_REQUIRED_GAS.into(),
+                "undeployedContractAccesses": [],
+                "extCodeAccessInfo": {},
+                "validationData": null,
+            }],
+            "revertData": null,
+            "returnData": "0x",
+            "accessedContractAddresses": [],
+            "associatedSlotsByAddress": {},
+            "factoryCalledCreate2Twice": false,
+            "expectedStorage": {},
+        }))
+    }
+
+    fn create_tracer(
+        gas_cap: Option<U256>,
+    ) -> SimulateValidationTracerImpl<MockProvider, MockEntryPoint> {
+        let mut provider = MockProvider::new();
+        provider
+            .expect_debug_trace_call()
+            .returning(|tx, _, _| Ok(trace_with_gas(*tx.gas().unwrap())));
+        let mut entry_point = MockEntryPoint::new();
+        entry_point
+            .expect_simulate_validation()
+            .returning(|_, max_validation_gas| {
+                Ok(TypedTransaction::Eip1559(
+                    Eip1559TransactionRequest::new().gas(max_validation_gas),
+                ))
+            });
+        SimulateValidationTracerImpl::new(Arc::new(provider), entry_point).with_gas_cap(gas_cap)
+    }
+
+    #[tokio::test]
+    async fn test_trace_gas_cap() {
+        let max_validation_gas = 1_000_000;
+
+        let out = create_tracer(None)
+            .trace_simulate_validation(
+                UserOperation::default(),
+                BlockId::Number(BlockNumber::Latest),
+                max_validation_gas,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(out.phases[0].ran_out_of_gas);
+
+        let out = create_tracer(Some(NODE_REQUIRED_GAS.into()))
+            .trace_simulate_validation(
+                UserOperation::default(),
+                BlockId::Number(BlockNumber::Latest),
+                max_validation_gas,
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(!out.phases[0].ran_out_of_gas);
+    }
+
+    #[tokio::test]
+    async fn test_tracer_config_strict_spec_mode() {
+        let mut provider = MockProvider::new();
+        provider
+            .expect_debug_trace_call()
+            .withf(|_, _, options| {
+                options.tracing_options.tracer_config
+                    == Some(GethDebugTracerConfig::JsTracer(
+                        json!({ "strictSpecMode": true }),
+                    ))
+            })
+            .returning(|tx, _, _| Ok(trace_with_gas(*tx.gas().unwrap())));
+        let mut entry_point = MockEntryPoint::new();
+        entry_point
+            .expect_simulate_validation()
+            .returning(|_, max_validation_gas| {
+                Ok(TypedTransaction::Eip1559(
+                    Eip1559TransactionRequest::new().gas(max_validation_gas),
+                ))
+            });
+        let tracer = SimulateValidationTracerImpl::new(Arc::new(provider), entry_point)
+            .with_strict_spec_mode(true);
+
+        tracer
+            .trace_simulate_validation(
+                UserOperation::default(),
+                BlockId::Number(BlockNumber::Latest),
+                1_000_000,
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tracer_js_reused() {
+        const TRACER_JS: &str = "({ result() {}, fault() {} })";
+        let mut provider = MockProvider::new();
+        provider
+            .expect_debug_trace_call()
+            .times(3)
+            .withf(|_, _, options| {
+                options.tracing_options.tracer
+                    == Some(GethDebugTracerType::JsTracer(TRACER_JS.to_owned()))
+            })
+            .returning(|tx, _, _| Ok(trace_with_gas(*tx.gas().unwrap())));
+        let mut entry_point = MockEntryPoint::new();
+        entry_point
+            .expect_simulate_validation()
+            .returning(|_, max_validation_gas| {
+                Ok(TypedTransaction::Eip1559(
+                    Eip1559TransactionRequest::new().gas(max_validation_gas),
+                ))
+            });
+        let tracer = SimulateValidationTracerImpl::with_tracer_js(
+            Arc::new(provider),
+            entry_point,
+            TRACER_JS,
+        );
+
+        for _ in 0..3 {
+            tracer
+                .trace_simulate_validation(
+                    UserOperation::default(),
+                    BlockId::Number(BlockNumber::Latest),
+                    1_000_000,
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(tracer.tracer_hash(), Some(tracer_hash(TRACER_JS, false)));
+        let tracer = tracer.with_strict_spec_mode(true);
+        assert_eq!(tracer.tracer_hash(), Some(tracer_hash(TRACER_JS, true)));
+        assert_ne!(tracer_hash(TRACER_JS, false), tracer_hash(TRACER_JS, true));
+    }
+
+    #[test]
+    fn test_tracer_checks_handle_ops_selectors() {
+        let tracer = validation_tracer_js();
+        for selector in [
+            HandleOpsCall::selector(),
+            HandleAggregatedOpsCall::selector(),
+        ] {
+            let selector = format!("0x{}", hex::encode(selector));
+            assert!(tracer.contains(&selector), "missing selector {selector}");
+        }
+    }
+}
@@ -0,0 +1,28 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use ethers::types::BlockId;
+
+/// Source of the chain's minimum unstake delay, used in place of
+/// `Settings::min_unstake_delay` when deciding whether an entity is staked.
+///
+/// The v0.6 entry point does not expose a minimum, so this is implemented by
+/// operators for entry points or chains that publish one on-chain.
+#[async_trait]
+pub trait UnstakeDelaySource: Debug + Send + Sync + 'static {
+    /// Return the minimum unstake delay in seconds as of the given block
+    async fn min_unstake_delay(&self, block_id: BlockId) -> anyhow::Result<u32>;
+}
@@ -17,20 +17,28 @@ use ethers::{
     types::{Address, Bytes, U256},
 };
 use rundler_types::{
-    contracts::entry_point::{ValidationResult, ValidationResultWithAggregation},
-    Timestamp,
+    contracts::{
+        entry_point::{ValidationResult, ValidationResultWithAggregation},
+        shared_types::DepositInfo,
+    },
+    Timestamp, ValidTimeRange,
 };
 
 /// Equivalent to the generated `ValidationResult` or
 /// `ValidationResultWithAggregation` from `EntryPoint`, but with named structs
 /// instead of tuples and with a helper for deserializing.
-#[derive(Debug)]
-pub(crate) struct ValidationOutput {
-    pub(crate) return_info: ValidationReturnInfo,
-    pub(crate) sender_info: StakeInfo,
-    pub(crate) factory_info: StakeInfo,
-    pub(crate) paymaster_info: StakeInfo,
-    pub(crate) aggregator_info: Option<AggregatorInfo>,
+#[derive(Clone, Debug)]
+pub struct ValidationOutput {
+    /// Gas and validity information for the operation
+    pub return_info: ValidationReturnInfo,
+    /// Stake of the sender
+    pub sender_info: StakeInfo,
+    /// Stake of the factory, zero if there is no factory
+    pub factory_info: StakeInfo,
+    /// Stake of the paymaster, zero if there is no paymaster
+    pub paymaster_info: StakeInfo,
+    /// The aggregator and its stake, if the operation uses one
+    pub aggregator_info: Option<AggregatorInfo>,
 }
 
 impl AbiDecode for ValidationOutput {
@@ -82,27 +90,29 @@ impl From<ValidationResultWithAggregation> for ValidationOutput {
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct ValidationReturnInfo {
-    pub(crate) pre_op_gas: U256,
-    pub(crate) sig_failed: bool,
-    pub(crate) valid_after: Timestamp,
-    pub(crate) valid_until: Timestamp,
-    pub(crate) paymaster_context: Bytes,
+/// The `returnInfo` of the entry point's validation result
+#[derive(Clone, Debug)]
+pub struct ValidationReturnInfo {
+    /// Gas used by validation, including pre-verification gas
+    pub pre_op_gas: U256,
+    /// Prefund the operation is required to pay, in wei
+    pub prefund: U256,
+    /// Whether the signature check failed
+    pub sig_failed: bool,
+    /// Time after which the operation is valid
+    pub valid_after: Timestamp,
+    /// Time until which the operation is valid
+    pub valid_until: Timestamp,
+    /// Context returned by the paymaster's validation
+    pub paymaster_context: Bytes,
 }
 
 impl From<(U256, U256, bool, u64, u64, Bytes)> for ValidationReturnInfo {
     fn from(value: (U256, U256, bool, u64, u64, Bytes)) -> Self {
-        let (
-            pre_op_gas,
-            _, /* prefund */
-            sig_failed,
-            valid_after,
-            valid_until,
-            paymaster_context,
-        ) = value;
+        let (pre_op_gas, prefund, sig_failed, valid_after, valid_until, paymaster_context) = value;
         Self {
             pre_op_gas,
+            prefund,
             sig_failed,
             valid_after: valid_after.into(),
             valid_until: valid_until.into(),
@@ -111,10 +121,31 @@ impl From<(U256, U256, bool, u64, u64, Bytes)> for ValidationReturnInfo {
     }
 }
 
+/// Parse the validity window from the validation data returned by an account
+/// or paymaster. As in the entry point, a `validUntil` of zero means the
+/// operation never expires.
+pub(crate) fn valid_time_range_from_validation_data(validation_data: U256) -> ValidTimeRange {
+    let max_timestamp: u64 = (1 << 48) - 1;
+    let valid_until = (validation_data >> 160).low_u64() & max_timestamp;
+    let valid_after = (validation_data >> 208).low_u64() & max_timestamp;
+    ValidTimeRange::new(
+        valid_after.into(),
+        if valid_until == 0 {
+            max_timestamp
+        } else {
+            valid_until
+        }
+        .into(),
+    )
+}
+
+/// An entity's stake in the entry point
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct StakeInfo {
-    pub(crate) stake: U256,
-    pub(crate) unstake_delay_sec: U256,
+pub struct StakeInfo {
+    /// Amount staked, in wei
+    pub stake: U256,
+    /// Delay before the stake can be withdrawn, in seconds
+    pub unstake_delay_sec: U256,
 }
 
 impl From<(U256, U256)> for StakeInfo {
@@ -126,10 +157,22 @@ impl From<(U256, U256)> for StakeInfo {
     }
 }
 
+impl From<DepositInfo> for StakeInfo {
+    fn from(value: DepositInfo) -> Self {
+        Self {
+            stake: value.stake.into(),
+            unstake_delay_sec: value.unstake_delay_sec.into(),
+        }
+    }
+}
+
+/// The aggregator used by an operation and its stake
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct AggregatorInfo {
-    pub(crate) address: Address,
-    pub(crate) stake_info: StakeInfo,
+pub struct AggregatorInfo {
+    /// Address of the aggregator
+    pub address: Address,
+    /// Stake of the aggregator
+    pub stake_info: StakeInfo,
 }
 
 impl From<(Address, (U256, U256))> for AggregatorInfo {
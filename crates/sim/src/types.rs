@@ -15,12 +15,21 @@ use std::collections::{btree_map, BTreeMap};
 
 use anyhow::bail;
 use ethers::types::{Address, H256};
+use futures_util::future;
+use rundler_provider::{Provider, ProviderError};
+use rundler_types::UserOperation;
+use rundler_utils::math;
 use serde::{Deserialize, Serialize};
 
 /// The expected storage values for a user operation that must
 /// be checked to determine if this operation is valid.
+///
+/// Each value is the original value of the slot as observed during
+/// simulation, i.e. the value returned by the first `SLOAD` of the slot.
+/// Slots whose first access was an `SSTORE` are not included, since the
+/// operation does not depend on their prior value.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
-pub struct ExpectedStorage(BTreeMap<Address, BTreeMap<H256, H256>>);
+pub struct ExpectedStorage(pub(crate) BTreeMap<Address, BTreeMap<H256, H256>>);
 
 impl ExpectedStorage {
     /// Merge this expected storage with another one, accounting for conflicts.
@@ -46,15 +55,90 @@ impl ExpectedStorage {
         }
         Ok(())
     }
+
+    /// Returns the slots that are expected in only one of the two, or that are
+    /// expected to hold different values.
+    pub fn changed_slots(&self, other: &Self) -> Vec<(Address, H256)> {
+        let mut changed = vec![];
+        for (&address, values_by_slot) in &self.0 {
+            let other_values_by_slot = other.0.get(&address);
+            for (&slot, value) in values_by_slot {
+                if other_values_by_slot.and_then(|values| values.get(&slot)) != Some(value) {
+                    changed.push((address, slot));
+                }
+            }
+        }
+        for (&address, other_values_by_slot) in &other.0 {
+            let values_by_slot = self.0.get(&address);
+            for &slot in other_values_by_slot.keys() {
+                if values_by_slot.map_or(true, |values| !values.contains_key(&slot)) {
+                    changed.push((address, slot));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Rechecks the expected storage against the chain, returning the slots
+    /// whose current value at the given block differs from the original value
+    /// observed during simulation.
+    pub async fn recheck<P: Provider>(
+        &self,
+        provider: &P,
+        block_hash: Option<H256>,
+    ) -> Result<Vec<(Address, H256)>, ProviderError> {
+        let checks = self.0.iter().flat_map(|(&address, values_by_slot)| {
+            values_by_slot
+                .iter()
+                .map(move |(&slot, &original)| async move {
+                    let current = provider.get_storage_at(address, slot, block_hash).await?;
+                    Ok::<_, ProviderError>((current != original).then_some((address, slot)))
+                })
+        });
+        Ok(future::try_join_all(checks)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+}
+
+/// Returns true if `new` replaces `old`, that is, they have the same sender
+/// and nonce, and both of the fees of `new` are at least `min_bump_percent`
+/// higher than those of `old`.
+pub fn is_valid_replacement(
+    old: &UserOperation,
+    new: &UserOperation,
+    min_bump_percent: u64,
+) -> bool {
+    old.sender == new.sender
+        && old.nonce == new.nonce
+        && new.max_fee_per_gas >= math::increase_by_percent(old.max_fee_per_gas, min_bump_percent)
+        && new.max_priority_fee_per_gas
+            >= math::increase_by_percent(old.max_priority_fee_per_gas, min_bump_percent)
 }
 
-use std::fmt::{Display, Formatter};
+use std::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+};
 
 /// An error that occurs when a user operation violates a spec rule.
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug)]
 pub enum ViolationError<T> {
+    /// The operation violated one or more rules
     Violations(Vec<T>),
-    Other(#[from] anyhow::Error),
+    /// The node could not be reached or returned an error
+    Provider(ProviderError),
+    /// The caller cancelled the check before it completed
+    Cancelled,
+    /// The check was skipped because the node failed on the checks before it
+    NodeUnavailable,
+    /// The block checked against was reorged out of the chain before the
+    /// check completed, so the check should be retried
+    ReorgDetected,
+    /// Some other error occurred
+    Other(anyhow::Error),
 }
 
 impl<T> Clone for ViolationError<T>
@@ -66,6 +150,16 @@ where
             ViolationError::Violations(violations) => {
                 ViolationError::Violations(violations.clone())
             }
+            ViolationError::Provider(error) => ViolationError::Provider(match error {
+                ProviderError::JsonRpcError(error) => ProviderError::JsonRpcError(error.clone()),
+                ProviderError::ContractError(error) => ProviderError::ContractError(error.clone()),
+                ProviderError::Other(error) => {
+                    ProviderError::Other(anyhow::anyhow!(error.to_string()))
+                }
+            }),
+            ViolationError::Cancelled => ViolationError::Cancelled,
+            ViolationError::NodeUnavailable => ViolationError::NodeUnavailable,
+            ViolationError::ReorgDetected => ViolationError::ReorgDetected,
             ViolationError::Other(error) => {
                 ViolationError::Other(anyhow::anyhow!(error.to_string()))
             }
@@ -73,6 +167,22 @@ where
     }
 }
 
+impl<T> From<anyhow::Error> for ViolationError<T> {
+    fn from(error: anyhow::Error) -> Self {
+        // Provider errors are often propagated as `anyhow::Error`, recover them here
+        match error.downcast::<ProviderError>() {
+            Ok(error) => Self::Provider(error),
+            Err(error) => Self::Other(error),
+        }
+    }
+}
+
+impl<T> From<ProviderError> for ViolationError<T> {
+    fn from(error: ProviderError) -> Self {
+        Self::Provider(error)
+    }
+}
+
 impl<T> From<Vec<T>> for ViolationError<T> {
     fn from(violations: Vec<T>) -> Self {
         Self::Violations(violations)
@@ -94,7 +204,133 @@ impl<T: Display> Display for ViolationError<T> {
                     Ok(())
                 }
             }
+            ViolationError::Provider(error) => Display::fmt(error, f),
+            ViolationError::Cancelled => f.write_str("check was cancelled"),
+            ViolationError::NodeUnavailable => f.write_str("node is unavailable"),
+            ViolationError::ReorgDetected => f.write_str("block was reorged during the check"),
             ViolationError::Other(error) => Display::fmt(error, f),
         }
     }
 }
+
+impl<T: Debug + Display> Error for ViolationError<T> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ViolationError::Violations(_)
+            | ViolationError::Cancelled
+            | ViolationError::NodeUnavailable
+            | ViolationError::ReorgDetected => None,
+            ViolationError::Provider(error) => Some(error),
+            ViolationError::Other(error) => Some(error.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use rundler_provider::MockProvider;
+
+    use super::*;
+
+    fn replacement_test_op(
+        nonce: u64,
+        max_fee_per_gas: u64,
+        max_priority_fee_per_gas: u64,
+    ) -> UserOperation {
+        UserOperation {
+            sender: Address::random(),
+            nonce: nonce.into(),
+            max_fee_per_gas: max_fee_per_gas.into(),
+            max_priority_fee_per_gas: max_priority_fee_per_gas.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_replacement_insufficient_bump() {
+        let old = replacement_test_op(1, 100, 10);
+        let new = UserOperation {
+            max_fee_per_gas: 109.into(),
+            max_priority_fee_per_gas: 20.into(),
+            ..old.clone()
+        };
+        assert!(!is_valid_replacement(&old, &new, 10));
+    }
+
+    #[test]
+    fn test_replacement_sufficient_bump() {
+        let old = replacement_test_op(1, 100, 10);
+        let new = UserOperation {
+            max_fee_per_gas: 110.into(),
+            max_priority_fee_per_gas: 11.into(),
+            ..old.clone()
+        };
+        assert!(is_valid_replacement(&old, &new, 10));
+    }
+
+    #[test]
+    fn test_replacement_mismatched_nonce() {
+        let old = replacement_test_op(1, 100, 10);
+        let new = UserOperation {
+            nonce: 2.into(),
+            max_fee_per_gas: 200.into(),
+            max_priority_fee_per_gas: 20.into(),
+            ..old.clone()
+        };
+        assert!(!is_valid_replacement(&old, &new, 10));
+    }
+
+    #[test]
+    fn test_violations_have_no_source() {
+        let error: ViolationError<String> = vec!["first".to_string(), "second".to_string()].into();
+        assert!(error.source().is_none());
+        assert_eq!(error.to_string(), "multiple violations: first; second; ");
+
+        let error: ViolationError<String> = vec!["only".to_string()].into();
+        assert!(error.source().is_none());
+        assert_eq!(error.to_string(), "only");
+    }
+
+    #[test]
+    fn test_other_source_is_inner_error() {
+        let error: ViolationError<String> = anyhow!("provider failed").into();
+        assert_eq!(error.to_string(), "provider failed");
+        assert_eq!(error.source().unwrap().to_string(), "provider failed");
+    }
+
+    #[test]
+    fn test_provider_error_is_recovered_from_anyhow() {
+        let error: ViolationError<String> =
+            anyhow::Error::from(ProviderError::ContractError("reverted".to_string())).into();
+        assert!(matches!(
+            error,
+            ViolationError::Provider(ProviderError::ContractError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recheck_detects_changed_slot() {
+        let address = Address::random();
+        let unchanged_slot = H256::from_low_u64_be(1);
+        let changed_slot = H256::from_low_u64_be(2);
+        let mut values_by_slot = BTreeMap::new();
+        values_by_slot.insert(unchanged_slot, H256::from_low_u64_be(10));
+        values_by_slot.insert(changed_slot, H256::from_low_u64_be(20));
+        let expected = ExpectedStorage(BTreeMap::from([(address, values_by_slot)]));
+
+        let mut provider = MockProvider::new();
+        provider
+            .expect_get_storage_at()
+            .returning(move |_, slot, _| {
+                if slot == changed_slot {
+                    Ok(H256::from_low_u64_be(21))
+                } else {
+                    Ok(H256::from_low_u64_be(10))
+                }
+            });
+
+        let changed = expected.recheck(&provider, None).await.unwrap();
+        assert_eq!(changed, vec![(address, changed_slot)]);
+    }
+}
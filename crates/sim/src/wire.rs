@@ -0,0 +1,669 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+//! Compact binary encoding of simulation results for sending between services.
+//!
+//! Each type is converted to a private mirror type whose fields are in a fixed
+//! order and whose words and addresses are fixed-size byte arrays, and the
+//! mirror is encoded with `bincode`. Maps and sets are sorted so that equal
+//! values always encode to the same bytes.
+//!
+//! The encoding starts with a version byte, which is bumped whenever a mirror
+//! type changes, and decoding fails if the version does not match.
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Context;
+use ethers::types::{Bytes, Opcode, H160, H256, U256};
+use rundler_provider::AggregatorSimOut;
+use rundler_types::{Entity, EntityType, Timestamp, ValidTimeRange};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ExpectedStorage, GasEstimate, SimulationSuccess, SimulationViolation, SlotAccess, TokenCharge,
+    ViolationOpCode,
+};
+
+type Word = [u8; 32];
+type Addr = [u8; 20];
+
+fn word(value: U256) -> Word {
+    let mut out = [0; 32];
+    value.to_big_endian(&mut out);
+    out
+}
+
+fn from_word(word: Word) -> U256 {
+    U256::from_big_endian(&word)
+}
+
+// Version of the encoding, bumped whenever a mirror type changes
+const WIRE_VERSION: u8 = 1;
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut out = vec![WIRE_VERSION];
+    bincode::serialize_into(&mut out, value).expect("encoding to a vec should not fail");
+    out
+}
+
+fn decode<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> anyhow::Result<T> {
+    let (&version, bytes) = bytes
+        .split_first()
+        .context("encoding should not be empty")?;
+    anyhow::ensure!(
+        version == WIRE_VERSION,
+        "unsupported encoding version {version}, expected {WIRE_VERSION}"
+    );
+    bincode::deserialize(bytes).context("should decode bincode")
+}
+
+impl SimulationSuccess {
+    /// Encode as compact binary, to be decoded with `from_bincode`
+    pub fn to_bincode(&self) -> Vec<u8> {
+        encode(&WireSimulationSuccess::from(self))
+    }
+
+    /// Decode from the output of `to_bincode`
+    pub fn from_bincode(bytes: &[u8]) -> anyhow::Result<Self> {
+        decode::<WireSimulationSuccess>(bytes)?.try_into()
+    }
+}
+
+impl SimulationViolation {
+    /// Encode as compact binary, to be decoded with `from_bincode`
+    pub fn to_bincode(&self) -> Vec<u8> {
+        encode(&WireViolation::from(self))
+    }
+
+    /// Decode from the output of `to_bincode`
+    pub fn from_bincode(bytes: &[u8]) -> anyhow::Result<Self> {
+        decode::<WireViolation>(bytes)?.try_into()
+    }
+}
+
+impl GasEstimate {
+    /// Encode as compact binary, to be decoded with `from_bincode`
+    pub fn to_bincode(&self) -> Vec<u8> {
+        encode(&[
+            word(self.pre_verification_gas),
+            word(self.verification_gas_limit),
+            word(self.call_gas_limit),
+        ])
+    }
+
+    /// Decode from the output of `to_bincode`
+    pub fn from_bincode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let [pre_verification_gas, verification_gas_limit, call_gas_limit] =
+            decode::<[Word; 3]>(bytes)?;
+        Ok(Self {
+            pre_verification_gas: from_word(pre_verification_gas),
+            verification_gas_limit: from_word(verification_gas_limit),
+            call_gas_limit: from_word(call_gas_limit),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireSimulationSuccess {
+    mempools: Vec<Word>,
+    block_hash: Word,
+    block_number: u64,
+    pre_op_gas: Word,
+    valid_time_range: (u64, u64),
+    account_valid_range: (u64, u64),
+    paymaster_valid_range: Option<(u64, u64)>,
+    paymaster_window_ending_soon: bool,
+    aggregator: Option<(Addr, Vec<u8>)>,
+    code_hash: Word,
+    code_hashes: Vec<(Addr, Word)>,
+    entities_needing_stake: Vec<u8>,
+    account_is_staked: bool,
+    sender_nonce_after: Word,
+    nonce_key: Word,
+    accessed_addresses: Vec<Addr>,
+    expected_storage: Vec<(Addr, Vec<(Word, Word)>)>,
+    created_contracts: Vec<Addr>,
+    token_charge: Option<(Addr, Word)>,
+    partial: bool,
+    warnings: Vec<WireViolation>,
+    storage_accesses: Vec<Vec<WireSlotAccess>>,
+    opcodes_used: Vec<u8>,
+}
+
+// Address, then sorted read slots and sorted written slots
+type WireSlotAccess = (Addr, Vec<Word>, Vec<Word>);
+
+fn sorted_words(slots: &HashSet<U256>) -> Vec<Word> {
+    let mut words: Vec<_> = slots.iter().map(|&slot| word(slot)).collect();
+    words.sort();
+    words
+}
+
+fn time_range(range: ValidTimeRange) -> (u64, u64) {
+    (
+        range.valid_after.seconds_since_epoch(),
+        range.valid_until.seconds_since_epoch(),
+    )
+}
+
+fn from_time_range((valid_after, valid_until): (u64, u64)) -> ValidTimeRange {
+    ValidTimeRange::new(Timestamp::new(valid_after), Timestamp::new(valid_until))
+}
+
+impl From<&SimulationSuccess> for WireSimulationSuccess {
+    fn from(success: &SimulationSuccess) -> Self {
+        let mut code_hashes: Vec<_> = success
+            .code_hashes
+            .iter()
+            .map(|(address, hash)| (address.0, hash.0))
+            .collect();
+        code_hashes.sort();
+        let mut accessed_addresses: Vec<_> =
+            success.accessed_addresses.iter().map(|a| a.0).collect();
+        accessed_addresses.sort();
+        Self {
+            mempools: success.mempools.iter().map(|h| h.0).collect(),
+            block_hash: success.block_hash.0,
+            block_number: success.block_number,
+            pre_op_gas: word(success.pre_op_gas),
+            valid_time_range: time_range(success.valid_time_range),
+            account_valid_range: time_range(success.account_valid_range),
+            paymaster_valid_range: success.paymaster_valid_range.map(time_range),
+            paymaster_window_ending_soon: success.paymaster_window_ending_soon,
+            aggregator: success
+                .aggregator
+                .as_ref()
+                .map(|agg| (agg.address.0, agg.signature.to_vec())),
+            code_hash: success.code_hash.0,
+            code_hashes,
+            entities_needing_stake: success
+                .entities_needing_stake
+                .iter()
+                .map(|&kind| entity_type(kind))
+                .collect(),
+            account_is_staked: success.account_is_staked,
+            sender_nonce_after: word(success.sender_nonce_after),
+            nonce_key: word(success.nonce_key),
+            accessed_addresses,
+            expected_storage: success
+                .expected_storage
+                .0
+                .iter()
+                .map(|(address, values_by_slot)| {
+                    let values_by_slot = values_by_slot
+                        .iter()
+                        .map(|(slot, value)| (slot.0, value.0))
+                        .collect();
+                    (address.0, values_by_slot)
+                })
+                .collect(),
+            created_contracts: success.created_contracts.iter().map(|a| a.0).collect(),
+            token_charge: success
+                .token_charge
+                .map(|charge| (charge.token.0, word(charge.amount))),
+            partial: success.partial,
+            warnings: success.warnings.iter().map(WireViolation::from).collect(),
+            storage_accesses: success
+                .storage_accesses
+                .iter()
+                .map(|phase| {
+                    let mut phase: Vec<_> = phase
+                        .iter()
+                        .map(|(address, access)| {
+                            (
+                                address.0,
+                                sorted_words(&access.reads),
+                                sorted_words(&access.writes),
+                            )
+                        })
+                        .collect();
+                    phase.sort();
+                    phase
+                })
+                .collect(),
+            opcodes_used: success.opcodes_used.iter().map(|&op| op as u8).collect(),
+        }
+    }
+}
+
+impl TryFrom<WireSimulationSuccess> for SimulationSuccess {
+    type Error = anyhow::Error;
+
+    fn try_from(wire: WireSimulationSuccess) -> anyhow::Result<Self> {
+        Ok(Self {
+            mempools: wire.mempools.into_iter().map(H256).collect(),
+            block_hash: H256(wire.block_hash),
+            block_number: wire.block_number,
+            pre_op_gas: from_word(wire.pre_op_gas),
+            valid_time_range: from_time_range(wire.valid_time_range),
+            account_valid_range: from_time_range(wire.account_valid_range),
+            paymaster_valid_range: wire.paymaster_valid_range.map(from_time_range),
+            paymaster_window_ending_soon: wire.paymaster_window_ending_soon,
+            aggregator: wire
+                .aggregator
+                .map(|(address, signature)| AggregatorSimOut {
+                    address: H160(address),
+                    signature: signature.into(),
+                }),
+            code_hash: H256(wire.code_hash),
+            code_hashes: wire
+                .code_hashes
+                .into_iter()
+                .map(|(address, hash)| (H160(address), H256(hash)))
+                .collect(),
+            entities_needing_stake: wire
+                .entities_needing_stake
+                .into_iter()
+                .map(from_entity_type)
+                .collect::<anyhow::Result<_>>()?,
+            account_is_staked: wire.account_is_staked,
+            sender_nonce_after: from_word(wire.sender_nonce_after),
+            nonce_key: from_word(wire.nonce_key),
+            accessed_addresses: wire.accessed_addresses.into_iter().map(H160).collect(),
+            expected_storage: ExpectedStorage(
+                wire.expected_storage
+                    .into_iter()
+                    .map(|(address, values_by_slot)| {
+                        let values_by_slot: BTreeMap<_, _> = values_by_slot
+                            .into_iter()
+                            .map(|(slot, value)| (H256(slot), H256(value)))
+                            .collect();
+                        (H160(address), values_by_slot)
+                    })
+                    .collect(),
+            ),
+            created_contracts: wire.created_contracts.into_iter().map(H160).collect(),
+            token_charge: wire.token_charge.map(|(token, amount)| TokenCharge {
+                token: H160(token),
+                amount: from_word(amount),
+            }),
+            partial: wire.partial,
+            warnings: wire
+                .warnings
+                .into_iter()
+                .map(SimulationViolation::try_from)
+                .collect::<anyhow::Result<_>>()?,
+            storage_accesses: wire
+                .storage_accesses
+                .into_iter()
+                .map(|phase| {
+                    phase
+                        .into_iter()
+                        .map(|(address, reads, writes)| {
+                            let access = SlotAccess {
+                                reads: reads.into_iter().map(from_word).collect(),
+                                writes: writes.into_iter().map(from_word).collect(),
+                            };
+                            (H160(address), access)
+                        })
+                        .collect()
+                })
+                .collect(),
+            opcodes_used: wire
+                .opcodes_used
+                .into_iter()
+                .map(|op| Ok(from_opcode(op)?.0))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+fn entity_type(kind: EntityType) -> u8 {
+    match kind {
+        EntityType::Account => 0,
+        EntityType::Paymaster => 1,
+        EntityType::Aggregator => 2,
+        EntityType::Factory => 3,
+    }
+}
+
+fn from_entity_type(kind: u8) -> anyhow::Result<EntityType> {
+    Ok(match kind {
+        0 => EntityType::Account,
+        1 => EntityType::Paymaster,
+        2 => EntityType::Aggregator,
+        3 => EntityType::Factory,
+        _ => anyhow::bail!("invalid entity type {kind}"),
+    })
+}
+
+type WireEntity = (u8, Addr);
+
+fn entity(entity: Entity) -> WireEntity {
+    (entity_type(entity.kind), entity.address.0)
+}
+
+fn from_entity((kind, address): WireEntity) -> anyhow::Result<Entity> {
+    Ok(Entity {
+        kind: from_entity_type(kind)?,
+        address: H160(address),
+    })
+}
+
+fn from_opcode(opcode: u8) -> anyhow::Result<ViolationOpCode> {
+    Ok(ViolationOpCode(
+        Opcode::try_from(opcode).with_context(|| format!("invalid opcode {opcode:#x}"))?,
+    ))
+}
+
+// Changing the variants changes the encoding, so bump `WIRE_VERSION`
+#[derive(Serialize, Deserialize)]
+enum WireViolation {
+    InvalidSignature,
+    UsedForbiddenOpcode(WireEntity, Addr, u8, u64),
+    RestrictedOpcode(WireEntity, u8),
+    UsedForbiddenPrecompile(WireEntity, Addr, Addr),
+    AccessedUndeployedContract(WireEntity, Addr),
+    FactoryCalledCreate2Twice(Addr),
+    InvalidStorageAccess(WireEntity, Addr, Vec<Word>),
+    TransientStorageAccess(WireEntity),
+    UsedBlockHash(WireEntity),
+    CalledHandleOps(WireEntity),
+    CalledBannedEntryPointMethod(WireEntity),
+    CallHadValue(WireEntity),
+    TooManyAccessedAddresses(u64),
+    OpGasExceedsBlockLimit(Word, Word),
+    CodeHashChanged(Vec<Addr>),
+    NotStaked(WireEntity, Word, Word, Word, Word),
+    UnintendedRevertWithMessage(u8, String, Option<Addr>),
+    UnintendedRevert(u8),
+    DidNotRevert(Vec<u8>),
+    WrongNumberOfPhases(u32),
+    OutOfGas(WireEntity),
+    AggregatorValidationFailed,
+    TruncatedViolations(u64),
+    SenderDenied(Addr),
+    FactoryDenied(Addr),
+    PaymasterDenied(Addr),
+    FactoryNotDeployed(Addr),
+    AggregatorReturnedEmptySignature(Addr),
+    CustomPrecheckFailed(String),
+}
+
+impl From<&SimulationViolation> for WireViolation {
+    fn from(violation: &SimulationViolation) -> Self {
+        use SimulationViolation as V;
+        match violation {
+            V::InvalidSignature => Self::InvalidSignature,
+            V::UsedForbiddenOpcode(e, address, opcode, count) => {
+                Self::UsedForbiddenOpcode(entity(*e), address.0, opcode.0 as u8, *count as u64)
+            }
+            V::RestrictedOpcode(e, opcode) => Self::RestrictedOpcode(entity(*e), opcode.0 as u8),
+            V::UsedForbiddenPrecompile(e, address, precompile) => {
+                Self::UsedForbiddenPrecompile(entity(*e), address.0, precompile.0)
+            }
+            V::AccessedUndeployedContract(e, address) => {
+                Self::AccessedUndeployedContract(entity(*e), address.0)
+            }
+            V::FactoryCalledCreate2Twice(address) => Self::FactoryCalledCreate2Twice(address.0),
+            V::InvalidStorageAccess(e, address, slots) => Self::InvalidStorageAccess(
+                entity(*e),
+                address.0,
+                slots.iter().map(|slot| word(*slot)).collect(),
+            ),
+            V::TransientStorageAccess(e) => Self::TransientStorageAccess(entity(*e)),
+            V::UsedBlockHash(e) => Self::UsedBlockHash(entity(*e)),
+            V::CalledHandleOps(e) => Self::CalledHandleOps(entity(*e)),
+            V::CalledBannedEntryPointMethod(e) => Self::CalledBannedEntryPointMethod(entity(*e)),
+            V::CallHadValue(e) => Self::CallHadValue(entity(*e)),
+            V::TooManyAccessedAddresses(count) => Self::TooManyAccessedAddresses(*count as u64),
+            V::OpGasExceedsBlockLimit(gas, limit) => {
+                Self::OpGasExceedsBlockLimit(word(*gas), word(*limit))
+            }
+            V::CodeHashChanged(addresses) => {
+                Self::CodeHashChanged(addresses.iter().map(|address| address.0).collect())
+            }
+            V::NotStaked {
+                entity: e,
+                actual_stake,
+                required_stake,
+                actual_delay,
+                required_delay,
+            } => Self::NotStaked(
+                entity(*e),
+                word(*actual_stake),
+                word(*required_stake),
+                word(*actual_delay),
+                word(*required_delay),
+            ),
+            V::UnintendedRevertWithMessage(kind, message, address) => {
+                Self::UnintendedRevertWithMessage(
+                    entity_type(*kind),
+                    message.clone(),
+                    address.map(|a| a.0),
+                )
+            }
+            V::UnintendedRevert(kind) => Self::UnintendedRevert(entity_type(*kind)),
+            V::DidNotRevert(data) => Self::DidNotRevert(data.to_vec()),
+            V::WrongNumberOfPhases(count) => Self::WrongNumberOfPhases(*count),
+            V::OutOfGas(e) => Self::OutOfGas(entity(*e)),
+            V::AggregatorValidationFailed => Self::AggregatorValidationFailed,
+            V::TruncatedViolations(count) => Self::TruncatedViolations(*count as u64),
+            V::SenderDenied(address) => Self::SenderDenied(address.0),
+            V::FactoryDenied(address) => Self::FactoryDenied(address.0),
+            V::FactoryNotDeployed(address) => Self::FactoryNotDeployed(address.0),
+            V::PaymasterDenied(address) => Self::PaymasterDenied(address.0),
+            V::AggregatorReturnedEmptySignature(address) => {
+                Self::AggregatorReturnedEmptySignature(address.0)
+            }
+            V::CustomPrecheckFailed(reason) => Self::CustomPrecheckFailed(reason.clone()),
+        }
+    }
+}
+
+impl TryFrom<WireViolation> for SimulationViolation {
+    type Error = anyhow::Error;
+
+    fn try_from(wire: WireViolation) -> anyhow::Result<Self> {
+        use WireViolation as W;
+        Ok(match wire {
+            W::InvalidSignature => Self::InvalidSignature,
+            W::UsedForbiddenOpcode(e, address, opcode, count) => Self::UsedForbiddenOpcode(
+                from_entity(e)?,
+                H160(address),
+                from_opcode(opcode)?,
+                count as usize,
+            ),
+            W::RestrictedOpcode(e, opcode) => {
+                Self::RestrictedOpcode(from_entity(e)?, from_opcode(opcode)?)
+            }
+            W::UsedForbiddenPrecompile(e, address, precompile) => {
+                Self::UsedForbiddenPrecompile(from_entity(e)?, H160(address), H160(precompile))
+            }
+            W::AccessedUndeployedContract(e, address) => {
+                Self::AccessedUndeployedContract(from_entity(e)?, H160(address))
+            }
+            W::FactoryCalledCreate2Twice(address) => Self::FactoryCalledCreate2Twice(H160(address)),
+            W::InvalidStorageAccess(e, address, slots) => Self::InvalidStorageAccess(
+                from_entity(e)?,
+                H160(address),
+                slots.into_iter().map(from_word).collect(),
+            ),
+            W::TransientStorageAccess(e) => Self::TransientStorageAccess(from_entity(e)?),
+            W::UsedBlockHash(e) => Self::UsedBlockHash(from_entity(e)?),
+            W::CalledHandleOps(e) => Self::CalledHandleOps(from_entity(e)?),
+            W::CalledBannedEntryPointMethod(e) => {
+                Self::CalledBannedEntryPointMethod(from_entity(e)?)
+            }
+            W::CallHadValue(e) => Self::CallHadValue(from_entity(e)?),
+            W::TooManyAccessedAddresses(count) => Self::TooManyAccessedAddresses(count.try_into()?),
+            W::OpGasExceedsBlockLimit(gas, limit) => {
+                Self::OpGasExceedsBlockLimit(from_word(gas), from_word(limit))
+            }
+            W::CodeHashChanged(addresses) => {
+                Self::CodeHashChanged(addresses.into_iter().map(H160).collect())
+            }
+            W::NotStaked(e, actual_stake, required_stake, actual_delay, required_delay) => {
+                Self::NotStaked {
+                    entity: from_entity(e)?,
+                    actual_stake: from_word(actual_stake),
+                    required_stake: from_word(required_stake),
+                    actual_delay: from_word(actual_delay),
+                    required_delay: from_word(required_delay),
+                }
+            }
+            W::UnintendedRevertWithMessage(kind, message, address) => {
+                Self::UnintendedRevertWithMessage(
+                    from_entity_type(kind)?,
+                    message,
+                    address.map(H160),
+                )
+            }
+            W::UnintendedRevert(kind) => Self::UnintendedRevert(from_entity_type(kind)?),
+            W::DidNotRevert(data) => Self::DidNotRevert(Bytes::from(data)),
+            W::WrongNumberOfPhases(count) => Self::WrongNumberOfPhases(count),
+            W::OutOfGas(e) => Self::OutOfGas(from_entity(e)?),
+            W::AggregatorValidationFailed => Self::AggregatorValidationFailed,
+            W::TruncatedViolations(count) => Self::TruncatedViolations(count.try_into()?),
+            W::SenderDenied(address) => Self::SenderDenied(H160(address)),
+            W::FactoryDenied(address) => Self::FactoryDenied(H160(address)),
+            W::FactoryNotDeployed(address) => Self::FactoryNotDeployed(H160(address)),
+            W::PaymasterDenied(address) => Self::PaymasterDenied(H160(address)),
+            W::AggregatorReturnedEmptySignature(address) => {
+                Self::AggregatorReturnedEmptySignature(H160(address))
+            }
+            W::CustomPrecheckFailed(reason) => Self::CustomPrecheckFailed(reason),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use ethers::types::Address;
+
+    use super::*;
+
+    #[test]
+    fn test_simulation_success_round_trip() {
+        let address = Address::random();
+        let slot = H256::random();
+        let value = H256::random();
+        let success = SimulationSuccess {
+            mempools: vec![H256::random()],
+            block_hash: H256::random(),
+            block_number: 100,
+            pre_op_gas: 50_000.into(),
+            valid_time_range: ValidTimeRange::new(Timestamp::new(10), Timestamp::new(20)),
+            account_valid_range: ValidTimeRange::new(Timestamp::new(10), Timestamp::new(30)),
+            paymaster_valid_range: Some(ValidTimeRange::new(Timestamp::new(5), Timestamp::new(20))),
+            paymaster_window_ending_soon: true,
+            aggregator: Some(AggregatorSimOut {
+                address: Address::random(),
+                signature: vec![1, 2, 3].into(),
+            }),
+            code_hash: H256::random(),
+            code_hashes: HashMap::from([(address, H256::random())]),
+            entities_needing_stake: vec![EntityType::Paymaster, EntityType::Factory],
+            account_is_staked: true,
+            sender_nonce_after: U256::MAX,
+            nonce_key: 5.into(),
+            accessed_addresses: HashSet::from([address, Address::random()]),
+            expected_storage: ExpectedStorage(BTreeMap::from([(
+                address,
+                BTreeMap::from([(slot, value)]),
+            )])),
+            created_contracts: vec![address],
+            token_charge: Some(TokenCharge {
+                token: Address::random(),
+                amount: 1_000.into(),
+            }),
+            partial: false,
+            warnings: vec![SimulationViolation::AggregatorValidationFailed],
+            storage_accesses: vec![HashMap::from([(
+                address,
+                SlotAccess {
+                    reads: HashSet::from([1.into(), 2.into()]),
+                    writes: HashSet::from([2.into()]),
+                },
+            )])],
+            opcodes_used: BTreeSet::from([Opcode::SELFBALANCE]),
+        };
+
+        let decoded = SimulationSuccess::from_bincode(&success.to_bincode()).unwrap();
+        // `SimulationSuccess` is not `PartialEq`, so compare the encodings,
+        // which are deterministic
+        assert_eq!(decoded.to_bincode(), success.to_bincode());
+        assert_eq!(decoded.sender_nonce_after, U256::MAX);
+        assert_eq!(decoded.code_hashes, success.code_hashes);
+        assert_eq!(decoded.accessed_addresses, success.accessed_addresses);
+    }
+
+    #[test]
+    fn test_simulation_violation_round_trip() {
+        let entity = Entity::paymaster(Address::random());
+        let violations = vec![
+            SimulationViolation::InvalidSignature,
+            SimulationViolation::UsedForbiddenOpcode(
+                entity,
+                Address::random(),
+                ViolationOpCode(Opcode::GASPRICE),
+                1,
+            ),
+            SimulationViolation::InvalidStorageAccess(
+                entity,
+                Address::random(),
+                vec![U256::MAX, U256::one()],
+            ),
+            SimulationViolation::CodeHashChanged(vec![Address::random(), Address::random()]),
+            SimulationViolation::NotStaked {
+                entity,
+                actual_stake: 1.into(),
+                required_stake: 2.into(),
+                actual_delay: 3.into(),
+                required_delay: 4.into(),
+            },
+            SimulationViolation::UnintendedRevertWithMessage(
+                EntityType::Account,
+                "AA23 reverted".to_string(),
+                Some(Address::random()),
+            ),
+            SimulationViolation::DidNotRevert(vec![0xde, 0xad].into()),
+            SimulationViolation::TruncatedViolations(150),
+        ];
+        for violation in violations {
+            let decoded = SimulationViolation::from_bincode(&violation.to_bincode()).unwrap();
+            assert_eq!(decoded, violation);
+        }
+    }
+
+    #[test]
+    fn test_gas_estimate_round_trip() {
+        let estimate = GasEstimate {
+            pre_verification_gas: 50_000.into(),
+            verification_gas_limit: 100_000.into(),
+            call_gas_limit: U256::MAX,
+        };
+
+        // the version and three fixed-size words, with no length prefixes
+        let encoded = estimate.to_bincode();
+        assert_eq!(encoded.len(), 1 + 3 * 32);
+        let decoded = GasEstimate::from_bincode(&encoded).unwrap();
+        assert_eq!(decoded.pre_verification_gas, estimate.pre_verification_gas);
+        assert_eq!(
+            decoded.verification_gas_limit,
+            estimate.verification_gas_limit
+        );
+        assert_eq!(decoded.call_gas_limit, estimate.call_gas_limit);
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let mut encoded = SimulationViolation::InvalidSignature.to_bincode();
+        assert_eq!(encoded[0], WIRE_VERSION);
+        encoded[0] = WIRE_VERSION + 1;
+        assert!(SimulationViolation::from_bincode(&encoded).is_err());
+        assert!(SimulationViolation::from_bincode(&[]).is_err());
+    }
+}
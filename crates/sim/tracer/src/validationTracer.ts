@@ -21,27 +21,39 @@ declare function toWord(s: string | Bytes): Bytes;
 interface Output {
   phases: Phase[];
   revertData: string | null;
+  returnData: string;
   accessedContractAddresses: string[];
   associatedSlotsByAddress: Record<string, string[]>;
   factoryCalledCreate2Twice: boolean;
+  createdContracts: string[];
   expectedStorage: Record<string, Record<string, string>>;
 }
 
 interface Phase {
   forbiddenOpcodesUsed: string[];
+  restrictableOpcodesUsed: string[];
+  // Number of executions of each forbidden or restrictable opcode, by
+  // combined key
+  opcodeCounts: Record<string, number>;
   forbiddenPrecompilesUsed: string[];
   storageAccesses: StorageAccess[];
   calledBannedEntryPointMethod: boolean;
+  calledHandleOps: boolean;
+  usedTransientStorage: boolean;
+  usedBlockHash: boolean;
   addressesCallingWithValue: string[];
   calledNonEntryPointWithValue: boolean;
   ranOutOfGas: boolean;
   undeployedContractAccesses: string[];
   extCodeAccessInfo: Record<string, string>;
+  validationData: string | null;
 }
 
 interface StorageAccess {
   address: string;
   slots: string[];
+  readSlots: string[];
+  writtenSlots: string[];
 }
 
 interface RelevantStepData {
@@ -52,14 +64,18 @@ interface RelevantStepData {
 type InternalPhase = Omit<
   Phase,
   | "forbiddenOpcodesUsed"
+  | "restrictableOpcodesUsed"
   | "forbiddenPrecompilesUsed"
   | "storageAccesses"
   | "addressesCallingWithValue"
   | "undeployedContractAccesses"
 > & {
   forbiddenOpcodesUsed: StringSet;
+  restrictableOpcodesUsed: StringSet;
   forbiddenPrecompilesUsed: StringSet;
   storageAccesses: Record<string, StringSet>;
+  storageReads: Record<string, StringSet>;
+  storageWrites: Record<string, StringSet>;
   addressesCallingWithValue: StringSet;
   undeployedContractAccesses: StringSet;
 };
@@ -74,21 +90,30 @@ type StringSet = Record<string, boolean | undefined>;
   }
 
   const DEPOSIT_TO_SELECTOR = "0xb760faf9";
+  // Selectors of `handleOps` and `handleAggregatedOps`.
+  const HANDLE_OPS_SELECTORS = stringSet(["0x1fad948c", "0x4b1d7cf5"]);
   const FORBIDDEN_OPCODES = stringSet([
     "GASPRICE",
     "GASLIMIT",
     "DIFFICULTY",
     "TIMESTAMP",
     "BASEFEE",
-    "BLOCKHASH",
     "NUMBER",
-    "SELFBALANCE",
     "BALANCE",
-    "ORIGIN",
     "CREATE",
     "COINBASE",
     "SELFDESTRUCT",
   ]);
+  // Opcodes whose use is reported separately so that the bundler can decide
+  // per entity whether they are allowed.
+  const RESTRICTABLE_OPCODES = stringSet([
+    "ADDRESS",
+    "CHAINID",
+    "SELFBALANCE",
+    "ORIGIN",
+  ]);
+  // TLOAD and TSTORE, matched by number since older nodes don't name them.
+  const TRANSIENT_STORAGE_OPCODES = [0x5c, 0x5d];
   // If you add any opcodes to this list, make sure they take the contract
   // address as their *second* argument, or modify the handling below.
   const CALL_OPCODES = stringSet([
@@ -119,34 +144,54 @@ type StringSet = Record<string, boolean | undefined>;
   const associatedSlotsByAddressMap: Record<string, StringSet> = {};
   const allStorageAccesses: Record<string, Record<string, string | null>> = {};
   let factoryCreate2Count = 0;
+  const createdContracts: StringSet = {};
   let currentPhase = newInternalPhase();
   let entryPointAddress = "";
+  // Set from the tracer config to disable our deviations from the spec.
+  let strictSpecMode = false;
   let pendingKeccakAddress = "";
   let last: RelevantStepData | null = null;
   let secondLast: RelevantStepData | null = null;
+  let callDepth = 0;
 
   function newInternalPhase(): InternalPhase {
     return {
       forbiddenOpcodesUsed: {},
+      restrictableOpcodesUsed: {},
+      opcodeCounts: {},
       forbiddenPrecompilesUsed: {},
       storageAccesses: {},
+      storageReads: {},
+      storageWrites: {},
       calledBannedEntryPointMethod: false,
+      calledHandleOps: false,
+      usedTransientStorage: false,
+      usedBlockHash: false,
       addressesCallingWithValue: {},
       calledNonEntryPointWithValue: false,
       ranOutOfGas: false,
       undeployedContractAccesses: {},
       extCodeAccessInfo: {},
+      validationData: null,
     };
   }
 
   function concludePhase(): void {
     const {
       calledBannedEntryPointMethod,
+      calledHandleOps,
+      usedTransientStorage,
+      usedBlockHash,
       calledNonEntryPointWithValue,
       ranOutOfGas,
+      opcodeCounts,
       extCodeAccessInfo,
+      validationData,
     } = currentPhase;
     const forbiddenOpcodesUsed = Object.keys(currentPhase.forbiddenOpcodesUsed);
+    const restrictableOpcodesUsed = Object.keys(
+      currentPhase.restrictableOpcodesUsed
+    );
     const forbiddenPrecompilesUsed = Object.keys(
       currentPhase.forbiddenPrecompilesUsed
     );
@@ -159,24 +204,44 @@ type StringSet = Record<string, boolean | undefined>;
     const storageAccesses: StorageAccess[] = [];
     Object.keys(currentPhase.storageAccesses).forEach((address) => {
       const slotsSet = currentPhase.storageAccesses[address];
-      storageAccesses.push({ address, slots: Object.keys(slotsSet) });
+      storageAccesses.push({
+        address,
+        slots: Object.keys(slotsSet),
+        readSlots: Object.keys(currentPhase.storageReads[address] || {}),
+        writtenSlots: Object.keys(currentPhase.storageWrites[address] || {}),
+      });
     });
 
     const phase: Phase = {
       forbiddenOpcodesUsed,
+      restrictableOpcodesUsed,
+      opcodeCounts,
       forbiddenPrecompilesUsed,
       storageAccesses,
       calledBannedEntryPointMethod,
+      calledHandleOps,
+      usedTransientStorage,
+      usedBlockHash,
       addressesCallingWithValue,
       calledNonEntryPointWithValue,
       ranOutOfGas,
       undeployedContractAccesses,
       extCodeAccessInfo,
+      validationData,
     };
     phases.push(phase);
     currentPhase = newInternalPhase();
   }
 
+  function countOpcode(key: string): void {
+    currentPhase.opcodeCounts[key] = (currentPhase.opcodeCounts[key] || 0) + 1;
+  }
+
+  function recordForbiddenOpcode(key: string): void {
+    currentPhase.forbiddenOpcodesUsed[key] = true;
+    countOpcode(key);
+  }
+
   function bigIntToNumber(n: BigInt): number {
     return parseInt(n.toString());
   }
@@ -200,7 +265,11 @@ type StringSet = Record<string, boolean | undefined>;
   }
 
   return {
-    result(_ctx, _db): Output {
+    setup(config: string): void {
+      strictSpecMode = JSON.parse(config).strictSpecMode === true;
+    },
+
+    result(ctx, _db): Output {
       concludePhase();
       const associatedSlotsByAddress: Record<string, string[]> = {};
       Object.keys(associatedSlotsByAddressMap).forEach((address) => {
@@ -226,9 +295,11 @@ type StringSet = Record<string, boolean | undefined>;
       return {
         phases,
         revertData,
+        returnData: toHex(ctx.output),
         accessedContractAddresses: Object.keys(accessedContractAddresses),
         associatedSlotsByAddress,
         factoryCalledCreate2Twice: factoryCreate2Count > 1,
+        createdContracts: Object.keys(createdContracts),
         expectedStorage,
       };
     },
@@ -270,21 +341,37 @@ type StringSet = Record<string, boolean | undefined>;
         // The entry point is allowed to freely call `GAS`, but otherwise we
         // require that a call opcode comes next.
         if (last?.opcode === "GAS" && !CALL_OPCODES[opcode]) {
-          currentPhase.forbiddenOpcodesUsed[
-            getContractCombinedKey(log, "GAS")
-          ] = true;
+          recordForbiddenOpcode(getContractCombinedKey(log, "GAS"));
         }
 
         if (FORBIDDEN_OPCODES[opcode]) {
-          currentPhase.forbiddenOpcodesUsed[
-            getContractCombinedKey(log, opcode)
-          ] = true;
+          recordForbiddenOpcode(getContractCombinedKey(log, opcode));
+        } else if (RESTRICTABLE_OPCODES[opcode]) {
+          const key = getContractCombinedKey(log, opcode);
+          currentPhase.restrictableOpcodesUsed[key] = true;
+          countOpcode(key);
+        } else if (opcode === "BLOCKHASH") {
+          // Reported separately so that the bundler can allow it for staked
+          // entities.
+          currentPhase.usedBlockHash = true;
+        } else if (
+          TRANSIENT_STORAGE_OPCODES.indexOf(log.op.toNumber()) >= 0
+        ) {
+          currentPhase.usedTransientStorage = true;
         }
       }
 
       if (secondLast && EXT_OPCODES[secondLast.opcode]) {
         const opString = `${secondLast.opcode} ${last?.opcode}`;
-        if (secondLast?.stackEnd && opString !== "EXTCODESIZE ISZERO") {
+        // Opcodes that the chain's EVM doesn't define, such as PUSH0 before
+        // Shanghai, abort the call rather than use the accessed code. Geth
+        // names them "opcode 0x.. not defined".
+        const isDefined = !opcode.startsWith("opcode ");
+        if (
+          secondLast?.stackEnd &&
+          opString !== "EXTCODESIZE ISZERO" &&
+          isDefined
+        ) {
           const addr = toAddress(secondLast.stackEnd.toString(16));
           const hexAddr = toHex(addr);
           currentPhase.extCodeAccessInfo[hexAddr] = opcode;
@@ -296,9 +383,7 @@ type StringSet = Record<string, boolean | undefined>;
           // In factory phase.
           factoryCreate2Count++;
         } else {
-          currentPhase.forbiddenOpcodesUsed[
-            getContractCombinedKey(log, opcode)
-          ] = true;
+          recordForbiddenOpcode(getContractCombinedKey(log, opcode));
         }
       } else if (opcode === "KECCAK256") {
         //
@@ -325,6 +410,13 @@ type StringSet = Record<string, boolean | undefined>;
             addressHex,
             (): StringSet => ({})
           )[slotHex] = true;
+          computeIfAbsent(
+            opcode === "SLOAD"
+              ? currentPhase.storageReads
+              : currentPhase.storageWrites,
+            addressHex,
+            (): StringSet => ({})
+          )[slotHex] = true;
         }
         let initialValuesBySlot = computeIfAbsent(
           allStorageAccesses,
@@ -375,6 +467,11 @@ type StringSet = Record<string, boolean | undefined>;
     },
 
     enter(frame) {
+      callDepth++;
+      const frameType = frame.getType();
+      if (frameType === "CREATE" || frameType === "CREATE2") {
+        createdContracts[toHex(frame.getTo())] = true;
+      }
       const from = toHex(frame.getFrom());
       if (from === entryPointAddress) {
         return;
@@ -382,13 +479,17 @@ type StringSet = Record<string, boolean | undefined>;
       const isToEntryPoint = toHex(frame.getTo()) === entryPointAddress;
       if (isToEntryPoint) {
         const input = frame.getInput();
+        const selector = toHex(input).substring(0, 10);
         // The spec says that calling entry point methods other than `depositTo`
-        // is banned. We deviate and also allow calling the entrypoint with no
-        // calldata, as this is equivalent to calling `depositTo` and without it
-        // many spec tests fail.
-        if (
-          input.length > 0 &&
-          toHex(input).substring(0, 10) !== DEPOSIT_TO_SELECTOR
+        // is banned. Unless in strict spec mode, we deviate and also allow
+        // calling the entrypoint with no calldata, as this is equivalent to
+        // calling `depositTo` and without it many spec tests fail. Reentering
+        // either of the bundle execution methods is reported separately.
+        if (HANDLE_OPS_SELECTORS[selector]) {
+          currentPhase.calledHandleOps = true;
+        } else if (
+          (input.length > 0 || strictSpecMode) &&
+          selector !== DEPOSIT_TO_SELECTOR
         ) {
           currentPhase.calledBannedEntryPointMethod = true;
         }
@@ -403,6 +504,21 @@ type StringSet = Record<string, boolean | undefined>;
       }
     },
 
-    exit(_frame) {},
+    exit(frame) {
+      callDepth--;
+      // In the account and paymaster phases, the entry point's own call is to
+      // `validateUserOp`, which returns the validation data, or to
+      // `validatePaymasterUserOp`, which returns a context and then the
+      // validation data.
+      if (callDepth === 0 && (phases.length === 1 || phases.length === 2)) {
+        const output = frame.getOutput();
+        const offset = phases.length === 2 ? 32 : 0;
+        if (output.length >= offset + 32) {
+          currentPhase.validationData = toHex(
+            output.subarray(offset, offset + 32)
+          );
+        }
+      }
+    },
   };
 })();
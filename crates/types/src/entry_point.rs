@@ -0,0 +1,39 @@
+// This file is part of Rundler.
+//
+// Rundler is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Lesser General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version.
+//
+// Rundler is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with Rundler.
+// If not, see https://www.gnu.org/licenses/.
+
+use std::str::FromStr;
+
+/// Version of the entry point contract that user operations are submitted to
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum EntryPointVersion {
+    /// Entry point v0.6
+    #[default]
+    V0_6,
+    /// Entry point v0.7
+    V0_7,
+    /// Entry point v0.8
+    V0_8,
+}
+
+impl FromStr for EntryPointVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v0.6" => Ok(Self::V0_6),
+            "v0.7" => Ok(Self::V0_7),
+            "v0.8" => Ok(Self::V0_8),
+            _ => Err(format!("unknown entry point version: {s}")),
+        }
+    }
+}
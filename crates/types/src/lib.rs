@@ -33,6 +33,9 @@ pub use contracts::shared_types::{UserOperation, UserOpsPerAggregator};
 mod entity;
 pub use entity::{Entity, EntityType};
 
+mod entry_point;
+pub use entry_point::EntryPointVersion;
+
 mod gas;
 pub use gas::GasFees;
 
@@ -40,7 +43,7 @@ mod timestamp;
 pub use timestamp::{Timestamp, ValidTimeRange};
 
 mod user_operation;
-pub use user_operation::UserOperationId;
+pub use user_operation::{EncodingError, OpKind, OpMetadata, UserOperationId};
 
 mod storage;
 pub use storage::StorageSlot;
@@ -12,7 +12,7 @@
 // If not, see https://www.gnu.org/licenses/.
 
 use ethers::{
-    abi::{encode, Token},
+    abi::{encode, AbiDecode, AbiEncode, AbiError, Token},
     types::{Address, Bytes, H256, U256},
     utils::keccak256,
 };
@@ -20,12 +20,58 @@ use strum::IntoEnumIterator;
 
 use crate::{
     entity::{Entity, EntityType},
-    UserOperation,
+    EntryPointVersion, UserOperation,
 };
 
 /// Number of bytes in the fixed size portion of an ABI encoded user operation
 const PACKED_USER_OPERATION_FIXED_LEN: usize = 480;
 
+/// EIP-712 type of the packed user operation hashed by entry point v0.8
+const PACKED_USER_OPERATION_TYPE: &str = "PackedUserOperation(address sender,uint256 nonce,bytes initCode,bytes callData,bytes32 accountGasLimits,uint256 preVerificationGas,bytes32 gasFees,bytes paymasterAndData)";
+
+/// EIP-712 domain type used by entry point v0.8
+const EIP712_DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+/// Error returned when a user operation does not survive a round trip
+/// through the entry point ABI encoding
+#[derive(Debug, thiserror::Error)]
+pub enum EncodingError {
+    /// The encoded operation could not be decoded
+    #[error("user operation could not be decoded: {0}")]
+    Decode(#[from] AbiError),
+    /// The decoded operation differs from the original
+    #[error("user operation changed when encoded and decoded")]
+    Mismatch,
+}
+
+/// Classification of a user operation by the entities involved in its validation
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct OpKind {
+    /// True if the operation deploys its sender using `init_code`
+    pub creates_wallet: bool,
+    /// True if the operation is sponsored by a paymaster
+    pub has_paymaster: bool,
+    /// True if the operation's signature is validated by an aggregator.
+    ///
+    /// This is only known after validation, and is always false when
+    /// classifying an operation on its own.
+    pub is_aggregated: bool,
+}
+
+/// The hash and entity addresses of a user operation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct OpMetadata {
+    /// Hash of the user operation, see `UserOperation::op_hash`
+    pub hash: H256,
+    /// Address of the sender
+    pub sender: Address,
+    /// Address of the factory, if any
+    pub factory: Option<Address>,
+    /// Address of the paymaster, if any
+    pub paymaster: Option<Address>,
+}
+
 /// Unique identifier for a user operation from a given sender
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct UserOperationId {
@@ -47,6 +93,69 @@ impl UserOperation {
         .into()
     }
 
+    /// Hash a user operation as the given version of the entry point does in
+    /// `getUserOpHash`.
+    ///
+    /// v0.6 hashes the operation's fields as they are. v0.7 packs the gas
+    /// limits and fees into 32 byte words before hashing, and v0.8 hashes the
+    /// same packed fields as EIP-712 typed data with the entry point as the
+    /// verifying contract.
+    pub fn op_hash_for_version(
+        &self,
+        version: EntryPointVersion,
+        entry_point: Address,
+        chain_id: u64,
+    ) -> H256 {
+        match version {
+            EntryPointVersion::V0_6 => self.op_hash(entry_point, chain_id),
+            EntryPointVersion::V0_7 => keccak256(encode(&[
+                Token::FixedBytes(keccak256(encode(&self.packed_hash_tokens())).to_vec()),
+                Token::Address(entry_point),
+                Token::Uint(chain_id.into()),
+            ]))
+            .into(),
+            EntryPointVersion::V0_8 => {
+                let mut struct_tokens = vec![Token::FixedBytes(
+                    keccak256(PACKED_USER_OPERATION_TYPE).to_vec(),
+                )];
+                struct_tokens.extend(self.packed_hash_tokens());
+                let domain_separator = keccak256(encode(&[
+                    Token::FixedBytes(keccak256(EIP712_DOMAIN_TYPE).to_vec()),
+                    Token::FixedBytes(keccak256("ERC4337").to_vec()),
+                    Token::FixedBytes(keccak256("1").to_vec()),
+                    Token::Uint(chain_id.into()),
+                    Token::Address(entry_point),
+                ]));
+                let struct_hash = keccak256(encode(&struct_tokens));
+                keccak256([&[0x19, 0x01], &domain_separator[..], &struct_hash[..]].concat()).into()
+            }
+        }
+    }
+
+    /// Check that this user operation encodes to the entry point ABI and
+    /// decodes back to the same operation.
+    pub fn validate_encoding(&self) -> Result<(), EncodingError> {
+        self.check_decodes_to_self(&self.clone().encode())
+    }
+
+    fn check_decodes_to_self(&self, encoded: &[u8]) -> Result<(), EncodingError> {
+        if UserOperation::decode(encoded)? != *self {
+            return Err(EncodingError::Mismatch);
+        }
+        Ok(())
+    }
+
+    /// Compute the hash of this user operation and extract its entity
+    /// addresses together.
+    pub fn entities_and_hash(&self, entry_point: Address, chain_id: u64) -> OpMetadata {
+        OpMetadata {
+            hash: self.op_hash(entry_point, chain_id),
+            sender: self.sender,
+            factory: self.factory(),
+            paymaster: self.paymaster(),
+        }
+    }
+
     /// Get the unique identifier for this user operation from its sender
     pub fn id(&self) -> UserOperationId {
         UserOperationId {
@@ -55,6 +164,40 @@ impl UserOperation {
         }
     }
 
+    /// Classify this user operation by the entities involved in its validation.
+    ///
+    /// Matches the entry point, which treats any non-empty `init_code` as a
+    /// wallet creation and any non-empty `paymaster_and_data` as sponsored.
+    pub fn classify(&self) -> OpKind {
+        OpKind {
+            creates_wallet: !self.init_code.is_empty(),
+            has_paymaster: !self.paymaster_and_data.is_empty(),
+            is_aggregated: false,
+        }
+    }
+
+    /// Compute the gas price this user operation pays at the given base fee.
+    ///
+    /// Matches the entry point, which charges
+    /// `min(max_fee_per_gas, base_fee + max_priority_fee_per_gas)`.
+    pub fn effective_gas_price(&self, base_fee: U256) -> U256 {
+        self.max_fee_per_gas
+            .min(base_fee.saturating_add(self.max_priority_fee_per_gas))
+    }
+
+    /// Get the key of the operation's nonce, its high 192 bits. Operations
+    /// with different keys use independent nonce sequences, and a key of zero
+    /// is the sender's default sequential nonce.
+    pub fn nonce_key(&self) -> U256 {
+        self.nonce >> 64
+    }
+
+    /// Get the sequence number of the operation's nonce within its key, its
+    /// low 64 bits
+    pub fn nonce_sequence(&self) -> U256 {
+        self.nonce & U256::from(u64::MAX)
+    }
+
     /// Get the address of the factory entity associated with this user operation, if any
     pub fn factory(&self) -> Option<Address> {
         Self::get_address_from_field(&self.init_code)
@@ -114,6 +257,26 @@ impl UserOperation {
         .into()
     }
 
+    // The fields hashed by entry point v0.7 and later, with the gas limits and
+    // fees each packed into a single word as two 128 bit values
+    fn packed_hash_tokens(&self) -> Vec<Token> {
+        let pack = |high: U256, low: U256| {
+            let mut word = [0; 32];
+            ((high << 128) | low).to_big_endian(&mut word);
+            Token::FixedBytes(word.to_vec())
+        };
+        vec![
+            Token::Address(self.sender),
+            Token::Uint(self.nonce),
+            Token::FixedBytes(keccak256(&self.init_code).to_vec()),
+            Token::FixedBytes(keccak256(&self.call_data).to_vec()),
+            pack(self.verification_gas_limit, self.call_gas_limit),
+            Token::Uint(self.pre_verification_gas),
+            pack(self.max_priority_fee_per_gas, self.max_fee_per_gas),
+            Token::FixedBytes(keccak256(&self.paymaster_and_data).to_vec()),
+        ]
+    }
+
     /// Gets an iterator on all entities associated with this user operation
     pub fn entities(&'_ self) -> impl Iterator<Item = Entity> + '_ {
         EntityType::iter().filter_map(|entity| {
@@ -142,9 +305,9 @@ fn pad_len(b: &Bytes) -> usize {
 mod tests {
     use std::str::FromStr;
 
-    use ethers::{
-        abi::AbiEncode,
-        types::{Bytes, U256},
+    use ethers::types::{
+        transaction::eip712::{Eip712, TypedData},
+        BigEndianHash, Bytes, U256,
     };
 
     use super::*;
@@ -258,6 +421,140 @@ mod tests {
         );
     }
 
+    fn hash_test_operation() -> UserOperation {
+        UserOperation {
+            sender: "0x1306b01bc3e4ad202612d3843387e94737673f53"
+                .parse()
+                .unwrap(),
+            nonce: 8942.into(),
+            init_code: "0x6942069420694206942069420694206942069420"
+                .parse()
+                .unwrap(),
+            call_data: "0x0000000000000000000000000000000000000000080085"
+                .parse()
+                .unwrap(),
+            call_gas_limit: 10000.into(),
+            verification_gas_limit: 100000.into(),
+            pre_verification_gas: 100.into(),
+            max_fee_per_gas: 99999.into(),
+            max_priority_fee_per_gas: 9999999.into(),
+            paymaster_and_data:
+                "0x0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+                    .parse()
+                    .unwrap(),
+            signature: "0xda0929f527cded8d0a1eaf2e8861d7f7e2d8160b7b13942f99dd367df4473a"
+                .parse()
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_hash_for_version_v0_6() {
+        let operation = hash_test_operation();
+        let entry_point = "0x66a15edcc3b50a663e72f1457ffd49b9ae284ddc"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            operation.op_hash_for_version(EntryPointVersion::V0_6, entry_point, 1337),
+            "0x484add9e4d8c3172d11b5feb6a3cc712280e176d278027cfa02ee396eb28afa1"
+                .parse()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hash_for_version_v0_8() {
+        // The v0.8 entry point's getUserOpHash() is the EIP-712 hash of the
+        // packed user operation, so check against ethers' generic EIP-712
+        // encoder using the entry point's domain and type definitions.
+        let operation = hash_test_operation();
+        let entry_point: Address = "0x4337084d9e255ff0702461cf8895ce9e3b5ff108"
+            .parse()
+            .unwrap();
+        let chain_id = 1337;
+        let pack = |high: U256, low: U256| H256::from_uint(&((high << 128) | low));
+        let typed_data: TypedData = serde_json::from_value(serde_json::json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" },
+                ],
+                "PackedUserOperation": [
+                    { "name": "sender", "type": "address" },
+                    { "name": "nonce", "type": "uint256" },
+                    { "name": "initCode", "type": "bytes" },
+                    { "name": "callData", "type": "bytes" },
+                    { "name": "accountGasLimits", "type": "bytes32" },
+                    { "name": "preVerificationGas", "type": "uint256" },
+                    { "name": "gasFees", "type": "bytes32" },
+                    { "name": "paymasterAndData", "type": "bytes" },
+                ],
+            },
+            "primaryType": "PackedUserOperation",
+            "domain": {
+                "name": "ERC4337",
+                "version": "1",
+                "chainId": chain_id,
+                "verifyingContract": entry_point,
+            },
+            "message": {
+                "sender": operation.sender,
+                "nonce": operation.nonce,
+                "initCode": operation.init_code,
+                "callData": operation.call_data,
+                "accountGasLimits": pack(operation.verification_gas_limit, operation.call_gas_limit),
+                "preVerificationGas": operation.pre_verification_gas,
+                "gasFees": pack(operation.max_priority_fee_per_gas, operation.max_fee_per_gas),
+                "paymasterAndData": operation.paymaster_and_data,
+            },
+        }))
+        .unwrap();
+
+        let hash = operation.op_hash_for_version(EntryPointVersion::V0_8, entry_point, chain_id);
+        assert_eq!(hash, H256(typed_data.encode_eip712().unwrap()));
+        assert_ne!(
+            hash,
+            operation.op_hash_for_version(EntryPointVersion::V0_7, entry_point, chain_id)
+        );
+    }
+
+    #[test]
+    fn test_validate_encoding() {
+        assert!(hash_test_operation().validate_encoding().is_ok());
+        assert!(UserOperation::default().validate_encoding().is_ok());
+    }
+
+    #[test]
+    fn test_validate_encoding_malformed() {
+        let operation = hash_test_operation();
+        let encoded = operation.clone().encode();
+        // the init code length is the first word of its tail, which follows
+        // the 11 head words
+        let init_code_len = 11 * 32;
+        assert_eq!(
+            U256::from_big_endian(&encoded[init_code_len..init_code_len + 32]),
+            operation.init_code.len().into()
+        );
+
+        // a length that runs past the end of the encoding fails to decode
+        let mut overlong = encoded.clone();
+        overlong[init_code_len..init_code_len + 32].copy_from_slice(&[0xff; 32]);
+        assert!(matches!(
+            operation.check_decodes_to_self(&overlong),
+            Err(EncodingError::Decode(_))
+        ));
+
+        // a shorter length decodes to a different init code
+        let mut short = encoded;
+        short[init_code_len + 31] -= 1;
+        assert!(matches!(
+            operation.check_decodes_to_self(&short),
+            Err(EncodingError::Mismatch)
+        ));
+    }
+
     #[test]
     fn test_get_address_from_field() {
         let paymaster_and_data: Bytes =
@@ -296,4 +593,118 @@ mod tests {
             user_operation.abi_encoded_size()
         );
     }
+
+    #[test]
+    fn test_effective_gas_price_max_fee_binding() {
+        let op = UserOperation {
+            max_fee_per_gas: 100.into(),
+            max_priority_fee_per_gas: 20.into(),
+            ..Default::default()
+        };
+        assert_eq!(op.effective_gas_price(90.into()), 100.into());
+    }
+
+    #[test]
+    fn test_effective_gas_price_base_fee_binding() {
+        let op = UserOperation {
+            max_fee_per_gas: 100.into(),
+            max_priority_fee_per_gas: 20.into(),
+            ..Default::default()
+        };
+        assert_eq!(op.effective_gas_price(50.into()), 70.into());
+    }
+
+    #[test]
+    fn test_classify() {
+        let factory = Bytes::from_str("0x0123456789abcdef0123456789abcdef01234567").unwrap();
+        let paymaster = Bytes::from_str("0x89abcdef0123456789abcdef0123456789abcdef").unwrap();
+
+        for (init_code, paymaster_and_data, creates_wallet, has_paymaster) in [
+            (Bytes::default(), Bytes::default(), false, false),
+            (factory.clone(), Bytes::default(), true, false),
+            (Bytes::default(), paymaster.clone(), false, true),
+            (factory, paymaster, true, true),
+        ] {
+            let op = UserOperation {
+                init_code,
+                paymaster_and_data,
+                ..Default::default()
+            };
+            assert_eq!(
+                op.classify(),
+                OpKind {
+                    creates_wallet,
+                    has_paymaster,
+                    is_aggregated: false,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_entities_and_hash() {
+        let entry_point: Address = "0x66a15edcc3b50a663e72f1457ffd49b9ae284ddc"
+            .parse()
+            .unwrap();
+        let op = UserOperation {
+            sender: "0x1306b01bc3e4ad202612d3843387e94737673f53"
+                .parse()
+                .unwrap(),
+            nonce: 8942.into(),
+            init_code: Bytes::from_str(
+                "0x0123456789abcdef0123456789abcdef012345675fbfb9cf0000000000000000",
+            )
+            .unwrap(),
+            call_data: Bytes::from_str("0x5194544700000000").unwrap(),
+            call_gas_limit: 10_000.into(),
+            verification_gas_limit: 100_000.into(),
+            pre_verification_gas: 100.into(),
+            max_fee_per_gas: 99_999.into(),
+            max_priority_fee_per_gas: 9_999_999.into(),
+            paymaster_and_data: Bytes::from_str(
+                "0x89abcdef0123456789abcdef0123456789abcdef00000000000000000000abcd",
+            )
+            .unwrap(),
+            signature: Bytes::from_str(
+                "0xda0929f527cded8d0a1eaf2e8861d7f7e2d8160b7b13942f99dd367df4473a",
+            )
+            .unwrap(),
+        };
+
+        assert_eq!(
+            op.entities_and_hash(entry_point, 1337),
+            OpMetadata {
+                hash: op.op_hash(entry_point, 1337),
+                sender: op.sender,
+                factory: Some(
+                    "0x0123456789abcdef0123456789abcdef01234567"
+                        .parse()
+                        .unwrap()
+                ),
+                paymaster: Some(
+                    "0x89abcdef0123456789abcdef0123456789abcdef"
+                        .parse()
+                        .unwrap()
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn test_nonce_key_and_sequence() {
+        let key = U256::from_str("0xabcdef0000000000000000000000000000000000000001").unwrap();
+        let op = UserOperation {
+            nonce: (key << 64) | U256::from(u64::MAX),
+            ..Default::default()
+        };
+        assert_eq!(op.nonce_key(), key);
+        assert_eq!(op.nonce_sequence(), U256::from(u64::MAX));
+
+        let op = UserOperation {
+            nonce: 7.into(),
+            ..Default::default()
+        };
+        assert_eq!(op.nonce_key(), U256::zero());
+        assert_eq!(op.nonce_sequence(), 7.into());
+    }
 }